@@ -0,0 +1,183 @@
+//! Backfill of files written while the agent was not running
+//!
+//! The watcher only reports new activity. At startup, whenever the watch folders
+//! change and then periodically, the folders are compared against what the Hub
+//! already has from this instrument: a file counts as uploaded when an asset
+//! with the same relative path and checksum exists, or when the Hub reported
+//! its content as a duplicate and it has not changed since. Everything else is
+//! handed to the settler like a newly detected file.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use chrono::{DateTime, Local, NaiveDate};
+use openbio_core::agent::{FileFilter, IngestedFile};
+use openbio_core::storage::file_checksum;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::client::ApiClient;
+use crate::queue::unix_secs;
+use crate::rules::LocalRules;
+use crate::state::AgentState;
+use crate::watcher::{collect_files, upload_name, WatchRoot};
+
+/// What to scan: the watch folders and which files in them are wanted
+#[derive(Clone)]
+pub struct Scope {
+    pub roots: Vec<WatchRoot>,
+    pub filter: FileFilter,
+    pub rules: Arc<LocalRules>,
+}
+
+/// A local file considered for backfill
+struct LocalFile {
+    path: PathBuf,
+    filename: String,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Parse `--backfill-since`: `YYYY-MM-DD` (local midnight) or an RFC 3339 time
+pub fn parse_since(value: &str) -> Result<SystemTime, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.into());
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+        .map(SystemTime::from)
+        .ok_or_else(|| format!("expected YYYY-MM-DD or an RFC 3339 time, got {:?}", value))
+}
+
+/// Scan whenever the scope changes and every `interval`, sending files the Hub
+/// does not have to `tx`
+pub async fn run(
+    client: ApiClient,
+    machine_id: String,
+    since: Option<SystemTime>,
+    interval: Option<Duration>,
+    mut scope_rx: watch::Receiver<Option<Scope>>,
+    state: Arc<AgentState>,
+    tx: UnboundedSender<PathBuf>,
+) {
+    // Files already matched against the Hub, by size and modification time,
+    // so periodic scans do not hash them again
+    let mut verified = HashMap::new();
+
+    loop {
+        let scope = scope_rx.borrow_and_update().clone();
+        if let Some(scope) = scope {
+            match scan(&client, &machine_id, since, &scope, &state, &mut verified).await {
+                Ok(missing) => {
+                    if !missing.is_empty() {
+                        info!(
+                            "Backfill: {} file(s) in the watch folders are not on the Hub yet",
+                            missing.len()
+                        );
+                    }
+                    for path in missing {
+                        let _ = tx.send(path);
+                    }
+                }
+                Err(e) => warn!("Backfill scan failed: {:#}", e),
+            }
+        }
+
+        tokio::select! {
+            changed = scope_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            _ = tokio::time::sleep(interval.unwrap_or_default()), if interval.is_some() => {}
+        }
+    }
+}
+
+async fn scan(
+    client: &ApiClient,
+    machine_id: &str,
+    since: Option<SystemTime>,
+    scope: &Scope,
+    state: &AgentState,
+    verified: &mut HashMap<PathBuf, (u64, Option<SystemTime>)>,
+) -> Result<Vec<PathBuf>> {
+    let mut on_hub: HashMap<String, Vec<IngestedFile>> = HashMap::new();
+    for file in client.ingested(machine_id).await? {
+        on_hub.entry(file.filename.clone()).or_default().push(file);
+    }
+
+    let scope = scope.clone();
+    let files = tokio::task::spawn_blocking(move || list_files(&scope, since)).await?;
+
+    let mut missing = vec![];
+    for file in files {
+        if state.is_duplicate(&file.path, file.size, file.modified.and_then(unix_secs)) {
+            continue;
+        }
+        let key = (file.size, file.modified);
+        let known: Vec<&IngestedFile> = on_hub
+            .get(&file.filename)
+            .into_iter()
+            .flatten()
+            .filter(|known| known.size_bytes.is_none_or(|size| size == file.size as i64))
+            .collect();
+        if known.is_empty() {
+            missing.push(file.path);
+            continue;
+        }
+        if verified.get(&file.path) == Some(&key) {
+            continue;
+        }
+
+        let path = file.path.clone();
+        let checksum = tokio::task::spawn_blocking(move || file_checksum(&path)).await??;
+        if known
+            .iter()
+            .any(|known| known.checksum.as_deref() == Some(checksum.as_str()))
+        {
+            verified.insert(file.path, key);
+        } else {
+            debug!("{} differs from the copy on the Hub", file.filename);
+            missing.push(file.path);
+        }
+    }
+    Ok(missing)
+}
+
+fn list_files(scope: &Scope, since: Option<SystemTime>) -> Vec<LocalFile> {
+    let mut files = vec![];
+    for root in &scope.roots {
+        let mut paths = vec![];
+        collect_files(&root.path, &mut paths);
+
+        files.extend(paths.into_iter().filter_map(|path| {
+            let filename = upload_name(&scope.roots, root, &path)?;
+            let relative = path.strip_prefix(&root.path).ok()?;
+            if !scope.filter.matches(relative) || !scope.rules.matches(relative) {
+                return None;
+            }
+            let metadata = std::fs::metadata(&path).ok()?;
+            if !scope.rules.accepts_size(metadata.len()) {
+                return None;
+            }
+            let modified = metadata.modified().ok();
+            if since.is_some_and(|since| modified.is_some_and(|modified| modified < since)) {
+                return None;
+            }
+            Some(LocalFile {
+                path,
+                filename,
+                size: metadata.len(),
+                modified,
+            })
+        }));
+    }
+    files
+}
@@ -0,0 +1,138 @@
+//! HTTP client for the server's `/api/agent/*` endpoints
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result};
+use openbio_core::agent::{
+    AgentConfig, AssetReceipt, ChunkResponse, Heartbeat, HeartbeatResponse, IngestedFile,
+    UploadRequest, UploadResponse,
+};
+use reqwest::RequestBuilder;
+
+/// Talks to the OpenBio API on behalf of one instrument
+#[derive(Clone)]
+pub struct ApiClient {
+    http: reqwest::Client,
+    /// Shared by every clone, so a Hub found at a new address is used by all tasks
+    api_url: Arc<RwLock<String>>,
+    api_key: Option<String>,
+}
+
+impl ApiClient {
+    pub fn new(api_url: &str, api_key: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            api_url: Arc::new(RwLock::new(api_url.trim_end_matches('/').to_string())),
+            api_key,
+        }
+    }
+
+    pub fn api_url(&self) -> String {
+        self.api_url.read().unwrap().clone()
+    }
+
+    /// Point every clone at another Hub address; returns whether it changed
+    pub fn set_api_url(&self, api_url: &str) -> bool {
+        let api_url = api_url.trim_end_matches('/');
+        let mut current = self.api_url.write().unwrap();
+        if *current == api_url {
+            return false;
+        }
+        *current = api_url.to_string();
+        true
+    }
+
+    /// POST /api/agent/heartbeat
+    pub async fn heartbeat(&self, beat: &Heartbeat) -> Result<HeartbeatResponse> {
+        let response = self
+            .authorized(self.http.post(self.url("/api/agent/heartbeat")))
+            .json(beat)
+            .send()
+            .await
+            .context("Heartbeat request failed")?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    /// GET /api/agent/config
+    pub async fn config(&self, machine_id: &str) -> Result<AgentConfig> {
+        let response = self
+            .authorized(self.http.get(self.url("/api/agent/config")))
+            .query(&[("machine_id", machine_id)])
+            .send()
+            .await
+            .context("Config request failed")?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    /// GET /api/agent/assets
+    pub async fn ingested(&self, machine_id: &str) -> Result<Vec<IngestedFile>> {
+        let response = self
+            .authorized(self.http.get(self.url("/api/agent/assets")))
+            .query(&[("machine_id", machine_id)])
+            .send()
+            .await
+            .context("Asset listing failed")?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    /// POST /api/agent/uploads
+    pub async fn start_upload(&self, request: &UploadRequest) -> Result<UploadResponse> {
+        let response = self
+            .authorized(self.http.post(self.url("/api/agent/uploads")))
+            .json(request)
+            .send()
+            .await
+            .context("Upload request failed")?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    /// PUT /api/agent/uploads/{upload_id}?offset=...
+    pub async fn upload_chunk(
+        &self,
+        upload_id: &str,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<ChunkResponse> {
+        let response = self
+            .authorized(
+                self.http
+                    .put(self.url(&format!("/api/agent/uploads/{}", upload_id))),
+            )
+            .query(&[("offset", offset)])
+            .body(data)
+            .send()
+            .await
+            .context("Chunk upload failed")?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    /// POST /api/agent/uploads/{upload_id}/complete
+    pub async fn complete_upload(&self, upload_id: &str) -> Result<AssetReceipt> {
+        let response = self
+            .authorized(
+                self.http
+                    .post(self.url(&format!("/api/agent/uploads/{}/complete", upload_id))),
+            )
+            .send()
+            .await
+            .context("Completing upload failed")?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.api_url.read().unwrap(), path)
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+}
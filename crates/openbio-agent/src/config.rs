@@ -0,0 +1,60 @@
+//! Configuration pulled from the server
+//!
+//! The watch folder, globs and auto-import toggle are managed on the Equipment
+//! record. The agent polls for changes and the main loop re-applies them.
+
+use std::time::Duration;
+
+use openbio_core::agent::{AgentConfig, CONFIG_POLL_SECS};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::client::ApiClient;
+
+/// Longest wait between attempts while the server is unreachable at startup
+const MAX_RETRY_SECS: u64 = 60;
+
+/// Fetch the configuration, retrying until the server answers
+pub async fn fetch_initial(client: &ApiClient, machine_id: &str) -> AgentConfig {
+    let mut delay = 1;
+    loop {
+        match client.config(machine_id).await {
+            Ok(config) => return config,
+            Err(e) => {
+                warn!(
+                    "Could not fetch configuration ({:#}), retrying in {}s",
+                    e, delay
+                );
+                tokio::time::sleep(Duration::from_secs(delay)).await;
+                delay = (delay * 2).min(MAX_RETRY_SECS);
+            }
+        }
+    }
+}
+
+/// Publish configuration changes every [`CONFIG_POLL_SECS`]
+pub async fn poll(client: ApiClient, machine_id: String, tx: watch::Sender<AgentConfig>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(CONFIG_POLL_SECS));
+    // The first tick completes immediately; the initial config is already applied
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        match client.config(&machine_id).await {
+            Ok(config) => {
+                let changed = tx.send_if_modified(|current| {
+                    if *current == config {
+                        return false;
+                    }
+                    *current = config;
+                    true
+                });
+                if changed {
+                    info!("Configuration changed on the server");
+                }
+            }
+            Err(e) => warn!("Could not refresh configuration: {:#}", e),
+        }
+    }
+}
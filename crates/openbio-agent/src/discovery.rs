@@ -0,0 +1,80 @@
+//! Finding the Hub over mDNS instead of a fixed `--api-url`
+//!
+//! With `--discover` the agent browses for Hubs and uses the first one that
+//! answers, or only the one advertising `--lab-name`. The browser stays open
+//! for the life of the process: when the Hub comes back on another address
+//! (a DHCP lease changed, the Hub moved to another machine) every request
+//! follows it. Hubs are also re-resolved periodically in case an
+//! announcement was missed.
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use openbio_core::discovery::{DiscoveredHub, HubBrowser, HubEvent};
+use tracing::{debug, info, warn};
+
+use crate::client::ApiClient;
+
+/// How often the Hub is resolved afresh
+const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How often "still looking" is logged while no Hub answers
+const SEARCH_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Wait until a Hub (of `lab_name`, if given) answers
+pub async fn find_hub(browser: &mut HubBrowser, lab_name: Option<&str>) -> Result<DiscoveredHub> {
+    let wanted = match lab_name {
+        Some(lab_name) => format!("the Hub of '{}'", lab_name),
+        None => "a Hub".to_string(),
+    };
+    info!("Looking for {} on the local network", wanted);
+
+    let mut reminder = tokio::time::interval(SEARCH_LOG_INTERVAL);
+    reminder.tick().await;
+    loop {
+        tokio::select! {
+            event = browser.recv() => match event {
+                Some(HubEvent::Resolved(hub)) if hub.is_lab(lab_name) => return Ok(hub),
+                Some(HubEvent::Resolved(hub)) => {
+                    debug!("Ignoring the Hub of '{}' at {}", hub.name, hub.address);
+                }
+                Some(HubEvent::Removed(_)) => {}
+                None => bail!("mDNS browsing stopped"),
+            },
+            _ = reminder.tick() => {
+                info!("Still looking for {}", wanted);
+                browser.refresh()?;
+            }
+        }
+    }
+}
+
+/// Keep `client` pointed at the Hub named `hub_name` until the process exits
+pub async fn follow(client: ApiClient, mut browser: HubBrowser, hub_name: String) {
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+    refresh.tick().await;
+    loop {
+        tokio::select! {
+            event = browser.recv() => match event {
+                Some(HubEvent::Resolved(hub)) if hub.name == hub_name => {
+                    if client.set_api_url(&hub.address) {
+                        info!("Hub '{}' is now at {}", hub.name, hub.address);
+                    }
+                }
+                Some(HubEvent::Removed(name)) if name == hub_name => {
+                    warn!("Hub '{}' stopped advertising; waiting for it to return", name);
+                }
+                Some(_) => {}
+                None => {
+                    warn!("mDNS browsing stopped; staying with {}", client.api_url());
+                    return;
+                }
+            },
+            _ = refresh.tick() => {
+                if let Err(e) = browser.refresh() {
+                    warn!("Cannot re-resolve the Hub: {}", e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Periodic heartbeats so the server knows the instrument is connected
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use openbio_core::agent::{Heartbeat, HEARTBEAT_INTERVAL_SECS};
+use openbio_core::AgentStatus;
+use tracing::{info, warn};
+
+use crate::client::ApiClient;
+use crate::state::AgentState;
+
+/// Report in every [`HEARTBEAT_INTERVAL_SECS`] until the process exits
+pub async fn run(client: ApiClient, machine_id: String, state: Arc<AgentState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
+    let mut last_status = None;
+
+    loop {
+        interval.tick().await;
+
+        let watch_dirs = state.watch_dirs();
+        let beat = Heartbeat {
+            machine_id: machine_id.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            watch_dir: (!watch_dirs.is_empty()).then(|| {
+                watch_dirs
+                    .iter()
+                    .map(|dir| dir.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            }),
+            queue_depth: state.queue_depth(),
+        };
+        match client.heartbeat(&beat).await {
+            Ok(response) => {
+                if last_status != Some(response.status) {
+                    match response.status {
+                        AgentStatus::Locked => {
+                            warn!(
+                                "Equipment {} is locked on the server",
+                                response.equipment_id
+                            )
+                        }
+                        status => info!("Equipment {} is {}", response.equipment_id, status),
+                    }
+                    last_status = Some(response.status);
+                }
+            }
+            Err(e) => {
+                warn!("Heartbeat failed: {:#}", e);
+                last_status = None;
+            }
+        }
+    }
+}
@@ -1,36 +1,150 @@
 //! OpenBio Ingest Agent
 //! 
 //! Watches directories for new files and uploads them to the OpenBio API.
+//! Only the machine ID (and API key) are given locally; what to watch is
+//! configured on the instrument's Equipment record and pulled from the server.
+//! The Hub is given by URL or found over mDNS (`--discover`).
+//!
+//! Settings can also come from a config file (`--config`), and
+//! `openbio-agent systemd-unit` prints a unit that runs the agent as a
+//! service. SIGTERM or Ctrl-C stop it after the upload in progress finishes.
 
-use std::path::PathBuf;
-use std::sync::mpsc::channel;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use anyhow::Result;
-use clap::Parser;
-use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
-use tracing::{info, warn};
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use openbio_core::discovery::HubBrowser;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod backfill;
+mod client;
+mod config;
+mod discovery;
+mod heartbeat;
+mod queue;
+mod rules;
+mod settings;
+mod settle;
+mod state;
+mod upload;
+mod watcher;
+
+use client::ApiClient;
+use queue::{unix_now, UploadQueue};
+use settings::{Hub, Settings};
+use settle::{Settler, SETTLE_POLL_INTERVAL};
+use state::AgentState;
+use watcher::FolderWatcher;
+
 /// OpenBio Ingest Agent CLI
 #[derive(Parser, Debug)]
 #[command(name = "openbio-agent")]
 #[command(about = "Ingest agent for automated file ingestion from lab instruments")]
 struct Args {
-    /// Machine ID for this instrument
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// TOML file with settings; flags given on the command line take precedence
+    #[arg(long, global = true, env = "OPENBIO_AGENT_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Machine ID for this instrument (Equipment ID or QR code)
     #[arg(long)]
-    machine_id: String,
+    machine_id: Option<String>,
+
+    /// API URL to connect to [default: http://localhost:3000]
+    #[arg(long, conflicts_with_all = ["discover", "lab_name"])]
+    api_url: Option<String>,
 
-    /// API URL to connect to
-    #[arg(long, default_value = "http://localhost:3000")]
-    api_url: String,
+    /// Find the Hub on the local network over mDNS instead of using --api-url
+    #[arg(long)]
+    discover: bool,
 
-    /// Directory to watch for new files
+    /// Only use the Hub advertising this lab name (implies --discover)
     #[arg(long)]
-    watch_dir: PathBuf,
+    lab_name: Option<String>,
+
+    /// Directory to watch, overriding the watch folder configured on the server (repeatable;
+    /// with several, upload names start with the folder's name)
+    #[arg(long = "watch-dir")]
+    watch_dirs: Vec<PathBuf>,
 
-    /// API key for authentication (Enterprise mode)
+    /// API key issued for this instrument on the Hub (Equipment → API keys)
     #[arg(long, env = "OPENBIO_API_KEY")]
     api_key: Option<String>,
+
+    /// Seconds a file's size and modification time must stay unchanged before it is ingested
+    /// [default: 30]
+    #[arg(long)]
+    settle_secs: Option<u64>,
+
+    /// File whose appearance marks a folder unit as completely written, e.g. RTAComplete.txt
+    /// for Illumina run folders (repeatable; only used with --directory-units)
+    #[arg(long = "sentinel")]
+    sentinels: Vec<String>,
+
+    /// Ingest each top-level folder of the watch directory as one unit instead of file by file
+    #[arg(long)]
+    directory_units: bool,
+
+    /// Only upload files matching this glob, relative to the watch directory (repeatable;
+    /// applies on top of the globs configured on the server)
+    #[arg(long = "include")]
+    include: Vec<String>,
+
+    /// Never upload files matching this glob (repeatable). Temporary, lock and thumbnail
+    /// files are always skipped
+    #[arg(long = "exclude")]
+    exclude: Vec<String>,
+
+    /// Skip files smaller than this (bytes, or with a K, M or G suffix)
+    #[arg(long, value_parser = rules::parse_size)]
+    min_size: Option<u64>,
+
+    /// Skip files larger than this (bytes, or with a K, M or G suffix)
+    #[arg(long, value_parser = rules::parse_size)]
+    max_size: Option<u64>,
+
+    /// MIME type stored for an extension, e.g. `lif=application/x-leica-lif` (repeatable;
+    /// overrides the built-in mapping)
+    #[arg(long = "mime-type", value_parser = rules::parse_mime_type)]
+    mime_types: Vec<(String, String)>,
+
+    /// Only backfill files modified after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = backfill::parse_since)]
+    backfill_since: Option<SystemTime>,
+
+    /// Minutes between backfill scans of the watch directory (0: only at startup) [default: 15]
+    #[arg(long)]
+    backfill_interval_mins: Option<u64>,
+
+    /// Seconds to wait for the upload in progress when stopping [default: 120]
+    #[arg(long)]
+    shutdown_timeout_secs: Option<u64>,
+
+    /// Where the upload queue is kept [default: <data dir>/OpenBio/agent]
+    #[arg(long, global = true)]
+    state_dir: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the files waiting to be uploaded
+    Status,
+    /// Print a systemd unit that runs the agent with the file given by --config
+    SystemdUnit {
+        /// Unit for a user service (`systemctl --user`) instead of a system one
+        #[arg(long)]
+        user: bool,
+        /// File with `OPENBIO_API_KEY=...`, read by systemd so the key stays out of the unit
+        #[arg(long, default_value = "/etc/openbio/agent.env")]
+        env_file: PathBuf,
+    },
 }
 
 #[tokio::main]
@@ -41,39 +155,272 @@ async fn main() -> Result<()> {
         .init();
 
     let args = Args::parse();
+    let file = settings::load_file(&args)?;
+    match &args.command {
+        Some(Command::Status) => {
+            return print_status(&settings::state_dir(&args, file.state_dir));
+        }
+        Some(Command::SystemdUnit { user, env_file }) => {
+            let settings = Settings::resolve(&args, file)?;
+            return print_systemd_unit(&args, &settings, *user, env_file);
+        }
+        None => {}
+    }
+    let settings = Settings::resolve(&args, file)?;
+    let machine_id = settings.machine_id.clone();
 
-    info!(
-        "Starting OpenBio Agent for machine '{}' watching '{}'",
-        args.machine_id,
-        args.watch_dir.display()
-    );
+    info!("Starting OpenBio Agent for machine '{}'", machine_id);
+    if settings.api_key.is_none() {
+        warn!("No API key set (OPENBIO_API_KEY); the Hub will reject every request");
+    }
+    if !settings.settle.sentinels.is_empty() && !settings.settle.directory_units {
+        warn!("Sentinels only apply to folder units (--directory-units); files settle by time");
+    }
+
+    let client = match &settings.hub {
+        Hub::Url(api_url) => ApiClient::new(api_url, settings.api_key.clone()),
+        Hub::Discover { lab_name } => {
+            let mut browser = HubBrowser::start()?;
+            let hub = discovery::find_hub(&mut browser, lab_name.as_deref()).await?;
+            info!("Using the Hub of '{}' at {}", hub.name, hub.address);
+            let client = ApiClient::new(&hub.address, settings.api_key.clone());
+            tokio::spawn(discovery::follow(client.clone(), browser, hub.name));
+            client
+        }
+    };
+    let state = Arc::new(AgentState::default());
+
+    // Open the queue first so a corrupt journal is reported right away
+    let queue = UploadQueue::open(&settings.state_dir)?;
+    let (upload_tx, upload_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let mut uploads = tokio::spawn(upload::run(
+        client.clone(),
+        machine_id.clone(),
+        queue,
+        upload_rx,
+        state.clone(),
+        stop_rx,
+    ));
+
+    let initial = config::fetch_initial(&client, &machine_id).await;
+    let (config_tx, mut config_rx) = watch::channel(initial);
+    tokio::spawn(config::poll(client.clone(), machine_id.clone(), config_tx));
+    tokio::spawn(heartbeat::run(
+        client.clone(),
+        machine_id.clone(),
+        state.clone(),
+    ));
 
     // Set up file watcher
-    let (tx, rx) = channel();
-
-    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
-    watcher.watch(&args.watch_dir, RecursiveMode::Recursive)?;
-
-    info!("Watching for file changes...");
-
-    // Process events
-    for res in rx {
-        match res {
-            Ok(event) => {
-                if event.kind.is_create() {
-                    for path in event.paths {
-                        info!("New file detected: {}", path.display());
-                        // TODO: Implement handshake and upload
-                        // 1. POST /api/agent/handshake { machine_id, timestamp }
-                        // 2. Get experiment_id from response
-                        // 3. Upload file to storage
-                        // 4. Link to experiment via API
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = FolderWatcher::new(tx, Arc::new(settings.rules))?;
+    watcher.apply(
+        &config_rx.borrow_and_update(),
+        &settings.watch_roots,
+        &state,
+    );
+
+    // Files written while the agent was down are found by scanning
+    let (scope_tx, scope_rx) = watch::channel(watcher.scope());
+    let (backfill_tx, mut backfill_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(backfill::run(
+        client.clone(),
+        machine_id.clone(),
+        settings.backfill_since,
+        settings.backfill_interval,
+        scope_rx,
+        state.clone(),
+        backfill_tx,
+    ));
+
+    let mut settler = Settler::new(settings.settle);
+    let mut settle_interval = tokio::time::interval(SETTLE_POLL_INTERVAL);
+    settle_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // One check of the pending units at a time; results come back on this channel
+    let (checked_tx, mut checked_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut checking = false;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            signal = &mut shutdown => {
+                signal?;
+                break;
+            }
+            changed = config_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let config = config_rx.borrow_and_update().clone();
+                if watcher.apply(&config, &settings.watch_roots, &state) {
+                    settler.clear();
+                }
+                scope_tx.send_replace(watcher.scope());
+            }
+            Some(path) = backfill_rx.recv() => {
+                if let Some(root) = watcher.root_of(&path) {
+                    settler.touch(&root.path, &path);
+                }
+            }
+            Some(res) = rx.recv() => match res {
+                Ok(event) => {
+                    // Writes keep a file pending until it settles
+                    if event.kind.is_create() || event.kind.is_modify() {
+                        for path in event.paths {
+                            match watcher.root_of(&path) {
+                                Some(root) if watcher.wants(&path) => {
+                                    settler.touch(&root.path, &path);
+                                }
+                                _ => debug!("Ignoring {}", path.display()),
+                            }
+                        }
+                    }
+                }
+                Err(e) => warn!("Watch error: {:?}", e),
+            },
+            _ = settle_interval.tick(), if !checking => {
+                checking = true;
+                let checks = settler.due();
+                let checked_tx = checked_tx.clone();
+                tokio::spawn(async move {
+                    // Walking run folders takes a while; keep it off the async workers
+                    let checked = tokio::task::spawn_blocking(move || checks.run())
+                        .await
+                        .unwrap_or_default();
+                    let _ = checked_tx.send(checked);
+                });
+            }
+            Some(checked) = checked_rx.recv() => {
+                checking = false;
+                for unit in settler.update(checked) {
+                    if !watcher.auto_import() {
+                        info!("New data complete (auto-import is off): {}", unit.path().display());
+                        continue;
+                    }
+                    info!("New data complete: {}", unit.path().display());
+                    for file in watcher.files_of(&unit) {
+                        let _ = upload_tx.send(file);
                     }
                 }
+                state.set_pending(settler.len());
             }
-            Err(e) => warn!("Watch error: {:?}", e),
         }
     }
 
+    // Queued files are journalled, and files still settling are found again
+    // by the backfill, so only the upload in progress needs to finish
+    info!(
+        "Shutting down; waiting up to {}s for the upload in progress",
+        settings.shutdown_timeout.as_secs()
+    );
+    stop_tx.send_replace(true);
+    tokio::select! {
+        _ = &mut uploads => info!("Stopped"),
+        _ = tokio::time::sleep(settings.shutdown_timeout) => {
+            warn!("Upload still in progress; it resumes on the next start");
+        }
+        _ = shutdown_signal() => warn!("Stopping without waiting for the upload in progress"),
+    }
+    Ok(())
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix (what systemd sends on stop)
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+/// `openbio-agent systemd-unit`
+fn print_systemd_unit(args: &Args, settings: &Settings, user: bool, env_file: &Path) -> Result<()> {
+    let Some(config) = &args.config else {
+        bail!("Pass --config: the unit runs the agent with that file");
+    };
+    let config = config
+        .canonicalize()
+        .with_context(|| format!("Cannot find {}", config.display()))?;
+    let exe = std::env::current_exe().context("Cannot locate the agent executable")?;
+
+    println!("[Unit]");
+    println!("Description=OpenBio Ingest Agent ({})", settings.machine_id);
+    println!("Wants=network-online.target");
+    println!("After=network-online.target");
+    println!();
+    println!("[Service]");
+    println!("Type=simple");
+    println!(
+        "ExecStart={} --config {}",
+        quote_unit_arg(&exe),
+        quote_unit_arg(&config)
+    );
+    println!("EnvironmentFile=-{}", env_file.display());
+    println!("Restart=on-failure");
+    println!("RestartSec=10");
+    println!("KillSignal=SIGTERM");
+    println!(
+        "TimeoutStopSec={}",
+        settings.shutdown_timeout.as_secs() + 15
+    );
+    println!();
+    println!("[Install]");
+    println!(
+        "WantedBy={}",
+        if user {
+            "default.target"
+        } else {
+            "multi-user.target"
+        }
+    );
+    Ok(())
+}
+
+/// Paths with spaces must be quoted on an `ExecStart=` line
+fn quote_unit_arg(path: &Path) -> String {
+    let path = path.display().to_string();
+    if path.contains(char::is_whitespace) {
+        format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        path
+    }
+}
+
+/// `openbio-agent status`
+fn print_status(state_dir: &Path) -> Result<()> {
+    let queue = UploadQueue::open(state_dir).context("Cannot open the upload queue")?;
+    if queue.len() == 0 {
+        println!("Upload queue is empty ({})", queue.journal().display());
+        return Ok(());
+    }
+
+    println!(
+        "{} file(s) waiting to be uploaded ({})",
+        queue.len(),
+        queue.journal().display()
+    );
+    let now = unix_now();
+    for entry in queue.entries() {
+        let next = match entry.next_attempt_at - now {
+            wait if wait > 0 => format!("retry in {}s", wait),
+            _ => "due now".to_string(),
+        };
+        println!(
+            "  {}  {} bytes  {} attempt(s), {}",
+            entry.filename, entry.size_bytes, entry.attempts, next
+        );
+        if let Some(error) = &entry.last_error {
+            println!("      last error: {}", error);
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,169 @@
+//! Durable upload queue
+//!
+//! Completed files are recorded in `<state dir>/queue.json` before any upload
+//! is attempted, so nothing detected is lost when the Hub is unreachable or
+//! the agent restarts. The journal is rewritten atomically (temp file and
+//! rename) after every change. Failed uploads are retried with exponential
+//! backoff.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::upload::Detected;
+
+/// Delay before the first retry
+const BACKOFF_BASE_SECS: i64 = 5;
+/// Longest delay between retries
+const BACKOFF_MAX_SECS: i64 = 60 * 60;
+
+/// A file waiting to be uploaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueEntry {
+    pub path: PathBuf,
+    /// Path relative to the watch directory, with `/` separators
+    pub filename: String,
+    pub size_bytes: u64,
+    /// SHA-256 of the file when it was queued
+    pub checksum: String,
+    /// Modification time (Unix seconds), used to find the experiment booked then
+    pub modified_at: Option<i64>,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    pub queued_at: i64,
+    pub attempts: u32,
+    /// Unix seconds; the entry is not retried before this
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+}
+
+impl QueueEntry {
+    pub fn new(file: Detected, size_bytes: u64, checksum: String) -> Self {
+        let modified_at = fs::metadata(&file.path)
+            .ok()
+            .and_then(|metadata| modified_secs(&metadata));
+        let now = unix_now();
+        Self {
+            path: file.path,
+            filename: file.filename,
+            size_bytes,
+            checksum,
+            modified_at,
+            mime_type: file.mime_type,
+            queued_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+        }
+    }
+}
+
+pub struct UploadQueue {
+    journal: PathBuf,
+    entries: Vec<QueueEntry>,
+}
+
+impl UploadQueue {
+    /// Load the queue kept in `state_dir`, or start an empty one
+    pub fn open(state_dir: &Path) -> Result<Self> {
+        let journal = state_dir.join("queue.json");
+        let entries = match fs::read(&journal) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Corrupt upload queue {}", journal.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e).context(format!("Cannot read {}", journal.display())),
+        };
+        Ok(Self { journal, entries })
+    }
+
+    pub fn journal(&self) -> &Path {
+        &self.journal
+    }
+
+    pub fn entries(&self) -> &[QueueEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Queue a file. Returns false if the same content is already queued.
+    pub fn push(&mut self, entry: QueueEntry) -> Result<bool> {
+        if self.entries.iter().any(|e| e.checksum == entry.checksum) {
+            return Ok(false);
+        }
+        self.entries.push(entry);
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Entries whose retry time has come, oldest first
+    pub fn due(&self) -> Vec<QueueEntry> {
+        let now = unix_now();
+        let mut due: Vec<QueueEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.next_attempt_at <= now)
+            .cloned()
+            .collect();
+        due.sort_by_key(|entry| entry.queued_at);
+        due
+    }
+
+    /// Drop an entry once it is uploaded (or can no longer be)
+    pub fn remove(&mut self, checksum: &str) -> Result<()> {
+        self.entries.retain(|entry| entry.checksum != checksum);
+        self.save()
+    }
+
+    /// Record a failed attempt and schedule the next one
+    pub fn retry_later(&mut self, checksum: &str, error: String) -> Result<i64> {
+        let mut delay = 0;
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.checksum == checksum) {
+            delay = backoff_secs(entry.attempts);
+            entry.attempts += 1;
+            entry.next_attempt_at = unix_now() + delay;
+            entry.last_error = Some(error);
+        }
+        self.save()?;
+        Ok(delay)
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.journal.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.journal.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&self.entries)?)?;
+        fs::rename(&tmp, &self.journal)
+            .with_context(|| format!("Cannot write {}", self.journal.display()))
+    }
+}
+
+/// 5s, 10s, 20s, ... capped at an hour
+fn backoff_secs(attempts: u32) -> i64 {
+    BACKOFF_BASE_SECS
+        .saturating_mul(1i64 << attempts.min(20))
+        .min(BACKOFF_MAX_SECS)
+}
+
+/// A file's modification time in Unix seconds
+pub fn modified_secs(metadata: &fs::Metadata) -> Option<i64> {
+    unix_secs(metadata.modified().ok()?)
+}
+
+pub fn unix_secs(time: SystemTime) -> Option<i64> {
+    let since = time.duration_since(UNIX_EPOCH).ok()?;
+    Some(since.as_secs() as i64)
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,237 @@
+//! Local file rules from the command line
+//!
+//! These apply on top of the include/exclude globs configured on the Equipment
+//! record: a file is uploaded only if both accept it. Temporary, lock and
+//! thumbnail files are always skipped; the globs for them that match a file
+//! name are anchored at a path separator (`**/._*`), since `*` also matches
+//! `/` and `*._*` would skip `run._2/A1.fcs`.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use openbio_core::agent::FileFilter;
+
+/// Files instruments and operating systems leave behind that are never data
+const ALWAYS_EXCLUDED: &[&str] = &[
+    "*.tmp",
+    "*.temp",
+    "*.lock",
+    "**/.~lock.*",
+    "**/~$*",
+    "*.swp",
+    "**/Thumbs.db",
+    "**/desktop.ini",
+    "**/.DS_Store",
+    "**/._*",
+];
+
+/// MIME types by extension, extended and overridden by `--mime-type`
+const DEFAULT_MIME_TYPES: &[(&str, &str)] = &[
+    ("fcs", "application/vnd.isac.fcs"),
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("txt", "text/plain"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("xls", "application/vnd.ms-excel"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("fastq", "text/x-fastq"),
+    ("fq", "text/x-fastq"),
+    ("fasta", "text/x-fasta"),
+    ("fa", "text/x-fasta"),
+    ("bam", "application/x-bam"),
+    ("vcf", "text/x-vcf"),
+];
+
+pub struct LocalRules {
+    filter: FileFilter,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    mime_types: HashMap<String, String>,
+}
+
+impl LocalRules {
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        min_size: Option<u64>,
+        max_size: Option<u64>,
+        mime_overrides: &[(String, String)],
+    ) -> Result<Self> {
+        let exclude: Vec<String> = ALWAYS_EXCLUDED
+            .iter()
+            .map(|glob| glob.to_string())
+            .chain(exclude.iter().cloned())
+            .collect();
+
+        let mut mime_types: HashMap<String, String> = DEFAULT_MIME_TYPES
+            .iter()
+            .map(|(ext, mime)| (ext.to_string(), mime.to_string()))
+            .collect();
+        for (ext, mime) in mime_overrides {
+            mime_types.insert(ext.clone(), mime.clone());
+        }
+
+        Ok(Self {
+            filter: FileFilter::new(include, &exclude)?,
+            min_size,
+            max_size,
+            mime_types,
+        })
+    }
+
+    /// Whether a path relative to the watch folder may be uploaded
+    pub fn matches(&self, relative_path: &Path) -> bool {
+        self.filter.matches(relative_path)
+    }
+
+    /// Whether a file of `size` bytes is within `--min-size` / `--max-size`
+    pub fn accepts_size(&self, size: u64) -> bool {
+        self.min_size.is_none_or(|min| size >= min) && self.max_size.is_none_or(|max| size <= max)
+    }
+
+    /// MIME type by (case-insensitive) extension
+    pub fn mime_type(&self, path: &Path) -> Option<String> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        self.mime_types.get(&ext).cloned()
+    }
+}
+
+/// Parse `--min-size` / `--max-size`: bytes, or with a K, M or G suffix (powers of 1024)
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let upper = value.trim().to_uppercase();
+    let digits = upper.trim_end_matches('B');
+    let (number, factor) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1 << 10),
+        Some('M') => (&digits[..digits.len() - 1], 1 << 20),
+        Some('G') => (&digits[..digits.len() - 1], 1 << 30),
+        _ => (digits, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(factor))
+        .ok_or_else(|| format!("expected a size like 512, 64K, 10M or 2G, got {:?}", value))
+}
+
+/// Parse `--mime-type`: `EXT=TYPE`, e.g. `fcs=application/vnd.isac.fcs`
+pub fn parse_mime_type(value: &str) -> Result<(String, String), String> {
+    let (ext, mime) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected EXT=TYPE, got {:?}", value))?;
+    let ext = ext.trim().trim_start_matches('.').to_lowercase();
+    let mime = mime.trim();
+    if ext.is_empty() || !mime.contains('/') {
+        return Err(format!("expected EXT=TYPE, got {:?}", value));
+    }
+    Ok((ext, mime.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> LocalRules {
+        LocalRules::new(
+            &[],
+            &["*.bak".to_string()],
+            Some(10),
+            Some(1 << 20),
+            &[("fcs".to_string(), "application/x-fcs".to_string())],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn always_excluded_files_are_skipped_in_any_folder() {
+        let rules = rules();
+        for path in [
+            "scan.tmp",
+            "run1/Data/upload.lock",
+            "~$plate.xlsx",
+            "exports/~$plate.xlsx",
+            "exports/.~lock.plate.ods#",
+            "images/Thumbs.db",
+            ".DS_Store",
+            "run1/.DS_Store",
+            "._A1.fcs",
+            "plate1/._A1.fcs",
+            "plate1/A1.fcs.bak",
+        ] {
+            assert!(!rules.matches(Path::new(path)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn data_with_lookalike_names_is_kept() {
+        let rules = rules();
+        for path in [
+            "A1.fcs",
+            "run._2/A1.fcs",
+            "plate~$1/A1.fcs",
+            "MyThumbs.db",
+            "run1/RunInfo.xml",
+        ] {
+            assert!(rules.matches(Path::new(path)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn sizes_are_checked_against_both_bounds() {
+        let rules = rules();
+        assert!(!rules.accepts_size(9));
+        assert!(rules.accepts_size(10));
+        assert!(rules.accepts_size(1 << 20));
+        assert!(!rules.accepts_size((1 << 20) + 1));
+    }
+
+    #[test]
+    fn mime_types_by_extension_with_overrides() {
+        let rules = rules();
+        assert_eq!(
+            rules.mime_type(Path::new("run/A1.FCS")).as_deref(),
+            Some("application/x-fcs")
+        );
+        assert_eq!(
+            rules.mime_type(Path::new("plate.csv")).as_deref(),
+            Some("text/csv")
+        );
+        assert_eq!(rules.mime_type(Path::new("notes.xyz")), None);
+        assert_eq!(rules.mime_type(Path::new("Makefile")), None);
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("10m"), Ok(10 << 20));
+        assert_eq!(parse_size(" 2GB "), Ok(2 << 30));
+        for bad in ["", "G", "ten", "1.5G", "-1", "99999999999G"] {
+            assert!(parse_size(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parses_mime_type_overrides() {
+        assert_eq!(
+            parse_mime_type(".FCS = application/vnd.isac.fcs"),
+            Ok(("fcs".to_string(), "application/vnd.isac.fcs".to_string()))
+        );
+        for bad in ["fcs", "=text/plain", "fcs=plain", "fcs="] {
+            assert!(parse_mime_type(bad).is_err(), "{}", bad);
+        }
+    }
+}
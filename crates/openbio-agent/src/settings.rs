@@ -0,0 +1,278 @@
+//! Settings from the command line and the optional config file
+//!
+//! Everything the agent takes as a flag can also be set in a TOML file given
+//! with `--config`, which suits running it as a service. Flags win over the
+//! file and the file over the defaults; a list given as flags replaces the
+//! file's list, except MIME types, which are merged by extension.
+//!
+//! ```toml
+//! machine_id = "FACS-1"
+//! lab_name = "Smith Lab" # find the Hub over mDNS; or api_url = "http://hub:3000"
+//! settle_secs = 60
+//! exclude = ["*.bak"]
+//! max_size = "20G"
+//!
+//! [[watch]]
+//! path = "D:/Exports"
+//!
+//! [[watch]]
+//! path = "E:/Runs"
+//! name = "runs"
+//!
+//! [mime_types]
+//! lif = "application/x-leica-lif"
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer};
+
+use crate::backfill::parse_since;
+use crate::rules::{parse_mime_type, parse_size, LocalRules};
+use crate::settle::SettleConfig;
+use crate::watcher::WatchRoot;
+use crate::Args;
+
+const DEFAULT_API_URL: &str = "http://localhost:3000";
+const DEFAULT_SETTLE_SECS: u64 = 30;
+const DEFAULT_BACKFILL_INTERVAL_MINS: u64 = 15;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 120;
+
+/// The config file; every field is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileSettings {
+    pub machine_id: Option<String>,
+    pub api_url: Option<String>,
+    pub discover: Option<bool>,
+    pub lab_name: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub watch: Vec<WatchEntry>,
+    pub settle_secs: Option<u64>,
+    #[serde(default)]
+    pub sentinels: Vec<String>,
+    pub directory_units: Option<bool>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default, deserialize_with = "size")]
+    pub min_size: Option<u64>,
+    #[serde(default, deserialize_with = "size")]
+    pub max_size: Option<u64>,
+    #[serde(default)]
+    pub mime_types: BTreeMap<String, String>,
+    pub backfill_since: Option<String>,
+    pub backfill_interval_mins: Option<u64>,
+    pub shutdown_timeout_secs: Option<u64>,
+    pub state_dir: Option<PathBuf>,
+}
+
+/// A `[[watch]]` table
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchEntry {
+    pub path: PathBuf,
+    /// Prefix of upload names [default: the folder's name]
+    pub name: Option<String>,
+}
+
+/// How the Hub is reached
+#[derive(Debug, Clone)]
+pub enum Hub {
+    Url(String),
+    /// Over mDNS, only accepting the Hub of `lab_name` if given
+    Discover {
+        lab_name: Option<String>,
+    },
+}
+
+/// Everything the agent runs with
+pub struct Settings {
+    pub machine_id: String,
+    pub hub: Hub,
+    pub api_key: Option<String>,
+    /// Folders to watch instead of the server's watch folder
+    pub watch_roots: Vec<WatchRoot>,
+    pub settle: SettleConfig,
+    pub rules: LocalRules,
+    pub backfill_since: Option<SystemTime>,
+    pub backfill_interval: Option<Duration>,
+    /// How long to wait for uploads in progress on shutdown
+    pub shutdown_timeout: Duration,
+    pub state_dir: PathBuf,
+}
+
+impl FileSettings {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+}
+
+impl Settings {
+    pub fn resolve(args: &Args, file: FileSettings) -> Result<Self> {
+        let Some(machine_id) = args.machine_id.clone().or(file.machine_id) else {
+            bail!("No machine ID: pass --machine-id or set machine_id in the config file");
+        };
+
+        // A flag for either way of finding the Hub overrides the file's
+        let hub = if args.api_url.is_some() || args.discover || args.lab_name.is_some() {
+            hub(args.api_url.clone(), args.discover, args.lab_name.clone())?
+        } else {
+            hub(file.api_url, file.discover.unwrap_or(false), file.lab_name)?
+        };
+
+        let watch_roots: Vec<_> = if args.watch_dirs.is_empty() {
+            file.watch
+                .into_iter()
+                .map(|entry| WatchRoot::new(entry.path, entry.name))
+                .collect()
+        } else {
+            args.watch_dirs
+                .iter()
+                .map(|path| WatchRoot::new(path.clone(), None))
+                .collect()
+        };
+        check_roots(&watch_roots)?;
+
+        let mut mime_types = file
+            .mime_types
+            .iter()
+            .map(|(ext, mime)| parse_mime_type(&format!("{}={}", ext, mime)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(anyhow::Error::msg)?;
+        mime_types.extend(args.mime_types.iter().cloned());
+        let rules = LocalRules::new(
+            or_file(&args.include, &file.include),
+            or_file(&args.exclude, &file.exclude),
+            args.min_size.or(file.min_size),
+            args.max_size.or(file.max_size),
+            &mime_types,
+        )?;
+
+        let backfill_since = match (args.backfill_since, &file.backfill_since) {
+            (Some(since), _) => Some(since),
+            (None, Some(since)) => Some(parse_since(since).map_err(anyhow::Error::msg)?),
+            (None, None) => None,
+        };
+        let backfill_interval_mins = args
+            .backfill_interval_mins
+            .or(file.backfill_interval_mins)
+            .unwrap_or(DEFAULT_BACKFILL_INTERVAL_MINS);
+
+        Ok(Self {
+            machine_id,
+            hub,
+            api_key: args.api_key.clone().or(file.api_key),
+            watch_roots,
+            settle: SettleConfig {
+                window: Duration::from_secs(
+                    args.settle_secs
+                        .or(file.settle_secs)
+                        .unwrap_or(DEFAULT_SETTLE_SECS),
+                ),
+                sentinels: or_file(&args.sentinels, &file.sentinels).to_vec(),
+                directory_units: args.directory_units || file.directory_units.unwrap_or(false),
+            },
+            rules,
+            backfill_since,
+            backfill_interval: (backfill_interval_mins > 0)
+                .then(|| Duration::from_secs(backfill_interval_mins * 60)),
+            shutdown_timeout: Duration::from_secs(
+                args.shutdown_timeout_secs
+                    .or(file.shutdown_timeout_secs)
+                    .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+            ),
+            state_dir: state_dir(args, file.state_dir),
+        })
+    }
+}
+
+/// The config file named by `--config`, or an empty one
+pub fn load_file(args: &Args) -> Result<FileSettings> {
+    match &args.config {
+        Some(path) => FileSettings::load(path),
+        None => Ok(FileSettings::default()),
+    }
+}
+
+/// Where the upload queue is kept
+pub fn state_dir(args: &Args, from_file: Option<PathBuf>) -> PathBuf {
+    args.state_dir
+        .clone()
+        .or(from_file)
+        .unwrap_or_else(default_state_dir)
+}
+
+fn default_state_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("OpenBio")
+        .join("agent")
+}
+
+fn hub(api_url: Option<String>, discover: bool, lab_name: Option<String>) -> Result<Hub> {
+    match api_url {
+        Some(_) if discover || lab_name.is_some() => {
+            bail!("Give either an API URL or mDNS discovery (discover, lab name), not both")
+        }
+        Some(url) => Ok(Hub::Url(url)),
+        None if discover || lab_name.is_some() => Ok(Hub::Discover { lab_name }),
+        None => Ok(Hub::Url(DEFAULT_API_URL.to_string())),
+    }
+}
+
+fn or_file<'a>(flags: &'a [String], file: &'a [String]) -> &'a [String] {
+    if flags.is_empty() {
+        file
+    } else {
+        flags
+    }
+}
+
+/// Upload names must stay unambiguous: distinct names, no folder inside another
+fn check_roots(roots: &[WatchRoot]) -> Result<()> {
+    for (i, root) in roots.iter().enumerate() {
+        for other in &roots[i + 1..] {
+            if root.name == other.name {
+                bail!(
+                    "Watch folders {} and {} are both named '{}'; set a name for one of them",
+                    root.path.display(),
+                    other.path.display(),
+                    root.name
+                );
+            }
+            if root.path.starts_with(&other.path) || other.path.starts_with(&root.path) {
+                bail!(
+                    "Watch folders {} and {} overlap",
+                    root.path.display(),
+                    other.path.display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sizes as a number of bytes or a string like `"10M"`
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Option::<Size>::deserialize(deserializer)? {
+        Some(Size::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(Size::Text(text)) => parse_size(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
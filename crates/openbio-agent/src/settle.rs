@@ -0,0 +1,388 @@
+//! Write-completion detection
+//!
+//! Instruments write large files over minutes, so a create event usually fires
+//! on an empty or partial file. Detected paths are held here until they are
+//! complete:
+//!
+//! - files, once size and modification time have been unchanged for the
+//!   settle window;
+//! - directory units, the same way without sentinels, or with sentinels
+//!   (e.g. `RTAComplete.txt`) once one of them exists at the top of the unit
+//!   and nothing changed since the previous check.
+//!
+//! With directory units, a top-level folder of the watch directory (a
+//! sequencer run folder, say) is ingested as one unit instead of file by file.
+//! Sentinels only mark such folders: a file on its own has no folder that is
+//! guaranteed to ever receive one.
+//!
+//! Checking a unit means walking it on disk, which takes a while for a run
+//! folder with thousands of files. [`Settler::due`] hands out the units to
+//! check, [`Checks::run`] looks at them (off the async workers) and
+//! [`Settler::update`] takes the results back. A unit whose check was slow is
+//! checked less often.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::debug;
+
+/// How often pending units are re-checked
+pub const SETTLE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Checks taking longer than this back the unit off
+const SLOW_CHECK: Duration = Duration::from_millis(100);
+/// A slow unit waits this many times as long as its check took
+const BACKOFF_FACTOR: u32 = 10;
+/// Longest wait between checks of a unit
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct SettleConfig {
+    /// How long a unit must stay unchanged
+    pub window: Duration,
+    /// File names marking a directory unit as completely written
+    pub sentinels: Vec<String>,
+    /// Treat each top-level folder of the watch directory as one unit
+    pub directory_units: bool,
+}
+
+/// Something ready to be ingested
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Unit {
+    File(PathBuf),
+    Directory(PathBuf),
+}
+
+impl Unit {
+    pub fn path(&self) -> &Path {
+        match self {
+            Unit::File(path) | Unit::Directory(path) => path,
+        }
+    }
+}
+
+/// Size, newest modification time and file count of a unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Snapshot {
+    size: u64,
+    modified: Option<SystemTime>,
+    files: usize,
+}
+
+struct Pending {
+    snapshot: Option<Snapshot>,
+    /// When the snapshot last changed
+    changed_at: Instant,
+    /// Not checked again before this
+    next_check: Instant,
+}
+
+/// Units due for a look on disk, see [`Settler::due`]
+pub struct Checks {
+    units: Vec<Unit>,
+    sentinels: Vec<String>,
+}
+
+/// What a check found
+pub struct Checked {
+    unit: Unit,
+    found: Found,
+    took: Duration,
+}
+
+enum Found {
+    Gone,
+    /// Waiting for a sentinel, or the unit could not be read this time
+    NotYet,
+    Snapshot(Snapshot),
+}
+
+impl Checks {
+    /// Look at the units on disk. This walks directory units, so it blocks.
+    pub fn run(self) -> Vec<Checked> {
+        self.units
+            .into_iter()
+            .map(|unit| {
+                let started = Instant::now();
+                let found = if !unit.path().exists() {
+                    Found::Gone
+                } else if matches!(unit, Unit::Directory(_))
+                    && !self.sentinels.is_empty()
+                    && !has_sentinel(unit.path(), &self.sentinels)
+                {
+                    // Waiting for a sentinel is cheap; only walk the unit once one appears
+                    Found::NotYet
+                } else {
+                    snapshot(&unit).map_or(Found::NotYet, Found::Snapshot)
+                };
+                Checked {
+                    unit,
+                    found,
+                    took: started.elapsed(),
+                }
+            })
+            .collect()
+    }
+}
+
+pub struct Settler {
+    config: SettleConfig,
+    pending: HashMap<Unit, Pending>,
+}
+
+impl Settler {
+    pub fn new(config: SettleConfig) -> Self {
+        Self {
+            config,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Record activity on `path` inside the watch directory `root`
+    pub fn touch(&mut self, root: &Path, path: &Path) {
+        if self.config.directory_units && self.is_sentinel(path) {
+            // Sentinels complete their folder on the next poll
+            return;
+        }
+        let Some(unit) = self.unit_of(root, path) else {
+            return;
+        };
+        self.pending.entry(unit).or_insert_with(|| Pending {
+            snapshot: None,
+            changed_at: Instant::now(),
+            next_check: Instant::now(),
+        });
+    }
+
+    /// Units detected but not complete yet
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Forget everything pending (the watch directory changed)
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Pending units due for a check
+    pub fn due(&self) -> Checks {
+        let now = Instant::now();
+        Checks {
+            units: self
+                .pending
+                .iter()
+                .filter(|(_, pending)| pending.next_check <= now)
+                .map(|(unit, _)| unit.clone())
+                .collect(),
+            sentinels: self.config.sentinels.clone(),
+        }
+    }
+
+    /// Take the results of [`Checks::run`] and return the units that are complete
+    pub fn update(&mut self, checked: Vec<Checked>) -> Vec<Unit> {
+        let now = Instant::now();
+        let sentinel_units = !self.config.sentinels.is_empty();
+        let mut ready = vec![];
+
+        for Checked { unit, found, took } in checked {
+            // Cleared while it was being checked
+            let Some(pending) = self.pending.get_mut(&unit) else {
+                continue;
+            };
+            if took >= SLOW_CHECK {
+                pending.next_check = now + (took * BACKOFF_FACTOR).min(MAX_CHECK_INTERVAL);
+            }
+
+            let snapshot = match found {
+                Found::Gone => {
+                    debug!(
+                        "{} disappeared before it was complete",
+                        unit.path().display()
+                    );
+                    self.pending.remove(&unit);
+                    continue;
+                }
+                Found::NotYet => continue,
+                Found::Snapshot(snapshot) => snapshot,
+            };
+            let unchanged = pending.snapshot == Some(snapshot);
+            if !unchanged {
+                pending.snapshot = Some(snapshot);
+                pending.changed_at = now;
+            }
+
+            let sentinel = matches!(unit, Unit::Directory(_)) && sentinel_units;
+            if unchanged
+                && (sentinel || now.duration_since(pending.changed_at) >= self.config.window)
+            {
+                self.pending.remove(&unit);
+                ready.push(unit);
+            }
+        }
+
+        ready
+    }
+
+    fn is_sentinel(&self, path: &Path) -> bool {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| self.config.sentinels.iter().any(|s| s == name))
+    }
+
+    fn unit_of(&self, root: &Path, path: &Path) -> Option<Unit> {
+        let relative = path.strip_prefix(root).ok()?;
+        if self.config.directory_units {
+            let mut components = relative.components();
+            if let (Some(Component::Normal(top)), Some(_)) = (components.next(), components.next())
+            {
+                return Some(Unit::Directory(root.join(top)));
+            }
+        }
+        if path.is_dir() {
+            // Files inside report their own events
+            return None;
+        }
+        Some(Unit::File(path.to_path_buf()))
+    }
+}
+
+fn snapshot(unit: &Unit) -> Option<Snapshot> {
+    match unit {
+        Unit::File(path) => {
+            let metadata = fs::metadata(path).ok()?;
+            Some(Snapshot {
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+                files: 1,
+            })
+        }
+        Unit::Directory(path) => {
+            let mut snapshot = Snapshot {
+                size: 0,
+                modified: None,
+                files: 0,
+            };
+            add_directory(path, &mut snapshot).ok()?;
+            Some(snapshot)
+        }
+    }
+}
+
+fn add_directory(dir: &Path, snapshot: &mut Snapshot) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            add_directory(&entry.path(), snapshot)?;
+        } else {
+            snapshot.size += metadata.len();
+            snapshot.files += 1;
+            snapshot.modified = snapshot.modified.max(metadata.modified().ok());
+        }
+    }
+    Ok(())
+}
+
+/// A sentinel at the top of a directory unit
+fn has_sentinel(dir: &Path, sentinels: &[String]) -> bool {
+    sentinels.iter().any(|name| dir.join(name).is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settler(directory_units: bool) -> Settler {
+        Settler::new(SettleConfig {
+            window: Duration::ZERO,
+            sentinels: vec!["RTAComplete.txt".to_string()],
+            directory_units,
+        })
+    }
+
+    fn poll(settler: &mut Settler) -> Vec<Unit> {
+        let checks = settler.due();
+        settler.update(checks.run())
+    }
+
+    /// Polls until the snapshot has been seen unchanged once
+    fn poll_twice(settler: &mut Settler) -> Vec<Unit> {
+        let mut ready = poll(settler);
+        ready.extend(poll(settler));
+        ready
+    }
+
+    #[test]
+    fn files_settle_without_a_sentinel() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("plate1")).unwrap();
+        let file = root.path().join("plate1").join("A1.fcs");
+        fs::write(&file, b"FCS3.1").unwrap();
+
+        let mut settler = settler(false);
+        settler.touch(root.path(), &file);
+        assert_eq!(poll_twice(&mut settler), vec![Unit::File(file)]);
+        assert_eq!(settler.len(), 0);
+    }
+
+    #[test]
+    fn files_at_the_root_settle_with_directory_units() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("SampleSheet.csv");
+        fs::write(&file, b"Sample_ID").unwrap();
+
+        let mut settler = settler(true);
+        settler.touch(root.path(), &file);
+        assert_eq!(poll_twice(&mut settler), vec![Unit::File(file)]);
+    }
+
+    #[test]
+    fn directory_units_wait_for_a_sentinel() {
+        let root = tempfile::tempdir().unwrap();
+        let run = root.path().join("run1");
+        fs::create_dir(&run).unwrap();
+        fs::write(run.join("RunInfo.xml"), b"<RunInfo/>").unwrap();
+
+        let mut settler = settler(true);
+        settler.touch(root.path(), &run.join("RunInfo.xml"));
+        assert!(poll_twice(&mut settler).is_empty());
+
+        fs::write(run.join("RTAComplete.txt"), b"").unwrap();
+        settler.touch(root.path(), &run.join("RTAComplete.txt"));
+        assert_eq!(poll_twice(&mut settler), vec![Unit::Directory(run)]);
+    }
+
+    #[test]
+    fn slow_units_are_checked_less_often() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("A1.fcs");
+        fs::write(&file, b"FCS3.1").unwrap();
+
+        let mut settler = settler(false);
+        settler.touch(root.path(), &file);
+        let mut checked = settler.due().run();
+        assert_eq!(checked.len(), 1);
+        checked[0].took = Duration::from_secs(1);
+        assert!(settler.update(checked).is_empty());
+
+        // Not due for another 10 seconds
+        assert!(settler.due().units.is_empty());
+        assert_eq!(settler.len(), 1);
+    }
+
+    #[test]
+    fn cleared_units_ignore_checks_in_flight() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("A1.fcs");
+        fs::write(&file, b"FCS3.1").unwrap();
+
+        let mut settler = settler(false);
+        settler.touch(root.path(), &file);
+        let checked = settler.due().run();
+        settler.clear();
+        assert!(settler.update(checked).is_empty());
+        assert_eq!(settler.len(), 0);
+    }
+}
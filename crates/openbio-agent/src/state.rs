@@ -0,0 +1,61 @@
+//! State shared between the agent's tasks
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::RwLock;
+
+#[derive(Default)]
+pub struct AgentState {
+    watch_dirs: RwLock<Vec<PathBuf>>,
+    pending: AtomicU32,
+    queued: AtomicU32,
+    /// Files the Hub reported as duplicates, with their size and modification
+    /// time (Unix seconds) then
+    duplicates: RwLock<HashMap<PathBuf, (u64, Option<i64>)>>,
+}
+
+impl AgentState {
+    /// Directories currently being watched
+    pub fn watch_dirs(&self) -> Vec<PathBuf> {
+        self.watch_dirs.read().unwrap().clone()
+    }
+
+    pub fn set_watch_dirs(&self, dirs: Vec<PathBuf>) {
+        *self.watch_dirs.write().unwrap() = dirs;
+    }
+
+    /// Files detected but not yet uploaded
+    pub fn queue_depth(&self) -> u32 {
+        self.pending
+            .load(Ordering::Relaxed)
+            .saturating_add(self.queued.load(Ordering::Relaxed))
+    }
+
+    /// Units still being written
+    pub fn set_pending(&self, count: usize) {
+        store_count(&self.pending, count);
+    }
+
+    /// Files in the upload queue
+    pub fn set_queued(&self, count: usize) {
+        store_count(&self.queued, count);
+    }
+
+    /// Remember that the Hub already stores the content of `path`
+    pub fn add_duplicate(&self, path: &Path, size: u64, modified_at: Option<i64>) {
+        self.duplicates
+            .write()
+            .unwrap()
+            .insert(path.to_path_buf(), (size, modified_at));
+    }
+
+    /// Whether `path` was reported as a duplicate and has not changed since
+    pub fn is_duplicate(&self, path: &Path, size: u64, modified_at: Option<i64>) -> bool {
+        self.duplicates.read().unwrap().get(path) == Some(&(size, modified_at))
+    }
+}
+
+fn store_count(counter: &AtomicU32, count: usize) {
+    counter.store(u32::try_from(count).unwrap_or(u32::MAX), Ordering::Relaxed);
+}
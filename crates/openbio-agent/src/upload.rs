@@ -0,0 +1,245 @@
+//! Uploading queued files to the Hub
+//!
+//! Files are sent in [`UPLOAD_CHUNK_SIZE`] chunks. The server keeps partial
+//! uploads, so an interrupted upload continues from the offset it reports
+//! instead of starting over, and content it already stores is skipped.
+
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use openbio_core::agent::{UploadRequest, UploadResponse, UPLOAD_CHUNK_SIZE};
+use openbio_core::storage::file_checksum;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::client::ApiClient;
+use crate::queue::{modified_secs, QueueEntry, UploadQueue};
+use crate::state::AgentState;
+
+/// How often the queue is checked for entries due for (another) attempt
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A completed file handed over by the watcher
+#[derive(Debug, Clone)]
+pub struct Detected {
+    pub path: PathBuf,
+    /// Path relative to the watch directory, with `/` separators
+    pub filename: String,
+    pub mime_type: Option<String>,
+}
+
+enum Outcome {
+    Uploaded {
+        asset_id: String,
+        experiment_id: Option<String>,
+    },
+    Duplicate {
+        asset_id: String,
+    },
+    /// The file was deleted before it could be uploaded
+    Missing,
+    /// The file was rewritten after it was queued
+    Changed,
+}
+
+/// Queue detected files and upload them until `stop` is set. An upload in
+/// progress is finished first; the rest stay queued for the next start.
+pub async fn run(
+    client: ApiClient,
+    machine_id: String,
+    mut queue: UploadQueue,
+    mut rx: UnboundedReceiver<Detected>,
+    state: Arc<AgentState>,
+    mut stop: watch::Receiver<bool>,
+) {
+    if queue.len() > 0 {
+        info!("Resuming {} queued upload(s)", queue.len());
+    }
+    state.set_queued(queue.len());
+    let mut interval = tokio::time::interval(QUEUE_POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = stop.changed() => break,
+            Some(file) = rx.recv() => enqueue(&mut queue, file).await,
+            _ = interval.tick() => {
+                for entry in queue.due() {
+                    if *stop.borrow() {
+                        break;
+                    }
+                    process(&client, &machine_id, &mut queue, &state, entry).await;
+                    state.set_queued(queue.len());
+                }
+            }
+        }
+        if *stop.borrow() {
+            break;
+        }
+        state.set_queued(queue.len());
+    }
+    if queue.len() > 0 {
+        info!("{} upload(s) stay queued until the next start", queue.len());
+    }
+}
+
+async fn enqueue(queue: &mut UploadQueue, file: Detected) {
+    let path = file.path.clone();
+    // Hashing a multi-gigabyte file takes a while
+    let hashed = tokio::task::spawn_blocking(move || -> Result<(u64, String)> {
+        let size = std::fs::metadata(&path)?.len();
+        Ok((size, file_checksum(&path)?))
+    })
+    .await;
+    let (size, checksum) = match hashed {
+        Ok(Ok(hashed)) => hashed,
+        Ok(Err(e)) => {
+            warn!("Cannot read {}: {:#}", file.path.display(), e);
+            return;
+        }
+        Err(e) => {
+            warn!("Hashing {} failed: {}", file.path.display(), e);
+            return;
+        }
+    };
+
+    let entry = QueueEntry::new(file, size, checksum);
+    match queue.push(entry.clone()) {
+        Ok(true) => info!("Queued {} for upload", entry.filename),
+        Ok(false) => info!("{} is already queued (same content)", entry.filename),
+        Err(e) => warn!("Failed to queue {}: {:#}", entry.filename, e),
+    }
+}
+
+async fn process(
+    client: &ApiClient,
+    machine_id: &str,
+    queue: &mut UploadQueue,
+    state: &AgentState,
+    entry: QueueEntry,
+) {
+    let result = match upload(client, machine_id, &entry).await {
+        Ok(Outcome::Uploaded {
+            asset_id,
+            experiment_id,
+        }) => {
+            match experiment_id {
+                Some(experiment_id) => info!(
+                    "Uploaded {} as asset {} (experiment {})",
+                    entry.filename, asset_id, experiment_id
+                ),
+                None => info!(
+                    "Uploaded {} as asset {} (no booking at that time)",
+                    entry.filename, asset_id
+                ),
+            }
+            queue.remove(&entry.checksum)
+        }
+        Ok(Outcome::Duplicate { asset_id }) => {
+            info!(
+                "{} is already stored as asset {}, skipping",
+                entry.filename, asset_id
+            );
+            // Otherwise the backfill finds it missing again on every scan
+            state.add_duplicate(&entry.path, entry.size_bytes, entry.modified_at);
+            queue.remove(&entry.checksum)
+        }
+        Ok(Outcome::Missing) => {
+            warn!(
+                "{} was deleted before it was uploaded",
+                entry.path.display()
+            );
+            queue.remove(&entry.checksum)
+        }
+        Ok(Outcome::Changed) => {
+            info!(
+                "{} changed since it was queued, re-queueing",
+                entry.filename
+            );
+            let result = queue.remove(&entry.checksum);
+            enqueue(
+                queue,
+                Detected {
+                    path: entry.path,
+                    filename: entry.filename,
+                    mime_type: entry.mime_type,
+                },
+            )
+            .await;
+            result
+        }
+        Err(e) => queue
+            .retry_later(&entry.checksum, format!("{:#}", e))
+            .map(|delay| {
+                warn!(
+                    "Upload of {} failed ({:#}), retrying in {}s",
+                    entry.filename, e, delay
+                )
+            }),
+    };
+    if let Err(e) = result {
+        warn!("Failed to update the upload queue: {:#}", e);
+    }
+}
+
+async fn upload(client: &ApiClient, machine_id: &str, entry: &QueueEntry) -> Result<Outcome> {
+    let mut file = match tokio::fs::File::open(&entry.path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Outcome::Missing),
+        Err(e) => return Err(e.into()),
+    };
+    let metadata = file.metadata().await?;
+    if metadata.len() != entry.size_bytes || modified_secs(&metadata) != entry.modified_at {
+        return Ok(Outcome::Changed);
+    }
+
+    let response = client
+        .start_upload(&UploadRequest {
+            machine_id: machine_id.to_string(),
+            filename: entry.filename.clone(),
+            size_bytes: entry.size_bytes,
+            checksum: entry.checksum.clone(),
+            modified_at: entry.modified_at,
+            mime_type: entry.mime_type.clone(),
+        })
+        .await?;
+    let (upload_id, mut offset) = match response {
+        UploadResponse::Duplicate { asset_id } => return Ok(Outcome::Duplicate { asset_id }),
+        UploadResponse::Pending { upload_id, offset } => (upload_id, offset),
+    };
+    if offset > 0 {
+        info!(
+            "Resuming {} at {} of {} bytes",
+            entry.filename, offset, entry.size_bytes
+        );
+    }
+
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut chunk = vec![0; UPLOAD_CHUNK_SIZE];
+    while offset < entry.size_bytes {
+        let len = (entry.size_bytes - offset).min(UPLOAD_CHUNK_SIZE as u64) as usize;
+        file.read_exact(&mut chunk[..len]).await?;
+        let received = client
+            .upload_chunk(&upload_id, offset, chunk[..len].to_vec())
+            .await?
+            .offset;
+        if received != offset + len as u64 {
+            bail!(
+                "Server reported offset {} after a chunk ending at {}",
+                received,
+                offset + len as u64
+            );
+        }
+        offset = received;
+    }
+
+    let receipt = client.complete_upload(&upload_id).await?;
+    Ok(Outcome::Uploaded {
+        asset_id: receipt.asset_id,
+        experiment_id: receipt.experiment_id,
+    })
+}
@@ -0,0 +1,224 @@
+//! Watching the configured folders, re-applied when the configuration changes
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use openbio_core::agent::{AgentConfig, FileFilter};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, info, warn};
+
+use crate::backfill::Scope;
+use crate::rules::LocalRules;
+use crate::settle::Unit;
+use crate::state::AgentState;
+use crate::upload::Detected;
+
+/// A folder the agent watches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchRoot {
+    pub path: PathBuf,
+    /// Prefix of upload names when several folders are watched
+    pub name: String,
+}
+
+impl WatchRoot {
+    /// `name` defaults to the folder's own name
+    pub fn new(path: PathBuf, name: Option<String>) -> Self {
+        let name = name.unwrap_or_else(|| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string())
+        });
+        Self { path, name }
+    }
+}
+
+pub struct FolderWatcher {
+    watcher: RecommendedWatcher,
+    roots: Vec<WatchRoot>,
+    filter: FileFilter,
+    rules: Arc<LocalRules>,
+    auto_import: bool,
+}
+
+impl FolderWatcher {
+    /// File system events are sent to `tx`
+    pub fn new(tx: UnboundedSender<notify::Result<Event>>, rules: Arc<LocalRules>) -> Result<Self> {
+        let watcher = RecommendedWatcher::new(
+            move |res| {
+                let _ = tx.send(res);
+            },
+            notify::Config::default(),
+        )?;
+        Ok(Self {
+            watcher,
+            roots: vec![],
+            filter: FileFilter::default(),
+            rules,
+            auto_import: false,
+        })
+    }
+
+    /// Switch to the configured folders and filters; returns whether the folders changed.
+    ///
+    /// `local_roots` (`--watch-dir` or the config file) take precedence over the server's
+    /// watch folder.
+    pub fn apply(
+        &mut self,
+        config: &AgentConfig,
+        local_roots: &[WatchRoot],
+        state: &AgentState,
+    ) -> bool {
+        match FileFilter::new(&config.include, &config.exclude) {
+            Ok(filter) => self.filter = filter,
+            Err(e) => warn!("Keeping previous file filter: {}", e),
+        }
+        if self.auto_import != config.auto_import {
+            info!(
+                "Auto-import {}",
+                if config.auto_import {
+                    "enabled"
+                } else {
+                    "disabled"
+                }
+            );
+            self.auto_import = config.auto_import;
+        }
+
+        let roots = if local_roots.is_empty() {
+            config
+                .watch_folder
+                .iter()
+                .map(|folder| WatchRoot::new(PathBuf::from(folder), None))
+                .collect()
+        } else {
+            local_roots.to_vec()
+        };
+        if roots == self.roots {
+            return false;
+        }
+
+        for old in self.roots.drain(..) {
+            if let Err(e) = self.watcher.unwatch(&old.path) {
+                warn!("Failed to stop watching {}: {}", old.path.display(), e);
+            }
+        }
+        if roots.is_empty() {
+            warn!("No watch folder configured for this instrument");
+        }
+        for root in roots {
+            match self.watcher.watch(&root.path, RecursiveMode::Recursive) {
+                Ok(()) => {
+                    info!("Watching {} for new files", root.path.display());
+                    self.roots.push(root);
+                }
+                Err(e) => warn!("Cannot watch {}: {}", root.path.display(), e),
+            }
+        }
+        state.set_watch_dirs(self.roots.iter().map(|root| root.path.clone()).collect());
+        true
+    }
+
+    /// The watched folder `path` is in
+    pub fn root_of(&self, path: &Path) -> Option<&WatchRoot> {
+        self.roots
+            .iter()
+            .filter(|root| path.starts_with(&root.path))
+            .max_by_key(|root| root.path.components().count())
+    }
+
+    /// Whether a file should be ingested, judging by its path
+    pub fn wants(&self, path: &Path) -> bool {
+        self.root_of(path)
+            .and_then(|root| path.strip_prefix(&root.path).ok())
+            .is_some_and(|relative| self.filter.matches(relative) && self.rules.matches(relative))
+    }
+
+    /// Files of a completed unit that should be uploaded
+    pub fn files_of(&self, unit: &Unit) -> Vec<Detected> {
+        let Some(root) = self.root_of(unit.path()) else {
+            return vec![];
+        };
+        let mut paths = vec![];
+        match unit {
+            Unit::File(path) => paths.push(path.clone()),
+            Unit::Directory(dir) => collect_files(dir, &mut paths),
+        }
+        paths
+            .into_iter()
+            .filter(|path| self.wants(path))
+            .filter_map(|path| {
+                let size = fs::metadata(&path).ok()?.len();
+                if !self.rules.accepts_size(size) {
+                    debug!("Skipping {} ({} bytes)", path.display(), size);
+                    return None;
+                }
+                let filename = upload_name(&self.roots, root, &path)?;
+                let mime_type = self.rules.mime_type(&path);
+                Some(Detected {
+                    path,
+                    filename,
+                    mime_type,
+                })
+            })
+            .collect()
+    }
+
+    /// What the backfill should scan; nothing while auto-import is off
+    pub fn scope(&self) -> Option<Scope> {
+        if !self.auto_import || self.roots.is_empty() {
+            return None;
+        }
+        Some(Scope {
+            roots: self.roots.clone(),
+            filter: self.filter.clone(),
+            rules: self.rules.clone(),
+        })
+    }
+
+    pub fn auto_import(&self) -> bool {
+        self.auto_import
+    }
+}
+
+/// Name a file is uploaded under: its path relative to its watch folder, prefixed with the
+/// folder's name when several folders are watched so files from each stay apart
+pub fn upload_name(roots: &[WatchRoot], root: &WatchRoot, path: &Path) -> Option<String> {
+    let relative = relative_name(&root.path, path)?;
+    if roots.len() > 1 {
+        Some(format!("{}/{}", root.name, relative))
+    } else {
+        Some(relative)
+    }
+}
+
+/// `path` relative to `root`, `/`-separated as the Hub stores it
+pub fn relative_name(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    Some(
+        relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// Every file below `dir`
+pub fn collect_files(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        warn!("Cannot read {}", dir.display());
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, paths);
+        } else {
+            paths.push(path);
+        }
+    }
+}
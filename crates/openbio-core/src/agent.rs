@@ -0,0 +1,240 @@
+//! Protocol between the ingest agent and the server (`/api/agent/*`)
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How often the agent reports in
+pub const HEARTBEAT_INTERVAL_SECS: u64 = 30;
+
+/// An agent that has not reported in this long is marked offline
+pub const HEARTBEAT_TIMEOUT_SECS: u64 = 3 * HEARTBEAT_INTERVAL_SECS;
+
+/// How often the agent checks the server for configuration changes
+pub const CONFIG_POLL_SECS: u64 = 60;
+
+/// Files are uploaded in chunks of this size, so an interrupted upload resumes
+/// where it stopped
+pub const UPLOAD_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Connection state of an instrument's agent (stored on `Equipment.agentStatus`)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AgentStatus {
+    Offline,
+    Online,
+    /// Set by an administrator; the agent keeps reporting in but must not ingest
+    Locked,
+}
+
+impl AgentStatus {
+    /// Database representation of the status
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AgentStatus::Offline => "OFFLINE",
+            AgentStatus::Online => "ONLINE",
+            AgentStatus::Locked => "LOCKED",
+        }
+    }
+}
+
+impl fmt::Display for AgentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AgentStatus {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "OFFLINE" => Ok(AgentStatus::Offline),
+            "ONLINE" => Ok(AgentStatus::Online),
+            "LOCKED" => Ok(AgentStatus::Locked),
+            other => Err(crate::Error::Validation(format!(
+                "Unknown agent status '{}' (expected offline, online or locked)",
+                other.to_lowercase()
+            ))),
+        }
+    }
+}
+
+/// POST /api/agent/heartbeat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    /// `Equipment.id` or `Equipment.externalId` of the instrument
+    pub machine_id: String,
+    /// Agent version (`CARGO_PKG_VERSION`)
+    pub version: String,
+    /// Directory the agent is watching
+    pub watch_dir: Option<String>,
+    /// Files detected but not yet uploaded
+    pub queue_depth: u32,
+}
+
+/// Server reply to a [`Heartbeat`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeartbeatResponse {
+    pub equipment_id: String,
+    pub status: AgentStatus,
+}
+
+/// POST /api/agent/uploads: start (or resume) uploading a file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadRequest {
+    pub machine_id: String,
+    /// Path relative to the watch folder, `/`-separated
+    pub filename: String,
+    pub size_bytes: u64,
+    /// SHA-256, lowercase hex
+    pub checksum: String,
+    /// Last modification time (Unix seconds), used to find the booking it belongs to
+    pub modified_at: Option<i64>,
+    /// Stored as `DigitalAsset.mimeType`
+    #[serde(default)]
+    pub mime_type: Option<String>,
+}
+
+/// Server reply to an [`UploadRequest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum UploadResponse {
+    /// A file with the same checksum is already stored; nothing to upload
+    Duplicate { asset_id: String },
+    /// Send chunks starting at `offset` (bytes already received)
+    Pending { upload_id: String, offset: u64 },
+}
+
+/// Reply to PUT /api/agent/uploads/{upload_id}?offset=...
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkResponse {
+    /// Bytes received so far
+    pub offset: u64,
+}
+
+/// Reply to POST /api/agent/uploads/{upload_id}/complete
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetReceipt {
+    pub asset_id: String,
+    /// Experiment booked on the instrument when the file was written
+    pub experiment_id: Option<String>,
+}
+
+/// A file the Hub already has from an agent (GET /api/agent/assets)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngestedFile {
+    /// Path relative to the watch folder, `/`-separated
+    pub filename: String,
+    /// Unknown for files of 2 GiB and more
+    pub size_bytes: Option<i64>,
+    pub checksum: Option<String>,
+}
+
+/// GET /api/agent/config: what the agent should watch, managed on the Equipment record
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AgentConfig {
+    pub equipment_id: String,
+    /// Directory to watch (`Equipment.watchFolder`)
+    pub watch_folder: Option<String>,
+    /// Globs a file must match to be ingested; empty means every file
+    pub include: Vec<String>,
+    /// Globs of files to ignore, even when included
+    pub exclude: Vec<String>,
+    /// Upload detected files (`Equipment.autoImport`); when off files are only logged
+    pub auto_import: bool,
+}
+
+/// Include/exclude globs matched against paths relative to the watch folder.
+///
+/// `*` also matches `/`, so `*.fcs` matches FCS files in subdirectories too.
+#[derive(Debug, Clone)]
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl FileFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, crate::Error> {
+        Ok(Self {
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(build_globs(include)?)
+            },
+            exclude: build_globs(exclude)?,
+        })
+    }
+
+    pub fn matches(&self, relative_path: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative_path))
+            && !self.exclude.is_match(relative_path)
+    }
+}
+
+impl Default for FileFilter {
+    /// Matches every file
+    fn default() -> Self {
+        Self {
+            include: None,
+            exclude: GlobSet::empty(),
+        }
+    }
+}
+
+fn build_globs(patterns: &[String]) -> Result<GlobSet, crate::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| {
+            crate::Error::Validation(format!("Invalid glob '{}': {}", pattern, e.kind()))
+        })?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| crate::Error::Validation(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn globs(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn empty_filter_matches_every_file() {
+        let filter = FileFilter::new(&[], &[]).unwrap();
+        assert!(filter.matches(Path::new("A1.fcs")));
+        assert!(filter.matches(Path::new("run1/Data/RunInfo.xml")));
+        assert!(FileFilter::default().matches(Path::new("notes.txt")));
+    }
+
+    #[test]
+    fn include_globs_match_in_subdirectories() {
+        let filter = FileFilter::new(&globs(&["*.fcs", "*.csv"]), &[]).unwrap();
+        assert!(filter.matches(Path::new("A1.fcs")));
+        assert!(filter.matches(Path::new("plate1/day2/A1.fcs")));
+        assert!(filter.matches(Path::new("plate.csv")));
+        assert!(!filter.matches(Path::new("plate1/notes.txt")));
+    }
+
+    #[test]
+    fn exclude_globs_win_over_include_globs() {
+        let filter = FileFilter::new(&globs(&["*.fcs"]), &globs(&["calibration/**"])).unwrap();
+        assert!(filter.matches(Path::new("plate1/A1.fcs")));
+        assert!(!filter.matches(Path::new("calibration/beads.fcs")));
+    }
+
+    #[test]
+    fn invalid_globs_are_validation_errors() {
+        let error = FileFilter::new(&globs(&["[a-"]), &[]).unwrap_err();
+        assert!(matches!(error, crate::Error::Validation(message) if message.contains("[a-")));
+        assert!(FileFilter::new(&[], &globs(&["{a,b"])).is_err());
+    }
+}
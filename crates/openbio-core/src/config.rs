@@ -4,7 +4,7 @@
 //! by the Setup Wizard on first launch. The developer never touches user config.
 
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Deployment mode for the application
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
@@ -25,34 +25,50 @@ pub enum DeploymentMode {
 
 /// Main configuration structure
 /// This is generated by the Setup Wizard, not by developers
+///
+/// The desktop app writes camelCase keys (`serverPort`, `labName`), so those
+/// are accepted too.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Config {
     /// Deployment mode (set by Setup Wizard)
+    #[serde(default)]
     pub mode: DeploymentMode,
     
     /// API URL (for spoke/enterprise modes, set by wizard)
+    #[serde(default, alias = "apiUrl")]
     pub api_url: Option<String>,
     
     /// Local server port (for local/hub modes)
+    #[serde(default = "default_server_port", alias = "serverPort")]
     pub server_port: u16,
     
     /// Lab name (for hub mode - what appears in mDNS discovery)
+    #[serde(default, alias = "labName")]
     pub lab_name: Option<String>,
     
     /// Data directory path (where SQLite and files are stored)
     /// This is relative to app data dir, set by wizard
+    #[serde(default = "default_data_path", alias = "dataPath")]
     pub data_path: String,
 }
 
+fn default_server_port() -> u16 {
+    3000
+}
+
+fn default_data_path() -> String {
+    "data".to_string()
+}
+
 impl Config {
     /// Create a new unconfigured config (triggers Setup Wizard)
     pub fn new_unconfigured() -> Self {
         Self {
             mode: DeploymentMode::Unconfigured,
             api_url: None,
-            server_port: 3000,
+            server_port: default_server_port(),
             lab_name: None,
-            data_path: "data".to_string(),
+            data_path: default_data_path(),
         }
     }
 
@@ -98,7 +114,12 @@ impl Config {
             return Ok(Self::new_unconfigured());
         }
         
-        let content = std::fs::read_to_string(&path)?;
+        Self::load_from(&path)
+    }
+
+    /// Load configuration from a given file, e.g. for a headless server
+    pub fn load_from(path: &Path) -> Result<Self, crate::Error> {
+        let content = std::fs::read_to_string(path)?;
         let config: Config = toml::from_str(&content)?;
         Ok(config)
     }
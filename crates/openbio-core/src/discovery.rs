@@ -0,0 +1,185 @@
+//! Finding Hubs on the local network
+//!
+//! A Hub advertises [`SERVICE_TYPE`] over mDNS with its lab name as the
+//! instance name. The setup wizard lists what is on the network with
+//! [`scan`]; the ingest agent keeps a [`HubBrowser`] open so it notices when
+//! the Hub comes back on another address.
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use mdns_sd::{Receiver, ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+
+/// mDNS service type advertised by Hubs
+pub const SERVICE_TYPE: &str = "_openbio._tcp.local.";
+
+/// A Hub found on the network
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DiscoveredHub {
+    /// Lab name the Hub advertises
+    pub name: String,
+    /// Base URL of its API, e.g. `http://192.168.1.20:3000`
+    pub address: String,
+}
+
+impl DiscoveredHub {
+    /// Whether this is the Hub of `lab_name` (case-insensitive); any Hub
+    /// matches when no lab name is given
+    pub fn is_lab(&self, lab_name: Option<&str>) -> bool {
+        lab_name.is_none_or(|lab_name| self.name.eq_ignore_ascii_case(lab_name.trim()))
+    }
+}
+
+/// What a [`HubBrowser`] saw
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HubEvent {
+    /// A Hub was resolved, or re-resolved with a new address
+    Resolved(DiscoveredHub),
+    /// The Hub with this name stopped advertising
+    Removed(String),
+}
+
+/// Keeps a Hub advertised until dropped
+pub struct HubAdvertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl HubAdvertisement {
+    /// Advertise the Hub of `lab_name`, serving on `port` of every interface
+    pub fn start(lab_name: &str, port: u16) -> Result<Self, crate::Error> {
+        let daemon = ServiceDaemon::new().map_err(discovery_error)?;
+        let host_name = format!("{}.local.", lab_name.replace(' ', "-").to_lowercase());
+        let service = ServiceInfo::new(SERVICE_TYPE, lab_name, &host_name, "", port, None)
+            .map_err(discovery_error)?
+            .enable_addr_auto();
+        let fullname = service.get_fullname().to_string();
+        daemon.register(service).map_err(discovery_error)?;
+        Ok(Self { daemon, fullname })
+    }
+}
+
+impl Drop for HubAdvertisement {
+    fn drop(&mut self) {
+        let _ = self.daemon.unregister(&self.fullname);
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Browses for Hubs until dropped
+pub struct HubBrowser {
+    daemon: ServiceDaemon,
+    events: Receiver<ServiceEvent>,
+}
+
+impl HubBrowser {
+    pub fn start() -> Result<Self, crate::Error> {
+        let daemon = ServiceDaemon::new().map_err(discovery_error)?;
+        let events = daemon.browse(SERVICE_TYPE).map_err(discovery_error)?;
+        Ok(Self { daemon, events })
+    }
+
+    /// Browse again, so Hubs already seen are resolved afresh
+    pub fn refresh(&mut self) -> Result<(), crate::Error> {
+        let _ = self.daemon.stop_browse(SERVICE_TYPE);
+        self.events = self.daemon.browse(SERVICE_TYPE).map_err(discovery_error)?;
+        Ok(())
+    }
+
+    /// Next Hub event; `None` once the browser has stopped
+    pub async fn recv(&self) -> Option<HubEvent> {
+        loop {
+            let event = self.events.recv_async().await.ok()?;
+            if let Some(event) = hub_event(event) {
+                return Some(event);
+            }
+        }
+    }
+
+    /// Next Hub event, waiting until `deadline` at most
+    pub fn recv_until(&self, deadline: Instant) -> Option<HubEvent> {
+        loop {
+            let event = self.events.recv_deadline(deadline).ok()?;
+            if let Some(event) = hub_event(event) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+impl Drop for HubBrowser {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Every Hub that answers within `timeout`, by name. Blocks for the whole
+/// `timeout`.
+pub fn scan(timeout: Duration) -> Result<Vec<DiscoveredHub>, crate::Error> {
+    let browser = HubBrowser::start()?;
+    let deadline = Instant::now() + timeout;
+
+    let mut hubs: Vec<DiscoveredHub> = vec![];
+    while let Some(event) = browser.recv_until(deadline) {
+        match event {
+            HubEvent::Resolved(hub) => {
+                hubs.retain(|known| known.name != hub.name);
+                hubs.push(hub);
+            }
+            HubEvent::Removed(name) => hubs.retain(|known| known.name != name),
+        }
+    }
+
+    hubs.sort_by(|a, b| a.name.cmp(&b.name));
+    hubs.dedup_by(|a, b| a.address == b.address);
+    Ok(hubs)
+}
+
+fn hub_event(event: ServiceEvent) -> Option<HubEvent> {
+    match event {
+        ServiceEvent::ServiceResolved(info) => {
+            // Prefer IPv4. Link-local IPv6 addresses need an interface to be
+            // reachable, and addresses often arrive one announcement at a time,
+            // so a Hub with nothing usable yet is reported on a later event.
+            let addresses = info.get_addresses();
+            let ip = addresses
+                .iter()
+                .find(|ip| ip.is_ipv4())
+                .or_else(|| addresses.iter().find(|ip| !is_link_local(ip)))?;
+            let address = if ip.is_ipv6() {
+                format!("http://[{}]:{}", ip, info.get_port())
+            } else {
+                format!("http://{}:{}", ip, info.get_port())
+            };
+            Some(HubEvent::Resolved(DiscoveredHub {
+                name: instance_name(info.get_fullname()),
+                address,
+            }))
+        }
+        ServiceEvent::ServiceRemoved(_, fullname) => {
+            Some(HubEvent::Removed(instance_name(&fullname)))
+        }
+        _ => None,
+    }
+}
+
+fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+/// `Lab Name._openbio._tcp.local.` → `Lab Name`
+fn instance_name(fullname: &str) -> String {
+    fullname
+        .strip_suffix(SERVICE_TYPE)
+        .and_then(|name| name.strip_suffix('.'))
+        .unwrap_or(fullname)
+        .to_string()
+}
+
+fn discovery_error(e: mdns_sd::Error) -> crate::Error {
+    crate::Error::Discovery(e.to_string())
+}
@@ -0,0 +1,58 @@
+//! Equipment domain types shared between the server and clients
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Kind of upkeep done on an instrument (stored on `MaintenanceSchedule.type`
+/// and `MaintenanceRecord.type`)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MaintenanceType {
+    /// Verified against a reference; an instrument with a lapsed calibration
+    /// should not be used for measurements
+    Calibration,
+    /// Preventive service by an engineer
+    Service,
+    /// Fixing a fault
+    Repair,
+    Cleaning,
+    Inspection,
+}
+
+impl MaintenanceType {
+    /// Database representation of the type
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MaintenanceType::Calibration => "CALIBRATION",
+            MaintenanceType::Service => "SERVICE",
+            MaintenanceType::Repair => "REPAIR",
+            MaintenanceType::Cleaning => "CLEANING",
+            MaintenanceType::Inspection => "INSPECTION",
+        }
+    }
+}
+
+impl fmt::Display for MaintenanceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for MaintenanceType {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "CALIBRATION" => Ok(MaintenanceType::Calibration),
+            "SERVICE" => Ok(MaintenanceType::Service),
+            "REPAIR" => Ok(MaintenanceType::Repair),
+            "CLEANING" => Ok(MaintenanceType::Cleaning),
+            "INSPECTION" => Ok(MaintenanceType::Inspection),
+            other => Err(crate::Error::Validation(format!(
+                "Unknown maintenance type '{}' (expected calibration, service, repair, cleaning or inspection)",
+                other.to_lowercase()
+            ))),
+        }
+    }
+}
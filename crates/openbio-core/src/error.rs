@@ -21,4 +21,10 @@ pub enum Error {
 
     #[error("Validation error: {0}")]
     Validation(String),
+
+    #[error("Parse error: {0}")]
+    Parse(String),
+
+    #[error("Discovery error: {0}")]
+    Discovery(String),
 }
@@ -0,0 +1,366 @@
+//! Experiment domain types shared between the server and clients
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Role a sample plays in an experiment (stored on `ExperimentSample.role`)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleRole {
+    /// Starting material processed by the experiment
+    Input,
+    /// Untreated / reference sample
+    Control,
+    /// Sample receiving the experimental condition
+    Treatment,
+}
+
+impl SampleRole {
+    /// Database representation of the role
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SampleRole::Input => "input",
+            SampleRole::Control => "control",
+            SampleRole::Treatment => "treatment",
+        }
+    }
+}
+
+impl fmt::Display for SampleRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SampleRole {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "input" => Ok(SampleRole::Input),
+            "control" => Ok(SampleRole::Control),
+            "treatment" => Ok(SampleRole::Treatment),
+            other => Err(crate::Error::Validation(format!(
+                "Unknown sample role '{}' (expected input, control or treatment)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Lifecycle status of an experiment (stored on `Experiment.status`)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ExperimentStatus {
+    Draft,
+    Scheduled,
+    InProgress,
+    Completed,
+    Failed,
+}
+
+impl ExperimentStatus {
+    /// Database representation of the status
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExperimentStatus::Draft => "DRAFT",
+            ExperimentStatus::Scheduled => "SCHEDULED",
+            ExperimentStatus::InProgress => "IN_PROGRESS",
+            ExperimentStatus::Completed => "COMPLETED",
+            ExperimentStatus::Failed => "FAILED",
+        }
+    }
+
+    /// Whether the experiment is finished (only an explicit reopen can move it on)
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, ExperimentStatus::Completed | ExperimentStatus::Failed)
+    }
+
+    /// Whether a new experiment may start in this status; later ones are only
+    /// reached through transitions, so they are stamped and recorded
+    pub fn is_initial(&self) -> bool {
+        matches!(self, ExperimentStatus::Draft | ExperimentStatus::Scheduled)
+    }
+
+    /// Find the transition that moves this status to `to`.
+    ///
+    /// Reopening is never inferred here - it has to be requested explicitly
+    /// with [`StatusTransition::Reopen`].
+    pub fn transition_to(&self, to: ExperimentStatus) -> Result<StatusTransition, crate::Error> {
+        [
+            StatusTransition::Schedule,
+            StatusTransition::Unschedule,
+            StatusTransition::Start,
+            StatusTransition::Complete,
+            StatusTransition::Fail,
+        ]
+        .into_iter()
+        .find(|t| t.target() == to && t.allowed_from(*self))
+        .ok_or_else(|| {
+            crate::Error::Validation(format!(
+                "Illegal status transition {} -> {}",
+                self, to
+            ))
+        })
+    }
+}
+
+impl fmt::Display for ExperimentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ExperimentStatus {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "DRAFT" => Ok(ExperimentStatus::Draft),
+            "SCHEDULED" => Ok(ExperimentStatus::Scheduled),
+            "IN_PROGRESS" => Ok(ExperimentStatus::InProgress),
+            "COMPLETED" => Ok(ExperimentStatus::Completed),
+            "FAILED" => Ok(ExperimentStatus::Failed),
+            other => Err(crate::Error::Validation(format!(
+                "Unknown experiment status '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Named transitions between experiment statuses
+///
+/// ```text
+/// DRAFT --schedule--> SCHEDULED --start--> IN_PROGRESS --complete--> COMPLETED
+///   ^  <-unschedule--            \                    \--fail-----> FAILED
+///   |                             \--fail--> FAILED
+///   +-------------------- reopen (from COMPLETED / FAILED)
+/// ```
+/// A draft can also be started directly without being scheduled.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusTransition {
+    Schedule,
+    Unschedule,
+    Start,
+    Complete,
+    Fail,
+    Reopen,
+}
+
+impl StatusTransition {
+    /// Status the experiment ends up in after this transition
+    pub fn target(&self) -> ExperimentStatus {
+        match self {
+            StatusTransition::Schedule => ExperimentStatus::Scheduled,
+            StatusTransition::Unschedule => ExperimentStatus::Draft,
+            StatusTransition::Start => ExperimentStatus::InProgress,
+            StatusTransition::Complete => ExperimentStatus::Completed,
+            StatusTransition::Fail => ExperimentStatus::Failed,
+            StatusTransition::Reopen => ExperimentStatus::Draft,
+        }
+    }
+
+    /// Whether this transition may be applied to an experiment in status `from`
+    pub fn allowed_from(&self, from: ExperimentStatus) -> bool {
+        use ExperimentStatus::*;
+        match self {
+            StatusTransition::Schedule => from == Draft,
+            StatusTransition::Unschedule => from == Scheduled,
+            StatusTransition::Start => matches!(from, Draft | Scheduled),
+            StatusTransition::Complete => from == InProgress,
+            StatusTransition::Fail => matches!(from, Scheduled | InProgress),
+            StatusTransition::Reopen => from.is_terminal(),
+        }
+    }
+
+    /// Apply this transition to `from`, returning the new status
+    pub fn apply(&self, from: ExperimentStatus) -> Result<ExperimentStatus, crate::Error> {
+        if self.allowed_from(from) {
+            Ok(self.target())
+        } else {
+            Err(crate::Error::Validation(format!(
+                "Cannot {} an experiment that is {}",
+                self, from
+            )))
+        }
+    }
+}
+
+impl fmt::Display for StatusTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StatusTransition::Schedule => "schedule",
+            StatusTransition::Unschedule => "unschedule",
+            StatusTransition::Start => "start",
+            StatusTransition::Complete => "complete",
+            StatusTransition::Fail => "fail",
+            StatusTransition::Reopen => "reopen",
+        })
+    }
+}
+
+impl FromStr for StatusTransition {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "schedule" => Ok(StatusTransition::Schedule),
+            "unschedule" => Ok(StatusTransition::Unschedule),
+            "start" => Ok(StatusTransition::Start),
+            "complete" => Ok(StatusTransition::Complete),
+            "fail" => Ok(StatusTransition::Fail),
+            "reopen" => Ok(StatusTransition::Reopen),
+            other => Err(crate::Error::Validation(format!(
+                "Unknown transition '{}' (expected schedule, unschedule, start, complete, fail or reopen)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Role of a signature on a locked notebook
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SignatureRole {
+    /// The experimenter; signing locks the notebook
+    Author,
+    /// Second person countersigning the author's record
+    Witness,
+}
+
+impl SignatureRole {
+    /// Database representation of the role
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureRole::Author => "AUTHOR",
+            SignatureRole::Witness => "WITNESS",
+        }
+    }
+}
+
+impl fmt::Display for SignatureRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SignatureRole {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "AUTHOR" => Ok(SignatureRole::Author),
+            "WITNESS" => Ok(SignatureRole::Witness),
+            other => Err(crate::Error::Validation(format!(
+                "Unknown signature role '{}' (expected author or witness)",
+                other
+            ))),
+        }
+    }
+}
+
+/// SHA-256 of notebook content as lowercase hex, recorded with signatures
+pub fn content_hash(content: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ExperimentStatus::*;
+
+    const STATUSES: [ExperimentStatus; 5] = [Draft, Scheduled, InProgress, Completed, Failed];
+    const TRANSITIONS: [StatusTransition; 6] = [
+        StatusTransition::Schedule,
+        StatusTransition::Unschedule,
+        StatusTransition::Start,
+        StatusTransition::Complete,
+        StatusTransition::Fail,
+        StatusTransition::Reopen,
+    ];
+
+    /// Every legal (from, transition, to)
+    const LEGAL: [(ExperimentStatus, StatusTransition, ExperimentStatus); 9] = [
+        (Draft, StatusTransition::Schedule, Scheduled),
+        (Draft, StatusTransition::Start, InProgress),
+        (Scheduled, StatusTransition::Unschedule, Draft),
+        (Scheduled, StatusTransition::Start, InProgress),
+        (Scheduled, StatusTransition::Fail, Failed),
+        (InProgress, StatusTransition::Complete, Completed),
+        (InProgress, StatusTransition::Fail, Failed),
+        (Completed, StatusTransition::Reopen, Draft),
+        (Failed, StatusTransition::Reopen, Draft),
+    ];
+
+    #[test]
+    fn apply_allows_exactly_the_legal_transitions() {
+        for from in STATUSES {
+            for transition in TRANSITIONS {
+                let legal = LEGAL
+                    .iter()
+                    .find(|(f, t, _)| *f == from && *t == transition)
+                    .map(|(_, _, to)| *to);
+                match (transition.apply(from), legal) {
+                    (Ok(to), Some(expected)) => assert_eq!(to, expected, "{} {}", transition, from),
+                    (Err(_), None) => {}
+                    (result, _) => panic!("{} from {}: {:?}", transition, from, result),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transition_to_finds_the_named_transition() {
+        for (from, transition, to) in LEGAL {
+            if transition == StatusTransition::Reopen {
+                continue;
+            }
+            assert_eq!(from.transition_to(to).unwrap(), transition);
+        }
+    }
+
+    #[test]
+    fn transition_to_rejects_illegal_targets() {
+        for from in STATUSES {
+            for to in STATUSES {
+                let legal = LEGAL.iter().any(|(f, t, target)| {
+                    *f == from && *target == to && *t != StatusTransition::Reopen
+                });
+                assert_eq!(from.transition_to(to).is_ok(), legal, "{} -> {}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn only_reopen_leaves_a_finished_status() {
+        for from in [Completed, Failed] {
+            assert!(from.is_terminal());
+            for to in STATUSES {
+                assert!(from.transition_to(to).is_err(), "{} -> {}", from, to);
+            }
+            for transition in TRANSITIONS {
+                assert_eq!(
+                    transition.apply(from).is_ok(),
+                    transition == StatusTransition::Reopen,
+                    "{} from {}",
+                    transition,
+                    from
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn new_experiments_start_as_draft_or_scheduled() {
+        let initial: Vec<_> = STATUSES.into_iter().filter(|s| s.is_initial()).collect();
+        assert_eq!(initial, [Draft, Scheduled]);
+    }
+}
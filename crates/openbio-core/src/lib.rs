@@ -2,9 +2,19 @@
 //! 
 //! Shared types, traits, and utilities for the OpenBio ecosystem.
 
+pub mod agent;
 pub mod config;
+pub mod discovery;
 pub mod storage;
 pub mod error;
+pub mod equipment;
+pub mod experiment;
+pub mod notebook;
+pub mod parsers;
+pub mod sample_matching;
 
+pub use agent::AgentStatus;
 pub use config::Config;
+pub use equipment::MaintenanceType;
 pub use error::Error;
+pub use experiment::{ExperimentStatus, SampleRole, SignatureRole, StatusTransition};
@@ -0,0 +1,881 @@
+//! Notebook (TipTap / ProseMirror) document helpers
+//!
+//! `Experiment.content` stores the editor document as ProseMirror JSON;
+//! older notebooks hold the editor's HTML instead, which is read back into
+//! the same JSON (see [`parse_document`]). These helpers flatten it into
+//! readable text blocks, diff two versions, find its @mentions and render it
+//! as Markdown or HTML for exports.
+
+mod html;
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Read notebook content as a ProseMirror document.
+///
+/// Accepts ProseMirror JSON and the HTML the editor renders. Returns `None`
+/// for empty content and plain text.
+pub fn parse_document(content: &str) -> Option<Value> {
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+
+    match serde_json::from_str::<Value>(trimmed) {
+        Ok(doc) if doc.get("type").is_some() => Some(doc),
+        _ if trimmed.starts_with('<') => Some(html::parse_html(trimmed)),
+        _ => None,
+    }
+}
+
+/// Flatten a notebook document into one line of text per block.
+///
+/// Each line is prefixed with a marker for its block type (`# ` for headings,
+/// `- ` for list items, `> ` for quotes...) so that structural changes such as
+/// turning a paragraph into a heading show up in a text diff.
+/// Plain text content is split into lines.
+pub fn flatten_blocks(content: &str) -> Vec<String> {
+    if content.trim().is_empty() {
+        return vec![];
+    }
+
+    match parse_document(content) {
+        Some(doc) => {
+            let mut lines = vec![];
+            collect_blocks(&doc, "", &mut lines);
+            lines
+        }
+        None => content.lines().map(str::to_string).collect(),
+    }
+}
+
+fn collect_blocks(node: &Value, prefix: &str, lines: &mut Vec<String>) {
+    let node_type = node.get("type").and_then(Value::as_str).unwrap_or_default();
+    let children = node
+        .get("content")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    match node_type {
+        "paragraph" => lines.push(format!("{}{}", prefix, inline_text(children))),
+        "heading" => {
+            let level = node
+                .get("attrs")
+                .and_then(|a| a.get("level"))
+                .and_then(Value::as_u64)
+                .unwrap_or(1) as usize;
+            lines.push(format!("{}{} {}", prefix, "#".repeat(level), inline_text(children)));
+        }
+        "codeBlock" => {
+            lines.push(format!("{}```", prefix));
+            for line in inline_text(children).lines() {
+                lines.push(format!("{}{}", prefix, line));
+            }
+            lines.push(format!("{}```", prefix));
+        }
+        "horizontalRule" => lines.push(format!("{}---", prefix)),
+        "blockquote" => {
+            for child in children {
+                collect_blocks(child, &format!("{}> ", prefix), lines);
+            }
+        }
+        "bulletList" | "orderedList" | "taskList" => {
+            let ordered = node_type == "orderedList";
+            for (i, item) in children.iter().enumerate() {
+                let marker = if ordered {
+                    format!("{}. ", i + 1)
+                } else {
+                    "- ".to_string()
+                };
+                collect_list_item(item, prefix, &marker, lines);
+            }
+        }
+        _ => {
+            for child in children {
+                collect_blocks(child, prefix, lines);
+            }
+        }
+    }
+}
+
+fn collect_list_item(item: &Value, prefix: &str, marker: &str, lines: &mut Vec<String>) {
+    let children = item
+        .get("content")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let indent = " ".repeat(marker.len());
+
+    for (i, child) in children.iter().enumerate() {
+        // First block of the item carries the marker, the rest are indented under it
+        let child_prefix = if i == 0 {
+            format!("{}{}", prefix, marker)
+        } else {
+            format!("{}{}", prefix, indent)
+        };
+        collect_blocks(child, &child_prefix, lines);
+    }
+}
+
+/// Render inline nodes (text, mentions, hard breaks) as plain text
+fn inline_text(nodes: &[Value]) -> String {
+    let mut text = String::new();
+    for node in nodes {
+        match node.get("type").and_then(Value::as_str) {
+            Some("text") => text.push_str(node.get("text").and_then(Value::as_str).unwrap_or_default()),
+            Some("mention") => {
+                let attrs = node.get("attrs");
+                let label = attrs
+                    .and_then(|a| a.get("label"))
+                    .and_then(Value::as_str)
+                    .or_else(|| attrs.and_then(|a| a.get("id")).and_then(Value::as_str))
+                    .unwrap_or_default();
+                text.push('@');
+                text.push_str(label);
+            }
+            Some("hardBreak") => text.push('\n'),
+            _ => {
+                if let Some(children) = node.get("content").and_then(Value::as_array) {
+                    text.push_str(&inline_text(children));
+                }
+            }
+        }
+    }
+    text
+}
+
+/// A mention node found in a notebook document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MentionRef {
+    /// `sample`, `equipment` or `paper`, when the node carries it
+    pub entity_type: Option<String>,
+    pub entity_id: String,
+    pub label: Option<String>,
+    /// ProseMirror position of the node (what the editor uses to select it)
+    pub position: i32,
+}
+
+/// Find every mention node in a notebook document, in document order.
+///
+/// Reads ProseMirror JSON and editor HTML alike (see [`parse_document`]).
+pub fn extract_mentions(content: &str) -> Vec<MentionRef> {
+    let mut mentions = vec![];
+    if let Some(doc) = parse_document(content) {
+        if let Some(children) = doc.get("content").and_then(Value::as_array) {
+            let mut pos = 0;
+            for child in children {
+                pos += collect_mentions(child, pos, &mut mentions);
+            }
+        }
+    }
+    mentions
+}
+
+/// Walk a node starting at `pos`, returning its ProseMirror size
+fn collect_mentions(node: &Value, pos: usize, mentions: &mut Vec<MentionRef>) -> usize {
+    match node.get("type").and_then(Value::as_str) {
+        // Text counts in UTF-16 code units, like the editor
+        Some("text") => node
+            .get("text")
+            .and_then(Value::as_str)
+            .map_or(0, |t| t.encode_utf16().count()),
+        Some("mention") => {
+            let attrs = node.get("attrs");
+            let attr = |name: &str| {
+                attrs
+                    .and_then(|a| a.get(name))
+                    .and_then(Value::as_str)
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_string)
+            };
+            if let Some(entity_id) = attr("id") {
+                mentions.push(MentionRef {
+                    entity_type: attr("type"),
+                    entity_id,
+                    label: attr("label"),
+                    position: pos as i32,
+                });
+            }
+            1
+        }
+        _ => match node.get("content").and_then(Value::as_array) {
+            Some(children) => {
+                // Opening token, children, closing token
+                let mut size = 1;
+                for child in children {
+                    size += collect_mentions(child, pos + size, mentions);
+                }
+                size + 1
+            }
+            // Leaf nodes (hard breaks, images, rules) and empty blocks
+            None if is_leaf(node) => 1,
+            None => 2,
+        },
+    }
+}
+
+fn is_leaf(node: &Value) -> bool {
+    matches!(
+        node.get("type").and_then(Value::as_str),
+        Some("hardBreak" | "horizontalRule" | "image")
+    )
+}
+
+/// Kind of change for a diffed line
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffOp {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// One line of a notebook diff
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    pub text: String,
+}
+
+/// Line diff between two notebook documents
+#[derive(Debug, Clone, Serialize)]
+pub struct NotebookDiff {
+    pub lines: Vec<DiffLine>,
+    pub added: usize,
+    pub removed: usize,
+    /// The diff rendered as text with `+`/`-`/` ` prefixes
+    pub text: String,
+}
+
+/// Diff two notebook documents block by block
+pub fn diff_documents(old: &str, new: &str) -> NotebookDiff {
+    let lines = diff_lines(&flatten_blocks(old), &flatten_blocks(new));
+
+    let added = lines.iter().filter(|l| l.op == DiffOp::Insert).count();
+    let removed = lines.iter().filter(|l| l.op == DiffOp::Delete).count();
+    let text = lines
+        .iter()
+        .map(|l| {
+            let sign = match l.op {
+                DiffOp::Equal => ' ',
+                DiffOp::Insert => '+',
+                DiffOp::Delete => '-',
+            };
+            format!("{}{}\n", sign, l.text)
+        })
+        .collect();
+
+    NotebookDiff {
+        lines,
+        added,
+        removed,
+        text,
+    }
+}
+
+/// Largest LCS table [`diff_lines`] builds (4 bytes a cell); a bigger change
+/// is shown as its old lines removed and its new lines added
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Longest-common-subsequence line diff
+///
+/// Lines shared at the start and end are matched directly, so the table only
+/// covers the changed middle, and that is bounded by [`MAX_DIFF_CELLS`].
+fn diff_lines(old: &[String], new: &[String]) -> Vec<DiffLine> {
+    let equal = |t: &String| DiffLine { op: DiffOp::Equal, text: t.clone() };
+    let delete = |t: &String| DiffLine { op: DiffOp::Delete, text: t.clone() };
+    let insert = |t: &String| DiffLine { op: DiffOp::Insert, text: t.clone() };
+
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    let (n, m) = (old_mid.len(), new_mid.len());
+
+    let mut lines = Vec::with_capacity(old.len().max(new.len()));
+    lines.extend(old[..prefix].iter().map(equal));
+
+    if (n + 1).saturating_mul(m + 1) > MAX_DIFF_CELLS {
+        lines.extend(old_mid.iter().map(delete));
+        lines.extend(new_mid.iter().map(insert));
+    } else {
+        // lcs[i * (m + 1) + j] = length of the LCS of old_mid[i..] and new_mid[j..]
+        let at = |i: usize, j: usize| i * (m + 1) + j;
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[at(i, j)] = if old_mid[i] == new_mid[j] {
+                    lcs[at(i + 1, j + 1)] + 1
+                } else {
+                    lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if old_mid[i] == new_mid[j] {
+                lines.push(equal(&old_mid[i]));
+                i += 1;
+                j += 1;
+            } else if lcs[at(i + 1, j)] >= lcs[at(i, j + 1)] {
+                lines.push(delete(&old_mid[i]));
+                i += 1;
+            } else {
+                lines.push(insert(&new_mid[j]));
+                j += 1;
+            }
+        }
+        lines.extend(old_mid[i..].iter().map(delete));
+        lines.extend(new_mid[j..].iter().map(insert));
+    }
+
+    lines.extend(old[old.len() - suffix..].iter().map(equal));
+    lines
+}
+
+/// Append a checklist to a notebook document as a TipTap task list of unchecked items.
+///
+/// The items are written in the format the content is already stored in:
+/// ProseMirror JSON gets a `taskList` node, anything else gets the task list
+/// markup the editor renders. Plain text becomes paragraphs, so the result
+/// always loads in the editor.
+pub fn append_checklist(content: &str, items: &[String]) -> String {
+    if items.is_empty() {
+        return content.to_string();
+    }
+
+    let trimmed = content.trim();
+    match serde_json::from_str::<Value>(trimmed) {
+        Ok(mut doc) if doc.get("type").is_some() => {
+            let task_list = serde_json::json!({
+                "type": "taskList",
+                "content": items
+                    .iter()
+                    .map(|item| serde_json::json!({
+                        "type": "taskItem",
+                        "attrs": { "checked": false },
+                        "content": [{ "type": "paragraph", "content": [{ "type": "text", "text": item }] }],
+                    }))
+                    .collect::<Vec<_>>(),
+            });
+            match doc.get_mut("content").and_then(Value::as_array_mut) {
+                Some(children) => children.push(task_list),
+                None => doc["content"] = Value::Array(vec![task_list]),
+            }
+            doc.to_string()
+        }
+        _ => {
+            let mut html = if trimmed.starts_with('<') {
+                trimmed.to_string()
+            } else {
+                trimmed
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| format!("<p>{}</p>", escape_html(line)))
+                    .collect()
+            };
+            html.push_str("<ul data-type=\"taskList\">");
+            for item in items {
+                html.push_str(&format!(
+                    "<li data-type=\"taskItem\" data-checked=\"false\"><p>{}</p></li>",
+                    escape_html(item)
+                ));
+            }
+            html.push_str("</ul>");
+            html
+        }
+    }
+}
+
+/// Render a notebook document as Markdown.
+///
+/// Blocks are separated by blank lines; list items and code block lines stay together.
+pub fn to_markdown(content: &str) -> String {
+    let mut markdown = String::new();
+    let mut in_code = false;
+    let mut prev_list = false;
+
+    for line in flatten_blocks(content) {
+        let fence = line.trim_start_matches("> ").trim_start() == "```";
+        let list = is_list_line(&line);
+
+        if !markdown.is_empty() {
+            let tight = in_code || (list && prev_list);
+            markdown.push_str(if tight { "\n" } else { "\n\n" });
+        }
+        markdown.push_str(&line);
+
+        if fence {
+            in_code = !in_code;
+        }
+        prev_list = list;
+    }
+    markdown
+}
+
+fn is_list_line(line: &str) -> bool {
+    let line = line.trim_start_matches("> ");
+    if line.starts_with("- ") || line.starts_with("  ") {
+        return true;
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    digits > 0 && line[digits..].starts_with(". ")
+}
+
+/// Render a notebook document as an HTML fragment.
+///
+/// Editor HTML is read and rendered again, so only known markup ends up in the
+/// export. Plain text is rendered as escaped paragraphs.
+pub fn to_html(content: &str) -> String {
+    if content.trim().is_empty() {
+        return String::new();
+    }
+
+    match parse_document(content) {
+        Some(doc) => {
+            let mut html = String::new();
+            render_html(&doc, &mut html);
+            html
+        }
+        None => content
+            .split("\n\n")
+            .map(|p| format!("<p>{}</p>\n", escape_html(p).replace('\n', "<br>")))
+            .collect(),
+    }
+}
+
+fn render_html(node: &Value, html: &mut String) {
+    let node_type = node.get("type").and_then(Value::as_str).unwrap_or_default();
+    let attrs = node.get("attrs");
+    let attr = |name: &str| attrs.and_then(|a| a.get(name));
+
+    let tag = match node_type {
+        "paragraph" => "p",
+        "heading" => {
+            let level = attr("level").and_then(Value::as_u64).unwrap_or(1).clamp(1, 6);
+            let tag = format!("h{}", level);
+            html.push_str(&format!("<{}>", tag));
+            render_children(node, html);
+            html.push_str(&format!("</{}>\n", tag));
+            return;
+        }
+        "bulletList" => "ul",
+        "orderedList" => "ol",
+        "taskList" => "ul class=\"tasks\"",
+        "listItem" => "li",
+        "taskItem" => {
+            let checked = attr("checked").and_then(Value::as_bool).unwrap_or(false);
+            html.push_str(if checked {
+                "<li><input type=\"checkbox\" checked disabled> "
+            } else {
+                "<li><input type=\"checkbox\" disabled> "
+            });
+            render_children(node, html);
+            html.push_str("</li>\n");
+            return;
+        }
+        "blockquote" => "blockquote",
+        "codeBlock" => {
+            html.push_str("<pre><code>");
+            render_children(node, html);
+            html.push_str("</code></pre>\n");
+            return;
+        }
+        "table" => "table",
+        "tableRow" => "tr",
+        "tableHeader" => "th",
+        "tableCell" => "td",
+        "horizontalRule" => {
+            html.push_str("<hr>\n");
+            return;
+        }
+        "hardBreak" => {
+            html.push_str("<br>");
+            return;
+        }
+        "image" => {
+            let src = attr("src").and_then(Value::as_str).unwrap_or_default();
+            let alt = attr("alt").and_then(Value::as_str).unwrap_or_default();
+            html.push_str(&format!(
+                "<img src=\"{}\" alt=\"{}\">",
+                escape_html(src),
+                escape_html(alt)
+            ));
+            return;
+        }
+        "mention" => {
+            let label = attr("label")
+                .and_then(Value::as_str)
+                .or_else(|| attr("id").and_then(Value::as_str))
+                .unwrap_or_default();
+            let kind = attr("type").and_then(Value::as_str).unwrap_or("entity");
+            html.push_str(&format!(
+                "<span class=\"mention\" data-type=\"{}\">@{}</span>",
+                escape_html(kind),
+                escape_html(label)
+            ));
+            return;
+        }
+        "text" => {
+            render_text(node, html);
+            return;
+        }
+        _ => {
+            render_children(node, html);
+            return;
+        }
+    };
+
+    let close = tag.split(' ').next().unwrap_or(tag);
+    html.push_str(&format!("<{}>", tag));
+    render_children(node, html);
+    html.push_str(&format!("</{}>\n", close));
+}
+
+fn render_children(node: &Value, html: &mut String) {
+    if let Some(children) = node.get("content").and_then(Value::as_array) {
+        for child in children {
+            render_html(child, html);
+        }
+    }
+}
+
+/// Text node with its marks (bold, italic, links...)
+fn render_text(node: &Value, html: &mut String) {
+    let text = escape_html(node.get("text").and_then(Value::as_str).unwrap_or_default());
+    let marks = node
+        .get("marks")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    let mut open = String::new();
+    let mut close = String::new();
+    for mark in marks {
+        let (start, end) = match mark.get("type").and_then(Value::as_str) {
+            Some("bold") => ("<strong>".to_string(), "</strong>"),
+            Some("italic") => ("<em>".to_string(), "</em>"),
+            Some("underline") => ("<u>".to_string(), "</u>"),
+            Some("strike") => ("<s>".to_string(), "</s>"),
+            Some("code") => ("<code>".to_string(), "</code>"),
+            Some("subscript") => ("<sub>".to_string(), "</sub>"),
+            Some("superscript") => ("<sup>".to_string(), "</sup>"),
+            Some("link") => {
+                let href = mark
+                    .get("attrs")
+                    .and_then(|a| a.get("href"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                (format!("<a href=\"{}\">", escape_html(href)), "</a>")
+            }
+            _ => continue,
+        };
+        open.push_str(&start);
+        close.insert_str(0, end);
+    }
+
+    html.push_str(&open);
+    html.push_str(&text);
+    html.push_str(&close);
+}
+
+/// Escape text for use in HTML content and attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|t| t.to_string()).collect()
+    }
+
+    fn ops(diff: &[DiffLine]) -> Vec<(DiffOp, &str)> {
+        diff.iter().map(|l| (l.op, l.text.as_str())).collect()
+    }
+
+    #[test]
+    fn diff_lines_of_equal_input_is_all_equal() {
+        let text = lines(&["a", "b", "c"]);
+        let diff = diff_lines(&text, &text);
+        assert!(diff.iter().all(|l| l.op == DiffOp::Equal));
+        assert_eq!(diff.len(), 3);
+    }
+
+    #[test]
+    fn diff_lines_finds_insertions_and_deletions() {
+        let diff = diff_lines(&lines(&["a", "b", "c", "d"]), &lines(&["a", "c", "x", "d"]));
+        assert_eq!(
+            ops(&diff),
+            vec![
+                (DiffOp::Equal, "a"),
+                (DiffOp::Delete, "b"),
+                (DiffOp::Equal, "c"),
+                (DiffOp::Insert, "x"),
+                (DiffOp::Equal, "d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_lines_shows_a_changed_line_as_delete_then_insert() {
+        let diff = diff_lines(&lines(&["a", "b"]), &lines(&["a", "B"]));
+        assert_eq!(
+            ops(&diff),
+            vec![
+                (DiffOp::Equal, "a"),
+                (DiffOp::Delete, "b"),
+                (DiffOp::Insert, "B")
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_lines_of_a_large_change_stays_bounded() {
+        // 20k changed lines on each side would need 400M table cells
+        let old: Vec<String> = (0..20_000).map(|i| format!("old {}", i)).collect();
+        let new: Vec<String> = (0..20_000).map(|i| format!("new {}", i)).collect();
+        let mut before = lines(&["title"]);
+        before.extend(old.iter().cloned());
+        before.push("end".to_string());
+        let mut after = lines(&["title"]);
+        after.extend(new.iter().cloned());
+        after.push("end".to_string());
+
+        let diff = diff_lines(&before, &after);
+        assert_eq!(diff.len(), 40_002);
+        assert_eq!(ops(&diff[..2]), vec![(DiffOp::Equal, "title"), (DiffOp::Delete, "old 0")]);
+        assert_eq!(diff[20_001].op, DiffOp::Insert);
+        assert_eq!(diff.iter().filter(|l| l.op == DiffOp::Delete).count(), 20_000);
+        assert_eq!(ops(&diff[40_001..]), vec![(DiffOp::Equal, "end")]);
+    }
+
+    #[test]
+    fn diff_lines_of_a_small_edit_in_a_long_document_is_exact() {
+        let old: Vec<String> = (0..50_000).map(|i| format!("line {}", i)).collect();
+        let mut new = old.clone();
+        new[25_000] = "changed".to_string();
+
+        let diff = diff_lines(&old, &new);
+        let changed: Vec<DiffLine> =
+            diff.iter().filter(|l| l.op != DiffOp::Equal).cloned().collect();
+        assert_eq!(
+            ops(&changed),
+            vec![(DiffOp::Delete, "line 25000"), (DiffOp::Insert, "changed")]
+        );
+        assert_eq!(diff.len(), 50_001);
+    }
+
+    #[test]
+    fn diff_lines_handles_empty_sides() {
+        let text = lines(&["a", "b"]);
+        assert_eq!(
+            ops(&diff_lines(&[], &text)),
+            vec![(DiffOp::Insert, "a"), (DiffOp::Insert, "b")]
+        );
+        assert_eq!(
+            ops(&diff_lines(&text, &[])),
+            vec![(DiffOp::Delete, "a"), (DiffOp::Delete, "b")]
+        );
+        assert!(diff_lines(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn diff_lines_keeps_the_longest_common_subsequence() {
+        let old = lines(&["x", "a", "b", "c", "y"]);
+        let new = lines(&["a", "b", "z", "c"]);
+        let diff = diff_lines(&old, &new);
+        let kept: Vec<_> = diff
+            .iter()
+            .filter(|l| l.op == DiffOp::Equal)
+            .map(|l| l.text.as_str())
+            .collect();
+        assert_eq!(kept, vec!["a", "b", "c"]);
+
+        // Replaying the diff gives back both sides
+        let replay = |skip: DiffOp| -> Vec<String> {
+            diff.iter()
+                .filter(|l| l.op != skip)
+                .map(|l| l.text.clone())
+                .collect()
+        };
+        assert_eq!(replay(DiffOp::Insert), old);
+        assert_eq!(replay(DiffOp::Delete), new);
+    }
+
+    const JSON_DOC: &str = r#"{"type":"doc","content":[
+        {"type":"heading","attrs":{"level":2},"content":[{"type":"text","text":"Setup"}]},
+        {"type":"paragraph","content":[{"type":"text","text":"Thaw "},{"type":"mention","attrs":{"id":"s1","label":"HeLa"}},{"type":"text","text":" on ice"}]},
+        {"type":"bulletList","content":[{"type":"listItem","content":[{"type":"paragraph","content":[{"type":"text","text":"Spin 5 min"}]}]}]}
+    ]}"#;
+
+    const HTML_DOC: &str = "<h2>Setup</h2><p>Thaw <span data-type=\"mention\" class=\"mention\" data-id=\"s1\" data-label=\"HeLa\">@HeLa</span> on ice</p><ul><li><p>Spin 5 min</p></li></ul>";
+
+    #[test]
+    fn flatten_blocks_reads_json_and_html_alike() {
+        let expected = lines(&["## Setup", "Thaw @HeLa on ice", "- Spin 5 min"]);
+        assert_eq!(flatten_blocks(JSON_DOC), expected);
+        assert_eq!(flatten_blocks(HTML_DOC), expected);
+    }
+
+    #[test]
+    fn flatten_blocks_splits_plain_text_into_lines() {
+        assert_eq!(flatten_blocks("one\ntwo"), lines(&["one", "two"]));
+        assert!(flatten_blocks("  ").is_empty());
+    }
+
+    #[test]
+    fn diff_documents_counts_changed_blocks() {
+        let new = HTML_DOC.replace("Spin 5 min", "Spin 10 min");
+        let diff = diff_documents(JSON_DOC, &new);
+        assert_eq!((diff.added, diff.removed), (1, 1));
+        assert_eq!(
+            diff.text,
+            " ## Setup\n Thaw @HeLa on ice\n-- Spin 5 min\n+- Spin 10 min\n"
+        );
+    }
+
+    #[test]
+    fn parse_document_reads_editor_html() {
+        let doc = parse_document(
+            "<p>a &amp; b<br>c</p><ul data-type=\"taskList\"><li data-type=\"taskItem\" data-checked=\"true\"><label><input type=\"checkbox\" checked><span></span></label><div><p>done</p></div></li></ul><pre><code class=\"language-py\">x = 1\n</code></pre>",
+        )
+        .unwrap();
+        assert_eq!(
+            doc,
+            serde_json::json!({ "type": "doc", "content": [
+                { "type": "paragraph", "content": [
+                    { "type": "text", "text": "a & b" },
+                    { "type": "hardBreak" },
+                    { "type": "text", "text": "c" },
+                ]},
+                { "type": "taskList", "content": [
+                    { "type": "taskItem", "attrs": { "checked": true }, "content": [
+                        { "type": "paragraph", "content": [{ "type": "text", "text": "done" }] },
+                    ]},
+                ]},
+                { "type": "codeBlock", "attrs": { "language": "py" }, "content": [
+                    { "type": "text", "text": "x = 1\n" },
+                ]},
+            ]})
+        );
+        assert_eq!(parse_document("just text"), None);
+    }
+
+    #[test]
+    fn to_markdown_renders_editor_html() {
+        assert_eq!(
+            to_markdown(HTML_DOC),
+            "## Setup\n\nThaw @HeLa on ice\n\n- Spin 5 min"
+        );
+        assert_eq!(to_markdown(HTML_DOC), to_markdown(JSON_DOC));
+    }
+
+    #[test]
+    fn to_html_renders_editor_html_like_json() {
+        let html = to_html(HTML_DOC);
+        assert_eq!(html, to_html(JSON_DOC));
+        assert!(html.starts_with("<h2>Setup</h2>\n<p>Thaw <span class=\"mention\""));
+        assert!(html.contains("<ul><li><p>Spin 5 min</p>\n</li>\n</ul>"));
+    }
+
+    #[test]
+    fn to_html_drops_unknown_markup() {
+        let html = to_html("<p onclick=\"x()\">hi <script>alert(1)</script><b>there</b></p>");
+        assert_eq!(html, "<p>hi <strong>there</strong></p>\n");
+    }
+
+    #[test]
+    fn to_html_escapes_plain_text() {
+        assert_eq!(to_html("a < b\n\nc"), "<p>a &lt; b</p>\n<p>c</p>\n");
+    }
+
+    fn checklist() -> Vec<String> {
+        vec!["Thaw <primers>".to_string(), "Run".to_string()]
+    }
+
+    fn task_items(doc: &Value) -> Vec<(String, bool)> {
+        let task_list = doc["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|node| node["type"] == "taskList")
+            .expect("a task list");
+        task_list["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| {
+                let text = inline_text(item["content"][0]["content"].as_array().unwrap());
+                (text, item["attrs"]["checked"].as_bool().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn append_checklist_extends_json_documents() {
+        let content = append_checklist(JSON_DOC, &checklist());
+        let doc: Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(doc["content"].as_array().unwrap().len(), 4);
+        assert_eq!(task_items(&doc), vec![("Thaw <primers>".to_string(), false), ("Run".to_string(), false)]);
+    }
+
+    #[test]
+    fn append_checklist_writes_editor_html_otherwise() {
+        for content in [HTML_DOC, "", "Thaw cells\n\nSpin"] {
+            let appended = append_checklist(content, &checklist());
+            assert!(appended.starts_with('<'), "{}", appended);
+            let doc = parse_document(&appended).unwrap();
+            assert_eq!(task_items(&doc), vec![("Thaw <primers>".to_string(), false), ("Run".to_string(), false)]);
+        }
+
+        let appended = append_checklist(HTML_DOC, &checklist());
+        assert!(appended.starts_with(HTML_DOC));
+        assert!(appended.contains("<p>Thaw &lt;primers&gt;</p>"));
+        assert_eq!(flatten_blocks(&append_checklist("Thaw cells\n\nSpin", &checklist()))[..2], ["Thaw cells", "Spin"]);
+    }
+
+    #[test]
+    fn append_checklist_without_items_keeps_content() {
+        assert_eq!(append_checklist(HTML_DOC, &[]), HTML_DOC);
+    }
+
+    #[test]
+    fn extract_mentions_reads_json_and_html_alike() {
+        let json = extract_mentions(JSON_DOC);
+        assert_eq!(json.len(), 1);
+        assert_eq!(json[0].entity_id, "s1");
+        assert_eq!(json[0].label.as_deref(), Some("HeLa"));
+        assert_eq!(json[0].position, 13);
+        assert_eq!(extract_mentions(HTML_DOC), json);
+    }
+
+    #[test]
+    fn extract_mentions_keeps_the_entity_type() {
+        let html = "<p>a <span data-type=\"mention\" data-id=\"e1\" data-label=\"qPCR\" data-entity-type=\"equipment\">@qPCR</span></p>";
+        let mentions = extract_mentions(html);
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].entity_type.as_deref(), Some("equipment"));
+        assert_eq!(mentions[0].position, 3);
+        assert!(extract_mentions("plain @text").is_empty());
+    }
+}
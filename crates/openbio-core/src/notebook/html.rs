@@ -0,0 +1,578 @@
+//! Reading editor HTML into ProseMirror JSON
+//!
+//! Notebooks saved before the editor stored JSON hold the HTML TipTap
+//! renders (`editor.getHTML()`). This reads that HTML back into the same
+//! node tree the editor would build, so every notebook helper works on one
+//! format. It is tolerant rather than a full HTML parser: unknown elements
+//! are unwrapped and stray closing tags are ignored.
+
+use serde_json::{json, Map, Value};
+
+/// Elements that never have children or a closing tag
+const VOID_ELEMENTS: [&str; 8] = ["br", "hr", "img", "input", "meta", "link", "col", "wbr"];
+
+/// Parse editor HTML into a ProseMirror `doc` node
+pub(super) fn parse_html(html: &str) -> Value {
+    let nodes = build_tree(html);
+    let mut content = blocks(&nodes);
+    if content.is_empty() {
+        content.push(json!({ "type": "paragraph" }));
+    }
+    json!({ "type": "doc", "content": content })
+}
+
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+enum Token {
+    Open {
+        name: String,
+        attrs: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    Close(String),
+    Text(String),
+}
+
+fn build_tree(html: &str) -> Vec<Node> {
+    // Open elements; the bottom entry collects the top-level nodes
+    let mut stack = vec![Element {
+        name: String::new(),
+        attrs: vec![],
+        children: vec![],
+    }];
+
+    for token in tokenize(html) {
+        match token {
+            Token::Text(text) => stack.last_mut().unwrap().children.push(Node::Text(text)),
+            Token::Open {
+                name,
+                attrs,
+                self_closing,
+            } => {
+                let element = Element {
+                    name,
+                    attrs,
+                    children: vec![],
+                };
+                if self_closing || VOID_ELEMENTS.contains(&element.name.as_str()) {
+                    stack
+                        .last_mut()
+                        .unwrap()
+                        .children
+                        .push(Node::Element(element));
+                } else {
+                    stack.push(element);
+                }
+            }
+            Token::Close(name) => {
+                // Close up to the matching element; ignore tags that were never opened
+                if let Some(depth) = stack.iter().skip(1).rposition(|e| e.name == name) {
+                    while stack.len() > depth + 1 {
+                        close_top(&mut stack);
+                    }
+                }
+            }
+        }
+    }
+
+    while stack.len() > 1 {
+        close_top(&mut stack);
+    }
+    stack.pop().unwrap().children
+}
+
+fn close_top(stack: &mut Vec<Element>) {
+    let element = stack.pop().unwrap();
+    stack
+        .last_mut()
+        .unwrap()
+        .children
+        .push(Node::Element(element));
+}
+
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            tokens.push(Token::Text(decode_entities(rest)));
+            break;
+        };
+        if start > 0 {
+            tokens.push(Token::Text(decode_entities(&rest[..start])));
+        }
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let starts_tag =
+            rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || "/!?".contains(c));
+        let Some(end) = tag_end(rest).filter(|_| starts_tag) else {
+            // A lone `<` is text
+            tokens.push(Token::Text("<".to_string()));
+            rest = &rest[1..];
+            continue;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if tag.starts_with('!') || tag.starts_with('?') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            tokens.push(Token::Close(name.trim().to_ascii_lowercase()));
+            continue;
+        }
+
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let name_end = tag
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        tokens.push(Token::Open {
+            name,
+            attrs: parse_attrs(&tag[name_end..]),
+            self_closing,
+        });
+    }
+    tokens
+}
+
+/// Index of the `>` closing the tag at the start of `html`, skipping quoted values
+fn tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html.char_indices().skip(1) {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(i),
+            (None, '<') => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_attrs(mut rest: &str) -> Vec<(String, String)> {
+    let mut attrs = vec![];
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return attrs;
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let value = &value[1..];
+                        let end = value.find(quote).unwrap_or(value.len());
+                        rest = value.get(end + 1..).unwrap_or_default();
+                        &value[..end]
+                    }
+                    _ => {
+                        let end = value
+                            .find(|c: char| c.is_ascii_whitespace())
+                            .unwrap_or(value.len());
+                        rest = &value[end..];
+                        &value[..end]
+                    }
+                }
+            }
+            // Boolean attribute such as `checked`
+            None => "",
+        };
+        if !name.is_empty() {
+            attrs.push((name, decode_entities(value)));
+        }
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end + 1]).map(|c| (c, end + 2)));
+        match entity {
+            Some((c, len)) => {
+                decoded.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let code = name.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+/// Block nodes for a list of HTML nodes. Inline content between blocks is
+/// wrapped in paragraphs, as the editor does.
+fn blocks(nodes: &[Node]) -> Vec<Value> {
+    let mut blocks = vec![];
+    let mut inline: Vec<&Node> = vec![];
+
+    for node in nodes {
+        match node {
+            Node::Element(element) if is_block(element) => {
+                flush_paragraph(&mut inline, &mut blocks);
+                block(element, &mut blocks);
+            }
+            _ => inline.push(node),
+        }
+    }
+    flush_paragraph(&mut inline, &mut blocks);
+    blocks
+}
+
+fn flush_paragraph(inline: &mut Vec<&Node>, blocks: &mut Vec<Value>) {
+    let content = inline_nodes(inline.drain(..), &[]);
+    if !content.is_empty() {
+        blocks.push(with_content(json!({ "type": "paragraph" }), content));
+    }
+}
+
+fn is_block(element: &Element) -> bool {
+    matches!(
+        element.name.as_str(),
+        "p" | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "ul"
+            | "ol"
+            | "li"
+            | "blockquote"
+            | "pre"
+            | "hr"
+            | "table"
+            | "thead"
+            | "tbody"
+            | "tfoot"
+            | "tr"
+            | "th"
+            | "td"
+            | "div"
+            | "section"
+            | "article"
+            | "header"
+            | "footer"
+            | "label"
+    )
+}
+
+fn block(element: &Element, blocks: &mut Vec<Value>) {
+    let node = match element.name.as_str() {
+        "p" => with_content(json!({ "type": "paragraph" }), paragraph_content(element)),
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = element.name[1..].parse::<u64>().unwrap_or(1);
+            with_content(
+                json!({ "type": "heading", "attrs": { "level": level } }),
+                paragraph_content(element),
+            )
+        }
+        "ul" if element.attr("data-type") == Some("taskList") => {
+            list(element, json!({ "type": "taskList" }), true)
+        }
+        "ul" => list(element, json!({ "type": "bulletList" }), false),
+        "ol" => {
+            let start = element
+                .attr("start")
+                .and_then(|s| s.trim().parse::<u64>().ok())
+                .unwrap_or(1);
+            list(
+                element,
+                json!({ "type": "orderedList", "attrs": { "start": start } }),
+                false,
+            )
+        }
+        // A list item outside a list
+        "li" => list_item(element, false),
+        "blockquote" => with_content(json!({ "type": "blockquote" }), blocks_or_empty(element)),
+        "pre" => {
+            let mut text = String::new();
+            plain_text(&element.children, &mut text);
+            let language = find_child(element, "code")
+                .and_then(|code| code.attr("class"))
+                .and_then(|class| {
+                    class
+                        .split_whitespace()
+                        .find_map(|c| c.strip_prefix("language-"))
+                });
+            let node = json!({ "type": "codeBlock", "attrs": { "language": language } });
+            let content = if text.is_empty() {
+                vec![]
+            } else {
+                vec![json!({ "type": "text", "text": text })]
+            };
+            with_content(node, content)
+        }
+        "hr" => json!({ "type": "horizontalRule" }),
+        "table" => with_content(json!({ "type": "table" }), table_rows(&element.children)),
+        "tr" => table_row(element),
+        // The checkbox of a task item is its `data-checked` attribute
+        "label" if find_child(element, "input").is_some() => return,
+        _ => {
+            blocks.extend(self::blocks(&element.children));
+            return;
+        }
+    };
+    blocks.push(node);
+}
+
+fn list(element: &Element, node: Value, tasks: bool) -> Value {
+    let items = element
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            Node::Element(item) if item.name == "li" => Some(list_item(item, tasks)),
+            _ => None,
+        })
+        .collect();
+    with_content(node, items)
+}
+
+fn list_item(element: &Element, in_task_list: bool) -> Value {
+    let node = if in_task_list || element.attr("data-type") == Some("taskItem") {
+        let checked = element.attr("data-checked") == Some("true");
+        json!({ "type": "taskItem", "attrs": { "checked": checked } })
+    } else {
+        json!({ "type": "listItem" })
+    };
+    with_content(node, blocks_or_empty(element))
+}
+
+fn table_rows(nodes: &[Node]) -> Vec<Value> {
+    let mut rows = vec![];
+    for node in nodes {
+        if let Node::Element(element) = node {
+            match element.name.as_str() {
+                "tr" => rows.push(table_row(element)),
+                "thead" | "tbody" | "tfoot" => rows.extend(table_rows(&element.children)),
+                _ => {}
+            }
+        }
+    }
+    rows
+}
+
+fn table_row(element: &Element) -> Value {
+    let cells = element
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            Node::Element(cell) if cell.name == "th" || cell.name == "td" => {
+                let cell_type = if cell.name == "th" {
+                    "tableHeader"
+                } else {
+                    "tableCell"
+                };
+                Some(with_content(
+                    json!({ "type": cell_type }),
+                    blocks_or_empty(cell),
+                ))
+            }
+            _ => None,
+        })
+        .collect();
+    with_content(json!({ "type": "tableRow" }), cells)
+}
+
+/// Child blocks of a container, with an empty paragraph if it has none
+fn blocks_or_empty(element: &Element) -> Vec<Value> {
+    let mut content = blocks(&element.children);
+    if content.is_empty() {
+        content.push(json!({ "type": "paragraph" }));
+    }
+    content
+}
+
+fn paragraph_content(element: &Element) -> Vec<Value> {
+    inline_nodes(element.children.iter(), &[])
+}
+
+/// Inline nodes with leading and trailing whitespace trimmed, as the editor does
+fn inline_nodes<'a>(nodes: impl IntoIterator<Item = &'a Node>, marks: &[Value]) -> Vec<Value> {
+    let mut content = vec![];
+    for node in nodes {
+        collect_inline(node, marks, &mut content);
+    }
+
+    if let Some(text) = content.first_mut().and_then(|n| n.get_mut("text")) {
+        *text = Value::String(text.as_str().unwrap_or_default().trim_start().to_string());
+    }
+    if let Some(text) = content.last_mut().and_then(|n| n.get_mut("text")) {
+        *text = Value::String(text.as_str().unwrap_or_default().trim_end().to_string());
+    }
+    content.retain(|node| node.get("text").is_none_or(|t| t != ""));
+    content
+}
+
+fn collect_inline(node: &Node, marks: &[Value], content: &mut Vec<Value>) {
+    let element = match node {
+        Node::Text(text) => {
+            let text = collapse_whitespace(text);
+            // Whitespace carries over from the previous text node
+            let text = match content
+                .last()
+                .and_then(|n| n.get("text"))
+                .and_then(Value::as_str)
+            {
+                Some(previous) if previous.ends_with(' ') => text.trim_start().to_string(),
+                _ => text,
+            };
+            if !text.is_empty() {
+                let mut node = json!({ "type": "text", "text": text });
+                if !marks.is_empty() {
+                    node["marks"] = Value::Array(marks.to_vec());
+                }
+                content.push(node);
+            }
+            return;
+        }
+        Node::Element(element) => element,
+    };
+
+    let mark = match element.name.as_str() {
+        "br" => {
+            content.push(json!({ "type": "hardBreak" }));
+            return;
+        }
+        "img" => {
+            content.push(json!({
+                "type": "image",
+                "attrs": { "src": element.attr("src"), "alt": element.attr("alt") },
+            }));
+            return;
+        }
+        "span" if element.attr("data-type") == Some("mention") => {
+            let mut attrs = Map::new();
+            attrs.insert("id".into(), json!(element.attr("data-id")));
+            attrs.insert("label".into(), json!(element.attr("data-label")));
+            if let Some(entity_type) = element.attr("data-entity-type") {
+                attrs.insert("type".into(), json!(entity_type));
+            }
+            content.push(json!({ "type": "mention", "attrs": attrs }));
+            return;
+        }
+        "strong" | "b" => Some(json!({ "type": "bold" })),
+        "em" | "i" => Some(json!({ "type": "italic" })),
+        "u" => Some(json!({ "type": "underline" })),
+        "s" | "strike" | "del" => Some(json!({ "type": "strike" })),
+        "code" => Some(json!({ "type": "code" })),
+        "sub" => Some(json!({ "type": "subscript" })),
+        "sup" => Some(json!({ "type": "superscript" })),
+        "a" => Some(json!({ "type": "link", "attrs": { "href": element.attr("href") } })),
+        "script" | "style" | "input" => return,
+        _ => None,
+    };
+
+    let mut child_marks = marks.to_vec();
+    child_marks.extend(mark);
+    for child in &element.children {
+        collect_inline(child, &child_marks, content);
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_space = false;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !in_space {
+                collapsed.push(' ');
+            }
+            in_space = true;
+        } else {
+            collapsed.push(c);
+            in_space = false;
+        }
+    }
+    collapsed
+}
+
+/// Text of nodes as written, for code blocks
+fn plain_text(nodes: &[Node], text: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(t) => text.push_str(t),
+            Node::Element(element) if element.name == "br" => text.push('\n'),
+            Node::Element(element) => plain_text(&element.children, text),
+        }
+    }
+}
+
+fn find_child<'a>(element: &'a Element, name: &str) -> Option<&'a Element> {
+    element.children.iter().find_map(|child| match child {
+        Node::Element(e) if e.name == name => Some(e),
+        Node::Element(e) => find_child(e, name),
+        Node::Text(_) => None,
+    })
+}
+
+fn with_content(mut node: Value, content: Vec<Value>) -> Value {
+    if !content.is_empty() {
+        node["content"] = Value::Array(content);
+    }
+    node
+}
@@ -0,0 +1,195 @@
+//! Instrument file parsers
+//!
+//! Ingested files are offered to a [`ParserRegistry`]; the first parser that
+//! recognises a file extracts its metadata, which is stored on the
+//! `DigitalAsset` and summarised in the experiment notebook. Additional
+//! formats plug in by implementing [`FileParser`] and registering it.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub mod fcs;
+pub mod illumina;
+pub mod ome_tiff;
+pub mod plate_reader;
+
+/// How much of a file parsers see when deciding whether they handle it
+const HEAD_LEN: usize = 8 * 1024;
+
+/// Metadata extracted from an instrument file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FileMetadata {
+    /// Format identifier, e.g. `fcs` or `illumina_run_info`
+    pub format: String,
+    /// One-line description for the experiment notebook
+    pub summary: String,
+    /// Format-specific fields
+    pub fields: Value,
+}
+
+/// A parser for one file format
+pub trait FileParser: Send + Sync {
+    /// Format identifier stored with the metadata
+    fn format(&self) -> &'static str;
+
+    /// Whether this parser handles the file, judged by its name and first bytes
+    fn accepts(&self, filename: &str, head: &[u8]) -> bool;
+
+    fn parse(&self, path: &Path) -> Result<FileMetadata, crate::Error>;
+}
+
+/// Parsers tried in registration order
+pub struct ParserRegistry {
+    parsers: Vec<Box<dyn FileParser>>,
+}
+
+impl ParserRegistry {
+    /// A registry without any parsers
+    pub fn empty() -> Self {
+        Self { parsers: vec![] }
+    }
+
+    pub fn register(&mut self, parser: impl FileParser + 'static) -> &mut Self {
+        self.parsers.push(Box::new(parser));
+        self
+    }
+
+    /// Formats handled, in the order they are tried
+    pub fn formats(&self) -> Vec<&'static str> {
+        self.parsers.iter().map(|parser| parser.format()).collect()
+    }
+
+    /// Parse a file with the first parser that accepts it.
+    /// Returns `None` when no parser handles the file.
+    pub fn parse(&self, path: &Path, filename: &str) -> Option<Result<FileMetadata, crate::Error>> {
+        let head = match read_head(path) {
+            Ok(head) => head,
+            Err(e) => return Some(Err(e.into())),
+        };
+        self.parsers
+            .iter()
+            .find(|parser| parser.accepts(filename, &head))
+            .map(|parser| parser.parse(path))
+    }
+}
+
+impl Default for ParserRegistry {
+    /// The built-in parsers
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry
+            .register(fcs::FcsParser)
+            .register(illumina::RunInfoParser)
+            .register(illumina::SampleSheetParser)
+            .register(ome_tiff::OmeTiffParser)
+            .register(plate_reader::PlateReaderParser);
+        registry
+    }
+}
+
+fn read_head(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(HEAD_LEN);
+    File::open(path)?
+        .take(HEAD_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// Read a text format whole, refusing files over `max_len` bytes
+fn read_text(path: &Path, format: &str, max_len: u64) -> Result<String, crate::Error> {
+    let mut bytes = vec![];
+    File::open(path)?
+        .take(max_len + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > max_len {
+        return Err(parse_error(
+            format,
+            format!("larger than {} MB", max_len / (1024 * 1024)),
+        ));
+    }
+    String::from_utf8(bytes).map_err(|_| parse_error(format, "not UTF-8 text"))
+}
+
+fn parse_error(format: &str, message: impl std::fmt::Display) -> crate::Error {
+    crate::Error::Parse(format!("{}: {}", format, message))
+}
+
+/// Case-insensitive check of a file name's ending
+fn name_ends_with(filename: &str, suffix: &str) -> bool {
+    filename.to_lowercase().ends_with(&suffix.to_lowercase())
+}
+
+/// Numbers as JSON numbers, everything else as strings
+fn json_value(value: &str) -> Value {
+    let value = value.trim();
+    if let Ok(n) = value.parse::<i64>() {
+        return Value::from(n);
+    }
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map(Value::Number)
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::path::{Path, PathBuf};
+
+    use tempfile::TempDir;
+
+    /// A file from `tests/fixtures`
+    pub fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    pub fn read_fixture(name: &str) -> Vec<u8> {
+        std::fs::read(fixture(name)).unwrap()
+    }
+
+    /// Write `bytes` to a file called `name` in a directory that lives as
+    /// long as the returned guard
+    pub fn temp_file(name: &str, bytes: &[u8]) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        (dir, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{fixture, temp_file};
+    use super::*;
+
+    #[test]
+    fn default_registry_picks_the_parser_by_name_and_content() {
+        let registry = ParserRegistry::default();
+        for (name, format) in [
+            ("A1.fcs", "fcs"),
+            ("RunInfo.xml", illumina::RUN_INFO),
+            ("SampleSheet.csv", illumina::SAMPLE_SHEET),
+            ("HeLa.ome.tif", "ome_tiff"),
+            ("plate_grid.csv", "plate_reader"),
+            ("plate_list.txt", "plate_reader"),
+        ] {
+            let metadata = registry.parse(&fixture(name), name).unwrap().unwrap();
+            assert_eq!(metadata.format, format, "{}", name);
+        }
+    }
+
+    #[test]
+    fn default_registry_skips_unknown_files() {
+        let (_dir, path) = temp_file("notes.txt", b"Thawed the cells at 9:30");
+        assert!(ParserRegistry::default()
+            .parse(&path, "notes.txt")
+            .is_none());
+    }
+}
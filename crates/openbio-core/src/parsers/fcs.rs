@@ -0,0 +1,226 @@
+//! FCS flow cytometry files (FCS 3.x, and 2.0 where compatible)
+//!
+//! Only the HEADER and TEXT segments are read: the event data itself is left
+//! for analysis tools.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde_json::json;
+
+use super::{parse_error, FileMetadata, FileParser};
+
+const FORMAT: &str = "fcs";
+/// TEXT segments beyond this are not metadata anyone wants in a notebook
+const MAX_TEXT_LEN: u64 = 4 * 1024 * 1024;
+
+pub struct FcsParser;
+
+impl FileParser for FcsParser {
+    fn format(&self) -> &'static str {
+        FORMAT
+    }
+
+    fn accepts(&self, _filename: &str, head: &[u8]) -> bool {
+        head.starts_with(b"FCS")
+    }
+
+    fn parse(&self, path: &Path) -> Result<FileMetadata, crate::Error> {
+        let mut file = File::open(path)?;
+        let mut header = [0u8; 58];
+        file.read_exact(&mut header)?;
+
+        let version = std::str::from_utf8(&header[..6])
+            .map_err(|_| parse_error(FORMAT, "invalid header"))?
+            .to_string();
+        let text_start = header_offset(&header[10..18])?;
+        let text_end = header_offset(&header[18..26])?;
+        if text_end < text_start || text_end - text_start >= MAX_TEXT_LEN {
+            return Err(parse_error(FORMAT, "invalid TEXT segment offsets"));
+        }
+
+        let mut text = vec![0u8; (text_end - text_start + 1) as usize];
+        file.seek(SeekFrom::Start(text_start))?;
+        file.read_exact(&mut text)?;
+        let keywords = parse_text(&String::from_utf8_lossy(&text))?;
+
+        let get = |key: &str| keywords.get(key).cloned();
+        let events = get("$TOT").and_then(|n| n.trim().parse::<u64>().ok());
+        // Every parameter has a $PnN keyword, so a larger $PAR is corrupt
+        let named = keywords.keys().filter(|key| is_parameter_name(key)).count();
+        let parameter_count = get("$PAR")
+            .and_then(|n| n.trim().parse::<usize>().ok())
+            .unwrap_or(0)
+            .min(named);
+        let parameters: Vec<_> = (1..=parameter_count)
+            .map(|n| {
+                json!({
+                    "name": get(&format!("$P{}N", n)),
+                    "stain": get(&format!("$P{}S", n)),
+                })
+            })
+            .collect();
+        let cytometer = get("$CYT");
+
+        let names: Vec<String> = (1..=parameter_count)
+            .filter_map(|n| get(&format!("$P{}N", n)))
+            .collect();
+        let summary = format!(
+            "{}{}: {} events, {} parameters{}",
+            version.replace("FCS", "FCS "),
+            cytometer
+                .as_ref()
+                .map(|cyt| format!(" from {}", cyt))
+                .unwrap_or_default(),
+            events
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            parameter_count,
+            if names.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", names.join(", "))
+            }
+        );
+
+        Ok(FileMetadata {
+            format: FORMAT.to_string(),
+            summary,
+            fields: json!({
+                "version": version,
+                "cytometer": cytometer,
+                "events": events,
+                "parameters": parameters,
+                "date": get("$DATE"),
+                "beginTime": get("$BTIM"),
+                "endTime": get("$ETIM"),
+                "source": get("$SRC"),
+                "operator": get("$OP"),
+                "originalFilename": get("$FIL"),
+                "keywords": keywords,
+            }),
+        })
+    }
+}
+
+/// Offsets are right-justified ASCII numbers
+fn header_offset(bytes: &[u8]) -> Result<u64, crate::Error> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .ok_or_else(|| parse_error(FORMAT, "invalid header offset"))
+}
+
+/// `$P1N`, `$P2N`, ...
+fn is_parameter_name(key: &str) -> bool {
+    key.strip_prefix("$P")
+        .and_then(|rest| rest.strip_suffix('N'))
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Keyword/value pairs separated by the delimiter the segment starts with.
+/// A doubled delimiter stands for the delimiter character itself.
+fn parse_text(text: &str) -> Result<BTreeMap<String, String>, crate::Error> {
+    let mut chars = text.chars();
+    let delimiter = chars
+        .next()
+        .ok_or_else(|| parse_error(FORMAT, "empty TEXT segment"))?;
+
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut chars = chars.peekable();
+    while let Some(c) = chars.next() {
+        if c != delimiter {
+            current.push(c);
+        } else if chars.peek() == Some(&delimiter) {
+            chars.next();
+            current.push(delimiter);
+        } else {
+            tokens.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    Ok(tokens
+        .chunks_exact(2)
+        .map(|pair| (pair[0].trim().to_uppercase(), pair[1].trim().to_string()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::testing::{fixture, read_fixture, temp_file};
+
+    /// An FCS 3.1 file with `text` as its TEXT segment and no data
+    fn fcs_with_text(text: &str) -> Vec<u8> {
+        let end = 58 + text.len() - 1;
+        let mut bytes = format!(
+            "FCS3.1    {:>8}{:>8}{:>8}{:>8}{:>8}{:>8}",
+            58, end, 0, 0, 0, 0
+        )
+        .into_bytes();
+        bytes.extend_from_slice(text.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn parses_header_and_text_segment() {
+        let metadata = FcsParser.parse(&fixture("A1.fcs")).unwrap();
+        assert_eq!(metadata.format, "fcs");
+        assert_eq!(
+            metadata.summary,
+            "FCS 3.1 from FACSCanto II: 3 events, 2 parameters (FSC-A, FITC-A)"
+        );
+        assert_eq!(metadata.fields["events"], 3);
+        assert_eq!(metadata.fields["parameters"][1]["stain"], "CD3");
+        assert_eq!(metadata.fields["operator"], "jdoe");
+        // A doubled delimiter is the delimiter itself
+        assert_eq!(metadata.fields["source"], "HeLa|control");
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let bytes = read_fixture("A1.fcs");
+        for len in [20, 120] {
+            let (_dir, path) = temp_file("A1.fcs", &bytes[..len]);
+            assert!(FcsParser.parse(&path).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn invalid_offsets_are_errors() {
+        let mut bytes = read_fixture("A1.fcs");
+        bytes[10..18].copy_from_slice(b"    9999");
+        let (_dir, path) = temp_file("A1.fcs", &bytes);
+        assert!(FcsParser.parse(&path).is_err());
+
+        bytes[10..18].copy_from_slice(b"  abc   ");
+        let (_dir, path) = temp_file("A1.fcs", &bytes);
+        assert!(FcsParser.parse(&path).is_err());
+    }
+
+    #[test]
+    fn parameter_count_is_limited_to_named_parameters() {
+        let bytes = fcs_with_text("/$TOT/10/$PAR/4000000000/$P1N/FSC-A/$P2N/SSC-A/");
+        let (_dir, path) = temp_file("huge.fcs", &bytes);
+        let metadata = FcsParser.parse(&path).unwrap();
+        assert_eq!(metadata.fields["parameters"].as_array().unwrap().len(), 2);
+        assert!(metadata
+            .summary
+            .ends_with("10 events, 2 parameters (FSC-A, SSC-A)"));
+    }
+
+    #[test]
+    fn unparsable_parameter_count_means_none() {
+        let bytes = fcs_with_text("/$PAR/many/$P1N/FSC-A/");
+        let (_dir, path) = temp_file("bad.fcs", &bytes);
+        let metadata = FcsParser.parse(&path).unwrap();
+        assert!(metadata.fields["parameters"].as_array().unwrap().is_empty());
+        assert!(metadata.fields["events"].is_null());
+    }
+}
@@ -0,0 +1,296 @@
+//! Illumina sequencing run files: `RunInfo.xml` and `SampleSheet.csv`
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use super::{json_value, name_ends_with, parse_error, read_text, FileMetadata, FileParser};
+
+/// Format identifier of `RunInfo.xml` metadata
+pub const RUN_INFO: &str = "illumina_run_info";
+/// Format identifier of sample sheet metadata
+pub const SAMPLE_SHEET: &str = "illumina_sample_sheet";
+
+/// Both files are parsed whole; real ones are a few KB
+const MAX_FILE_LEN: u64 = 4 * 1024 * 1024;
+/// Samples stored per sheet (more than a NovaSeq X run multiplexes); the rest
+/// are counted and the metadata is marked `truncated`
+const MAX_SAMPLES: usize = 10_000;
+
+/// `RunInfo.xml`: run ID, instrument, flowcell and read structure
+pub struct RunInfoParser;
+
+impl FileParser for RunInfoParser {
+    fn format(&self) -> &'static str {
+        RUN_INFO
+    }
+
+    fn accepts(&self, filename: &str, _head: &[u8]) -> bool {
+        name_ends_with(filename, "RunInfo.xml")
+    }
+
+    fn parse(&self, path: &Path) -> Result<FileMetadata, crate::Error> {
+        let xml = read_text(path, RUN_INFO, MAX_FILE_LEN)?;
+        let doc = roxmltree::Document::parse(&xml).map_err(|e| parse_error(RUN_INFO, e))?;
+        let run = doc
+            .descendants()
+            .find(|node| node.has_tag_name("Run"))
+            .ok_or_else(|| parse_error(RUN_INFO, "no <Run> element"))?;
+
+        let child_text = |name: &str| {
+            run.children()
+                .find(|node| node.has_tag_name(name))
+                .and_then(|node| node.text())
+                .map(|text| text.trim().to_string())
+        };
+        let run_id = run.attribute("Id").map(str::to_string);
+        let instrument = child_text("Instrument");
+        let flowcell = child_text("Flowcell");
+
+        let reads: Vec<Value> = run
+            .descendants()
+            .filter(|node| node.has_tag_name("Read"))
+            .map(|read| {
+                json!({
+                    "number": read.attribute("Number").and_then(|n| n.parse::<u32>().ok()),
+                    "cycles": read.attribute("NumCycles").and_then(|n| n.parse::<u32>().ok()),
+                    "indexed": read.attribute("IsIndexedRead") == Some("Y"),
+                })
+            })
+            .collect();
+        let lanes = run
+            .descendants()
+            .find(|node| node.has_tag_name("FlowcellLayout"))
+            .and_then(|layout| layout.attribute("LaneCount"))
+            .and_then(|n| n.parse::<u32>().ok());
+
+        let structure: Vec<String> = reads
+            .iter()
+            .map(|read| read["cycles"].to_string())
+            .collect();
+        let summary = format!(
+            "Illumina run {}{}{}: reads {}{}",
+            run_id.as_deref().unwrap_or("(unnamed)"),
+            instrument
+                .as_ref()
+                .map(|i| format!(" on {}", i))
+                .unwrap_or_default(),
+            flowcell
+                .as_ref()
+                .map(|f| format!(", flowcell {}", f))
+                .unwrap_or_default(),
+            structure.join("+"),
+            lanes
+                .map(|n| format!(", {} lane(s)", n))
+                .unwrap_or_default(),
+        );
+
+        Ok(FileMetadata {
+            format: RUN_INFO.to_string(),
+            summary,
+            fields: json!({
+                "runId": run_id,
+                "runNumber": run.attribute("Number").and_then(|n| n.parse::<u32>().ok()),
+                "instrument": instrument,
+                "flowcell": flowcell,
+                "date": child_text("Date"),
+                "reads": reads,
+                "lanes": lanes,
+            }),
+        })
+    }
+}
+
+/// `SampleSheet.csv` (v1 and BCL Convert v2): header settings and samples
+pub struct SampleSheetParser;
+
+impl FileParser for SampleSheetParser {
+    fn format(&self) -> &'static str {
+        SAMPLE_SHEET
+    }
+
+    fn accepts(&self, filename: &str, head: &[u8]) -> bool {
+        name_ends_with(filename, "SampleSheet.csv")
+            || (name_ends_with(filename, ".csv")
+                && String::from_utf8_lossy(head)
+                    .trim_start_matches('\u{feff}')
+                    .starts_with("[Header]"))
+    }
+
+    fn parse(&self, path: &Path) -> Result<FileMetadata, crate::Error> {
+        let text = read_text(path, SAMPLE_SHEET, MAX_FILE_LEN)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(text.as_bytes());
+
+        let mut header = BTreeMap::new();
+        let mut reads = vec![];
+        let mut samples: Vec<Value> = vec![];
+        let mut sample_count = 0;
+        let mut section = String::new();
+        let mut columns: Option<Vec<String>> = None;
+
+        for record in reader.records() {
+            let record = record.map_err(|e| parse_error(SAMPLE_SHEET, e))?;
+            let cells: Vec<&str> = record.iter().map(str::trim).collect();
+            let first = cells
+                .first()
+                .copied()
+                .unwrap_or_default()
+                .trim_start_matches('\u{feff}');
+            if cells.iter().all(|cell| cell.is_empty()) {
+                continue;
+            }
+            if first.starts_with('[') && first.ends_with(']') {
+                section = first[1..first.len() - 1].to_string();
+                columns = None;
+                continue;
+            }
+
+            match section.as_str() {
+                "Header" => {
+                    header.insert(first.to_string(), json_value(cells.get(1).unwrap_or(&"")));
+                }
+                "Reads" => reads.push(
+                    cells
+                        .iter()
+                        .map(|cell| json_value(cell))
+                        .collect::<Vec<_>>(),
+                ),
+                // [Data] in v1, [BCLConvert_Data] etc. in v2
+                name if name.ends_with("Data") => match &columns {
+                    None => columns = Some(cells.iter().map(|cell| cell.to_string()).collect()),
+                    Some(_) if samples.len() == MAX_SAMPLES => sample_count += 1,
+                    Some(columns) => {
+                        sample_count += 1;
+                        let sample: serde_json::Map<String, Value> = columns
+                            .iter()
+                            .zip(&cells)
+                            .filter(|(_, value)| !value.is_empty())
+                            .map(|(column, value)| {
+                                (column.clone(), Value::String(value.to_string()))
+                            })
+                            .collect();
+                        samples.push(Value::Object(sample));
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        let name = header
+            .get("Experiment Name")
+            .or_else(|| header.get("RunName"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let summary = format!(
+            "Illumina sample sheet{}: {} sample(s)",
+            name.as_ref()
+                .map(|name| format!(" \"{}\"", name))
+                .unwrap_or_default(),
+            sample_count
+        );
+
+        Ok(FileMetadata {
+            format: SAMPLE_SHEET.to_string(),
+            summary,
+            fields: json!({
+                "experimentName": name,
+                "header": header,
+                "reads": reads,
+                "samples": samples,
+                "truncated": sample_count > samples.len(),
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::testing::{fixture, read_fixture, temp_file};
+
+    #[test]
+    fn parses_run_info() {
+        let metadata = RunInfoParser.parse(&fixture("RunInfo.xml")).unwrap();
+        assert_eq!(
+            metadata.summary,
+            "Illumina run 261018_M00123_0042_000000000-ABC12 on M00123, \
+             flowcell 000000000-ABC12: reads 151+8+8+151, 1 lane(s)"
+        );
+        assert_eq!(metadata.fields["runNumber"], 42);
+        assert_eq!(metadata.fields["reads"][1]["indexed"], true);
+    }
+
+    #[test]
+    fn truncated_or_malformed_run_info_is_an_error() {
+        let bytes = read_fixture("RunInfo.xml");
+        let (_dir, path) = temp_file("RunInfo.xml", &bytes[..bytes.len() / 2]);
+        assert!(RunInfoParser.parse(&path).is_err());
+
+        let (_dir, path) = temp_file("RunInfo.xml", b"<RunInfo><Runs/></RunInfo>");
+        assert!(RunInfoParser.parse(&path).is_err());
+    }
+
+    #[test]
+    fn parses_sample_sheets() {
+        let metadata = SampleSheetParser
+            .parse(&fixture("SampleSheet.csv"))
+            .unwrap();
+        assert_eq!(
+            metadata.summary,
+            "Illumina sample sheet \"qPCR follow-up\": 3 sample(s)"
+        );
+        assert_eq!(metadata.fields["header"]["FileFormatVersion"], 2);
+        assert_eq!(metadata.fields["samples"][2]["Sample_ID"], "HEK-1");
+        assert_eq!(metadata.fields["samples"][2]["Index2"], "CCTATCCT");
+        assert_eq!(metadata.fields["truncated"], false);
+    }
+
+    #[test]
+    fn truncated_sample_sheets_keep_the_complete_rows() {
+        let text = String::from_utf8(read_fixture("SampleSheet.csv")).unwrap();
+        let cut = text.find("HEK-1").unwrap() + 3;
+        let (_dir, path) = temp_file("SampleSheet.csv", &text.as_bytes()[..cut]);
+        let metadata = SampleSheetParser.parse(&path).unwrap();
+        let samples = metadata.fields["samples"].as_array().unwrap();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[2], json!({ "Sample_ID": "HEK" }));
+    }
+
+    #[test]
+    fn sample_sheet_samples_are_capped() {
+        let mut text = String::from("[Header]\nRunName,Large\n[Data]\nSample_ID,Index\n");
+        for i in 0..MAX_SAMPLES + 5 {
+            text.push_str(&format!("S{},ACGTACGT\n", i));
+        }
+        let (_dir, path) = temp_file("SampleSheet.csv", text.as_bytes());
+        let metadata = SampleSheetParser.parse(&path).unwrap();
+
+        assert_eq!(
+            metadata.summary,
+            "Illumina sample sheet \"Large\": 10005 sample(s)"
+        );
+        assert_eq!(
+            metadata.fields["samples"].as_array().unwrap().len(),
+            MAX_SAMPLES
+        );
+        assert_eq!(metadata.fields["truncated"], true);
+    }
+
+    #[test]
+    fn oversized_files_are_errors() {
+        let mut xml = read_fixture("RunInfo.xml");
+        xml.extend(std::iter::repeat_n(b' ', MAX_FILE_LEN as usize));
+        let (_dir, path) = temp_file("RunInfo.xml", &xml);
+        assert!(RunInfoParser.parse(&path).is_err());
+    }
+
+    #[test]
+    fn sample_sheets_that_are_not_text_are_errors() {
+        let (_dir, path) = temp_file("SampleSheet.csv", b"[Header]\nRunName,\xff\xfe\n");
+        assert!(SampleSheetParser.parse(&path).is_err());
+    }
+}
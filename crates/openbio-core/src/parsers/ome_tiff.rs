@@ -0,0 +1,431 @@
+//! OME-TIFF microscopy images
+//!
+//! The OME-XML block lives in the first IFD's `ImageDescription` tag. Only the
+//! tags needed are read, so image data is never loaded. Plain TIFFs without
+//! OME-XML get their dimensions recorded.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use super::{parse_error, FileMetadata, FileParser};
+
+const FORMAT: &str = "ome_tiff";
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_IMAGE_DESCRIPTION: u16 = 270;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+
+/// OME-XML beyond this is not read
+const MAX_DESCRIPTION_LEN: u64 = 16 * 1024 * 1024;
+
+pub struct OmeTiffParser;
+
+impl FileParser for OmeTiffParser {
+    fn format(&self) -> &'static str {
+        FORMAT
+    }
+
+    fn accepts(&self, _filename: &str, head: &[u8]) -> bool {
+        head.starts_with(b"II*\0")
+            || head.starts_with(b"MM\0*")
+            || head.starts_with(b"II+\0")
+            || head.starts_with(b"MM\0+")
+    }
+
+    fn parse(&self, path: &Path) -> Result<FileMetadata, crate::Error> {
+        let mut tiff = Tiff::open(path)?;
+        let entries = tiff.first_ifd()?;
+        let find = |tag| entries.iter().find(|entry| entry.tag == tag);
+
+        let width = find(TAG_IMAGE_WIDTH).and_then(|e| e.inline_number(tiff.little_endian));
+        let height = find(TAG_IMAGE_LENGTH).and_then(|e| e.inline_number(tiff.little_endian));
+        let bits = find(TAG_BITS_PER_SAMPLE).and_then(|e| e.inline_number(tiff.little_endian));
+        let samples = find(TAG_SAMPLES_PER_PIXEL).and_then(|e| e.inline_number(tiff.little_endian));
+        let description = match find(TAG_IMAGE_DESCRIPTION) {
+            Some(entry) => Some(tiff.ascii(entry)?),
+            None => None,
+        };
+
+        match description.filter(|d| d.contains("<OME")) {
+            Some(xml) => parse_ome(&xml),
+            None => Ok(FileMetadata {
+                format: "tiff".to_string(),
+                summary: format!(
+                    "TIFF image {}×{}{}",
+                    width.unwrap_or(0),
+                    height.unwrap_or(0),
+                    bits.map(|b| format!(", {}-bit", b)).unwrap_or_default()
+                ),
+                fields: json!({
+                    "width": width,
+                    "height": height,
+                    "bitsPerSample": bits,
+                    "samplesPerPixel": samples,
+                }),
+            }),
+        }
+    }
+}
+
+fn parse_ome(xml: &str) -> Result<FileMetadata, crate::Error> {
+    let doc = roxmltree::Document::parse(xml).map_err(|e| parse_error(FORMAT, e))?;
+    let number = |node: roxmltree::Node, name: &str| {
+        node.attribute(name).and_then(|v| v.parse::<f64>().ok())
+    };
+
+    let images: Vec<Value> = doc
+        .descendants()
+        .filter(|node| node.has_tag_name("Image"))
+        .map(|image| {
+            let pixels = image.children().find(|node| node.has_tag_name("Pixels"));
+            let channels: Vec<Value> = pixels
+                .iter()
+                .flat_map(|pixels| pixels.children())
+                .filter(|node| node.has_tag_name("Channel"))
+                .map(|channel| {
+                    json!({
+                        "name": channel.attribute("Name"),
+                        "excitationWavelength": number(channel, "ExcitationWavelength"),
+                        "emissionWavelength": number(channel, "EmissionWavelength"),
+                    })
+                })
+                .collect();
+            let acquired = image
+                .children()
+                .find(|node| node.has_tag_name("AcquisitionDate"))
+                .and_then(|node| node.text());
+            json!({
+                "name": image.attribute("Name"),
+                "acquisitionDate": acquired,
+                "sizeX": pixels.and_then(|p| number(p, "SizeX")),
+                "sizeY": pixels.and_then(|p| number(p, "SizeY")),
+                "sizeZ": pixels.and_then(|p| number(p, "SizeZ")),
+                "sizeC": pixels.and_then(|p| number(p, "SizeC")),
+                "sizeT": pixels.and_then(|p| number(p, "SizeT")),
+                "pixelType": pixels.and_then(|p| p.attribute("Type")),
+                "dimensionOrder": pixels.and_then(|p| p.attribute("DimensionOrder")),
+                "physicalSizeX": pixels.and_then(|p| number(p, "PhysicalSizeX")),
+                "physicalSizeY": pixels.and_then(|p| number(p, "PhysicalSizeY")),
+                "physicalSizeZ": pixels.and_then(|p| number(p, "PhysicalSizeZ")),
+                "channels": channels,
+            })
+        })
+        .collect();
+
+    let microscope = doc
+        .descendants()
+        .find(|node| node.has_tag_name("Microscope"))
+        .map(|m| {
+            [m.attribute("Manufacturer"), m.attribute("Model")]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|name| !name.is_empty());
+    let objective = doc
+        .descendants()
+        .find(|node| node.has_tag_name("Objective"))
+        .map(|o| {
+            json!({
+                "model": o.attribute("Model"),
+                "magnification": number(o, "NominalMagnification"),
+                "numericalAperture": number(o, "LensNA"),
+            })
+        });
+
+    let summary = match images.first() {
+        Some(image) => {
+            let size = |key: &str| image[key].as_f64().unwrap_or(1.0) as u64;
+            let channel_names: Vec<&str> = image["channels"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|channel| channel["name"].as_str())
+                .collect();
+            format!(
+                "OME-TIFF, {} image(s); first {}×{}, {} channel(s){}, {} z-slice(s), {} timepoint(s){}{}",
+                images.len(),
+                size("sizeX"),
+                size("sizeY"),
+                size("sizeC"),
+                if channel_names.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", channel_names.join(", "))
+                },
+                size("sizeZ"),
+                size("sizeT"),
+                image["pixelType"]
+                    .as_str()
+                    .map(|t| format!(", {}", t))
+                    .unwrap_or_default(),
+                microscope
+                    .as_ref()
+                    .map(|m| format!(", {}", m))
+                    .unwrap_or_default(),
+            )
+        }
+        None => "OME-TIFF without image metadata".to_string(),
+    };
+
+    Ok(FileMetadata {
+        format: FORMAT.to_string(),
+        summary,
+        fields: json!({
+            "images": images,
+            "microscope": microscope,
+            "objective": objective,
+        }),
+    })
+}
+
+/// Just enough of a TIFF reader to get at the first IFD's tags
+struct Tiff {
+    file: File,
+    little_endian: bool,
+    big_tiff: bool,
+}
+
+struct IfdEntry {
+    tag: u16,
+    field_type: u16,
+    count: u64,
+    /// The value itself when it fits, otherwise its offset
+    value: [u8; 8],
+}
+
+impl IfdEntry {
+    /// A single SHORT or LONG value stored in the entry itself
+    fn inline_number(&self, little_endian: bool) -> Option<u64> {
+        if self.count != 1 {
+            return None;
+        }
+        match self.field_type {
+            3 => Some(u16_from(&self.value[..2], little_endian) as u64),
+            4 => Some(u32_from(&self.value[..4], little_endian) as u64),
+            _ => None,
+        }
+    }
+}
+
+impl Tiff {
+    fn open(path: &Path) -> Result<Self, crate::Error> {
+        let mut file = File::open(path)?;
+        let mut header = [0u8; 4];
+        file.read_exact(&mut header)?;
+        let little_endian = match &header[..2] {
+            b"II" => true,
+            b"MM" => false,
+            _ => return Err(parse_error(FORMAT, "not a TIFF file")),
+        };
+        let big_tiff = match u16_from(&header[2..], little_endian) {
+            42 => false,
+            43 => true,
+            _ => return Err(parse_error(FORMAT, "not a TIFF file")),
+        };
+        Ok(Self {
+            file,
+            little_endian,
+            big_tiff,
+        })
+    }
+
+    fn first_ifd(&mut self) -> Result<Vec<IfdEntry>, crate::Error> {
+        let (offset, count) = if self.big_tiff {
+            self.file.seek(SeekFrom::Start(8))?;
+            let offset = self.read_u64()?;
+            self.file.seek(SeekFrom::Start(offset))?;
+            (offset, self.read_u64()?)
+        } else {
+            self.file.seek(SeekFrom::Start(4))?;
+            let offset = self.read_u32()? as u64;
+            self.file.seek(SeekFrom::Start(offset))?;
+            (offset, self.read_u16()? as u64)
+        };
+        if offset == 0 || count > 4096 {
+            return Err(parse_error(FORMAT, "invalid IFD"));
+        }
+
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let tag = self.read_u16()?;
+            let field_type = self.read_u16()?;
+            let mut value = [0u8; 8];
+            let count = if self.big_tiff {
+                let count = self.read_u64()?;
+                self.file.read_exact(&mut value)?;
+                count
+            } else {
+                let count = self.read_u32()? as u64;
+                self.file.read_exact(&mut value[..4])?;
+                count
+            };
+            entries.push(IfdEntry {
+                tag,
+                field_type,
+                count,
+                value,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// An ASCII tag's text
+    fn ascii(&mut self, entry: &IfdEntry) -> Result<String, crate::Error> {
+        if entry.count > MAX_DESCRIPTION_LEN {
+            return Err(parse_error(FORMAT, "ImageDescription too large"));
+        }
+        let inline_len = if self.big_tiff { 8 } else { 4 };
+        let bytes = if entry.count <= inline_len {
+            entry.value[..entry.count as usize].to_vec()
+        } else {
+            let offset = if self.big_tiff {
+                u64_from(&entry.value, self.little_endian)
+            } else {
+                u32_from(&entry.value[..4], self.little_endian) as u64
+            };
+            let mut bytes = vec![0u8; entry.count as usize];
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut bytes)?;
+            bytes
+        };
+        Ok(String::from_utf8_lossy(&bytes)
+            .trim_end_matches('\0')
+            .to_string())
+    }
+
+    fn read_u16(&mut self) -> Result<u16, crate::Error> {
+        let mut bytes = [0u8; 2];
+        self.file.read_exact(&mut bytes)?;
+        Ok(u16_from(&bytes, self.little_endian))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, crate::Error> {
+        let mut bytes = [0u8; 4];
+        self.file.read_exact(&mut bytes)?;
+        Ok(u32_from(&bytes, self.little_endian))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, crate::Error> {
+        let mut bytes = [0u8; 8];
+        self.file.read_exact(&mut bytes)?;
+        Ok(u64_from(&bytes, self.little_endian))
+    }
+}
+
+fn u16_from(bytes: &[u8], little_endian: bool) -> u16 {
+    let bytes = [bytes[0], bytes[1]];
+    if little_endian {
+        u16::from_le_bytes(bytes)
+    } else {
+        u16::from_be_bytes(bytes)
+    }
+}
+
+fn u32_from(bytes: &[u8], little_endian: bool) -> u32 {
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    if little_endian {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    }
+}
+
+fn u64_from(bytes: &[u8], little_endian: bool) -> u64 {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(&bytes[..8]);
+    if little_endian {
+        u64::from_le_bytes(array)
+    } else {
+        u64::from_be_bytes(array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::testing::{fixture, read_fixture, temp_file};
+
+    /// A little-endian TIFF whose first IFD holds `(tag, type, count, value)` entries
+    fn tiff(entries: &[(u16, u16, u32, u32)]) -> Vec<u8> {
+        let mut bytes = b"II*\0".to_vec();
+        bytes.extend_from_slice(&8u32.to_le_bytes());
+        bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for &(tag, field_type, count, value) in entries {
+            bytes.extend_from_slice(&tag.to_le_bytes());
+            bytes.extend_from_slice(&field_type.to_le_bytes());
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn parses_ome_xml() {
+        let metadata = OmeTiffParser.parse(&fixture("HeLa.ome.tif")).unwrap();
+        assert_eq!(metadata.format, "ome_tiff");
+        assert_eq!(
+            metadata.summary,
+            "OME-TIFF, 1 image(s); first 64×48, 2 channel(s) (DAPI, GFP), 3 z-slice(s), \
+             1 timepoint(s), uint16, Zeiss LSM 980"
+        );
+        let image = &metadata.fields["images"][0];
+        assert_eq!(image["channels"][1]["emissionWavelength"], 509.0);
+        assert_eq!(metadata.fields["objective"]["magnification"], 63.0);
+    }
+
+    #[test]
+    fn plain_tiffs_get_their_dimensions() {
+        let bytes = tiff(&[(256, 3, 1, 640), (257, 4, 1, 480), (258, 3, 1, 8)]);
+        let (_dir, path) = temp_file("plain.tif", &bytes);
+        let metadata = OmeTiffParser.parse(&path).unwrap();
+        assert_eq!(metadata.format, "tiff");
+        assert_eq!(metadata.summary, "TIFF image 640×480, 8-bit");
+    }
+
+    #[test]
+    fn only_single_values_are_read_inline() {
+        // Three samples of 8 bits each: the value field holds an offset
+        let bytes = tiff(&[(256, 3, 1, 640), (257, 3, 1, 480), (258, 3, 3, 200)]);
+        let (_dir, path) = temp_file("rgb.tif", &bytes);
+        let metadata = OmeTiffParser.parse(&path).unwrap();
+        assert!(metadata.fields["bitsPerSample"].is_null());
+        assert_eq!(metadata.summary, "TIFF image 640×480");
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let bytes = read_fixture("HeLa.ome.tif");
+        for len in [3, 30, 200] {
+            let (_dir, path) = temp_file("HeLa.ome.tif", &bytes[..len]);
+            assert!(OmeTiffParser.parse(&path).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        // Not a TIFF version
+        let (_dir, path) = temp_file("bad.tif", b"II\x2b\x01\x08\0\0\0");
+        assert!(OmeTiffParser.parse(&path).is_err());
+
+        // Absurd entry count
+        let mut bytes = tiff(&[(256, 3, 1, 640)]);
+        bytes[8..10].copy_from_slice(&60000u16.to_le_bytes());
+        let (_dir, path) = temp_file("bad.tif", &bytes);
+        assert!(OmeTiffParser.parse(&path).is_err());
+
+        // OME-XML that is not XML
+        let mut bytes = read_fixture("HeLa.ome.tif");
+        let at = bytes.windows(4).position(|w| w == b"<OME").unwrap();
+        bytes[at + 4] = b'<';
+        let (_dir, path) = temp_file("bad.ome.tif", &bytes);
+        assert!(OmeTiffParser.parse(&path).is_err());
+    }
+}
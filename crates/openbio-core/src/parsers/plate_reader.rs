@@ -0,0 +1,370 @@
+//! Plate-reader CSV exports
+//!
+//! Vendors differ, but exports come in two shapes: plate grids (a header row
+//! of column numbers followed by rows labelled A, B, C, ...), one per read,
+//! often preceded by `Key: value` or `Key,value` lines; or lists with a `Well`
+//! column. Both are recognised; values are stored by well, up to
+//! [`MAX_VALUES`] of them.
+
+use std::path::Path;
+
+use serde_json::{json, Map, Value};
+
+use super::{json_value, name_ends_with, parse_error, read_text, FileMetadata, FileParser};
+
+const FORMAT: &str = "plate_reader";
+/// Exports are parsed whole; even long kinetic runs stay well below this
+const MAX_FILE_LEN: u64 = 16 * 1024 * 1024;
+/// Values stored per file (a 1536-well plate read 65 times); the rest are
+/// left in the file and the metadata is marked `truncated`
+const MAX_VALUES: usize = 100_000;
+
+pub struct PlateReaderParser;
+
+impl FileParser for PlateReaderParser {
+    fn format(&self) -> &'static str {
+        FORMAT
+    }
+
+    fn accepts(&self, filename: &str, head: &[u8]) -> bool {
+        if !(name_ends_with(filename, ".csv") || name_ends_with(filename, ".txt")) {
+            return false;
+        }
+        let head = String::from_utf8_lossy(head);
+        let delimiter = detect_delimiter(&head);
+        head.lines().any(|line| {
+            let cells: Vec<&str> = line.split(delimiter as char).map(clean).collect();
+            grid_columns(&cells).is_some() || well_column(&cells).is_some()
+        })
+    }
+
+    fn parse(&self, path: &Path) -> Result<FileMetadata, crate::Error> {
+        let text = read_text(path, FORMAT, MAX_FILE_LEN)?;
+        let delimiter = detect_delimiter(&text);
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(delimiter)
+            .from_reader(text.as_bytes());
+        let mut rows: Vec<Vec<String>> = vec![];
+        for record in reader.records() {
+            let record = record.map_err(|e| parse_error(FORMAT, e))?;
+            rows.push(record.iter().map(|cell| clean(cell).to_string()).collect());
+        }
+
+        if let Some(start) = rows.iter().position(|row| {
+            let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+            grid_columns(&cells).is_some()
+        }) {
+            return Ok(parse_grids(&rows, start));
+        }
+        if let Some((start, column)) = rows.iter().enumerate().find_map(|(i, row)| {
+            let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+            well_column(&cells).map(|column| (i, column))
+        }) {
+            return Ok(parse_list(&rows, start, column));
+        }
+        Err(parse_error(FORMAT, "no plate layout found"))
+    }
+}
+
+/// Grids, with the lines before the first one as key/value settings
+fn parse_grids(rows: &[Vec<String>], first_grid: usize) -> FileMetadata {
+    let settings = settings(&rows[..first_grid]);
+
+    let mut reads = vec![];
+    let mut plate_rows = 0;
+    let mut plate_columns = 0;
+    let mut stored = 0;
+    let mut truncated = false;
+    let mut i = first_grid;
+    while i < rows.len() && !truncated {
+        let cells: Vec<&str> = rows[i].iter().map(String::as_str).collect();
+        let Some(columns) = grid_columns(&cells) else {
+            i += 1;
+            continue;
+        };
+        // The line above a grid usually names the read (wavelength, label)
+        let label = i
+            .checked_sub(1)
+            .map(|above| &rows[above])
+            .filter(|above| !above.first().is_some_and(|cell| is_row_label(cell)))
+            .map(|above| {
+                above
+                    .iter()
+                    .filter(|cell| !cell.is_empty())
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .filter(|label| !label.is_empty());
+
+        let mut values = Map::new();
+        let mut row_count = 0;
+        i += 1;
+        while let Some(row) = rows.get(i) {
+            let Some(letter) = row.first().filter(|cell| is_row_label(cell)) else {
+                break;
+            };
+            for (column, value) in columns.iter().zip(row.iter().skip(1)) {
+                if value.is_empty() {
+                    continue;
+                }
+                if stored == MAX_VALUES {
+                    truncated = true;
+                    break;
+                }
+                values.insert(
+                    format!("{}{}", letter.to_uppercase(), column),
+                    json_value(value),
+                );
+                stored += 1;
+            }
+            row_count += 1;
+            i += 1;
+        }
+        plate_rows = plate_rows.max(row_count);
+        plate_columns = plate_columns.max(columns.len());
+        reads.push(json!({ "label": label, "values": values }));
+    }
+
+    let wells = plate_rows * plate_columns;
+    FileMetadata {
+        format: FORMAT.to_string(),
+        summary: format!(
+            "Plate reader export: {}-well plate, {} read(s){}",
+            wells,
+            reads.len(),
+            truncated_note(truncated)
+        ),
+        fields: json!({
+            "layout": "grid",
+            "plateFormat": wells,
+            "settings": settings,
+            "reads": reads,
+            "truncated": truncated,
+        }),
+    }
+}
+
+/// One row per well under a header naming the columns
+fn parse_list(rows: &[Vec<String>], header: usize, well_column: usize) -> FileMetadata {
+    let settings = settings(&rows[..header]);
+    let columns = &rows[header];
+
+    let mut wells = Map::new();
+    let mut stored = 0;
+    let mut truncated = false;
+    for row in &rows[header + 1..] {
+        let Some(well) = row.get(well_column).filter(|cell| is_well(cell)) else {
+            continue;
+        };
+        let values: Map<String, Value> = columns
+            .iter()
+            .zip(row)
+            .enumerate()
+            .filter(|(i, (_, value))| *i != well_column && !value.is_empty())
+            .map(|(_, (column, value))| (column.clone(), json_value(value)))
+            .collect();
+        if stored + values.len() > MAX_VALUES {
+            truncated = true;
+            break;
+        }
+        stored += values.len();
+        wells.insert(well.to_uppercase(), Value::Object(values));
+    }
+
+    FileMetadata {
+        format: FORMAT.to_string(),
+        summary: format!(
+            "Plate reader export: {} well(s), columns {}{}",
+            wells.len(),
+            columns
+                .iter()
+                .filter(|column| !column.is_empty())
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", "),
+            truncated_note(truncated)
+        ),
+        fields: json!({
+            "layout": "list",
+            "settings": settings,
+            "columns": columns,
+            "wells": wells,
+            "truncated": truncated,
+        }),
+    }
+}
+
+fn truncated_note(truncated: bool) -> String {
+    if truncated {
+        format!(" (first {} values)", MAX_VALUES)
+    } else {
+        String::new()
+    }
+}
+
+/// `Key: value` and `Key,value` lines
+fn settings(rows: &[Vec<String>]) -> Map<String, Value> {
+    rows.iter()
+        .filter_map(|row| {
+            let cells: Vec<&str> = row
+                .iter()
+                .map(String::as_str)
+                .filter(|cell| !cell.is_empty())
+                .collect();
+            match cells.as_slice() {
+                [single] => {
+                    let (key, value) = single.split_once(':')?;
+                    Some((key.trim().to_string(), json_value(value)))
+                }
+                [key, value, ..] => Some((
+                    key.trim_end_matches(':').trim().to_string(),
+                    json_value(value),
+                )),
+                [] => None,
+            }
+        })
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+/// Column numbers of a grid header row (`,1,2,3,...` or `<>,1,2,3,...`)
+fn grid_columns(cells: &[&str]) -> Option<Vec<u32>> {
+    let (first, rest) = cells.split_first()?;
+    if !(first.is_empty() || *first == "<>") {
+        return None;
+    }
+    let numbers: Vec<u32> = rest
+        .iter()
+        .take_while(|cell| !cell.is_empty())
+        .map(|cell| cell.parse().ok())
+        .collect::<Option<_>>()?;
+    let consecutive = numbers.iter().enumerate().all(|(i, &n)| n == i as u32 + 1);
+    (numbers.len() >= 6 && consecutive).then_some(numbers)
+}
+
+/// Index of a `Well` column whose header row marks a well list
+fn well_column(cells: &[&str]) -> Option<usize> {
+    cells.iter().position(|cell| {
+        cell.eq_ignore_ascii_case("well") || cell.eq_ignore_ascii_case("well position")
+    })
+}
+
+fn is_row_label(cell: &str) -> bool {
+    cell.len() == 1 && cell.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+/// A1, B12, P24, ...
+fn is_well(cell: &str) -> bool {
+    let mut chars = cell.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && !chars.as_str().is_empty()
+        && chars.as_str().chars().all(|c| c.is_ascii_digit())
+}
+
+fn clean(cell: &str) -> &str {
+    cell.trim().trim_matches('"').trim()
+}
+
+/// Whichever of comma, semicolon and tab is most common
+fn detect_delimiter(text: &str) -> u8 {
+    [b',', b';', b'\t']
+        .into_iter()
+        .max_by_key(|&d| text.bytes().filter(|&b| b == d).count())
+        .unwrap_or(b',')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::testing::{fixture, read_fixture, temp_file};
+
+    #[test]
+    fn parses_plate_grids() {
+        let metadata = PlateReaderParser.parse(&fixture("plate_grid.csv")).unwrap();
+        assert_eq!(
+            metadata.summary,
+            "Plate reader export: 96-well plate, 1 read(s)"
+        );
+        assert_eq!(metadata.fields["settings"]["Plate"], "Assay plate 1");
+        let read = &metadata.fields["reads"][0];
+        assert_eq!(read["label"], "450 nm");
+        assert_eq!(read["values"]["A1"], 0.05);
+        assert_eq!(read["values"]["H12"], 1.0);
+    }
+
+    #[test]
+    fn parses_well_lists() {
+        let metadata = PlateReaderParser.parse(&fixture("plate_list.txt")).unwrap();
+        assert_eq!(
+            metadata.summary,
+            "Plate reader export: 3 well(s), columns Well, Sample Name, Target, CT"
+        );
+        assert_eq!(metadata.fields["settings"]["Instrument"], "QuantStudio 5");
+        assert_eq!(metadata.fields["wells"]["A2"]["CT"], 18.43);
+        assert_eq!(metadata.fields["wells"]["B1"]["CT"], "Undetermined");
+        assert_eq!(metadata.fields["truncated"], false);
+    }
+
+    #[test]
+    fn truncated_grids_keep_the_complete_rows() {
+        let text = String::from_utf8(read_fixture("plate_grid.csv")).unwrap();
+        let cut = text.find("\nC,").unwrap();
+        let (_dir, path) = temp_file("plate.csv", &text.as_bytes()[..cut]);
+        let metadata = PlateReaderParser.parse(&path).unwrap();
+        assert_eq!(
+            metadata.summary,
+            "Plate reader export: 24-well plate, 1 read(s)"
+        );
+
+        // Cut before the grid starts: nothing left to recognise
+        let cut = text.find("450 nm").unwrap();
+        let (_dir, path) = temp_file("plate.csv", &text.as_bytes()[..cut]);
+        assert!(PlateReaderParser.parse(&path).is_err());
+    }
+
+    #[test]
+    fn grid_values_are_capped() {
+        let mut text = String::new();
+        let reads = MAX_VALUES / 96 + 2;
+        for read in 0..reads {
+            text.push_str(&format!("Read {}\n,1,2,3,4,5,6,7,8,9,10,11,12\n", read));
+            for row in 'A'..='H' {
+                text.push_str(&format!(
+                    "{},0.1,0.2,0.3,0.4,0.5,0.6,0.7,0.8,0.9,1,1.1,1.2\n",
+                    row
+                ));
+            }
+        }
+        let (_dir, path) = temp_file("kinetic.csv", text.as_bytes());
+        let metadata = PlateReaderParser.parse(&path).unwrap();
+
+        assert_eq!(metadata.fields["truncated"], true);
+        let reads = metadata.fields["reads"].as_array().unwrap();
+        let stored: usize = reads
+            .iter()
+            .map(|read| read["values"].as_object().unwrap().len())
+            .sum();
+        assert_eq!(stored, MAX_VALUES);
+        assert!(metadata.summary.ends_with("(first 100000 values)"));
+    }
+
+    #[test]
+    fn oversized_files_are_errors() {
+        let mut text = String::from(",1,2,3,4,5,6\nA,1,2,3,4,5,6\n");
+        text.push_str(&"x".repeat(MAX_FILE_LEN as usize));
+        let (_dir, path) = temp_file("plate.csv", text.as_bytes());
+        assert!(PlateReaderParser.parse(&path).is_err());
+    }
+
+    #[test]
+    fn files_without_a_plate_layout_are_errors() {
+        let (_dir, path) = temp_file("plate.csv", b"Sample,Value\nHeLa,1\n");
+        assert!(PlateReaderParser.parse(&path).is_err());
+        // Not consecutive column numbers, so not a grid header
+        let (_dir, path) = temp_file("plate.csv", b",1,2,3,5,6,7\nA,1,2,3,4,5,6\n");
+        assert!(PlateReaderParser.parse(&path).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Linking ingested files to samples
+//!
+//! Instruments name their output after wells or sample IDs. Each Equipment
+//! record carries [`SampleRule`]s: a regex pulls an identifier out of the
+//! file's path, optionally maps it through the run's sample sheet, and the
+//! result is compared with `Sample.externalId` or `Sample.name`.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Which `Sample` field an identifier is compared with
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleField {
+    #[default]
+    ExternalId,
+    Name,
+}
+
+/// Maps an identifier from a file name through a sample sheet row
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SheetLookup {
+    /// Column the identifier is looked up in, e.g. `Sample_ID` or `Sample_Well`
+    pub key_column: String,
+    /// Column holding the sample's identifier, e.g. `Sample_Name`
+    pub sample_column: String,
+}
+
+/// One rule, stored as JSON on `Equipment.sampleRules`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SampleRule {
+    /// Regex matched against the path relative to the watch folder. The
+    /// `sample` group, else the first group, else the whole match is the
+    /// identifier.
+    pub pattern: String,
+    #[serde(default)]
+    pub field: SampleField,
+    /// Look the identifier up in the sample sheet before matching
+    #[serde(default)]
+    pub sheet: Option<SheetLookup>,
+}
+
+/// An identifier to look for among the samples
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleKey {
+    pub value: String,
+    pub field: SampleField,
+}
+
+/// Compiled [`SampleRule`]s, tried in order
+#[derive(Debug, Clone, Default)]
+pub struct SampleMatcher {
+    rules: Vec<(Regex, SampleRule)>,
+}
+
+impl SampleMatcher {
+    pub fn new(rules: &[SampleRule]) -> Result<Self, crate::Error> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let regex = Regex::new(&rule.pattern).map_err(|e| {
+                    crate::Error::Validation(format!("Invalid pattern '{}': {}", rule.pattern, e))
+                })?;
+                if let Some(sheet) = &rule.sheet {
+                    if sheet.key_column.trim().is_empty() || sheet.sample_column.trim().is_empty() {
+                        return Err(crate::Error::Validation(format!(
+                            "Rule '{}' needs both sample sheet columns",
+                            rule.pattern
+                        )));
+                    }
+                }
+                Ok((regex, rule.clone()))
+            })
+            .collect::<Result<_, crate::Error>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether any rule needs a sample sheet
+    pub fn uses_sheet(&self) -> bool {
+        self.rules.iter().any(|(_, rule)| rule.sheet.is_some())
+    }
+
+    /// Identifiers for a file, in rule order. `samples` are the rows of the
+    /// run's sample sheet (`fields.samples` of its parsed metadata), if any.
+    /// Rules whose pattern does not match the file are skipped; a sheet rule
+    /// without a matching row yields the raw identifier so it shows up in the
+    /// unmatched report.
+    pub fn keys(&self, relative_path: &str, samples: Option<&[Value]>) -> Vec<SampleKey> {
+        self.rules
+            .iter()
+            .filter_map(|(regex, rule)| {
+                let captures = regex.captures(relative_path)?;
+                let found = captures
+                    .name("sample")
+                    .or_else(|| captures.get(1))
+                    .or_else(|| captures.get(0))?
+                    .as_str()
+                    .trim();
+                if found.is_empty() {
+                    return None;
+                }
+                let value = rule
+                    .sheet
+                    .as_ref()
+                    .and_then(|sheet| sheet_value(samples?, sheet, found))
+                    .unwrap_or(found);
+                Some(SampleKey {
+                    value: value.to_string(),
+                    field: rule.field,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The sample column of the first row whose key column equals `key`
+fn sheet_value<'a>(samples: &'a [Value], sheet: &SheetLookup, key: &str) -> Option<&'a str> {
+    samples
+        .iter()
+        .find(|row| {
+            row[&sheet.key_column]
+                .as_str()
+                .is_some_and(|cell| cell.trim().eq_ignore_ascii_case(key))
+        })
+        .and_then(|row| row[&sheet.sample_column].as_str())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
@@ -2,6 +2,8 @@
 //! 
 //! Provides a unified interface for file storage across local filesystem and S3.
 
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
 /// Storage backend trait
@@ -9,6 +11,12 @@ use std::path::Path;
 pub trait StorageBackend: Send + Sync {
     /// Store a file
     async fn put(&self, key: &str, data: &[u8]) -> Result<(), crate::Error>;
+
+    /// Store the file at `source` (which may be moved)
+    async fn put_file(&self, key: &str, source: &Path) -> Result<(), crate::Error> {
+        let data = std::fs::read(source)?;
+        self.put(key, &data).await
+    }
     
     /// Retrieve a file
     async fn get(&self, key: &str) -> Result<Vec<u8>, crate::Error>;
@@ -46,6 +54,19 @@ impl StorageBackend for LocalStorage {
         Ok(())
     }
 
+    async fn put_file(&self, key: &str, source: &Path) -> Result<(), crate::Error> {
+        let path = self.base_path.join(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Renaming fails across filesystems; fall back to copying
+        if std::fs::rename(source, &path).is_err() {
+            std::fs::copy(source, &path)?;
+            std::fs::remove_file(source)?;
+        }
+        Ok(())
+    }
+
     async fn get(&self, key: &str) -> Result<Vec<u8>, crate::Error> {
         let path = self.base_path.join(key);
         Ok(std::fs::read(&path)?)
@@ -67,3 +88,24 @@ impl StorageBackend for LocalStorage {
         Ok(path.to_string_lossy().to_string())
     }
 }
+
+/// SHA-256 of a file as lowercase hex (the `DigitalAsset.checksum` format)
+pub fn file_checksum(path: &Path) -> Result<String, crate::Error> {
+    use sha2::{Digest, Sha256};
+
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
@@ -0,0 +1,191 @@
+//! Asset files and resumable uploads
+//!
+//! Agents upload files in chunks. Each upload is a `.part` file under
+//! `<data dir>/uploads` with a JSON sidecar describing it; its length is the
+//! offset the agent resumes from. A finished upload is verified against its
+//! checksum and moved to `<data dir>/assets/<storage key>`.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use openbio_core::storage::{file_checksum, LocalStorage, StorageBackend};
+use serde::{Deserialize, Serialize};
+
+/// A file being uploaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: String,
+    pub machine_id: String,
+    pub equipment_id: String,
+    /// Path relative to the agent's watch folder
+    pub filename: String,
+    pub size_bytes: u64,
+    pub checksum: String,
+    pub modified_at: Option<i64>,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("Upload {0} not found")]
+    NotFound(String),
+    #[error("Upload continues at byte {expected}")]
+    OffsetMismatch { expected: u64 },
+    #[error("Upload would exceed the announced size of {0} bytes")]
+    TooLarge(u64),
+    #[error("Upload is incomplete: {received} of {expected} bytes received")]
+    Incomplete { received: u64, expected: u64 },
+    #[error("Checksum mismatch: expected {expected}, received {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("Storage error: {0}")]
+    Storage(#[from] openbio_core::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Where asset files live on the Hub
+pub struct AssetStore {
+    storage: Arc<LocalStorage>,
+    assets_dir: PathBuf,
+    uploads_dir: PathBuf,
+}
+
+impl AssetStore {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            storage: Arc::new(LocalStorage::new(data_dir.join("assets"))),
+            assets_dir: data_dir.join("assets"),
+            uploads_dir: data_dir.join("uploads"),
+        }
+    }
+
+    /// Start an upload, or resume it if it was started before.
+    /// Returns the bytes received so far.
+    pub fn begin(&self, session: &UploadSession) -> Result<u64, UploadError> {
+        fs::create_dir_all(&self.uploads_dir)?;
+        fs::write(
+            self.sidecar_path(&session.id),
+            serde_json::to_vec(session).unwrap_or_default(),
+        )?;
+
+        let part = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.part_path(&session.id))?;
+        Ok(part.metadata()?.len())
+    }
+
+    pub fn session(&self, id: &str) -> Result<UploadSession, UploadError> {
+        if !is_upload_id(id) {
+            return Err(UploadError::NotFound(id.to_string()));
+        }
+        let sidecar = match fs::read(self.sidecar_path(id)) {
+            Ok(sidecar) => sidecar,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(UploadError::NotFound(id.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_slice(&sidecar).map_err(|_| UploadError::NotFound(id.to_string()))
+    }
+
+    /// Append a chunk that starts at `offset`; returns the new offset
+    pub fn append(
+        &self,
+        session: &UploadSession,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<u64, UploadError> {
+        let mut part = OpenOptions::new()
+            .append(true)
+            .open(self.part_path(&session.id))?;
+        let received = part.metadata()?.len();
+        if offset != received {
+            return Err(UploadError::OffsetMismatch { expected: received });
+        }
+        if received + data.len() as u64 > session.size_bytes {
+            return Err(UploadError::TooLarge(session.size_bytes));
+        }
+        part.write_all(data)?;
+        Ok(received + data.len() as u64)
+    }
+
+    /// Verify a fully received upload and move it to `storage_key`.
+    ///
+    /// Hashing the file, and copying it when the assets are on another
+    /// filesystem, take minutes for large files, so both run on the blocking
+    /// thread pool.
+    pub async fn finish(
+        &self,
+        session: &UploadSession,
+        storage_key: &str,
+    ) -> Result<(), UploadError> {
+        let part = self.part_path(&session.id);
+        let received = fs::metadata(&part)?.len();
+        if received != session.size_bytes {
+            return Err(UploadError::Incomplete {
+                received,
+                expected: session.size_bytes,
+            });
+        }
+
+        let actual = blocking({
+            let part = part.clone();
+            move || Ok(file_checksum(&part)?)
+        })
+        .await?;
+        if actual != session.checksum {
+            // Corrupt data cannot be resumed; start over
+            self.discard(&session.id);
+            return Err(UploadError::ChecksumMismatch {
+                expected: session.checksum.clone(),
+                actual,
+            });
+        }
+
+        let (storage, key) = (self.storage.clone(), storage_key.to_string());
+        blocking(move || {
+            let runtime = tokio::runtime::Handle::current();
+            Ok(runtime.block_on(storage.put_file(&key, &part))?)
+        })
+        .await?;
+        self.discard(&session.id);
+        Ok(())
+    }
+
+    /// Where a stored asset's file is
+    pub fn path(&self, storage_key: &str) -> PathBuf {
+        self.assets_dir.join(storage_key)
+    }
+
+    /// Remove an upload's temporary files
+    pub fn discard(&self, id: &str) {
+        let _ = fs::remove_file(self.part_path(id));
+        let _ = fs::remove_file(self.sidecar_path(id));
+    }
+
+    fn part_path(&self, id: &str) -> PathBuf {
+        self.uploads_dir.join(format!("{}.part", id))
+    }
+
+    fn sidecar_path(&self, id: &str) -> PathBuf {
+        self.uploads_dir.join(format!("{}.json", id))
+    }
+}
+
+/// Run file work that blocks on the blocking thread pool
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, UploadError> + Send + 'static,
+) -> Result<T, UploadError> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| UploadError::Io(std::io::Error::other(e)))?
+}
+
+/// Upload IDs are hex digests; anything else could escape the uploads directory
+fn is_upload_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit())
+}
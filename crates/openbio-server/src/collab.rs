@@ -0,0 +1,536 @@
+//! Real-time collaborative notebook editing
+//!
+//! The server is the central authority of the `prosemirror-collab` protocol:
+//! clients send ProseMirror steps based on the version they have seen, the
+//! room accepts them only if that version is current, and broadcasts accepted
+//! steps to everyone (including the sender, which confirms them by client ID).
+//! Clients whose steps are rejected rebase onto the broadcast steps and resend.
+//!
+//! Along with its steps a client sends the resulting document; since steps
+//! are applied in one global order this is the merged state, which is
+//! periodically persisted to `Experiment.content`. The server has no copy of
+//! the editor schema and cannot apply steps itself, so it trusts the document
+//! from the client whose steps it just accepted on the current version (last
+//! writer trusted) and only checks that it is a ProseMirror document.
+//!
+//! A save only replaces the content the room was opened with (or last saved).
+//! The REST API refuses content writes while a room has unsaved changes; any
+//! other REST write replaces the room's document and peers reload it. Rooms
+//! also track presence (who is viewing / typing).
+//!
+//! Messages are JSON text frames tagged with `type`, see [`ClientMessage`]
+//! and [`ServerMessage`].
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Accepted steps kept per room for clients catching up
+const STEP_HISTORY: usize = 1000;
+
+/// What a connected user is doing
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceState {
+    Viewing,
+    Typing,
+    Idle,
+}
+
+/// A user connected to a room
+#[derive(Debug, Clone, Serialize)]
+pub struct Peer {
+    pub conn_id: u64,
+    pub user: String,
+    pub state: PresenceState,
+    /// Editor selection, as sent by the client
+    pub cursor: Option<Value>,
+}
+
+/// Messages sent by clients
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ClientMessage {
+    /// Steps made on top of `version`, with the document after applying them
+    Steps {
+        version: u64,
+        client_id: String,
+        steps: Vec<Value>,
+        doc: Value,
+    },
+    Presence {
+        state: PresenceState,
+        cursor: Option<Value>,
+    },
+}
+
+/// Messages sent by the server
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ServerMessage {
+    /// Full state on connect (or after falling too far behind)
+    Init {
+        conn_id: u64,
+        version: u64,
+        doc: Value,
+        read_only: bool,
+        peers: Vec<Peer>,
+    },
+    /// Accepted steps; `version` is the room version after applying them
+    Steps {
+        version: u64,
+        steps: Vec<Value>,
+        client_ids: Vec<String>,
+    },
+    /// Steps were rejected because they were not based on the current `version`
+    Conflict {
+        version: u64,
+    },
+    /// The stored content was replaced outside the room; load `doc` at `version`
+    Reset {
+        version: u64,
+        doc: Value,
+    },
+    Presence {
+        peers: Vec<Peer>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Merged document waiting to be written to `Experiment.content`
+pub struct PendingSave {
+    pub experiment_id: String,
+    /// Room version the document is at
+    pub version: u64,
+    pub content: String,
+    /// What `Experiment.content` holds if nobody else wrote it since the room
+    /// opened or last saved
+    pub base_content: String,
+    /// User who made the last change
+    pub author: Option<String>,
+}
+
+struct RoomState {
+    version: u64,
+    /// (step, client ID) for versions `version - steps.len() .. version`
+    steps: VecDeque<(Value, String)>,
+    doc: Value,
+    /// `Experiment.content` as of opening the room or its last save
+    saved_content: String,
+    read_only: bool,
+    /// Changed since the last successful save
+    dirty: bool,
+    /// A save is in flight
+    saving: bool,
+    /// After a failed save, don't try again before this
+    retry_at: Option<Instant>,
+    last_change: Instant,
+    last_editor: Option<String>,
+    peers: HashMap<u64, Peer>,
+}
+
+/// Editing session for one experiment
+pub struct Room {
+    pub experiment_id: String,
+    state: Mutex<RoomState>,
+    tx: broadcast::Sender<Arc<str>>,
+}
+
+impl Room {
+    fn new(experiment_id: String, doc: Value, saved_content: String, read_only: bool) -> Self {
+        let (tx, _) = broadcast::channel(256);
+        Self {
+            experiment_id,
+            state: Mutex::new(RoomState {
+                version: 0,
+                steps: VecDeque::new(),
+                doc,
+                saved_content,
+                read_only,
+                dirty: false,
+                saving: false,
+                retry_at: None,
+                last_change: Instant::now(),
+                last_editor: None,
+                peers: HashMap::new(),
+            }),
+            tx,
+        }
+    }
+
+    /// Add a peer; returns its initial state and a receiver for room broadcasts
+    fn connect(&self, conn_id: u64, user: String) -> (ServerMessage, broadcast::Receiver<Arc<str>>) {
+        let rx = self.tx.subscribe();
+        let mut state = self.state.lock().unwrap();
+        state.peers.insert(
+            conn_id,
+            Peer {
+                conn_id,
+                user,
+                state: PresenceState::Viewing,
+                cursor: None,
+            },
+        );
+        (Self::init_message(&state, conn_id), rx)
+    }
+
+    /// Full state for a peer that has to (re)load the document
+    pub fn init_for(&self, conn_id: u64) -> ServerMessage {
+        Self::init_message(&self.state.lock().unwrap(), conn_id)
+    }
+
+    fn init_message(state: &RoomState, conn_id: u64) -> ServerMessage {
+        ServerMessage::Init {
+            conn_id,
+            version: state.version,
+            doc: state.doc.clone(),
+            read_only: state.read_only,
+            peers: state.peers.values().cloned().collect(),
+        }
+    }
+
+    pub fn leave(&self, conn_id: u64) {
+        self.state.lock().unwrap().peers.remove(&conn_id);
+        self.broadcast_presence();
+    }
+
+    /// Handle a client message; returns a reply meant only for that client
+    pub fn handle(&self, conn_id: u64, message: ClientMessage) -> Option<ServerMessage> {
+        match message {
+            ClientMessage::Steps {
+                version,
+                client_id,
+                steps,
+                doc,
+            } => self.receive_steps(conn_id, version, client_id, steps, doc),
+            ClientMessage::Presence { state, cursor } => {
+                if let Some(peer) = self.state.lock().unwrap().peers.get_mut(&conn_id) {
+                    peer.state = state;
+                    peer.cursor = cursor;
+                }
+                self.broadcast_presence();
+                None
+            }
+        }
+    }
+
+    fn receive_steps(
+        &self,
+        conn_id: u64,
+        version: u64,
+        client_id: String,
+        steps: Vec<Value>,
+        doc: Value,
+    ) -> Option<ServerMessage> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.read_only {
+            return Some(ServerMessage::Error {
+                message: "This experiment is signed and locked".to_string(),
+            });
+        }
+
+        if version != state.version {
+            // The steps it is missing are already on their way through the broadcast;
+            // the client rebases on them and resends. Versions we cannot account
+            // for mean it has to reload.
+            let oldest = state.version - state.steps.len() as u64;
+            if version < oldest || version > state.version {
+                return Some(Self::init_message(&state, conn_id));
+            }
+            return Some(ServerMessage::Conflict {
+                version: state.version,
+            });
+        }
+
+        if steps.is_empty() {
+            return None;
+        }
+        if !is_document(&doc) {
+            return Some(ServerMessage::Error {
+                message: "Steps must come with the resulting ProseMirror document".to_string(),
+            });
+        }
+
+        for step in &steps {
+            state.steps.push_back((step.clone(), client_id.clone()));
+        }
+        while state.steps.len() > STEP_HISTORY {
+            state.steps.pop_front();
+        }
+        state.version += steps.len() as u64;
+        state.doc = doc;
+        state.dirty = true;
+        state.last_change = Instant::now();
+        state.last_editor = state.peers.get(&conn_id).map(|p| p.user.clone());
+
+        let message = ServerMessage::Steps {
+            version: state.version,
+            client_ids: vec![client_id; steps.len()],
+            steps,
+        };
+        drop(state);
+
+        let _ = self.tx.send(message.to_json().into());
+        None
+    }
+
+    /// Edits not yet written to `Experiment.content` (or being written)
+    pub fn has_unsaved_changes(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.dirty || state.saving
+    }
+
+    /// `content` was written to `Experiment.content` outside the room: peers
+    /// reload it as `doc`. Steps based on the old document are answered with a
+    /// fresh init.
+    ///
+    /// A room that got edited in the meantime is left alone; its next save
+    /// finds the content changed and turns it read-only.
+    pub fn replace_content(&self, doc: Value, content: String) {
+        let message = {
+            let mut state = self.state.lock().unwrap();
+            if state.dirty || state.saving || state.read_only {
+                return;
+            }
+            state.version += 1;
+            state.steps.clear();
+            state.doc = doc.clone();
+            state.saved_content = content;
+            ServerMessage::Reset {
+                version: state.version,
+                doc,
+            }
+        };
+        let _ = self.tx.send(message.to_json().into());
+    }
+
+    /// A save of `version` wrote `content`; the room is clean unless edited since
+    pub fn saved(&self, version: u64, content: String) {
+        let mut state = self.state.lock().unwrap();
+        state.saving = false;
+        state.retry_at = None;
+        state.saved_content = content;
+        if state.version == version {
+            state.dirty = false;
+        }
+    }
+
+    /// A save failed; the document stays dirty and is saved again after `retry_after`
+    pub fn save_failed(&self, retry_after: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.saving = false;
+        state.retry_at = Some(Instant::now() + retry_after);
+    }
+
+    /// Stop accepting edits (e.g. the experiment got signed) and tell everyone
+    pub fn set_read_only(&self, message: &str) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.read_only = true;
+            state.dirty = false;
+            state.saving = false;
+        }
+        let error = ServerMessage::Error {
+            message: message.to_string(),
+        };
+        let _ = self.tx.send(error.to_json().into());
+    }
+
+    fn broadcast_presence(&self) {
+        let peers = self.state.lock().unwrap().peers.values().cloned().collect();
+        let _ = self.tx.send(ServerMessage::Presence { peers }.to_json().into());
+    }
+}
+
+/// A ProseMirror document node: `{ "type": "doc", "content": [...] }`
+fn is_document(doc: &Value) -> bool {
+    doc.get("type").and_then(Value::as_str) == Some("doc")
+        && doc.get("content").is_none_or(Value::is_array)
+}
+
+/// All open editing rooms
+#[derive(Default)]
+pub struct CollabHub {
+    rooms: Mutex<HashMap<String, Arc<Room>>>,
+    next_conn_id: AtomicU64,
+}
+
+impl CollabHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the open room for an experiment
+    pub fn room(&self, experiment_id: &str) -> Option<Arc<Room>> {
+        self.rooms.lock().unwrap().get(experiment_id).cloned()
+    }
+
+    /// Take a room out of service: connected peers keep it until they leave,
+    /// the next to join opens a fresh room from the stored document
+    pub fn close(&self, experiment_id: &str) -> Option<Arc<Room>> {
+        self.rooms.lock().unwrap().remove(experiment_id)
+    }
+
+    /// Join the room for an experiment, opening it with `doc` (stored as
+    /// `content`) if nobody has it open.
+    ///
+    /// Returns the room, the new connection's ID, its initial state and a
+    /// receiver for room broadcasts.
+    pub fn join(
+        &self,
+        experiment_id: &str,
+        user: String,
+        doc: Value,
+        content: String,
+        read_only: bool,
+    ) -> (Arc<Room>, u64, ServerMessage, broadcast::Receiver<Arc<str>>) {
+        let conn_id = self.next_conn_id.fetch_add(1, Ordering::Relaxed);
+
+        // Connect while holding the room map so the room cannot be closed in between
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms
+            .entry(experiment_id.to_string())
+            .or_insert_with(|| {
+                Arc::new(Room::new(experiment_id.to_string(), doc, content, read_only))
+            })
+            .clone();
+        let (init, rx) = room.connect(conn_id, user);
+        drop(rooms);
+
+        room.broadcast_presence();
+        (room, conn_id, init, rx)
+    }
+
+    /// Collect documents that are ready to persist: changed and either idle for
+    /// `idle` or abandoned by every peer. Empty, saved rooms are closed.
+    ///
+    /// Rooms stay dirty until the save is reported with [`Room::saved`], so a
+    /// failed save is retried rather than lost.
+    pub fn take_pending(&self, idle: Duration) -> Vec<PendingSave> {
+        self.take(Some(idle))
+    }
+
+    /// Collect every changed document that is not being saved, however
+    /// recently it changed or failed to save: the last save before shutdown
+    pub fn take_unsaved(&self) -> Vec<PendingSave> {
+        self.take(None)
+    }
+
+    /// `idle: None` takes documents without waiting for the room to go quiet
+    /// or for a failed save's retry
+    fn take(&self, idle: Option<Duration>) -> Vec<PendingSave> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let mut pending = vec![];
+        let now = Instant::now();
+
+        rooms.retain(|id, room| {
+            let mut state = room.state.lock().unwrap();
+            let abandoned = state.peers.is_empty();
+            let ready =
+                !state.saving && (idle.is_none() || state.retry_at.is_none_or(|at| now >= at));
+            let quiet = idle.is_none_or(|idle| abandoned || state.last_change.elapsed() >= idle);
+
+            if state.dirty && ready && quiet {
+                state.saving = true;
+                pending.push(PendingSave {
+                    experiment_id: id.clone(),
+                    version: state.version,
+                    content: serde_json::to_string(&state.doc).unwrap_or_default(),
+                    base_content: state.saved_content.clone(),
+                    author: state.last_editor.clone(),
+                });
+            }
+
+            // Keep the room while someone is connected or a save is still pending
+            !abandoned || state.dirty
+        });
+
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn doc(text: &str) -> Value {
+        let paragraph = json!({ "type": "paragraph", "content": [{ "type": "text", "text": text }] });
+        json!({ "type": "doc", "content": [paragraph] })
+    }
+
+    fn steps(version: u64, doc: Value) -> ClientMessage {
+        ClientMessage::Steps {
+            version,
+            client_id: "c1".to_string(),
+            steps: vec![json!({ "stepType": "replace" })],
+            doc,
+        }
+    }
+
+    fn open_room() -> (Room, broadcast::Receiver<Arc<str>>) {
+        let room = Room::new("exp".to_string(), doc("a"), String::new(), false);
+        let (_, rx) = room.connect(1, "Ana".to_string());
+        (room, rx)
+    }
+
+    #[test]
+    fn steps_without_a_document_are_refused() {
+        let (room, _rx) = open_room();
+        for bad in [
+            json!(null),
+            json!("text"),
+            json!({ "type": "paragraph" }),
+            json!({ "type": "doc", "content": {} }),
+        ] {
+            let reply = room.handle(1, steps(0, bad));
+            assert!(matches!(reply, Some(ServerMessage::Error { .. })));
+        }
+        assert!(!room.has_unsaved_changes());
+        assert_eq!(room.state.lock().unwrap().version, 0);
+    }
+
+    #[test]
+    fn rest_writes_reload_a_clean_room() {
+        let (room, mut rx) = open_room();
+        room.replace_content(doc("b"), "stored".to_string());
+
+        let reset: Value = serde_json::from_str(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(reset["type"], "reset");
+        assert_eq!(reset["version"], 1);
+        assert_eq!(reset["doc"], doc("b"));
+        assert_eq!(room.state.lock().unwrap().saved_content, "stored");
+
+        // Steps made on the replaced document have to reload it
+        let reply = room.handle(1, steps(0, doc("c")));
+        assert!(matches!(
+            reply,
+            Some(ServerMessage::Init { version: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn rest_writes_leave_unsaved_edits_alone() {
+        let (room, _rx) = open_room();
+        assert!(room.handle(1, steps(0, doc("c"))).is_none());
+        assert!(room.has_unsaved_changes());
+
+        room.replace_content(doc("b"), "stored".to_string());
+        let state = room.state.lock().unwrap();
+        assert_eq!(state.doc, doc("c"));
+        assert_eq!(state.saved_content, "");
+    }
+}
@@ -26,6 +26,50 @@ fn get_migrations() -> Vec<Migration> {
             name: "20260128081537_refactor_experiment_notebook".to_string(),
             sql: include_str!("../../../../database/migrations/20260128081537_refactor_experiment_notebook/migration.sql"),
         },
+        Migration {
+            name: "20261018090000_experiment_status_transitions".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018090000_experiment_status_transitions/migration.sql"),
+        },
+        Migration {
+            name: "20261018100000_experiment_revisions".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018100000_experiment_revisions/migration.sql"),
+        },
+        Migration {
+            name: "20261018110000_experiment_signatures".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018110000_experiment_signatures/migration.sql"),
+        },
+        Migration {
+            name: "20261018120000_experiment_templates".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018120000_experiment_templates/migration.sql"),
+        },
+        Migration {
+            name: "20261018130000_equipment_bookings".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018130000_equipment_bookings/migration.sql"),
+        },
+        Migration {
+            name: "20261018140000_equipment_maintenance".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018140000_equipment_maintenance/migration.sql"),
+        },
+        Migration {
+            name: "20261018150000_agent_heartbeat".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018150000_agent_heartbeat/migration.sql"),
+        },
+        Migration {
+            name: "20261018160000_agent_config".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018160000_agent_config/migration.sql"),
+        },
+        Migration {
+            name: "20261018170000_asset_metadata".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018170000_asset_metadata/migration.sql"),
+        },
+        Migration {
+            name: "20261018180000_sample_matching".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018180000_sample_matching/migration.sql"),
+        },
+        Migration {
+            name: "20261018190000_agent_api_keys".to_string(),
+            sql: include_str!("../../../../database/migrations/20261018190000_agent_api_keys/migration.sql"),
+        },
     ]
 }
 
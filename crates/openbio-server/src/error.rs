@@ -0,0 +1,139 @@
+//! API error type
+//!
+//! Handlers return `Result<Json<T>, ApiError>` so failures map to a proper
+//! HTTP status with a `{ "error": "..." }` body instead of panicking.
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use prisma_client_rust::{
+    prisma_errors::query_engine::{RecordNotFound, UniqueKeyViolation},
+    QueryError,
+};
+
+/// Errors returned by API handlers
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0} not found")]
+    NotFound(String),
+
+    #[error("{0}")]
+    BadRequest(String),
+
+    /// Missing or invalid credentials
+    #[error("{0}")]
+    Unauthorized(String),
+
+    /// Valid credentials that do not allow the request
+    #[error("{0}")]
+    Forbidden(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    #[error("{0}")]
+    Unprocessable(String),
+
+    /// `If-Match` did not match; carries the current server version of the record
+    #[error("{message}")]
+    PreconditionFailed {
+        message: String,
+        etag: String,
+        current: serde_json::Value,
+    },
+
+    #[error("{0}")]
+    PreconditionRequired(String),
+
+    #[error("Database error: {0}")]
+    Database(QueryError),
+
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+/// Result type for JSON API handlers
+pub type ApiResult<T> = Result<Json<T>, ApiError>;
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            ApiError::PreconditionRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<QueryError> for ApiError {
+    fn from(e: QueryError) -> Self {
+        if e.is_prisma_error::<RecordNotFound>() {
+            ApiError::NotFound("Record".to_string())
+        } else if e.is_prisma_error::<UniqueKeyViolation>() {
+            ApiError::Conflict("A record with this unique key already exists".to_string())
+        } else {
+            ApiError::Database(e)
+        }
+    }
+}
+
+impl From<openbio_core::Error> for ApiError {
+    fn from(e: openbio_core::Error) -> Self {
+        match e {
+            openbio_core::Error::NotFound(what) => ApiError::NotFound(what),
+            openbio_core::Error::Validation(msg) => ApiError::Unprocessable(msg),
+            openbio_core::Error::Config(e) => ApiError::BadRequest(e.to_string()),
+            other => ApiError::Internal(other.to_string()),
+        }
+    }
+}
+
+impl From<crate::assets::UploadError> for ApiError {
+    fn from(e: crate::assets::UploadError) -> Self {
+        use crate::assets::UploadError;
+
+        match e {
+            UploadError::NotFound(id) => ApiError::NotFound(format!("Upload {}", id)),
+            UploadError::OffsetMismatch { .. } | UploadError::Incomplete { .. } => {
+                ApiError::Conflict(e.to_string())
+            }
+            UploadError::TooLarge(_) | UploadError::ChecksumMismatch { .. } => {
+                ApiError::Unprocessable(e.to_string())
+            }
+            UploadError::Storage(_) | UploadError::Io(_) => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("{}", self);
+        }
+
+        if let ApiError::PreconditionFailed {
+            message,
+            etag,
+            current,
+        } = self
+        {
+            let body = serde_json::json!({ "error": message, "current": current });
+            return (status, [(header::ETAG, etag)], Json(body)).into_response();
+        }
+        if let ApiError::Unauthorized(message) = &self {
+            let body = serde_json::json!({ "error": message });
+            return (status, [(header::WWW_AUTHENTICATE, "Bearer")], Json(body)).into_response();
+        }
+
+        (status, Json(serde_json::json!({ "error": self.to_string() }))).into_response()
+    }
+}
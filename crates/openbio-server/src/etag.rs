@@ -0,0 +1,67 @@
+//! ETags for optimistic concurrency
+//!
+//! A record's ETag is its `updatedAt` in epoch milliseconds, quoted
+//! (e.g. `"1760781462123"`), so clients can also derive it from the JSON body.
+//! PATCH handlers require a matching `If-Match` header and answer
+//! `412 Precondition Failed` with the current server version otherwise.
+
+use axum::{
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use prisma_client_rust::chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+
+use crate::ApiError;
+
+/// ETag for a record last updated at `updated_at`
+pub fn etag_for(updated_at: &DateTime<FixedOffset>) -> String {
+    format!("\"{}\"", updated_at.timestamp_millis())
+}
+
+/// JSON response carrying an `ETag` header
+pub fn with_etag<T: Serialize>(data: T, updated_at: &DateTime<FixedOffset>) -> Response {
+    ([(header::ETAG, etag_for(updated_at))], Json(data)).into_response()
+}
+
+/// Check the request's `If-Match` header against the current version of a record.
+///
+/// Returns 428 if the header is missing and 412 (with `current` in the body) if it
+/// does not match. `*` matches any existing record.
+pub fn check_if_match<T: Serialize>(
+    headers: &HeaderMap,
+    current: &T,
+    updated_at: &DateTime<FixedOffset>,
+) -> Result<(), ApiError> {
+    let if_match = headers
+        .get(header::IF_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| {
+            ApiError::PreconditionRequired("Updates require an If-Match header".to_string())
+        })?;
+
+    let etag = etag_for(updated_at);
+    let matches = if_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag);
+
+    if matches {
+        Ok(())
+    } else {
+        Err(precondition_failed(current, updated_at))
+    }
+}
+
+/// 412 error carrying the current server version of a record
+pub fn precondition_failed<T: Serialize>(
+    current: &T,
+    updated_at: &DateTime<FixedOffset>,
+) -> ApiError {
+    ApiError::PreconditionFailed {
+        message: "The record was modified by someone else".to_string(),
+        etag: etag_for(updated_at),
+        current: serde_json::to_value(current).unwrap_or_default(),
+    }
+}
@@ -0,0 +1,115 @@
+//! Data change notifications
+//!
+//! Handlers publish a [`ChangeEvent`] after every write to samples,
+//! experiments (and what hangs off them: entries, sample links, mentions,
+//! signatures, revisions), equipment and assets, so connected clients (web UI,
+//! Spoke apps) can invalidate their caches instead of polling. Containers,
+//! papers and templates have no events yet.
+//! Events are streamed to clients over SSE from `/api/events`.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// A change to data on the server
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChangeEvent {
+    SampleCreated {
+        id: String,
+    },
+    SampleUpdated {
+        id: String,
+    },
+    SampleDeleted {
+        id: String,
+    },
+    ExperimentCreated {
+        id: String,
+    },
+    ExperimentUpdated {
+        id: String,
+    },
+    ExperimentStatusChanged {
+        id: String,
+        from: String,
+        to: String,
+    },
+    ExperimentDeleted {
+        id: String,
+    },
+    /// An instrument's settings, maintenance or agent keys changed
+    EquipmentUpdated {
+        id: String,
+    },
+    AssetIngested {
+        id: String,
+        experiment_id: Option<String>,
+        machine_id: Option<String>,
+    },
+    /// An asset was linked to a sample (or unlinked, with `sample_id: None`)
+    AssetSampleLinked {
+        id: String,
+        sample_id: Option<String>,
+    },
+    AgentOnline {
+        equipment_id: String,
+    },
+    AgentOffline {
+        equipment_id: String,
+    },
+    AgentLocked {
+        equipment_id: String,
+    },
+    AgentUnlocked {
+        equipment_id: String,
+    },
+}
+
+impl ChangeEvent {
+    /// Topic clients filter on (also used as the SSE event name)
+    pub fn topic(&self) -> &'static str {
+        match self {
+            ChangeEvent::SampleCreated { .. }
+            | ChangeEvent::SampleUpdated { .. }
+            | ChangeEvent::SampleDeleted { .. } => "samples",
+            ChangeEvent::ExperimentCreated { .. }
+            | ChangeEvent::ExperimentUpdated { .. }
+            | ChangeEvent::ExperimentStatusChanged { .. }
+            | ChangeEvent::ExperimentDeleted { .. } => "experiments",
+            ChangeEvent::EquipmentUpdated { .. } => "equipment",
+            ChangeEvent::AssetIngested { .. } | ChangeEvent::AssetSampleLinked { .. } => "assets",
+            ChangeEvent::AgentOnline { .. }
+            | ChangeEvent::AgentOffline { .. }
+            | ChangeEvent::AgentLocked { .. }
+            | ChangeEvent::AgentUnlocked { .. } => "agents",
+        }
+    }
+}
+
+/// Broadcast channel for [`ChangeEvent`]s
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<ChangeEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(1024);
+        Self { tx }
+    }
+
+    /// Publish an event to every subscriber (dropped if nobody listens)
+    pub fn publish(&self, event: ChangeEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChangeEvent> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,928 @@
+//! Experiment reports for collaborators and auditors
+//!
+//! A [`Report`] gathers everything needed to review a notebook outside the app:
+//! the content, its entries, mention snapshots, linked samples, attached assets
+//! and the signing state. It renders to Markdown, a self-contained HTML page
+//! (inline styles, no external resources) or a PDF laid out from its structure.
+//! PDFs embed the DejaVu fonts (see `assets/fonts`) so text in any script they
+//! cover is kept, not just Latin.
+
+use std::str::FromStr;
+
+use openbio_core::notebook::{escape_html, flatten_blocks, to_html, to_markdown};
+use printpdf::{Line, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference, Point};
+use serde_json::Value;
+
+use crate::ApiError;
+
+/// Requested export format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Pdf,
+    Html,
+    Markdown,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Pdf => "application/pdf",
+            ExportFormat::Html => "text/html; charset=utf-8",
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Pdf => "pdf",
+            ExportFormat::Html => "html",
+            ExportFormat::Markdown => "md",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pdf" => Ok(ExportFormat::Pdf),
+            "html" => Ok(ExportFormat::Html),
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            other => Err(ApiError::BadRequest(format!(
+                "Unknown export format: {} (expected pdf, html or md)",
+                other
+            ))),
+        }
+    }
+}
+
+pub struct ReportSignature {
+    pub role: String,
+    pub signer: String,
+    pub meaning: String,
+    pub signed_at: String,
+    pub content_hash: String,
+}
+
+pub struct ReportEntry {
+    pub timestamp: String,
+    pub author: Option<String>,
+    pub content: String,
+    pub is_amendment: bool,
+    pub signed_by: Option<String>,
+    pub signature_meaning: Option<String>,
+}
+
+pub struct ReportMention {
+    pub entity_type: String,
+    pub entity_id: String,
+    /// `ExperimentMention.snapshotData`, as captured when the mention was made
+    pub snapshot: Value,
+}
+
+pub struct ReportSample {
+    pub id: String,
+    pub name: String,
+    pub type_: String,
+    pub role: Option<String>,
+    pub location: Option<String>,
+}
+
+pub struct ReportAsset {
+    pub filename: String,
+    pub asset_type: String,
+    pub mime_type: Option<String>,
+    pub size_bytes: Option<i32>,
+    pub checksum: Option<String>,
+    pub machine_id: Option<String>,
+    pub created_at: String,
+}
+
+/// Everything that goes into an experiment export
+pub struct Report {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub status: String,
+    pub created_by: Option<String>,
+    pub created_at: String,
+    pub locked_at: Option<String>,
+    /// Hash of the record as it is now (same scheme as signatures)
+    pub content_hash: String,
+    pub generated_at: String,
+    pub content: String,
+    pub signatures: Vec<ReportSignature>,
+    pub entries: Vec<ReportEntry>,
+    pub mentions: Vec<ReportMention>,
+    pub samples: Vec<ReportSample>,
+    pub assets: Vec<ReportAsset>,
+}
+
+impl Report {
+    pub fn render(&self, format: ExportFormat) -> Result<Vec<u8>, ApiError> {
+        match format {
+            ExportFormat::Markdown => Ok(self.to_markdown().into_bytes()),
+            ExportFormat::Html => Ok(self.to_html().into_bytes()),
+            ExportFormat::Pdf => self.to_pdf(),
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = format!("# {}\n\n", self.name);
+
+        if let Some(description) = &self.description {
+            md.push_str(&format!("{}\n\n", description));
+        }
+
+        md.push_str(&format!("- Status: {}\n", self.status));
+        md.push_str(&format!(
+            "- Created: {} by {}\n",
+            self.created_at,
+            self.created_by.as_deref().unwrap_or("unknown")
+        ));
+        match &self.locked_at {
+            Some(locked_at) => md.push_str(&format!("- Locked: {}\n", locked_at)),
+            None => md.push_str("- Locked: no\n"),
+        }
+        md.push_str(&format!("- Content hash (SHA-256): {}\n", self.content_hash));
+        md.push_str(&format!("- Experiment ID: {}\n", self.id));
+        md.push_str(&format!("- Exported: {}\n\n", self.generated_at));
+
+        md.push_str("## Signatures\n\n");
+        if self.signatures.is_empty() {
+            md.push_str("Not signed.\n\n");
+        }
+        for s in &self.signatures {
+            md.push_str(&format!(
+                "- {}: {} at {} ({}), hash {}{}\n",
+                s.role,
+                s.signer,
+                s.signed_at,
+                s.meaning,
+                s.content_hash,
+                self.hash_note(&s.content_hash)
+            ));
+        }
+        if !self.signatures.is_empty() {
+            md.push('\n');
+        }
+
+        md.push_str("## Notebook\n\n");
+        let content = to_markdown(&self.content);
+        md.push_str(if content.is_empty() { "(empty)" } else { &content });
+        md.push_str("\n\n");
+
+        md.push_str("## Entries\n\n");
+        if self.entries.is_empty() {
+            md.push_str("No entries.\n\n");
+        }
+        for entry in &self.entries {
+            md.push_str(&format!(
+                "### {}{}{}\n\n",
+                entry.timestamp,
+                entry.author.as_ref().map(|a| format!(" — {}", a)).unwrap_or_default(),
+                if entry.is_amendment { " (amendment)" } else { "" }
+            ));
+            md.push_str(&to_markdown(&entry.content));
+            md.push_str("\n\n");
+            if let Some(signed_by) = &entry.signed_by {
+                md.push_str(&format!(
+                    "Signed by {}: {}\n\n",
+                    signed_by,
+                    entry.signature_meaning.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+
+        md.push_str("## Samples\n\n");
+        if self.samples.is_empty() {
+            md.push_str("No linked samples.\n\n");
+        }
+        for sample in &self.samples {
+            md.push_str(&format!(
+                "- {} ({}), role: {}, location: {} [{}]\n",
+                sample.name,
+                sample.type_,
+                sample.role.as_deref().unwrap_or("-"),
+                sample.location.as_deref().unwrap_or("-"),
+                sample.id
+            ));
+        }
+        if !self.samples.is_empty() {
+            md.push('\n');
+        }
+
+        md.push_str("## Mentions\n\n");
+        if self.mentions.is_empty() {
+            md.push_str("No mentions.\n\n");
+        }
+        for mention in &self.mentions {
+            md.push_str(&format!(
+                "- {} {} [{}]\n",
+                mention.entity_type,
+                snapshot_label(&mention.snapshot).unwrap_or(&mention.entity_id),
+                mention.entity_id
+            ));
+            for (key, value) in snapshot_fields(&mention.snapshot) {
+                md.push_str(&format!("  - {}: {}\n", key, value));
+            }
+        }
+        if !self.mentions.is_empty() {
+            md.push('\n');
+        }
+
+        md.push_str("## Assets\n\n");
+        if self.assets.is_empty() {
+            md.push_str("No attached assets.\n");
+        }
+        for asset in &self.assets {
+            md.push_str(&format!(
+                "- {} ({}, {}, {}) from {} at {}, SHA-256 {}\n",
+                asset.filename,
+                asset.asset_type,
+                asset.mime_type.as_deref().unwrap_or("unknown type"),
+                asset.size_bytes.map(format_size).unwrap_or_else(|| "unknown size".to_string()),
+                asset.machine_id.as_deref().unwrap_or("unknown machine"),
+                asset.created_at,
+                asset.checksum.as_deref().unwrap_or("-")
+            ));
+        }
+
+        md
+    }
+
+    pub fn to_html(&self) -> String {
+        let e = escape_html;
+        let mut body = format!("<h1>{}</h1>\n", e(&self.name));
+
+        if let Some(description) = &self.description {
+            body.push_str(&format!("<p class=\"description\">{}</p>\n", e(description)));
+        }
+
+        body.push_str("<table class=\"meta\">\n");
+        let mut meta_row = |label: &str, value: &str| {
+            body.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", label, e(value)));
+        };
+        meta_row("Status", &self.status);
+        meta_row(
+            "Created",
+            &format!(
+                "{} by {}",
+                self.created_at,
+                self.created_by.as_deref().unwrap_or("unknown")
+            ),
+        );
+        meta_row("Locked", self.locked_at.as_deref().unwrap_or("no"));
+        meta_row("Content hash (SHA-256)", &self.content_hash);
+        meta_row("Experiment ID", &self.id);
+        meta_row("Exported", &self.generated_at);
+        body.push_str("</table>\n");
+
+        body.push_str("<h2>Signatures</h2>\n");
+        if self.signatures.is_empty() {
+            body.push_str("<p class=\"empty\">Not signed.</p>\n");
+        } else {
+            body.push_str(
+                "<table><tr><th>Role</th><th>Signer</th><th>Meaning</th><th>Signed</th><th>Hash</th></tr>\n",
+            );
+            for s in &self.signatures {
+                body.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"hash\">{}{}</td></tr>\n",
+                    e(&s.role),
+                    e(&s.signer),
+                    e(&s.meaning),
+                    e(&s.signed_at),
+                    e(&s.content_hash),
+                    e(self.hash_note(&s.content_hash))
+                ));
+            }
+            body.push_str("</table>\n");
+        }
+
+        body.push_str("<h2>Notebook</h2>\n<div class=\"notebook\">\n");
+        body.push_str(&to_html(&self.content));
+        body.push_str("</div>\n");
+
+        body.push_str("<h2>Entries</h2>\n");
+        if self.entries.is_empty() {
+            body.push_str("<p class=\"empty\">No entries.</p>\n");
+        }
+        for entry in &self.entries {
+            body.push_str(&format!(
+                "<div class=\"entry{}\"><div class=\"entry-meta\">{}{}{}</div>\n",
+                if entry.is_amendment { " amendment" } else { "" },
+                e(&entry.timestamp),
+                entry.author.as_ref().map(|a| format!(" — {}", e(a))).unwrap_or_default(),
+                if entry.is_amendment { " (amendment)" } else { "" }
+            ));
+            body.push_str(&to_html(&entry.content));
+            if let Some(signed_by) = &entry.signed_by {
+                body.push_str(&format!(
+                    "<p class=\"signed\">Signed by {}: {}</p>\n",
+                    e(signed_by),
+                    e(entry.signature_meaning.as_deref().unwrap_or_default())
+                ));
+            }
+            body.push_str("</div>\n");
+        }
+
+        body.push_str("<h2>Samples</h2>\n");
+        if self.samples.is_empty() {
+            body.push_str("<p class=\"empty\">No linked samples.</p>\n");
+        } else {
+            body.push_str("<table><tr><th>Name</th><th>Type</th><th>Role</th><th>Location</th><th>ID</th></tr>\n");
+            for sample in &self.samples {
+                body.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    e(&sample.name),
+                    e(&sample.type_),
+                    e(sample.role.as_deref().unwrap_or("-")),
+                    e(sample.location.as_deref().unwrap_or("-")),
+                    e(&sample.id)
+                ));
+            }
+            body.push_str("</table>\n");
+        }
+
+        body.push_str("<h2>Mentions</h2>\n");
+        if self.mentions.is_empty() {
+            body.push_str("<p class=\"empty\">No mentions.</p>\n");
+        }
+        for mention in &self.mentions {
+            body.push_str(&format!(
+                "<div class=\"mention-snapshot\"><strong>{} {}</strong> <span class=\"id\">{}</span>\n<dl>",
+                e(&mention.entity_type),
+                e(snapshot_label(&mention.snapshot).unwrap_or(&mention.entity_id)),
+                e(&mention.entity_id)
+            ));
+            for (key, value) in snapshot_fields(&mention.snapshot) {
+                body.push_str(&format!("<dt>{}</dt><dd>{}</dd>", e(&key), e(&value)));
+            }
+            body.push_str("</dl></div>\n");
+        }
+
+        body.push_str("<h2>Assets</h2>\n");
+        if self.assets.is_empty() {
+            body.push_str("<p class=\"empty\">No attached assets.</p>\n");
+        } else {
+            body.push_str("<table><tr><th>File</th><th>Type</th><th>Size</th><th>Machine</th><th>Added</th><th>SHA-256</th></tr>\n");
+            for asset in &self.assets {
+                body.push_str(&format!(
+                    "<tr><td>{}</td><td>{} {}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"hash\">{}</td></tr>\n",
+                    e(&asset.filename),
+                    e(&asset.asset_type),
+                    e(asset.mime_type.as_deref().unwrap_or_default()),
+                    asset.size_bytes.map(format_size).unwrap_or_default(),
+                    e(asset.machine_id.as_deref().unwrap_or_default()),
+                    e(&asset.created_at),
+                    e(asset.checksum.as_deref().unwrap_or_default())
+                ));
+            }
+            body.push_str("</table>\n");
+        }
+
+        format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+            e(&self.name),
+            REPORT_CSS,
+            body
+        )
+    }
+
+    /// Lay the report out on A4 pages.
+    ///
+    /// The layout is built from the report's fields and the notebook's blocks,
+    /// so no Markdown syntax ends up on the page. The PDF base fonts only cover
+    /// Latin-1, so the fonts are embedded: DejaVu Sans for text and headings,
+    /// DejaVu Sans Mono for code blocks (only added when there are any, each
+    /// embedded font adds its whole file to the PDF).
+    pub fn to_pdf(&self) -> Result<Vec<u8>, ApiError> {
+        let lines = self.pdf_lines();
+        let (doc, page, layer) = PdfDocument::new(&self.name, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Report");
+        let regular = doc.add_external_font(SANS_FONT).map_err(pdf_error)?;
+        let mono = if lines.iter().any(|line| matches!(line, PdfLine::Code(..))) {
+            doc.add_external_font(MONO_FONT).map_err(pdf_error)?
+        } else {
+            regular.clone()
+        };
+
+        let mut pdf = PdfPages {
+            layer: doc.get_page(page).get_layer(layer),
+            y: PAGE_HEIGHT - MARGIN,
+        };
+
+        for line in &lines {
+            let (indent, text, font, size) = match line {
+                PdfLine::Heading(level, text) => {
+                    let size = match level {
+                        1 => 18.0,
+                        2 => 14.0,
+                        _ => 12.0,
+                    };
+                    pdf.advance(&doc, size * 0.5);
+                    (0, text, &regular, size)
+                }
+                PdfLine::Text(indent, text) => (*indent, text, &regular, 10.0),
+                PdfLine::Code(indent, text) => (*indent, text, &mono, 9.0),
+                PdfLine::Rule => {
+                    pdf.advance(&doc, 10.0);
+                    pdf.layer.add_line(Line {
+                        points: vec![
+                            (Point::new(Mm(MARGIN), Mm(pdf.y)), false),
+                            (Point::new(Mm(PAGE_WIDTH - MARGIN), Mm(pdf.y)), false),
+                        ],
+                        is_closed: false,
+                    });
+                    continue;
+                }
+                PdfLine::Gap => {
+                    pdf.advance(&doc, 5.0);
+                    continue;
+                }
+            };
+
+            // An average DejaVu glyph is a bit over half as wide as it is tall
+            let x = MARGIN + indent as f32 * INDENT;
+            let max_chars = ((PAGE_WIDTH - MARGIN - x) / (size * PT_TO_MM * 0.6)) as usize;
+            for chunk in wrap(text, max_chars) {
+                pdf.advance(&doc, size * 1.3);
+                pdf.layer.use_text(chunk, size, Mm(x), Mm(pdf.y), font);
+            }
+        }
+
+        doc.save_to_bytes().map_err(pdf_error)
+    }
+
+    /// The report as PDF lines, in the same order as the other formats
+    fn pdf_lines(&self) -> Vec<PdfLine> {
+        let text = |indent: usize, text: String| PdfLine::Text(indent, text);
+        let mut lines = vec![PdfLine::Heading(1, self.name.clone())];
+
+        if let Some(description) = &self.description {
+            lines.push(text(0, plain_text(description)));
+        }
+        lines.push(PdfLine::Gap);
+
+        lines.push(text(0, format!("Status: {}", self.status)));
+        lines.push(text(
+            0,
+            format!(
+                "Created: {} by {}",
+                self.created_at,
+                self.created_by.as_deref().unwrap_or("unknown")
+            ),
+        ));
+        lines.push(text(0, format!("Locked: {}", self.locked_at.as_deref().unwrap_or("no"))));
+        lines.push(text(0, format!("Content hash (SHA-256): {}", self.content_hash)));
+        lines.push(text(0, format!("Experiment ID: {}", self.id)));
+        lines.push(text(0, format!("Exported: {}", self.generated_at)));
+
+        lines.push(PdfLine::Heading(2, "Signatures".to_string()));
+        if self.signatures.is_empty() {
+            lines.push(text(0, "Not signed.".to_string()));
+        }
+        for s in &self.signatures {
+            lines.push(text(
+                0,
+                format!(
+                    "{}: {} at {} ({}), hash {}{}",
+                    s.role,
+                    s.signer,
+                    s.signed_at,
+                    s.meaning,
+                    s.content_hash,
+                    self.hash_note(&s.content_hash)
+                ),
+            ));
+        }
+
+        lines.push(PdfLine::Heading(2, "Notebook".to_string()));
+        if self.content.trim().is_empty() {
+            lines.push(text(0, "(empty)".to_string()));
+        }
+        notebook_lines(&self.content, &mut lines);
+
+        lines.push(PdfLine::Heading(2, "Entries".to_string()));
+        if self.entries.is_empty() {
+            lines.push(text(0, "No entries.".to_string()));
+        }
+        for entry in &self.entries {
+            lines.push(PdfLine::Heading(
+                3,
+                format!(
+                    "{}{}{}",
+                    entry.timestamp,
+                    entry.author.as_ref().map(|a| format!(" — {}", a)).unwrap_or_default(),
+                    if entry.is_amendment { " (amendment)" } else { "" }
+                ),
+            ));
+            notebook_lines(&entry.content, &mut lines);
+            if let Some(signed_by) = &entry.signed_by {
+                lines.push(text(
+                    0,
+                    format!(
+                        "Signed by {}: {}",
+                        signed_by,
+                        entry.signature_meaning.as_deref().unwrap_or_default()
+                    ),
+                ));
+            }
+        }
+
+        lines.push(PdfLine::Heading(2, "Samples".to_string()));
+        if self.samples.is_empty() {
+            lines.push(text(0, "No linked samples.".to_string()));
+        }
+        for sample in &self.samples {
+            lines.push(text(
+                0,
+                format!(
+                    "{} ({}), role: {}, location: {}, ID {}",
+                    sample.name,
+                    sample.type_,
+                    sample.role.as_deref().unwrap_or("-"),
+                    sample.location.as_deref().unwrap_or("-"),
+                    sample.id
+                ),
+            ));
+        }
+
+        lines.push(PdfLine::Heading(2, "Mentions".to_string()));
+        if self.mentions.is_empty() {
+            lines.push(text(0, "No mentions.".to_string()));
+        }
+        for mention in &self.mentions {
+            lines.push(text(
+                0,
+                format!(
+                    "{} {}, ID {}",
+                    mention.entity_type,
+                    snapshot_label(&mention.snapshot).unwrap_or(&mention.entity_id),
+                    mention.entity_id
+                ),
+            ));
+            for (key, value) in snapshot_fields(&mention.snapshot) {
+                lines.push(text(1, format!("{}: {}", key, value)));
+            }
+        }
+
+        lines.push(PdfLine::Heading(2, "Assets".to_string()));
+        if self.assets.is_empty() {
+            lines.push(text(0, "No attached assets.".to_string()));
+        }
+        for asset in &self.assets {
+            lines.push(text(
+                0,
+                format!(
+                    "{} ({}, {}, {}) from {} at {}, SHA-256 {}",
+                    asset.filename,
+                    asset.asset_type,
+                    asset.mime_type.as_deref().unwrap_or("unknown type"),
+                    asset.size_bytes.map(format_size).unwrap_or_else(|| "unknown size".to_string()),
+                    asset.machine_id.as_deref().unwrap_or("unknown machine"),
+                    asset.created_at,
+                    asset.checksum.as_deref().unwrap_or("-")
+                ),
+            ));
+        }
+
+        lines
+    }
+
+    /// Flag signatures whose hash no longer matches the record
+    fn hash_note(&self, signed_hash: &str) -> &'static str {
+        if signed_hash == self.content_hash {
+            ""
+        } else {
+            " (record changed since signing)"
+        }
+    }
+}
+
+const SANS_FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+const MONO_FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSansMono.ttf");
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+const PT_TO_MM: f32 = 0.3528;
+
+/// Indentation step for nested lists, quotes and mention fields
+const INDENT: f32 = 6.0;
+
+/// A line of the PDF layout
+#[derive(Debug, PartialEq)]
+enum PdfLine {
+    /// Heading of `level` 1 (the title), 2 (a section) or 3 (an entry or a
+    /// notebook heading)
+    Heading(u8, String),
+    /// Text indented by the given number of steps
+    Text(usize, String),
+    /// A line of a code block
+    Code(usize, String),
+    /// Horizontal rule
+    Rule,
+    /// Space between blocks
+    Gap,
+}
+
+/// Lay out a notebook document (or a plain text entry) block by block.
+///
+/// Works on [`flatten_blocks`]: block markers become layout (headings, bullets,
+/// indentation for list items and quotes, code lines in the mono font) and
+/// inline Markdown typed into plain text is reduced to its text.
+fn notebook_lines(content: &str, lines: &mut Vec<PdfLine>) {
+    let mut in_code = false;
+    let mut prev_list = false;
+
+    for block in flatten_blocks(content) {
+        let mut rest = block.as_str();
+        let mut indent = 0;
+        while let Some(quoted) = rest.strip_prefix("> ") {
+            rest = quoted;
+            indent += 1;
+        }
+
+        if rest.trim_start() == "```" {
+            if !in_code {
+                lines.push(PdfLine::Gap);
+            }
+            in_code = !in_code;
+            prev_list = false;
+            continue;
+        }
+        if in_code {
+            lines.push(PdfLine::Code(indent, rest.to_string()));
+            continue;
+        }
+
+        // Continuation blocks of list items are indented under their marker
+        let spaces = rest.len() - rest.trim_start_matches(' ').len();
+        let rest = &rest[spaces..];
+        indent += spaces.div_ceil(2);
+
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        let list = spaces > 0
+            || rest.starts_with("- ")
+            || (digits > 0 && rest[digits..].starts_with(". "));
+        if !(list && prev_list) {
+            lines.push(PdfLine::Gap);
+        }
+        prev_list = list;
+
+        let hashes = rest.chars().take_while(|c| *c == '#').count();
+        if let Some(item) = rest.strip_prefix("- ") {
+            lines.push(PdfLine::Text(indent, format!("• {}", plain_text(item))));
+        } else if (1..=6).contains(&hashes) && rest[hashes..].starts_with(' ') {
+            lines.push(PdfLine::Heading(3, plain_text(rest[hashes..].trim_start())));
+        } else if rest == "---" {
+            lines.push(PdfLine::Rule);
+        } else {
+            // Hard breaks inside a block
+            for line in rest.split('\n') {
+                lines.push(PdfLine::Text(indent, plain_text(line)));
+            }
+        }
+    }
+}
+
+/// Text with inline Markdown reduced to what it shows: `**bold**`, `~~strike~~`
+/// and `` `code` `` lose their markers, `[text](url)` becomes `text (url)` and
+/// images their alt text
+fn plain_text(text: &str) -> String {
+    let text = text.replace("**", "").replace("~~", "").replace('`', "");
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+
+    while let Some(open) = rest.find('[') {
+        let link = rest[open + 1..].find("](").and_then(|label_end| {
+            let label = &rest[open + 1..open + 1 + label_end];
+            let target_start = open + 1 + label_end + 2;
+            let target_end = target_start + rest[target_start..].find(')')?;
+            Some((label, &rest[target_start..target_end], target_end + 1))
+        });
+        match link {
+            Some((label, target, end)) => {
+                let image = rest[..open].ends_with('!');
+                plain.push_str(if image { &rest[..open - 1] } else { &rest[..open] });
+                plain.push_str(label);
+                if !image && !target.is_empty() && target != label {
+                    plain.push_str(&format!(" ({})", target));
+                }
+                rest = &rest[end..];
+            }
+            None => {
+                plain.push_str(&rest[..=open]);
+                rest = &rest[open + 1..];
+            }
+        }
+    }
+    plain.push_str(rest);
+    plain
+}
+
+/// Current write position, starting a new page when the bottom margin is reached
+struct PdfPages {
+    layer: PdfLayerReference,
+    y: f32,
+}
+
+impl PdfPages {
+    fn advance(&mut self, doc: &PdfDocumentReference, points: f32) {
+        self.y -= points * PT_TO_MM;
+        if self.y < MARGIN {
+            let (page, layer) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Report");
+            self.layer = doc.get_page(page).get_layer(layer);
+            self.y = PAGE_HEIGHT - MARGIN - points * PT_TO_MM;
+        }
+    }
+}
+
+/// Break a line into chunks of at most `width` characters, preferring spaces
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let width = width.max(10);
+    let mut chunks = vec![];
+    let mut current = String::new();
+
+    for word in line.split(' ') {
+        let mut word = word.to_string();
+        while word.chars().count() > width {
+            if !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+            }
+            let split = word.char_indices().nth(width).map_or(word.len(), |(i, _)| i);
+            chunks.push(word[..split].to_string());
+            word = word[split..].to_string();
+        }
+
+        let needed = current.chars().count() + word.chars().count() + 1;
+        if !current.is_empty() && needed > width {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(&word);
+    }
+    chunks.push(current);
+    chunks
+}
+
+/// Display name from a mention snapshot
+fn snapshot_label(snapshot: &Value) -> Option<&str> {
+    ["name", "title", "label"]
+        .iter()
+        .find_map(|key| snapshot.get(*key).and_then(Value::as_str))
+}
+
+/// Scalar fields of a mention snapshot, for display
+fn snapshot_fields(snapshot: &Value) -> Vec<(String, String)> {
+    let Some(fields) = snapshot.as_object() else {
+        return vec![];
+    };
+    fields
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                Value::Number(n) => n.to_string(),
+                Value::Bool(b) => b.to_string(),
+                _ => return None,
+            };
+            Some((key.clone(), value))
+        })
+        .collect()
+}
+
+fn format_size(bytes: i32) -> String {
+    let bytes = bytes as f64;
+    if bytes >= 1024.0 * 1024.0 * 1024.0 {
+        format!("{:.1} GB", bytes / (1024.0 * 1024.0 * 1024.0))
+    } else if bytes >= 1024.0 * 1024.0 {
+        format!("{:.1} MB", bytes / (1024.0 * 1024.0))
+    } else if bytes >= 1024.0 {
+        format!("{:.1} KB", bytes / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+fn pdf_error(e: printpdf::Error) -> ApiError {
+    ApiError::Internal(format!("Failed to render PDF: {}", e))
+}
+
+const REPORT_CSS: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; max-width: 860px; margin: 2em auto; padding: 0 1em; color: #1f2328; line-height: 1.5; }
+h1 { border-bottom: 2px solid #d0d7de; padding-bottom: .3em; }
+h2 { border-bottom: 1px solid #d0d7de; padding-bottom: .2em; margin-top: 2em; }
+table { border-collapse: collapse; width: 100%; margin: 1em 0; font-size: .9em; }
+th, td { border: 1px solid #d0d7de; padding: .35em .6em; text-align: left; vertical-align: top; }
+table.meta th { width: 14em; background: #f6f8fa; }
+.hash, .id { font-family: monospace; font-size: .85em; word-break: break-all; }
+.id { color: #656d76; }
+.notebook { border: 1px solid #d0d7de; border-radius: 6px; padding: .5em 1.2em; }
+.mention { background: #ddf4ff; color: #0969da; border-radius: 4px; padding: 0 .2em; }
+.entry { border-left: 3px solid #d0d7de; padding: .2em 1em; margin: 1em 0; }
+.entry.amendment { border-left-color: #bf8700; }
+.entry-meta { color: #656d76; font-size: .85em; }
+.signed { font-style: italic; }
+.empty { color: #656d76; }
+pre { background: #f6f8fa; padding: .8em; border-radius: 6px; overflow-x: auto; }
+blockquote { border-left: 3px solid #d0d7de; margin-left: 0; padding-left: 1em; color: #656d76; }
+ul.tasks { list-style: none; padding-left: 1em; }
+dl { display: grid; grid-template-columns: max-content auto; gap: 0 1em; font-size: .9em; margin: .3em 0 1em; }
+dt { color: #656d76; }
+dd { margin: 0; }
+@media print { body { margin: 0; max-width: none; } }
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(name: &str, content: &str) -> Report {
+        Report {
+            id: "exp1".to_string(),
+            name: name.to_string(),
+            description: None,
+            status: "COMPLETED".to_string(),
+            created_by: None,
+            created_at: "2026-10-18T10:00:00+00:00".to_string(),
+            locked_at: None,
+            content_hash: "abc".to_string(),
+            generated_at: "2026-10-18T12:00:00+00:00".to_string(),
+            content: content.to_string(),
+            signatures: vec![],
+            entries: vec![],
+            mentions: vec![],
+            samples: vec![],
+            assets: vec![],
+        }
+    }
+
+    #[test]
+    fn pdf_embeds_a_unicode_font() {
+        let pdf = report("Клеточная культура", "<p>Incubate at 37 °C, 5 µl of α-tubulin</p>")
+            .to_pdf()
+            .unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/FontFile2"));
+        assert!(!pdf.contains("/Helvetica"));
+    }
+
+    /// Text lines of the notebook section
+    fn notebook_text(content: &str) -> Vec<PdfLine> {
+        let mut lines = vec![];
+        notebook_lines(content, &mut lines);
+        lines.retain(|line| *line != PdfLine::Gap);
+        lines
+    }
+
+    #[test]
+    fn pdf_lays_out_blocks_without_markdown() {
+        let content = r#"{"type":"doc","content":[
+            {"type":"heading","attrs":{"level":2},"content":[{"type":"text","text":"Setup"}]},
+            {"type":"paragraph","content":[{"type":"text","text":"Mix","marks":[{"type":"bold"}]}]},
+            {"type":"bulletList","content":[{"type":"listItem","content":[
+                {"type":"paragraph","content":[{"type":"text","text":"Buffer"}]},
+                {"type":"bulletList","content":[{"type":"listItem","content":[
+                    {"type":"paragraph","content":[{"type":"text","text":"10 ml"}]}]}]}]}]},
+            {"type":"blockquote","content":[{"type":"paragraph","content":[{"type":"text","text":"Note"}]}]},
+            {"type":"horizontalRule"}
+        ]}"#;
+
+        assert_eq!(
+            notebook_text(content),
+            vec![
+                PdfLine::Heading(3, "Setup".to_string()),
+                PdfLine::Text(0, "Mix".to_string()),
+                PdfLine::Text(0, "• Buffer".to_string()),
+                PdfLine::Text(1, "• 10 ml".to_string()),
+                PdfLine::Text(1, "Note".to_string()),
+                PdfLine::Rule,
+            ]
+        );
+    }
+
+    #[test]
+    fn pdf_reduces_inline_markdown_in_plain_text() {
+        assert_eq!(
+            notebook_text("**Spun** at `4 °C`, see [protocol](https://lab/p/1)\n![gel](gel.png) ~~lane 3~~"),
+            vec![
+                PdfLine::Text(0, "Spun at 4 °C, see protocol (https://lab/p/1)".to_string()),
+                PdfLine::Text(0, "gel lane 3".to_string()),
+            ]
+        );
+        assert_eq!(plain_text("[a] and [b](c"), "[a] and [b](c");
+    }
+
+    #[test]
+    fn pdf_adds_the_mono_font_only_for_code() {
+        let plain = report("Plain", "<p>No code here</p>").to_pdf().unwrap();
+        let code = report("Code", "<pre><code>x = 1</code></pre>").to_pdf().unwrap();
+        let fonts = |pdf: &[u8]| String::from_utf8_lossy(pdf).matches("/FontFile2").count();
+        assert_eq!(fonts(&plain), 1);
+        assert_eq!(fonts(&code), 2);
+    }
+}
@@ -0,0 +1,138 @@
+//! Minimal iCalendar (RFC 5545) writer for equipment booking feeds
+
+use prisma_client_rust::chrono::{DateTime, Utc};
+
+/// One VEVENT
+pub struct CalendarEvent {
+    /// Stable across feed refreshes so calendar apps update instead of duplicating
+    pub uid: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// When the event was last modified
+    pub stamp: DateTime<Utc>,
+    pub summary: String,
+    pub description: Option<String>,
+}
+
+/// Render a VCALENDAR with the given name and events
+pub fn render_calendar(name: &str, events: &[CalendarEvent]) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:-//OpenBio//OpenBio Hub {}//EN", env!("CARGO_PKG_VERSION")),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        format!("X-WR-CALNAME:{}", escape_text(name)),
+    ];
+
+    for event in events {
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{}", event.uid));
+        lines.push(format!("DTSTAMP:{}", format_time(&event.stamp)));
+        lines.push(format!("DTSTART:{}", format_time(&event.start)));
+        lines.push(format!("DTEND:{}", format_time(&event.end)));
+        lines.push(format!("SUMMARY:{}", escape_text(&event.summary)));
+        if let Some(description) = &event.description {
+            lines.push(format!("DESCRIPTION:{}", escape_text(description)));
+        }
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut calendar = String::new();
+    for line in lines {
+        fold_line(&line, &mut calendar);
+    }
+    calendar
+}
+
+/// UTC date-time form, e.g. 20261018T093000Z
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Lines are CRLF-terminated and folded at 75 octets (never inside a UTF-8 character)
+fn fold_line(line: &str, out: &mut String) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prisma_client_rust::chrono::TimeZone;
+
+    fn event(summary: &str, description: Option<&str>) -> CalendarEvent {
+        let start = Utc.with_ymd_and_hms(2026, 10, 18, 9, 30, 0).unwrap();
+        CalendarEvent {
+            uid: "booking-1@openbio".to_string(),
+            start,
+            end: Utc.with_ymd_and_hms(2026, 10, 18, 11, 0, 0).unwrap(),
+            stamp: start,
+            summary: summary.to_string(),
+            description: description.map(str::to_string),
+        }
+    }
+
+    /// Undo line folding (RFC 5545 3.1)
+    fn unfold(calendar: &str) -> String {
+        calendar.replace("\r\n ", "")
+    }
+
+    #[test]
+    fn long_multibyte_lines_fold_at_75_octets_on_char_boundaries() {
+        // 2-, 3- and 4-byte characters, so folds land next to every width
+        let summary = "Réaction µ-PCR 細胞培養 🧪 ".repeat(8);
+        let calendar = render_calendar("Confocal", &[event(&summary, None)]);
+
+        assert!(calendar.ends_with("\r\n"));
+        let lines: Vec<&str> = calendar.trim_end_matches("\r\n").split("\r\n").collect();
+        assert!(lines.iter().all(|line| line.len() <= 75), "{:?}", lines);
+        let folded = lines.iter().filter(|line| line.starts_with(' ')).count();
+        assert!(folded >= 3);
+        // Some lines end short of 75 octets because the next character did not fit
+        let first = lines
+            .iter()
+            .position(|l| l.starts_with("SUMMARY:"))
+            .unwrap();
+        let summary_lines = &lines[first..first + folded];
+        assert!(summary_lines.iter().any(|line| line.len() < 75));
+
+        let summary_line = format!("SUMMARY:{}\r\n", escape_text(&summary));
+        assert!(unfold(&calendar).contains(&summary_line));
+    }
+
+    #[test]
+    fn text_values_escape_separators_and_newlines() {
+        assert_eq!(
+            escape_text("Ana, Bo; lane 3\\4\nspin\r\ndone"),
+            "Ana\\, Bo\\; lane 3\\\\4\\nspin\\ndone"
+        );
+
+        let calendar = render_calendar(
+            "FACS; Room 2",
+            &[event("Sort, gate A", Some("Bring tubes;\nlabelled"))],
+        );
+        assert!(calendar.contains("X-WR-CALNAME:FACS\\; Room 2\r\n"));
+        assert!(calendar.contains("SUMMARY:Sort\\, gate A\r\n"));
+        assert!(calendar.contains("DESCRIPTION:Bring tubes\\;\\nlabelled\r\n"));
+        assert!(calendar.contains("DTSTART:20261018T093000Z\r\n"));
+        assert!(calendar.contains("DTEND:20261018T110000Z\r\n"));
+    }
+}
@@ -2,28 +2,58 @@
 //!
 //! Axum HTTP API server that can run embedded in Tauri or as a standalone Docker container.
 
+use std::future::{Future, IntoFuture};
 use std::net::SocketAddr;
+use std::time::Duration;
 
-use axum::{routing::get, Router};
+use axum::{http::header, routing::get, Router};
+use tokio::sync::watch;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 
+pub mod assets;
+pub mod collab;
 pub mod db;
+pub mod error;
+pub mod etag;
+pub mod events;
+pub mod export;
+pub mod ics;
 pub mod routes;
 pub mod state;
 
+pub use error::{ApiError, ApiResult};
 pub use state::AppState;
 
+/// How long open connections (WebSockets, event streams) get to finish once
+/// the server is shutting down
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
 /// Start the API server (blocking - call from async context)
-pub async fn run_server(addr: SocketAddr, state: AppState) -> anyhow::Result<()> {
+///
+/// Runs until `shutdown` resolves, then stops accepting connections, waits up
+/// to [`SHUTDOWN_GRACE`] for open ones, and saves notebooks still being edited
+/// before returning.
+pub async fn run_server(
+    addr: SocketAddr,
+    state: AppState,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers([header::ETAG, header::CONTENT_DISPOSITION]);
+
+    // Persist documents edited over the collaboration WebSocket
+    let (stop_saving, saving_stopped) = watch::channel(false);
+    let saver = tokio::spawn(routes::collab::save_rooms(state.clone(), saving_stopped));
+    // Mark instruments offline when their agent stops reporting in
+    tokio::spawn(routes::agent::watch_heartbeats(state.clone()));
 
     let app = Router::new()
         .route("/health", get(routes::health))
-        .nest("/api", routes::api_routes())
+        .nest("/api", routes::api_routes(state.clone()))
         .layer(cors)
         .layer(TraceLayer::new_for_http())
         .with_state(state);
@@ -31,9 +61,34 @@ pub async fn run_server(addr: SocketAddr, state: AppState) -> anyhow::Result<()>
     tracing::info!("Starting OpenBio server on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let (stopping, mut stop_requested) = watch::channel(false);
+    // The peer address tells administrative requests from the Hub's own machine apart
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        shutdown.await;
+        let _ = stopping.send(true);
+    })
+    .into_future();
+
+    // Collaboration sockets and event streams stay open until clients leave,
+    // so they are only waited for briefly
+    let result = tokio::select! {
+        result = server => result,
+        _ = async {
+            let _ = stop_requested.wait_for(|stop| *stop).await;
+            tokio::time::sleep(SHUTDOWN_GRACE).await;
+        } => {
+            tracing::info!("Closing connections that are still open");
+            Ok(())
+        }
+    };
 
-    Ok(())
+    let _ = stop_saving.send(true);
+    saver.await?;
+    Ok(result?)
 }
 
 /// Start the API server in a new tokio runtime on a background thread
@@ -54,7 +109,8 @@ pub fn spawn_embedded_server(port: u16, database_url: String, apply_migrations:
                 .await
                 .expect("Failed to create app state");
 
-            if let Err(e) = run_server(addr, state).await {
+            // Runs until the process exits
+            if let Err(e) = run_server(addr, state, std::future::pending()).await {
                 tracing::error!("Server error: {}", e);
             }
         });
@@ -1,16 +1,151 @@
-//! Standalone server binary for Docker deployment
+//! Standalone server binary: a headless Hub for a lab server or Docker
+//!
+//! Settings come from flags or environment variables, then from the app's
+//! `config.toml` ([`openbio_core::Config`]), then from defaults. With a lab
+//! name the Hub is advertised over mDNS like the desktop Hub, so spokes and
+//! ingest agents find it without being given a URL.
 
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
+use anyhow::Context;
+use clap::Parser;
+use openbio_core::discovery::HubAdvertisement;
+use openbio_core::Config;
 use openbio_server::{run_server, AppState};
+use tracing::{info, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+/// OpenBio Hub server
+#[derive(Parser, Debug)]
+#[command(name = "openbio-server", version)]
+#[command(about = "Headless OpenBio Hub: API server with optional mDNS advertisement")]
+struct Args {
+    /// Config file [default: config.toml in the OpenBio app data directory]
+    #[arg(long, env = "OPENBIO_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on [default: 0.0.0.0 and the config's server_port, or 3000]
+    #[arg(long, env = "OPENBIO_BIND")]
+    bind: Option<SocketAddr>,
+
+    /// Where the database and uploaded files are kept [default: the config's data_path in
+    /// the OpenBio app data directory]
+    #[arg(long, env = "OPENBIO_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// SQLite database URL [default: file:<data dir>/openbio.db]
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: Option<String>,
+
+    /// Lab name advertised over mDNS [default: the config's lab_name]
+    #[arg(long, env = "OPENBIO_LAB_NAME")]
+    lab_name: Option<String>,
+
+    /// Don't advertise the Hub over mDNS
+    #[arg(long, env = "OPENBIO_NO_MDNS")]
+    no_mdns: bool,
+
+    /// Token that lets other machines issue agent API keys and lock agents, sent as
+    /// `Authorization: Bearer <token>` [default: only this machine can]
+    #[arg(long, env = "OPENBIO_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+
+    /// Don't apply database migrations on startup
+    #[arg(long)]
+    no_migrations: bool,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let addr: SocketAddr = "0.0.0.0:3000".parse()?;
-    
-    // Use .dev directory for development database (ignored by git)
-    std::fs::create_dir_all(".dev").ok();
-    let state = AppState::new("file:./.dev/openbio.db".to_string(), true).await?;
-    
-    run_server(addr, state).await
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+
+    let args = Args::parse();
+    let config = match &args.config {
+        Some(path) => Config::load_from(path)
+            .with_context(|| format!("Cannot load config file {}", path.display()))?,
+        None => Config::load().context("Cannot load the OpenBio config file")?,
+    };
+
+    let data_dir = args.data_dir.clone().unwrap_or_else(|| config.data_dir());
+    std::fs::create_dir_all(&data_dir)
+        .with_context(|| format!("Cannot create data directory {}", data_dir.display()))?;
+    let database_url = args
+        .database_url
+        .clone()
+        .unwrap_or_else(|| format!("file:{}", data_dir.join("openbio.db").display()));
+    let addr = args
+        .bind
+        .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], config.server_port)));
+    let lab_name = args
+        .lab_name
+        .clone()
+        .or(config.lab_name.clone())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+
+    info!("Keeping data in {}", data_dir.display());
+    let state = AppState::with_data_dir(database_url, !args.no_migrations, &data_dir)
+        .await?
+        .with_admin_token(args.admin_token.clone());
+    if state.admin_token.is_none() {
+        info!("No admin token set; agent keys and locks can only be managed from this machine");
+    }
+
+    // Kept until the process exits; dropping it withdraws the advertisement
+    let _advertisement = match lab_name {
+        Some(_) if args.no_mdns => None,
+        Some(lab_name) => {
+            if addr.ip().is_loopback() {
+                warn!("Listening on {} only; other machines cannot reach the Hub", addr);
+            }
+            match HubAdvertisement::start(&lab_name, addr.port()) {
+                Ok(advertisement) => {
+                    info!("Advertising '{}' over mDNS on port {}", lab_name, addr.port());
+                    Some(advertisement)
+                }
+                Err(e) => {
+                    warn!("Cannot advertise the Hub over mDNS: {}", e);
+                    None
+                }
+            }
+        }
+        None => {
+            info!("No lab name set; the Hub is not advertised over mDNS");
+            None
+        }
+    };
+
+    let shutdown = shutdown_signal()?;
+    run_server(addr, state, async move {
+        shutdown.await;
+        info!("Shutting down");
+    })
+    .await
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix (what Docker and systemd send on stop)
+fn shutdown_signal() -> anyhow::Result<impl std::future::Future<Output = ()>> {
+    #[cfg(unix)]
+    let mut terminate = {
+        use tokio::signal::unix::{signal, SignalKind};
+        signal(SignalKind::terminate()).context("Cannot listen for SIGTERM")?
+    };
+
+    // Like a signal, failing to wait for Ctrl-C stops the server
+    Ok(async move {
+        #[cfg(unix)]
+        let result = tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        };
+        #[cfg(not(unix))]
+        let result = tokio::signal::ctrl_c().await;
+        if let Err(e) = result {
+            warn!("Cannot listen for Ctrl-C: {}", e);
+        }
+    })
 }
@@ -1,14 +1,40 @@
 //! API route handlers
 
 use axum::{
-    extract::{Path, State},
-    routing::get,
+    extract::{DefaultBodyLimit, Path, State},
+    http::HeaderMap,
+    response::Response,
+    routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::db::prisma::{container, experiment, experiment_entry, experiment_mention, sample, paper};
-use crate::AppState;
+use openbio_core::experiment::content_hash;
+use openbio_core::{ExperimentStatus, SampleRole};
+use prisma_client_rust::chrono::{DateTime, FixedOffset, Utc};
+
+use crate::db::prisma::{
+    container, experiment, experiment_entry, experiment_mention, experiment_sample, paper, sample,
+    SortOrder,
+};
+use crate::events::ChangeEvent;
+use crate::{etag, ApiError, ApiResult, AppState};
+
+pub(crate) mod admin;
+pub(crate) mod agent;
+mod agent_keys;
+mod bookings;
+pub(crate) mod collab;
+mod events;
+mod export;
+mod lifecycle;
+mod maintenance;
+mod mentions;
+mod revisions;
+mod sample_matching;
+mod signatures;
+mod templates;
+mod uploads;
 
 /// Health check response
 #[derive(Serialize)]
@@ -26,20 +52,33 @@ pub async fn health() -> Json<HealthResponse> {
 }
 
 /// API routes
-pub fn api_routes() -> Router<AppState> {
+pub fn api_routes(state: AppState) -> Router<AppState> {
     Router::new()
         // Inventory (Module A) routes
         .nest("/inventory", inventory_routes())
         // Experiments routes (experiments ARE the notebooks)
         .nest("/experiments", experiment_routes())
+        // Equipment routes
+        .nest("/equipment", equipment_routes(state.clone()))
         // Library routes (papers)
         .nest("/library", library_routes())
+        // Ingested files
+        .nest("/assets", asset_routes())
+        // Ingest agent routes
+        .nest("/agent", agent_routes(state))
+        // Live change notifications (SSE)
+        .route("/events", get(events::event_stream))
 }
 
 fn inventory_routes() -> Router<AppState> {
     Router::new()
         .route("/samples", get(list_samples).post(create_sample))
-        .route("/samples/{id}", axum::routing::delete(delete_sample).patch(update_sample))
+        .route(
+            "/samples/{id}",
+            get(get_sample).delete(delete_sample).patch(update_sample),
+        )
+        .route("/samples/{id}/experiments", get(list_sample_experiments))
+        .route("/samples/{id}/mentioned-in", get(mentions::sample_mentioned_in))
         .route("/containers", get(list_containers).post(create_container))
         .route("/containers/{id}", axum::routing::delete(delete_container))
 }
@@ -47,16 +86,120 @@ fn inventory_routes() -> Router<AppState> {
 fn experiment_routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_experiments).post(create_experiment))
+        .route("/templates", get(templates::list_templates).post(templates::create_template))
+        .route(
+            "/templates/{id}",
+            get(templates::get_template)
+                .patch(templates::update_template)
+                .delete(templates::delete_template),
+        )
+        .route("/templates/{id}/experiments", post(templates::create_from_template))
         .route("/{id}", get(get_experiment).patch(update_experiment).delete(delete_experiment))
         .route("/{id}/entries", get(list_experiment_entries).post(create_experiment_entry))
+        .route("/{id}/entries/{entry_id}", axum::routing::delete(delete_experiment_entry))
+        .route("/{id}/collab", get(collab::notebook_socket))
+        .route("/{id}/signatures", get(signatures::list_signatures))
+        .route("/{id}/signatures/{role}", post(signatures::sign_experiment))
+        .route("/{id}/export", get(export::export_experiment))
+        .route("/{id}/clone", post(templates::clone_experiment))
+        .route("/{id}/transitions/{action}", post(lifecycle::transition_experiment))
+        .route("/{id}/status-history", get(lifecycle::list_status_changes))
+        .route("/{id}/revisions", get(revisions::list_revisions))
+        .route("/{id}/revisions/diff", get(revisions::diff_revisions))
+        .route("/{id}/revisions/{revision}", get(revisions::get_revision))
+        .route("/{id}/revisions/{revision}/restore", post(revisions::restore_revision))
         .route("/{id}/mentions", get(list_experiment_mentions).post(create_experiment_mention))
+        .route("/{id}/mentions/stale", get(mentions::stale_mentions))
+        .route("/{id}/mentions/{mention_id}/refresh", post(mentions::refresh_mention))
+        .route(
+            "/{id}/samples",
+            get(list_experiment_samples)
+                .post(link_experiment_samples)
+                .delete(unlink_experiment_samples),
+        )
+        .route("/{id}/samples/{sample_id}", axum::routing::delete(unlink_experiment_sample))
         .route("/search-entities", get(search_entities))
 }
 
+fn equipment_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/{id}/bookings", get(bookings::list_bookings).post(bookings::create_booking))
+        .route("/{id}/bookings.ics", get(bookings::bookings_calendar))
+        .route(
+            "/{id}/bookings/{experiment_id}",
+            axum::routing::delete(bookings::cancel_booking),
+        )
+        .route("/agents", get(agent::list_agents))
+        .route("/{id}/api-keys", get(agent_keys::list_api_keys))
+        .route(
+            "/{id}/agent-config",
+            get(agent::get_equipment_agent_config).patch(agent::update_equipment_agent_config),
+        )
+        .route(
+            "/{id}/sample-rules",
+            get(sample_matching::get_sample_rules).put(sample_matching::update_sample_rules),
+        )
+        .route("/{id}/sample-rules/apply", post(sample_matching::apply_sample_rules))
+        .route("/{id}/unmatched-assets", get(sample_matching::list_unmatched_assets))
+        .route("/{id}/mentioned-in", get(mentions::equipment_mentioned_in))
+        .route("/maintenance/overdue", get(maintenance::list_overdue))
+        .route(
+            "/{id}/maintenance/schedules",
+            get(maintenance::list_schedules).post(maintenance::create_schedule),
+        )
+        .route(
+            "/{id}/maintenance/schedules/{schedule_id}",
+            axum::routing::patch(maintenance::update_schedule)
+                .delete(maintenance::delete_schedule),
+        )
+        .route(
+            "/{id}/maintenance/records",
+            get(maintenance::list_records).post(maintenance::create_record),
+        )
+        .merge(equipment_admin_routes(state))
+}
+
+/// Only reachable by an administrator (see [`admin`])
+fn equipment_admin_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/{id}/api-keys", post(agent_keys::create_api_key))
+        .route("/{id}/api-keys/{key_id}", axum::routing::delete(agent_keys::revoke_api_key))
+        .route("/{id}/api-keys/{key_id}/rotate", post(agent_keys::rotate_api_key))
+        .route("/{id}/agent-lock", post(agent::lock_agent).delete(agent::unlock_agent))
+        .route_layer(axum::middleware::from_fn_with_state(state, admin::require_admin))
+}
+
+fn asset_routes() -> Router<AppState> {
+    Router::new().route(
+        "/{id}/sample",
+        axum::routing::put(sample_matching::assign_asset_sample),
+    )
+}
+
+/// Only reachable with an agent API key (see [`agent_keys`])
+fn agent_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/heartbeat", post(agent::heartbeat))
+        .route("/config", get(agent::agent_config))
+        .route("/assets", get(uploads::list_ingested))
+        .route("/uploads", post(uploads::start_upload))
+        .route(
+            "/uploads/{upload_id}",
+            axum::routing::put(uploads::upload_chunk)
+                .layer(DefaultBodyLimit::max(uploads::CHUNK_BODY_LIMIT)),
+        )
+        .route("/uploads/{upload_id}/complete", post(uploads::complete_upload))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state,
+            agent_keys::require_agent_key,
+        ))
+}
+
 fn library_routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_papers).post(create_paper))
         .route("/{id}", get(get_paper).patch(update_paper).delete(delete_paper))
+        .route("/{id}/mentioned-in", get(mentions::paper_mentioned_in))
 }
 
 // ==========================================
@@ -113,6 +256,10 @@ async fn create_sample(
         .exec()
         .await
         .expect("Failed to create sample");
+
+    state.events.publish(ChangeEvent::SampleCreated {
+        id: sample.id.clone(),
+    });
     Json(sample)
 }
 
@@ -122,11 +269,36 @@ pub struct UpdateSampleRequest {
     pub metadata: Option<String>,
 }
 
+async fn get_sample(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, ApiError> {
+    let sample = state
+        .db
+        .sample()
+        .find_unique(sample::id::equals(id.clone()))
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Sample {}", id)))?;
+    let updated_at = sample.updated_at;
+    Ok(etag::with_etag(sample, &updated_at))
+}
+
 async fn update_sample(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    headers: HeaderMap,
     Json(payload): Json<UpdateSampleRequest>,
-) -> Json<sample::Data> {
+) -> Result<Response, ApiError> {
+    let current = state
+        .db
+        .sample()
+        .find_unique(sample::id::equals(id.clone()))
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Sample {}", id)))?;
+    etag::check_if_match(&headers, &current, &current.updated_at)?;
+
     let mut params: Vec<sample::SetParam> = vec![];
 
     if let Some(name) = payload.name {
@@ -137,14 +309,38 @@ async fn update_sample(
         params.push(sample::metadata::set(Some(metadata)));
     }
 
+    // Guard on the version we checked so a concurrent save cannot slip in between
+    let written = state
+        .db
+        .sample()
+        .update_many(
+            vec![
+                sample::id::equals(id.clone()),
+                sample::updated_at::equals(current.updated_at),
+            ],
+            params,
+        )
+        .exec()
+        .await?;
+
     let sample = state
         .db
         .sample()
-        .update(sample::id::equals(id), params)
+        .find_unique(sample::id::equals(id.clone()))
         .exec()
-        .await
-        .expect("Failed to update sample");
-    Json(sample)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Sample {}", id)))?;
+
+    if written == 0 {
+        return Err(etag::precondition_failed(&sample, &sample.updated_at));
+    }
+
+    state.events.publish(ChangeEvent::SampleUpdated {
+        id: sample.id.clone(),
+    });
+
+    let updated_at = sample.updated_at;
+    Ok(etag::with_etag(sample, &updated_at))
 }
 
 async fn delete_sample(
@@ -154,13 +350,30 @@ async fn delete_sample(
     state
         .db
         .sample()
-        .delete(sample::id::equals(id))
+        .delete(sample::id::equals(id.clone()))
         .exec()
         .await
         .expect("Failed to delete sample");
+
+    state.events.publish(ChangeEvent::SampleDeleted { id });
     Json(())
 }
 
+/// Every experiment a sample was used in, with the role it played
+async fn list_sample_experiments(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<experiment_sample::Data>> {
+    let links = state
+        .db
+        .experiment_sample()
+        .find_many(vec![experiment_sample::sample_id::equals(id)])
+        .with(experiment_sample::experiment::fetch())
+        .exec()
+        .await?;
+    Ok(Json(links))
+}
+
 #[derive(Deserialize)]
 pub struct CreateContainerRequest {
     pub name: String,
@@ -235,6 +448,7 @@ pub struct CreateExperimentRequest {
     pub description: Option<String>,
     pub content: Option<String>,
     pub status: Option<String>,
+    pub created_by: Option<String>,
 }
 
 async fn list_experiments(State(state): State<AppState>) -> Json<Vec<experiment::Data>> {
@@ -251,47 +465,70 @@ async fn list_experiments(State(state): State<AppState>) -> Json<Vec<experiment:
 async fn create_experiment(
     State(state): State<AppState>,
     Json(payload): Json<CreateExperimentRequest>,
-) -> Json<experiment::Data> {
+) -> ApiResult<experiment::Data> {
     let mut params: Vec<experiment::SetParam> = vec![];
     
     if let Some(description) = payload.description {
         params.push(experiment::description::set(Some(description)));
     }
     
-    if let Some(content) = payload.content {
+    if let Some(content) = payload.content.clone() {
         params.push(experiment::content::set(content));
     }
     
     if let Some(status) = payload.status {
-        params.push(experiment::status::set(status));
+        let status: ExperimentStatus = status.parse()?;
+        if !status.is_initial() {
+            return Err(ApiError::Unprocessable(format!(
+                "A new experiment cannot be {}; start it as DRAFT or SCHEDULED",
+                status
+            )));
+        }
+        params.push(experiment::status::set(status.to_string()));
+    }
+
+    if let Some(created_by) = payload.created_by.clone() {
+        params.push(experiment::created_by::set(Some(created_by)));
     }
 
     let experiment = state
         .db
-        .experiment()
-        .create(payload.name, params)
-        .exec()
-        .await
-        .expect("Failed to create experiment");
-    Json(experiment)
+        ._transaction()
+        .run(|tx| async move {
+            let experiment = tx.experiment().create(payload.name, params).exec().await?;
+
+            if let Some(content) = payload.content.filter(|c| !c.is_empty()) {
+                mentions::sync_mentions(&tx, &experiment.id, &content).await?;
+                revisions::record_revision(&tx, &experiment.id, content, payload.created_by, None)
+                    .await?;
+            }
+
+            Ok::<_, ApiError>(experiment)
+        })
+        .await?;
+
+    state.events.publish(ChangeEvent::ExperimentCreated {
+        id: experiment.id.clone(),
+    });
+    Ok(Json(experiment))
 }
 
 async fn get_experiment(
     State(state): State<AppState>,
     Path(id): Path<String>,
-) -> Json<Option<experiment::Data>> {
+) -> Result<Response, ApiError> {
     let experiment = state
         .db
         .experiment()
-        .find_unique(experiment::id::equals(id))
+        .find_unique(experiment::id::equals(id.clone()))
         .with(experiment::mentions::fetch(vec![]))
         .with(experiment::entries::fetch(vec![]))
         .with(experiment::samples::fetch(vec![]))
         .exec()
-        .await
-        .ok()
-        .flatten();
-    Json(experiment)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", id)))?;
+    let updated_at = experiment.updated_at;
+    Ok(etag::with_etag(experiment, &updated_at))
 }
 
 #[derive(Deserialize)]
@@ -300,53 +537,134 @@ pub struct UpdateExperimentRequest {
     pub content: Option<String>,
     pub description: Option<String>,
     pub status: Option<String>,
+    /// Who is making the change (stamped on status transitions)
+    pub actor: Option<String>,
 }
 
 async fn update_experiment(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    headers: HeaderMap,
     Json(payload): Json<UpdateExperimentRequest>,
-) -> Json<experiment::Data> {
+) -> Result<Response, ApiError> {
+    let content_changed = payload.content.is_some();
+    if content_changed {
+        collab::ensure_room_saved(&state, &id)?;
+    }
+
     let mut params: Vec<experiment::SetParam> = vec![];
     
     if let Some(name) = payload.name {
         params.push(experiment::name::set(name));
     }
     
-    if let Some(content) = payload.content {
+    if let Some(content) = payload.content.clone() {
         params.push(experiment::content::set(content));
     }
     
     if let Some(description) = payload.description {
         params.push(experiment::description::set(Some(description)));
     }
-    
-    if let Some(status) = payload.status {
-        params.push(experiment::status::set(status));
-    }
 
-    let experiment = state
+    // The version check, the status transition and the field update commit together, so a
+    // stale client changes nothing. Every content save is kept as a revision.
+    let (experiment, status_change) = state
         .db
-        .experiment()
-        .update(experiment::id::equals(id), params)
-        .exec()
-        .await
-        .expect("Failed to update experiment");
-    Json(experiment)
+        ._transaction()
+        .run(|tx| async move {
+            let current = tx
+                .experiment()
+                .find_unique(experiment::id::equals(id.clone()))
+                .exec()
+                .await?
+                .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", id)))?;
+            if current.locked_at.is_some() {
+                return Err(signatures::locked_error(&id));
+            }
+            etag::check_if_match(&headers, &current, &current.updated_at)?;
+
+            // Only write if nobody else saved or signed since the client's version
+            let written = tx
+                .experiment()
+                .update_many(
+                    vec![
+                        experiment::id::equals(id.clone()),
+                        experiment::updated_at::equals(current.updated_at),
+                        experiment::locked_at::equals(None),
+                    ],
+                    params,
+                )
+                .exec()
+                .await?;
+            if written == 0 {
+                return Err(etag::precondition_failed(&current, &current.updated_at));
+            }
+
+            // Status changes go through the state machine; reopening needs the explicit endpoint
+            let mut status_change = None;
+            if let Some(status) = payload.status {
+                let to: ExperimentStatus = status.parse()?;
+                let from: ExperimentStatus = current.status.parse()?;
+
+                if from != to {
+                    let transition = from.transition_to(to)?;
+                    let (_, from) = lifecycle::record_transition(
+                        &tx,
+                        id.clone(),
+                        transition,
+                        payload.actor.clone(),
+                        None,
+                    )
+                    .await?;
+                    status_change = Some(from);
+                }
+            }
+
+            if let Some(content) = payload.content {
+                mentions::sync_mentions(&tx, &id, &content).await?;
+                revisions::record_revision(&tx, &id, content, payload.actor, None).await?;
+            }
+
+            let experiment = tx
+                .experiment()
+                .find_unique(experiment::id::equals(id.clone()))
+                .exec()
+                .await?
+                .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", id)))?;
+            Ok::<_, ApiError>((experiment, status_change))
+        })
+        .await?;
+
+    if content_changed {
+        collab::content_written(&state, &experiment.id, &experiment.content);
+    }
+    if let Some(from) = status_change {
+        lifecycle::publish_transition(&state, &experiment, from);
+    }
+    state.events.publish(ChangeEvent::ExperimentUpdated {
+        id: experiment.id.clone(),
+    });
+
+    let updated_at = experiment.updated_at;
+    Ok(etag::with_etag(experiment, &updated_at))
 }
 
 async fn delete_experiment(
     State(state): State<AppState>,
     Path(id): Path<String>,
-) -> Json<()> {
-    state
+) -> ApiResult<()> {
+    let deleted = state
         .db
-        .experiment()
-        .delete(experiment::id::equals(id))
-        .exec()
-        .await
-        .expect("Failed to delete experiment");
-    Json(())
+        ._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &id).await?;
+            let deleted = tx.experiment().delete(experiment::id::equals(id)).exec().await?;
+            Ok::<_, ApiError>(deleted)
+        })
+        .await?;
+
+    state.events.publish(ChangeEvent::ExperimentDeleted { id: deleted.id });
+    Ok(Json(()))
 }
 
 // Experiment Entries
@@ -355,6 +673,9 @@ pub struct CreateExperimentEntryRequest {
     pub content: String,
     pub author: Option<String>,
     pub attached_asset_id: Option<String>,
+    /// Signature for the entry; required to amend a signed (locked) experiment
+    pub signed_by: Option<String>,
+    pub signature_meaning: Option<String>,
 }
 
 async fn list_experiment_entries(
@@ -375,7 +696,7 @@ async fn create_experiment_entry(
     State(state): State<AppState>,
     Path(experiment_id): Path<String>,
     Json(payload): Json<CreateExperimentEntryRequest>,
-) -> Json<experiment_entry::Data> {
+) -> ApiResult<experiment_entry::Data> {
     let mut params: Vec<experiment_entry::SetParam> = vec![];
     
     if let Some(author) = payload.author {
@@ -386,26 +707,257 @@ async fn create_experiment_entry(
         params.push(experiment_entry::attached_asset_id::set(Some(asset_id)));
     }
 
+    let signature = match (payload.signed_by, payload.signature_meaning) {
+        (Some(signer), Some(meaning)) if !signer.trim().is_empty() && !meaning.trim().is_empty() => {
+            Some((signer, meaning))
+        }
+        _ => None,
+    };
+
+    // Read the lock in the same transaction as the write, so an entry added while the
+    // author signs is either part of the signed record or a signed amendment
     let entry = state
         .db
-        .experiment_entry()
-        .create(
-            experiment::id::equals(experiment_id),
-            payload.content,
-            params,
-        )
+        ._transaction()
+        .run(|tx| async move {
+            let experiment = tx
+                .experiment()
+                .find_unique(experiment::id::equals(experiment_id.clone()))
+                .exec()
+                .await?
+                .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", experiment_id)))?;
+            let is_amendment = experiment.locked_at.is_some();
+
+            match signature {
+                Some((signer, meaning)) => {
+                    let now: DateTime<FixedOffset> = Utc::now().into();
+                    params.push(experiment_entry::is_amendment::set(is_amendment));
+                    params.push(experiment_entry::signed_by::set(Some(signer)));
+                    params.push(experiment_entry::signature_meaning::set(Some(meaning)));
+                    params.push(experiment_entry::signed_at::set(Some(now)));
+                    params.push(experiment_entry::content_hash::set(Some(content_hash(
+                        &payload.content,
+                    ))));
+                }
+                None if is_amendment => return Err(signatures::locked_error(&experiment_id)),
+                None => {}
+            }
+
+            let entry = tx
+                .experiment_entry()
+                .create(experiment::id::equals(experiment_id), payload.content, params)
+                .exec()
+                .await?;
+            Ok::<_, ApiError>(entry)
+        })
+        .await?;
+
+    state.events.publish(ChangeEvent::ExperimentUpdated {
+        id: entry.experiment_id.clone(),
+    });
+    Ok(Json(entry))
+}
+
+async fn delete_experiment_entry(
+    State(state): State<AppState>,
+    Path((experiment_id, entry_id)): Path<(String, String)>,
+) -> ApiResult<()> {
+    let (experiment_id, deleted) = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &experiment_id).await?;
+            let deleted = tx
+                .experiment_entry()
+                .delete_many(vec![
+                    experiment_entry::id::equals(entry_id),
+                    experiment_entry::experiment_id::equals(experiment_id.clone()),
+                ])
+                .exec()
+                .await?;
+            Ok::<_, ApiError>((experiment_id, deleted))
+        })
+        .await?;
+
+    if deleted > 0 {
+        state.events.publish(ChangeEvent::ExperimentUpdated { id: experiment_id });
+    }
+    Ok(Json(()))
+}
+
+// Experiment Samples
+#[derive(Deserialize)]
+pub struct SampleLink {
+    pub sample_id: String,
+    pub role: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LinkSamplesRequest {
+    pub samples: Vec<SampleLink>,
+}
+
+#[derive(Deserialize)]
+pub struct UnlinkSamplesRequest {
+    pub sample_ids: Vec<String>,
+}
+
+async fn list_experiment_samples(
+    State(state): State<AppState>,
+    Path(experiment_id): Path<String>,
+) -> ApiResult<Vec<experiment_sample::Data>> {
+    let links = state
+        .db
+        .experiment_sample()
+        .find_many(vec![experiment_sample::experiment_id::equals(experiment_id)])
+        .with(experiment_sample::sample::fetch())
         .exec()
-        .await
-        .expect("Failed to create experiment entry");
-    Json(entry)
+        .await?;
+    Ok(Json(links))
+}
+
+/// Link one or more samples to an experiment.
+/// Re-linking an already linked sample updates its role.
+async fn link_experiment_samples(
+    State(state): State<AppState>,
+    Path(experiment_id): Path<String>,
+    Json(payload): Json<LinkSamplesRequest>,
+) -> ApiResult<Vec<experiment_sample::Data>> {
+    if payload.samples.is_empty() {
+        return Err(ApiError::BadRequest("No samples given".to_string()));
+    }
+
+    // Validate roles and sample ids up front so the batch is all-or-nothing
+    let mut links: Vec<(String, Option<String>)> = vec![];
+    for link in payload.samples {
+        let role = match link.role {
+            Some(role) => Some(role.parse::<SampleRole>()?.as_str().to_string()),
+            None => None,
+        };
+        links.push((link.sample_id, role));
+    }
+
+    let sample_ids: Vec<String> = links.iter().map(|(id, _)| id.clone()).collect();
+    let found = state
+        .db
+        .sample()
+        .find_many(vec![sample::id::in_vec(sample_ids.clone())])
+        .exec()
+        .await?;
+    if let Some(missing) = sample_ids
+        .iter()
+        .find(|id| !found.iter().any(|s| &s.id == *id))
+    {
+        return Err(ApiError::NotFound(format!("Sample {}", missing)));
+    }
+
+    let (experiment_id, linked) = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &experiment_id).await?;
+
+            let mut linked = Vec::with_capacity(links.len());
+            for (sample_id, role) in links {
+                let link = tx
+                    .experiment_sample()
+                    .upsert(
+                        experiment_sample::experiment_id_sample_id(
+                            experiment_id.clone(),
+                            sample_id.clone(),
+                        ),
+                        experiment_sample::create(
+                            experiment::id::equals(experiment_id.clone()),
+                            sample::id::equals(sample_id),
+                            vec![experiment_sample::role::set(role.clone())],
+                        ),
+                        vec![experiment_sample::role::set(role)],
+                    )
+                    .exec()
+                    .await?;
+                linked.push(link);
+            }
+            Ok::<_, ApiError>((experiment_id, linked))
+        })
+        .await?;
+
+    publish_sample_links(&state, experiment_id, sample_ids);
+    Ok(Json(linked))
+}
+
+/// Unlink several samples from an experiment
+async fn unlink_experiment_samples(
+    State(state): State<AppState>,
+    Path(experiment_id): Path<String>,
+    Json(payload): Json<UnlinkSamplesRequest>,
+) -> ApiResult<i64> {
+    let sample_ids = payload.sample_ids;
+    let (experiment_id, sample_ids, removed) = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &experiment_id).await?;
+            let removed = tx
+                .experiment_sample()
+                .delete_many(vec![
+                    experiment_sample::experiment_id::equals(experiment_id.clone()),
+                    experiment_sample::sample_id::in_vec(sample_ids.clone()),
+                ])
+                .exec()
+                .await?;
+            Ok::<_, ApiError>((experiment_id, sample_ids, removed))
+        })
+        .await?;
+
+    if removed > 0 {
+        publish_sample_links(&state, experiment_id, sample_ids);
+    }
+    Ok(Json(removed))
+}
+
+async fn unlink_experiment_sample(
+    State(state): State<AppState>,
+    Path((experiment_id, sample_id)): Path<(String, String)>,
+) -> ApiResult<()> {
+    let link = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &experiment_id).await?;
+            let link = tx
+                .experiment_sample()
+                .delete(experiment_sample::experiment_id_sample_id(experiment_id, sample_id))
+                .exec()
+                .await?;
+            Ok::<_, ApiError>(link)
+        })
+        .await?;
+
+    publish_sample_links(&state, link.experiment_id, [link.sample_id]);
+    Ok(Json(()))
+}
+
+/// Linking or unlinking samples changes what both the experiment and the
+/// samples show
+fn publish_sample_links(
+    state: &AppState,
+    experiment_id: String,
+    sample_ids: impl IntoIterator<Item = String>,
+) {
+    state.events.publish(ChangeEvent::ExperimentUpdated { id: experiment_id });
+    for id in sample_ids {
+        state.events.publish(ChangeEvent::SampleUpdated { id });
+    }
 }
 
 // Experiment Mentions
+//
+// Rows are normally kept in sync with the notebook content on save (see
+// `mentions::sync_mentions`); snapshots are always taken server-side.
 #[derive(Deserialize)]
 pub struct CreateExperimentMentionRequest {
     pub entity_type: String,
     pub entity_id: String,
-    pub snapshot_data: String,
     pub position: Option<i32>,
 }
 
@@ -417,6 +969,7 @@ async fn list_experiment_mentions(
         .db
         .experiment_mention()
         .find_many(vec![experiment_mention::experiment_id::equals(experiment_id)])
+        .order_by(experiment_mention::position::order(SortOrder::Asc))
         .exec()
         .await
         .unwrap_or_default();
@@ -427,7 +980,13 @@ async fn create_experiment_mention(
     State(state): State<AppState>,
     Path(experiment_id): Path<String>,
     Json(payload): Json<CreateExperimentMentionRequest>,
-) -> Json<experiment_mention::Data> {
+) -> ApiResult<experiment_mention::Data> {
+    let snapshot = mentions::snapshot_entity(&state.db, &payload.entity_type, &payload.entity_id)
+        .await?
+        .ok_or_else(|| {
+            ApiError::NotFound(format!("{} {}", payload.entity_type, payload.entity_id))
+        })?;
+
     let mut params: Vec<experiment_mention::SetParam> = vec![];
     
     if let Some(position) = payload.position {
@@ -436,18 +995,28 @@ async fn create_experiment_mention(
 
     let mention = state
         .db
-        .experiment_mention()
-        .create(
-            experiment::id::equals(experiment_id),
-            payload.entity_type,
-            payload.entity_id,
-            payload.snapshot_data,
-            params,
-        )
-        .exec()
-        .await
-        .expect("Failed to create experiment mention");
-    Json(mention)
+        ._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &experiment_id).await?;
+            let mention = tx
+                .experiment_mention()
+                .create(
+                    experiment::id::equals(experiment_id),
+                    payload.entity_type,
+                    payload.entity_id,
+                    snapshot.to_string(),
+                    params,
+                )
+                .exec()
+                .await?;
+            Ok::<_, ApiError>(mention)
+        })
+        .await?;
+
+    state.events.publish(ChangeEvent::ExperimentUpdated {
+        id: mention.experiment_id.clone(),
+    });
+    Ok(Json(mention))
 }
 
 // Search entities for @mentions
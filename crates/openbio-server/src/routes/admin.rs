@@ -0,0 +1,60 @@
+//! Administrative endpoints
+//!
+//! Most of the API is open to anyone who can reach the Hub: the lab network
+//! is trusted for day-to-day work. Actions that hand out or take away an
+//! instrument's ingest access (issuing, rotating and revoking agent API keys,
+//! locking an agent) are not. [`require_admin`] lets them through when the
+//! request comes from the Hub's own machine (the desktop app, or a shell on
+//! the lab server), or when it carries `Authorization: Bearer <admin token>`
+//! with the token the server was started with (`--admin-token` /
+//! `OPENBIO_ADMIN_TOKEN`). Without a token, administration is only possible
+//! from the Hub's machine.
+//!
+//! A reverse proxy on the same machine makes every request it forwards look
+//! local, so it has to restrict these routes itself.
+
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use openbio_core::experiment::content_hash;
+
+use crate::{ApiError, AppState};
+
+/// Middleware on administrative routes
+pub(crate) async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let local = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .is_some_and(|ConnectInfo(peer)| peer.ip().to_canonical().is_loopback());
+    if local {
+        return Ok(next.run(request).await);
+    }
+
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty());
+    match (token, state.admin_token.as_deref()) {
+        // Hashes are compared so the time taken says nothing about the token
+        (Some(token), Some(admin)) if content_hash(token) == content_hash(admin) => {
+            Ok(next.run(request).await)
+        }
+        (Some(_), Some(_)) => Err(ApiError::Unauthorized("Invalid admin token".to_string())),
+        (None, Some(_)) => Err(ApiError::Unauthorized("Missing admin token".to_string())),
+        (_, None) => Err(ApiError::Forbidden(
+            "Only allowed from the Hub's own machine".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,370 @@
+//! Ingest agent endpoints
+//!
+//! Each instrument PC runs `openbio-agent`, which identifies itself with the
+//! instrument's `Equipment.id` (or `externalId`) and reports in every
+//! [`HEARTBEAT_INTERVAL_SECS`]. Agents that stay silent for
+//! [`HEARTBEAT_TIMEOUT_SECS`] are marked offline by [`watch_heartbeats`].
+//! What the agent watches is configured on the Equipment record and pulled by
+//! the agent from `/agent/config`. Agents authenticate with a per-instrument
+//! API key (see [`agent_keys`](super::agent_keys)). An administrator can lock
+//! an instrument's agent, which refuses its uploads until it is unlocked.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use openbio_core::agent::{
+    AgentConfig, FileFilter, Heartbeat, HeartbeatResponse, HEARTBEAT_INTERVAL_SECS,
+    HEARTBEAT_TIMEOUT_SECS,
+};
+use openbio_core::AgentStatus;
+use prisma_client_rust::chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::agent_keys::AgentIdentity;
+use super::bookings::find_equipment;
+use crate::db::prisma::{equipment, PrismaClient, SortOrder};
+use crate::events::ChangeEvent;
+use crate::{ApiError, ApiResult, AppState};
+
+/// POST /agent/heartbeat
+pub(super) async fn heartbeat(
+    State(state): State<AppState>,
+    Extension(agent): Extension<AgentIdentity>,
+    Json(beat): Json<Heartbeat>,
+) -> ApiResult<HeartbeatResponse> {
+    let equipment = find_agent_equipment(&state.db, &agent, &beat.machine_id).await?;
+    let previous = equipment
+        .agent_status
+        .parse()
+        .unwrap_or(AgentStatus::Offline);
+    // A locked instrument stays locked until an administrator unlocks it
+    let status = match previous {
+        AgentStatus::Locked => AgentStatus::Locked,
+        _ => AgentStatus::Online,
+    };
+
+    state
+        .db
+        .equipment()
+        .update(
+            equipment::id::equals(equipment.id.clone()),
+            vec![
+                equipment::agent_status::set(status.as_str().to_string()),
+                equipment::last_sync_at::set(Some(Utc::now().fixed_offset())),
+                equipment::agent_version::set(Some(beat.version)),
+                equipment::agent_watch_dir::set(beat.watch_dir),
+                equipment::agent_queue_depth::set(Some(
+                    i32::try_from(beat.queue_depth).unwrap_or(i32::MAX),
+                )),
+            ],
+        )
+        .exec()
+        .await?;
+
+    if previous == AgentStatus::Offline {
+        tracing::info!("Agent for {} ({}) is online", equipment.name, equipment.id);
+        state.events.publish(ChangeEvent::AgentOnline {
+            equipment_id: equipment.id.clone(),
+        });
+    }
+
+    Ok(Json(HeartbeatResponse {
+        equipment_id: equipment.id,
+        status,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct AgentConfigQuery {
+    pub machine_id: String,
+}
+
+/// GET /agent/config?machine_id=...
+pub(super) async fn agent_config(
+    State(state): State<AppState>,
+    Extension(agent): Extension<AgentIdentity>,
+    Query(query): Query<AgentConfigQuery>,
+) -> ApiResult<AgentConfig> {
+    let equipment = find_agent_equipment(&state.db, &agent, &query.machine_id).await?;
+    Ok(Json(config_of(&equipment)))
+}
+
+/// GET /equipment/{id}/agent-config
+pub(super) async fn get_equipment_agent_config(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<AgentConfig> {
+    let equipment = find_equipment(&state.db, &id).await?;
+    Ok(Json(config_of(&equipment)))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateAgentConfigRequest {
+    /// Empty string clears the folder
+    pub watch_folder: Option<String>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub auto_import: Option<bool>,
+}
+
+/// PATCH /equipment/{id}/agent-config
+///
+/// The agent picks up changes within [`CONFIG_POLL_SECS`](openbio_core::agent::CONFIG_POLL_SECS).
+pub(super) async fn update_equipment_agent_config(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateAgentConfigRequest>,
+) -> ApiResult<AgentConfig> {
+    let current = config_of(&find_equipment(&state.db, &id).await?);
+    // Reject globs the agent would not be able to use
+    FileFilter::new(
+        payload.include.as_deref().unwrap_or(&current.include),
+        payload.exclude.as_deref().unwrap_or(&current.exclude),
+    )?;
+
+    let mut params: Vec<equipment::SetParam> = vec![];
+    if let Some(watch_folder) = payload.watch_folder {
+        let watch_folder = watch_folder.trim().to_string();
+        params.push(equipment::watch_folder::set(
+            (!watch_folder.is_empty()).then_some(watch_folder),
+        ));
+    }
+    if let Some(include) = payload.include {
+        params.push(equipment::include_globs::set(globs_json(&include)));
+    }
+    if let Some(exclude) = payload.exclude {
+        params.push(equipment::exclude_globs::set(globs_json(&exclude)));
+    }
+    if let Some(auto_import) = payload.auto_import {
+        params.push(equipment::auto_import::set(auto_import));
+    }
+
+    let equipment = state
+        .db
+        .equipment()
+        .update(equipment::id::equals(id), params)
+        .exec()
+        .await?;
+
+    state.events.publish(ChangeEvent::EquipmentUpdated {
+        id: equipment.id.clone(),
+    });
+    Ok(Json(config_of(&equipment)))
+}
+
+fn config_of(equipment: &equipment::Data) -> AgentConfig {
+    AgentConfig {
+        equipment_id: equipment.id.clone(),
+        watch_folder: equipment.watch_folder.clone(),
+        include: serde_json::from_str(&equipment.include_globs).unwrap_or_default(),
+        exclude: serde_json::from_str(&equipment.exclude_globs).unwrap_or_default(),
+        auto_import: equipment.auto_import,
+    }
+}
+
+fn globs_json(globs: &[String]) -> String {
+    let globs: Vec<&str> = globs
+        .iter()
+        .map(|glob| glob.trim())
+        .filter(|glob| !glob.is_empty())
+        .collect();
+    serde_json::to_string(&globs).unwrap_or_default()
+}
+
+/// An instrument's agent as last reported
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentOverview {
+    pub equipment_id: String,
+    pub equipment_name: String,
+    pub equipment_type: String,
+    pub location: Option<String>,
+    pub status: String,
+    pub last_seen_at: Option<DateTime<FixedOffset>>,
+    pub version: Option<String>,
+    /// Directory the agent reported watching
+    pub watch_dir: Option<String>,
+    pub queue_depth: Option<i32>,
+    pub auto_import: bool,
+}
+
+/// GET /equipment/agents
+///
+/// Every instrument that has an agent configured or has ever reported in.
+pub(super) async fn list_agents(State(state): State<AppState>) -> ApiResult<Vec<AgentOverview>> {
+    let equipment = state
+        .db
+        .equipment()
+        .find_many(vec![prisma_client_rust::or![
+            equipment::last_sync_at::not(None),
+            equipment::watch_folder::not(None),
+        ]])
+        .order_by(equipment::name::order(SortOrder::Asc))
+        .exec()
+        .await?;
+
+    Ok(Json(equipment.into_iter().map(overview_of).collect()))
+}
+
+fn overview_of(equipment: equipment::Data) -> AgentOverview {
+    AgentOverview {
+        equipment_id: equipment.id,
+        equipment_name: equipment.name,
+        equipment_type: equipment.r#type,
+        location: equipment.location,
+        status: equipment.agent_status,
+        last_seen_at: equipment.last_sync_at,
+        version: equipment.agent_version,
+        watch_dir: equipment.agent_watch_dir,
+        queue_depth: equipment.agent_queue_depth,
+        auto_import: equipment.auto_import,
+    }
+}
+
+/// POST /equipment/{id}/agent-lock
+///
+/// Refuse the instrument's uploads until it is unlocked, e.g. while it is
+/// out of calibration. Heartbeats are still recorded but leave it `LOCKED`.
+pub(super) async fn lock_agent(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<AgentOverview> {
+    let equipment = find_equipment(&state.db, &id).await?;
+    if equipment.agent_status == AgentStatus::Locked.as_str() {
+        return Ok(Json(overview_of(equipment)));
+    }
+
+    let equipment = state
+        .db
+        .equipment()
+        .update(
+            equipment::id::equals(id),
+            vec![equipment::agent_status::set(AgentStatus::Locked.as_str().to_string())],
+        )
+        .exec()
+        .await?;
+
+    tracing::info!("Agent for {} ({}) is locked", equipment.name, equipment.id);
+    state.events.publish(ChangeEvent::AgentLocked {
+        equipment_id: equipment.id.clone(),
+    });
+    Ok(Json(overview_of(equipment)))
+}
+
+/// DELETE /equipment/{id}/agent-lock
+///
+/// The agent is `OFFLINE` until its next heartbeat brings it back online.
+pub(super) async fn unlock_agent(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<AgentOverview> {
+    find_equipment(&state.db, &id).await?;
+
+    // Guarded so that unlocking twice does not knock an online agent offline
+    let unlocked = state
+        .db
+        .equipment()
+        .update_many(
+            vec![
+                equipment::id::equals(id.clone()),
+                equipment::agent_status::equals(AgentStatus::Locked.as_str().to_string()),
+            ],
+            vec![equipment::agent_status::set(AgentStatus::Offline.as_str().to_string())],
+        )
+        .exec()
+        .await?;
+
+    let equipment = find_equipment(&state.db, &id).await?;
+    if unlocked > 0 {
+        tracing::info!("Agent for {} ({}) is unlocked", equipment.name, equipment.id);
+        state.events.publish(ChangeEvent::AgentUnlocked {
+            equipment_id: equipment.id.clone(),
+        });
+    }
+    Ok(Json(overview_of(equipment)))
+}
+
+/// Background task marking agents offline once their heartbeats stop
+pub(crate) async fn watch_heartbeats(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = mark_silent_agents_offline(&state).await {
+            tracing::error!("Failed to check agent heartbeats: {}", e);
+        }
+    }
+}
+
+async fn mark_silent_agents_offline(state: &AppState) -> Result<(), ApiError> {
+    let cutoff = Utc::now().fixed_offset() - TimeDelta::seconds(HEARTBEAT_TIMEOUT_SECS as i64);
+    let online = || equipment::agent_status::equals(AgentStatus::Online.as_str().to_string());
+
+    let silent = state
+        .db
+        .equipment()
+        .find_many(vec![online(), equipment::last_sync_at::lt(cutoff)])
+        .exec()
+        .await?;
+
+    for equipment in silent {
+        // Guarded so a heartbeat arriving in between keeps the agent online
+        let updated = state
+            .db
+            .equipment()
+            .update_many(
+                vec![
+                    equipment::id::equals(equipment.id.clone()),
+                    online(),
+                    equipment::last_sync_at::lt(cutoff),
+                ],
+                vec![equipment::agent_status::set(
+                    AgentStatus::Offline.as_str().to_string(),
+                )],
+            )
+            .exec()
+            .await?;
+
+        if updated > 0 {
+            tracing::warn!(
+                "Agent for {} ({}) missed its heartbeats, marking it offline",
+                equipment.name,
+                equipment.id
+            );
+            state.events.publish(ChangeEvent::AgentOffline {
+                equipment_id: equipment.id,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Instrument an agent's machine ID refers to (`Equipment.id` or `externalId`),
+/// which must be the one the agent's API key was issued for
+pub(crate) async fn find_agent_equipment(
+    db: &PrismaClient,
+    agent: &AgentIdentity,
+    machine_id: &str,
+) -> Result<equipment::Data, ApiError> {
+    let equipment = db
+        .equipment()
+        .find_first(vec![prisma_client_rust::or![
+            equipment::id::equals(machine_id.to_string()),
+            equipment::external_id::equals(Some(machine_id.to_string())),
+        ]])
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Equipment for machine {}", machine_id)))?;
+    agent.check(&equipment.id)?;
+    Ok(equipment)
+}
+
+/// Machine IDs an instrument's agent may use; uploads record whichever it sent
+pub(crate) fn agent_machine_ids(equipment: &equipment::Data) -> Vec<String> {
+    let mut ids = vec![equipment.id.clone()];
+    ids.extend(equipment.external_id.clone());
+    ids
+}
@@ -0,0 +1,339 @@
+//! API keys for ingest agents
+//!
+//! Every request to `/api/agent/*` carries `Authorization: Bearer <key>`,
+//! checked by [`require_agent_key`]. Keys belong to one instrument and only
+//! work on the agent endpoints, for that instrument. Only a SHA-256 of each
+//! key is stored; the key itself is returned once, when it is issued.
+//!
+//! Rotating a key issues a new one and lets the old one keep working for a
+//! grace period, so the agent can be reconfigured without dropping uploads.
+
+use axum::{
+    extract::{Path, Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use openbio_core::experiment::content_hash;
+use prisma_client_rust::chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::bookings::find_equipment;
+use crate::db::prisma::{agent_api_key, equipment, SortOrder};
+use crate::events::ChangeEvent;
+use crate::{ApiError, ApiResult, AppState};
+
+/// Start of every key, so leaked keys are easy to recognise
+const KEY_PREFIX: &str = "obk_";
+
+/// Characters of the key kept in the clear to tell keys apart
+const DISPLAY_PREFIX_LEN: usize = KEY_PREFIX.len() + 8;
+
+/// `lastUsedAt` is only written when it is older than this, so heartbeats and
+/// chunk uploads don't each cost a write
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+/// How long a rotated key keeps working by default
+const DEFAULT_ROTATION_GRACE_MINUTES: i64 = 60;
+
+/// Longest grace period for a rotated key (30 days)
+const MAX_ROTATION_GRACE_MINUTES: i64 = 30 * 24 * 60;
+
+/// The instrument a request was authenticated for, added to request
+/// extensions by [`require_agent_key`]
+#[derive(Debug, Clone)]
+pub(crate) struct AgentIdentity {
+    pub equipment_id: String,
+}
+
+impl AgentIdentity {
+    /// Keys only act for their own instrument
+    pub fn check(&self, equipment_id: &str) -> Result<(), ApiError> {
+        if self.equipment_id != equipment_id {
+            return Err(ApiError::Forbidden(
+                "API key belongs to a different instrument".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Middleware on `/api/agent/*`
+pub(crate) async fn require_agent_key(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| ApiError::Unauthorized("Missing API key".to_string()))?;
+
+    let now = Utc::now().fixed_offset();
+    let key = state
+        .db
+        .agent_api_key()
+        .find_unique(agent_api_key::key_hash::equals(content_hash(token)))
+        .exec()
+        .await?
+        .filter(|key| is_active(key, now))
+        .ok_or_else(|| ApiError::Unauthorized("Unknown or inactive API key".to_string()))?;
+
+    let stale = key
+        .last_used_at
+        .is_none_or(|used| now - used >= TimeDelta::seconds(LAST_USED_RESOLUTION_SECS));
+    if stale {
+        state
+            .db
+            .agent_api_key()
+            .update(
+                agent_api_key::id::equals(key.id.clone()),
+                vec![agent_api_key::last_used_at::set(Some(now))],
+            )
+            .exec()
+            .await?;
+    }
+
+    request.extensions_mut().insert(AgentIdentity {
+        equipment_id: key.equipment_id,
+    });
+    Ok(next.run(request).await)
+}
+
+/// An API key without its secret
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentApiKeyInfo {
+    pub id: String,
+    pub equipment_id: String,
+    pub name: Option<String>,
+    /// Start of the key, e.g. `obk_1a2b3c4d`
+    pub prefix: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub created_by: Option<String>,
+    pub last_used_at: Option<DateTime<FixedOffset>>,
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    pub revoked_at: Option<DateTime<FixedOffset>>,
+    pub replaced_by_id: Option<String>,
+    /// Not revoked and not expired
+    pub active: bool,
+}
+
+impl From<agent_api_key::Data> for AgentApiKeyInfo {
+    fn from(key: agent_api_key::Data) -> Self {
+        let active = is_active(&key, Utc::now().fixed_offset());
+        Self {
+            id: key.id,
+            equipment_id: key.equipment_id,
+            name: key.name,
+            prefix: key.prefix,
+            created_at: key.created_at,
+            created_by: key.created_by,
+            last_used_at: key.last_used_at,
+            expires_at: key.expires_at,
+            revoked_at: key.revoked_at,
+            replaced_by_id: key.replaced_by_id,
+            active,
+        }
+    }
+}
+
+/// A newly issued key; `key` is not shown again
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IssuedAgentApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub info: AgentApiKeyInfo,
+}
+
+/// GET /equipment/{id}/api-keys
+pub(super) async fn list_api_keys(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<AgentApiKeyInfo>> {
+    find_equipment(&state.db, &id).await?;
+    let keys = state
+        .db
+        .agent_api_key()
+        .find_many(vec![agent_api_key::equipment_id::equals(id)])
+        .order_by(agent_api_key::created_at::order(SortOrder::Desc))
+        .exec()
+        .await?;
+    Ok(Json(keys.into_iter().map(AgentApiKeyInfo::from).collect()))
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: Option<String>,
+    pub created_by: Option<String>,
+}
+
+/// POST /equipment/{id}/api-keys
+pub(super) async fn create_api_key(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> ApiResult<IssuedAgentApiKey> {
+    find_equipment(&state.db, &id).await?;
+    let (key, data) = issue_key(&state, &id, payload.name, payload.created_by).await?;
+    tracing::info!("Issued agent API key {} for equipment {}", data.prefix, id);
+    state.events.publish(ChangeEvent::EquipmentUpdated { id });
+    Ok(Json(IssuedAgentApiKey {
+        key,
+        info: data.into(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct RotateApiKeyRequest {
+    /// How long the old key keeps working (default 60, at most 30 days; 0 ends it now)
+    pub grace_minutes: Option<i64>,
+    pub created_by: Option<String>,
+}
+
+/// POST /equipment/{id}/api-keys/{key_id}/rotate
+///
+/// Issues a replacement with the same name; the old key expires after the
+/// grace period.
+pub(super) async fn rotate_api_key(
+    State(state): State<AppState>,
+    Path((id, key_id)): Path<(String, String)>,
+    Json(payload): Json<RotateApiKeyRequest>,
+) -> ApiResult<IssuedAgentApiKey> {
+    let grace_minutes = payload
+        .grace_minutes
+        .unwrap_or(DEFAULT_ROTATION_GRACE_MINUTES);
+    if !(0..=MAX_ROTATION_GRACE_MINUTES).contains(&grace_minutes) {
+        return Err(ApiError::BadRequest(format!(
+            "grace_minutes must be between 0 and {} (30 days)",
+            MAX_ROTATION_GRACE_MINUTES
+        )));
+    }
+
+    let old = find_key(&state, &id, &key_id).await?;
+    let now = Utc::now().fixed_offset();
+    if !is_active(&old, now) {
+        return Err(ApiError::Conflict(format!(
+            "API key {} is no longer active",
+            old.prefix
+        )));
+    }
+
+    let (key, data) = issue_key(&state, &id, old.name.clone(), payload.created_by).await?;
+    let expires_at = now + TimeDelta::minutes(grace_minutes);
+    state
+        .db
+        .agent_api_key()
+        .update(
+            agent_api_key::id::equals(old.id),
+            vec![
+                agent_api_key::expires_at::set(Some(
+                    old.expires_at.map_or(expires_at, |at| at.min(expires_at)),
+                )),
+                agent_api_key::replaced_by_id::set(Some(data.id.clone())),
+            ],
+        )
+        .exec()
+        .await?;
+
+    tracing::info!(
+        "Rotated agent API key {} to {} for equipment {}",
+        old.prefix,
+        data.prefix,
+        id
+    );
+    state.events.publish(ChangeEvent::EquipmentUpdated { id });
+    Ok(Json(IssuedAgentApiKey {
+        key,
+        info: data.into(),
+    }))
+}
+
+/// DELETE /equipment/{id}/api-keys/{key_id}
+///
+/// Revokes the key at once. The record is kept for the audit trail.
+pub(super) async fn revoke_api_key(
+    State(state): State<AppState>,
+    Path((id, key_id)): Path<(String, String)>,
+) -> ApiResult<AgentApiKeyInfo> {
+    let key = find_key(&state, &id, &key_id).await?;
+    if key.revoked_at.is_some() {
+        return Ok(Json(key.into()));
+    }
+
+    let key = state
+        .db
+        .agent_api_key()
+        .update(
+            agent_api_key::id::equals(key.id),
+            vec![agent_api_key::revoked_at::set(Some(
+                Utc::now().fixed_offset(),
+            ))],
+        )
+        .exec()
+        .await?;
+    tracing::info!("Revoked agent API key {} for equipment {}", key.prefix, id);
+    state.events.publish(ChangeEvent::EquipmentUpdated { id });
+    Ok(Json(key.into()))
+}
+
+/// Create a key for an instrument: the key itself and its stored record
+async fn issue_key(
+    state: &AppState,
+    equipment_id: &str,
+    name: Option<String>,
+    created_by: Option<String>,
+) -> Result<(String, agent_api_key::Data), ApiError> {
+    let key = format!(
+        "{}{}{}",
+        KEY_PREFIX,
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let name = name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+
+    let data = state
+        .db
+        .agent_api_key()
+        .create(
+            equipment::id::equals(equipment_id.to_string()),
+            key[..DISPLAY_PREFIX_LEN].to_string(),
+            content_hash(&key),
+            vec![
+                agent_api_key::name::set(name),
+                agent_api_key::created_by::set(created_by),
+            ],
+        )
+        .exec()
+        .await?;
+    Ok((key, data))
+}
+
+async fn find_key(
+    state: &AppState,
+    equipment_id: &str,
+    key_id: &str,
+) -> Result<agent_api_key::Data, ApiError> {
+    state
+        .db
+        .agent_api_key()
+        .find_first(vec![
+            agent_api_key::id::equals(key_id.to_string()),
+            agent_api_key::equipment_id::equals(equipment_id.to_string()),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("API key {}", key_id)))
+}
+
+fn is_active(key: &agent_api_key::Data, now: DateTime<FixedOffset>) -> bool {
+    key.revoked_at.is_none() && key.expires_at.is_none_or(|at| at > now)
+}
@@ -0,0 +1,346 @@
+//! Equipment booking calendar
+//!
+//! A booking is an experiment scheduled on an instrument from `scheduledAt` to
+//! `scheduledEnd`. Bookings on the same instrument may not overlap, and an
+//! instrument whose calibration has lapsed can only be booked with a reason.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use openbio_core::ExperimentStatus;
+use prisma_client_rust::chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{maintenance, signatures};
+use crate::db::prisma::{equipment, experiment, experiment_entry, PrismaClient, SortOrder};
+use crate::events::ChangeEvent;
+use crate::ics::{render_calendar, CalendarEvent};
+use crate::{ApiError, ApiResult, AppState};
+
+/// Length assumed for bookings made before end times were recorded
+const DEFAULT_BOOKING_HOURS: i64 = 1;
+
+/// A slot on an instrument's calendar
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Booking {
+    pub experiment_id: String,
+    pub experiment_name: String,
+    pub status: String,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    pub created_by: Option<String>,
+}
+
+impl Booking {
+    fn from_experiment(experiment: &experiment::Data) -> Option<Self> {
+        let start = experiment.scheduled_at?;
+        Some(Booking {
+            experiment_id: experiment.id.clone(),
+            experiment_name: experiment.name.clone(),
+            status: experiment.status.clone(),
+            start,
+            end: booking_end(start, experiment.scheduled_end),
+            created_by: experiment.created_by.clone(),
+        })
+    }
+
+    fn overlaps(&self, start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BookingRangeQuery {
+    /// RFC 3339 time or YYYY-MM-DD; bookings ending after this
+    pub from: Option<String>,
+    /// RFC 3339 time or YYYY-MM-DD; bookings starting before this
+    pub to: Option<String>,
+}
+
+/// GET /equipment/{id}/bookings?from=2026-10-01&to=2026-11-01
+pub(super) async fn list_bookings(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<BookingRangeQuery>,
+) -> ApiResult<Vec<Booking>> {
+    find_equipment(&state.db, &id).await?;
+
+    let from = query.from.as_deref().map(parse_time).transpose()?;
+    let to = query.to.as_deref().map(parse_time).transpose()?;
+
+    let bookings = equipment_bookings(&state.db, &id)
+        .await?
+        .into_iter()
+        .filter(|b| from.is_none_or(|from| b.end > from))
+        .filter(|b| to.is_none_or(|to| b.start < to))
+        .collect();
+    Ok(Json(bookings))
+}
+
+#[derive(Deserialize)]
+pub struct CreateBookingRequest {
+    pub experiment_id: String,
+    /// RFC 3339
+    pub start: String,
+    /// RFC 3339
+    pub end: String,
+    /// Required to book an instrument whose calibration has lapsed; recorded
+    /// as an entry on the experiment
+    pub override_reason: Option<String>,
+    pub actor: Option<String>,
+}
+
+/// POST /equipment/{id}/bookings
+///
+/// Schedules the experiment on this instrument (moving it if it was already booked).
+/// Overlapping another booking returns 409, as does booking an instrument whose
+/// calibration is overdue at the start time unless `override_reason` is given.
+pub(super) async fn create_booking(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<CreateBookingRequest>,
+) -> ApiResult<Booking> {
+    let start = parse_time(&payload.start)?;
+    let end = parse_time(&payload.end)?;
+    if end <= start {
+        return Err(ApiError::Unprocessable(
+            "A booking has to end after it starts".to_string(),
+        ));
+    }
+
+    let equipment = find_equipment(&state.db, &id).await?;
+
+    let override_reason = payload
+        .override_reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+    let override_note = match maintenance::lapsed_calibration(&state.db, &id, start).await? {
+        Some(calibration) => match &override_reason {
+            Some(reason) => Some(format!(
+                "Booked {} although its calibration was due {}: {}",
+                equipment.name,
+                calibration.next_due_at.to_rfc3339(),
+                reason
+            )),
+            None => {
+                return Err(ApiError::Conflict(format!(
+                    "Calibration of {} was due {}; give an override_reason to book it anyway",
+                    equipment.name,
+                    calibration.next_due_at.to_rfc3339()
+                )))
+            }
+        },
+        None => None,
+    };
+
+    let experiment = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &payload.experiment_id).await?;
+
+            let existing = equipment_bookings(&tx, &id).await?;
+            if let Some(conflict) = existing
+                .iter()
+                .find(|b| b.experiment_id != payload.experiment_id && b.overlaps(start, end))
+            {
+                return Err(ApiError::Conflict(format!(
+                    "Equipment {} is booked by \"{}\" from {} to {}",
+                    id,
+                    conflict.experiment_name,
+                    conflict.start.to_rfc3339(),
+                    conflict.end.to_rfc3339()
+                )));
+            }
+
+            let experiment = tx
+                .experiment()
+                .update(
+                    experiment::id::equals(payload.experiment_id),
+                    vec![
+                        experiment::equipment::connect(equipment::id::equals(id)),
+                        experiment::scheduled_at::set(Some(start)),
+                        experiment::scheduled_end::set(Some(end)),
+                    ],
+                )
+                .exec()
+                .await?;
+
+            if let Some(note) = override_note {
+                tx.experiment_entry()
+                    .create(
+                        experiment::id::equals(experiment.id.clone()),
+                        note,
+                        vec![experiment_entry::author::set(payload.actor)],
+                    )
+                    .exec()
+                    .await?;
+            }
+            Ok::<_, ApiError>(experiment)
+        })
+        .await?;
+
+    state.events.publish(ChangeEvent::ExperimentUpdated {
+        id: experiment.id.clone(),
+    });
+    Booking::from_experiment(&experiment)
+        .map(Json)
+        .ok_or_else(|| ApiError::Internal("Booking was not saved".to_string()))
+}
+
+/// DELETE /equipment/{id}/bookings/{experiment_id}
+///
+/// Frees the slot; the experiment keeps its equipment.
+pub(super) async fn cancel_booking(
+    State(state): State<AppState>,
+    Path((id, experiment_id)): Path<(String, String)>,
+) -> ApiResult<()> {
+    let experiment_id = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &experiment_id).await?;
+            let cancelled = tx
+                .experiment()
+                .update_many(
+                    vec![
+                        experiment::id::equals(experiment_id.clone()),
+                        experiment::equipment_id::equals(Some(id.clone())),
+                    ],
+                    vec![
+                        experiment::scheduled_at::set(None),
+                        experiment::scheduled_end::set(None),
+                    ],
+                )
+                .exec()
+                .await?;
+
+            if cancelled == 0 {
+                return Err(ApiError::NotFound(format!(
+                    "Booking of equipment {} by experiment {}",
+                    id, experiment_id
+                )));
+            }
+            Ok::<_, ApiError>(experiment_id)
+        })
+        .await?;
+
+    state.events.publish(ChangeEvent::ExperimentUpdated { id: experiment_id });
+    Ok(Json(()))
+}
+
+/// GET /equipment/{id}/bookings.ics
+///
+/// iCalendar feed people can subscribe to from their calendar app.
+pub(super) async fn bookings_calendar(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, ApiError> {
+    let equipment = find_equipment(&state.db, &id).await?;
+
+    let experiments = booked_experiments(&state.db, &id).await?;
+    let events: Vec<CalendarEvent> = experiments
+        .iter()
+        .filter_map(|experiment| {
+            let booking = Booking::from_experiment(experiment)?;
+            let mut description = format!("Status: {}", booking.status);
+            if let Some(created_by) = &booking.created_by {
+                description.push_str(&format!("\nBooked by: {}", created_by));
+            }
+            Some(CalendarEvent {
+                uid: format!("{}@openbio", booking.experiment_id),
+                start: booking.start.with_timezone(&Utc),
+                end: booking.end.with_timezone(&Utc),
+                stamp: experiment.updated_at.with_timezone(&Utc),
+                summary: format!("{}: {}", equipment.name, booking.experiment_name),
+                description: Some(description),
+            })
+        })
+        .collect();
+
+    let calendar = render_calendar(&equipment.name, &events);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/calendar; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"{}.ics\"", equipment.id),
+            ),
+        ],
+        calendar,
+    )
+        .into_response())
+}
+
+/// All bookings of an instrument, earliest first
+pub(crate) async fn equipment_bookings(
+    db: &PrismaClient,
+    equipment_id: &str,
+) -> Result<Vec<Booking>, ApiError> {
+    Ok(booked_experiments(db, equipment_id)
+        .await?
+        .iter()
+        .filter_map(Booking::from_experiment)
+        .collect())
+}
+
+/// Experiments holding a slot on the instrument. Finished (completed or
+/// failed) experiments give their slot back; cancelled bookings have no
+/// `scheduledAt` left.
+async fn booked_experiments(
+    db: &PrismaClient,
+    equipment_id: &str,
+) -> Result<Vec<experiment::Data>, ApiError> {
+    Ok(db
+        .experiment()
+        .find_many(vec![
+            experiment::equipment_id::equals(Some(equipment_id.to_string())),
+            experiment::scheduled_at::not(None),
+            experiment::status::not_in_vec(vec![
+                ExperimentStatus::Completed.as_str().to_string(),
+                ExperimentStatus::Failed.as_str().to_string(),
+            ]),
+        ])
+        .order_by(experiment::scheduled_at::order(SortOrder::Asc))
+        .exec()
+        .await?)
+}
+
+pub(crate) async fn find_equipment(
+    db: &PrismaClient,
+    id: &str,
+) -> Result<equipment::Data, ApiError> {
+    db.equipment()
+        .find_unique(equipment::id::equals(id.to_string()))
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Equipment {}", id)))
+}
+
+fn booking_end(
+    start: DateTime<FixedOffset>,
+    end: Option<DateTime<FixedOffset>>,
+) -> DateTime<FixedOffset> {
+    end.unwrap_or(start + Duration::hours(DEFAULT_BOOKING_HOURS))
+}
+
+/// Parse an RFC 3339 time, or a date meaning midnight UTC
+pub(crate) fn parse_time(value: &str) -> Result<DateTime<FixedOffset>, ApiError> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time);
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|midnight| midnight.and_utc().fixed_offset())
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "Invalid time {:?} (expected RFC 3339 or YYYY-MM-DD)",
+                value
+            ))
+        })
+}
@@ -0,0 +1,231 @@
+//! WebSocket endpoint for collaborative notebook editing
+//!
+//! See [`crate::collab`] for the protocol.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    response::Response,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+
+use super::{mentions, revisions, signatures};
+use crate::collab::{ClientMessage, PendingSave, Room, ServerMessage};
+use crate::db::prisma::{experiment, PrismaClient};
+use crate::events::ChangeEvent;
+use crate::{ApiError, AppState};
+
+/// How long a room has to be quiet before its document is saved
+const SAVE_AFTER_IDLE: Duration = Duration::from_secs(3);
+
+/// Wait before saving again after a failed save
+const SAVE_RETRY: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+pub struct CollabQuery {
+    /// Display name shown to other peers
+    pub user: Option<String>,
+}
+
+/// GET /experiments/{id}/collab (WebSocket upgrade)
+pub(super) async fn notebook_socket(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<CollabQuery>,
+) -> Result<Response, ApiError> {
+    // Load the stored document up front so unknown experiments get a 404
+    let experiment = state
+        .db
+        .experiment()
+        .find_unique(experiment::id::equals(id.clone()))
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", id)))?;
+
+    let doc = parse_document(&experiment.content);
+    let read_only = experiment.locked_at.is_some();
+    let user = query.user.unwrap_or_else(|| "Anonymous".to_string());
+
+    Ok(ws.on_upgrade(move |socket| async move {
+        let (room, conn_id, init, rx) =
+            state.collab.join(&id, user, doc, experiment.content, read_only);
+        run_session(socket, room, conn_id, init, rx).await;
+    }))
+}
+
+async fn run_session(
+    mut socket: WebSocket,
+    room: Arc<Room>,
+    conn_id: u64,
+    init: ServerMessage,
+    mut rx: tokio::sync::broadcast::Receiver<Arc<str>>,
+) {
+    if send(&mut socket, &init.to_json()).await.is_err() {
+        room.leave(conn_id);
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = match serde_json::from_str::<ClientMessage>(text.as_str()) {
+                        Ok(message) => room.handle(conn_id, message),
+                        Err(e) => Some(ServerMessage::Error { message: e.to_string() }),
+                    };
+                    if let Some(reply) = reply {
+                        if send(&mut socket, &reply.to_json()).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            outgoing = rx.recv() => match outgoing {
+                Ok(text) => {
+                    if send(&mut socket, &text).await.is_err() {
+                        break;
+                    }
+                }
+                // Missed broadcasts cannot be replayed; reload the client
+                Err(RecvError::Lagged(_)) => {
+                    if send(&mut socket, &room.init_for(conn_id).to_json()).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+
+    room.leave(conn_id);
+}
+
+async fn send(socket: &mut WebSocket, text: &str) -> Result<(), axum::Error> {
+    socket.send(Message::Text(text.to_owned().into())).await
+}
+
+/// The stored notebook as sent to editors
+fn parse_document(content: &str) -> Value {
+    match content {
+        "" => Value::Null,
+        content => serde_json::from_str(content).unwrap_or(Value::String(content.to_string())),
+    }
+}
+
+/// Refuse REST writes of the content of a notebook whose editing room has
+/// changes it has not saved yet; they would be lost to one side or the other
+pub(crate) fn ensure_room_saved(state: &AppState, experiment_id: &str) -> Result<(), ApiError> {
+    match state.collab.room(experiment_id) {
+        Some(room) if room.has_unsaved_changes() => Err(ApiError::Conflict(format!(
+            "Experiment {} has unsaved changes from collaborative editing; try again once they are saved",
+            experiment_id
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Content was written through the REST API: an open room reloads it
+pub(crate) fn content_written(state: &AppState, experiment_id: &str, content: &str) {
+    if let Some(room) = state.collab.room(experiment_id) {
+        room.replace_content(parse_document(content), content.to_string());
+    }
+}
+
+/// Background task persisting merged documents from editing rooms. Once
+/// `stop` turns true it saves every changed document one last time and ends.
+pub(crate) async fn save_rooms(state: AppState, mut stop: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop.wait_for(|stop| *stop) => break,
+        }
+        save_pending(&state, state.collab.take_pending(SAVE_AFTER_IDLE)).await;
+    }
+
+    let unsaved = state.collab.take_unsaved();
+    if !unsaved.is_empty() {
+        tracing::info!("Saving {} notebook(s) before shutting down", unsaved.len());
+    }
+    save_pending(&state, unsaved).await;
+}
+
+async fn save_pending(state: &AppState, pending: Vec<PendingSave>) {
+    for pending in pending {
+        let experiment_id = pending.experiment_id.clone();
+        let (version, content) = (pending.version, pending.content.clone());
+        let room = state.collab.room(&experiment_id);
+
+        match save_document(&state.db, pending).await {
+            Ok(()) => {
+                if let Some(room) = room {
+                    room.saved(version, content);
+                }
+                state.events.publish(ChangeEvent::ExperimentUpdated { id: experiment_id });
+            }
+            Err(ApiError::Conflict(message)) => {
+                // Signed or rewritten while people were editing: the room becomes
+                // read-only and the next to open the notebook gets the stored version
+                if let Some(room) = state.collab.close(&experiment_id) {
+                    room.set_read_only(&message);
+                }
+            }
+            Err(e) => {
+                tracing::error!("Failed to save notebook {}: {}", experiment_id, e);
+                if let Some(room) = room {
+                    room.save_failed(SAVE_RETRY);
+                }
+            }
+        }
+    }
+}
+
+async fn save_document(db: &PrismaClient, pending: PendingSave) -> Result<(), ApiError> {
+    db._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &pending.experiment_id).await?;
+
+            // Only replace the content the room started from
+            let written = tx
+                .experiment()
+                .update_many(
+                    vec![
+                        experiment::id::equals(pending.experiment_id.clone()),
+                        experiment::content::equals(pending.base_content),
+                    ],
+                    vec![experiment::content::set(pending.content.clone())],
+                )
+                .exec()
+                .await?;
+            if written == 0 {
+                return Err(ApiError::Conflict(format!(
+                    "Experiment {} was changed outside this editing session; reopen it to continue",
+                    pending.experiment_id
+                )));
+            }
+
+            mentions::sync_mentions(&tx, &pending.experiment_id, &pending.content).await?;
+            revisions::record_revision(
+                &tx,
+                &pending.experiment_id,
+                pending.content,
+                pending.author,
+                None,
+            )
+            .await?;
+
+            Ok::<_, ApiError>(())
+        })
+        .await
+}
@@ -0,0 +1,56 @@
+//! Server-sent event stream of data changes
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use serde::Deserialize;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    /// Comma-separated topics (samples, experiments, equipment, assets, agents);
+    /// all if omitted
+    pub topics: Option<String>,
+}
+
+/// GET /events?topics=samples,experiments
+///
+/// Each SSE event is named after its topic and carries the JSON `ChangeEvent`.
+/// A `resync` event means the client missed events and should refetch everything.
+pub(super) async fn event_stream(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let topics: Option<Vec<String>> = query.topics.map(|t| {
+        t.split(',')
+            .map(|topic| topic.trim().to_lowercase())
+            .filter(|topic| !topic.is_empty())
+            .collect()
+    });
+
+    let stream = BroadcastStream::new(state.events.subscribe()).filter_map(move |received| {
+        match received {
+            Ok(event) => {
+                let wanted = topics
+                    .as_ref()
+                    .is_none_or(|topics| topics.iter().any(|t| t == event.topic()));
+                if !wanted {
+                    return None;
+                }
+                Event::default().event(event.topic()).json_data(&event).ok().map(Ok)
+            }
+            Err(BroadcastStreamRecvError::Lagged(missed)) => Some(Ok(Event::default()
+                .event("resync")
+                .data(missed.to_string()))),
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
+}
@@ -0,0 +1,167 @@
+//! Experiment export (PDF / HTML / Markdown report)
+
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use prisma_client_rust::chrono::Utc;
+use serde::Deserialize;
+
+use super::signatures;
+use crate::db::prisma::{experiment, experiment_sample, sample};
+use crate::export::{
+    ExportFormat, Report, ReportAsset, ReportEntry, ReportMention, ReportSample, ReportSignature,
+};
+use crate::{ApiError, AppState};
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    /// pdf, html or md (default: pdf)
+    pub format: Option<String>,
+}
+
+/// GET /experiments/{id}/export?format=pdf|html|md
+pub(super) async fn export_experiment(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, ApiError> {
+    let format: ExportFormat = query.format.as_deref().unwrap_or("pdf").parse()?;
+
+    let experiment = state
+        .db
+        .experiment()
+        .find_unique(experiment::id::equals(id.clone()))
+        .with(experiment::entries::fetch(vec![]))
+        .with(experiment::mentions::fetch(vec![]))
+        .with(experiment::signatures::fetch(vec![]))
+        .with(experiment::assets::fetch(vec![]))
+        .with(
+            experiment::samples::fetch(vec![])
+                .with(experiment_sample::sample::fetch().with(sample::container::fetch())),
+        )
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", id)))?;
+
+    let report = build_report(&experiment)?;
+    let body = report.render(format)?;
+
+    let filename = format!("{}.{}", file_stem(&experiment.name), format.extension());
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+fn build_report(experiment: &experiment::Data) -> Result<Report, ApiError> {
+    let entries = experiment.entries().map_err(internal)?;
+
+    let mut sorted_entries: Vec<_> = entries.iter().collect();
+    sorted_entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.id.cmp(&b.id)));
+
+    let mut signatures: Vec<_> = experiment.signatures().map_err(internal)?.iter().collect();
+    signatures.sort_by_key(|s| s.signed_at);
+
+    let mut mentions: Vec<_> = experiment.mentions().map_err(internal)?.iter().collect();
+    mentions.sort_by_key(|m| (m.position, m.created_at));
+
+    let mut samples = vec![];
+    for link in experiment.samples().map_err(internal)? {
+        let sample = link.sample().map_err(internal)?;
+        let container = sample.container().ok().flatten();
+        let location = match (container, &sample.slot_position) {
+            (Some(c), Some(slot)) => Some(format!("{} / {}", c.name, slot)),
+            (Some(c), None) => Some(c.name.clone()),
+            (None, slot) => slot.clone(),
+        };
+        samples.push(ReportSample {
+            id: sample.id.clone(),
+            name: sample.name.clone(),
+            type_: sample.r#type.clone(),
+            role: link.role.clone(),
+            location,
+        });
+    }
+
+    Ok(Report {
+        id: experiment.id.clone(),
+        name: experiment.name.clone(),
+        description: experiment.description.clone(),
+        status: experiment.status.clone(),
+        created_by: experiment.created_by.clone(),
+        created_at: experiment.created_at.to_rfc3339(),
+        locked_at: experiment.locked_at.map(|t| t.to_rfc3339()),
+        content_hash: signatures::record_hash(&experiment.content, entries),
+        generated_at: Utc::now().to_rfc3339(),
+        content: experiment.content.clone(),
+        signatures: signatures
+            .into_iter()
+            .map(|s| ReportSignature {
+                role: s.role.clone(),
+                signer: s.signer.clone(),
+                meaning: s.meaning.clone(),
+                signed_at: s.signed_at.to_rfc3339(),
+                content_hash: s.content_hash.clone(),
+            })
+            .collect(),
+        entries: sorted_entries
+            .into_iter()
+            .map(|e| ReportEntry {
+                timestamp: e.timestamp.to_rfc3339(),
+                author: e.author.clone(),
+                content: e.content.clone(),
+                is_amendment: e.is_amendment,
+                signed_by: e.signed_by.clone(),
+                signature_meaning: e.signature_meaning.clone(),
+            })
+            .collect(),
+        mentions: mentions
+            .into_iter()
+            .map(|m| ReportMention {
+                entity_type: m.entity_type.clone(),
+                entity_id: m.entity_id.clone(),
+                snapshot: serde_json::from_str(&m.snapshot_data).unwrap_or_default(),
+            })
+            .collect(),
+        samples,
+        assets: experiment
+            .assets()
+            .map_err(internal)?
+            .iter()
+            .map(|a| ReportAsset {
+                filename: a.filename.clone(),
+                asset_type: a.asset_type.clone(),
+                mime_type: a.mime_type.clone(),
+                size_bytes: a.size_bytes,
+                checksum: a.checksum.clone(),
+                machine_id: a.machine_id.clone(),
+                created_at: a.created_at.to_rfc3339(),
+            })
+            .collect(),
+    })
+}
+
+/// Experiment name made safe for a download filename
+fn file_stem(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    match stem.trim_matches('_') {
+        "" => "experiment".to_string(),
+        stem => stem.to_string(),
+    }
+}
+
+fn internal(e: impl std::fmt::Display) -> ApiError {
+    ApiError::Internal(e.to_string())
+}
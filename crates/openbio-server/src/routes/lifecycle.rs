@@ -0,0 +1,158 @@
+//! Experiment status transitions
+//!
+//! `Experiment.status` only changes through the state machine in
+//! `openbio_core::experiment`; every change is stamped on the experiment and
+//! recorded as an `ExperimentStatusChange` row.
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use openbio_core::{ExperimentStatus, StatusTransition};
+use prisma_client_rust::chrono::{DateTime, FixedOffset, Utc};
+use serde::Deserialize;
+
+use super::signatures;
+use crate::db::prisma::{experiment, experiment_status_change, PrismaClient, SortOrder};
+use crate::events::ChangeEvent;
+use crate::{ApiError, ApiResult, AppState};
+
+#[derive(Deserialize)]
+pub struct TransitionRequest {
+    /// Who performed the transition
+    pub actor: Option<String>,
+    /// Why (required when reopening)
+    pub reason: Option<String>,
+}
+
+/// POST /experiments/{id}/transitions/{action}
+pub(super) async fn transition_experiment(
+    State(state): State<AppState>,
+    Path((id, action)): Path<(String, String)>,
+    Json(payload): Json<TransitionRequest>,
+) -> ApiResult<experiment::Data> {
+    // An unknown action is a malformed request, not a missing resource
+    let transition: StatusTransition = action
+        .parse()
+        .map_err(|e: openbio_core::Error| ApiError::BadRequest(e.to_string()))?;
+
+    let has_reason = matches!(payload.reason.as_deref(), Some(r) if !r.trim().is_empty());
+    if transition == StatusTransition::Reopen && !has_reason {
+        return Err(ApiError::Unprocessable(
+            "A reason is required to reopen an experiment".to_string(),
+        ));
+    }
+
+    let experiment =
+        apply_transition(&state, id, transition, payload.actor, payload.reason).await?;
+    Ok(Json(experiment))
+}
+
+/// GET /experiments/{id}/status-history
+pub(super) async fn list_status_changes(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<experiment_status_change::Data>> {
+    let changes = state
+        .db
+        .experiment_status_change()
+        .find_many(vec![experiment_status_change::experiment_id::equals(id)])
+        .order_by(experiment_status_change::changed_at::order(SortOrder::Asc))
+        .exec()
+        .await?;
+    Ok(Json(changes))
+}
+
+/// Apply `transition` to an experiment, stamping who/when and logging the change.
+///
+/// Returns 422 if the transition is not allowed from the current status.
+pub(crate) async fn apply_transition(
+    state: &AppState,
+    experiment_id: String,
+    transition: StatusTransition,
+    actor: Option<String>,
+    reason: Option<String>,
+) -> Result<experiment::Data, ApiError> {
+    let (updated, from) =
+        state
+            .db
+            ._transaction()
+            .run(|tx| async move {
+                record_transition(&tx, experiment_id, transition, actor, reason).await
+            })
+            .await?;
+
+    publish_transition(state, &updated, from);
+    Ok(updated)
+}
+
+/// [`apply_transition`] within a caller's transaction.
+///
+/// Returns the updated experiment and the status it left; the caller publishes
+/// the change with [`publish_transition`] once the transaction has committed.
+pub(crate) async fn record_transition(
+    db: &PrismaClient,
+    experiment_id: String,
+    transition: StatusTransition,
+    actor: Option<String>,
+    reason: Option<String>,
+) -> Result<(experiment::Data, ExperimentStatus), ApiError> {
+    let current = db
+        .experiment()
+        .find_unique(experiment::id::equals(experiment_id.clone()))
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", experiment_id)))?;
+
+    // A signed notebook is final, it cannot even be reopened
+    if current.locked_at.is_some() {
+        return Err(signatures::locked_error(&experiment_id));
+    }
+
+    let from: ExperimentStatus = current.status.parse()?;
+    let to = transition.apply(from)?;
+    let now: DateTime<FixedOffset> = Utc::now().into();
+
+    db.experiment_status_change()
+        .create(
+            experiment::id::equals(experiment_id.clone()),
+            from.to_string(),
+            to.to_string(),
+            transition.to_string(),
+            vec![
+                experiment_status_change::reason::set(reason),
+                experiment_status_change::changed_at::set(now),
+                experiment_status_change::changed_by::set(actor.clone()),
+            ],
+        )
+        .exec()
+        .await?;
+
+    let updated = db
+        .experiment()
+        .update(
+            experiment::id::equals(experiment_id),
+            vec![
+                experiment::status::set(to.to_string()),
+                experiment::status_changed_at::set(Some(now)),
+                experiment::status_changed_by::set(actor),
+            ],
+        )
+        .exec()
+        .await?;
+
+    Ok((updated, from))
+}
+
+/// Tell listeners that an experiment left status `from`
+pub(crate) fn publish_transition(
+    state: &AppState,
+    updated: &experiment::Data,
+    from: ExperimentStatus,
+) {
+    state.events.publish(ChangeEvent::ExperimentStatusChanged {
+        id: updated.id.clone(),
+        from: from.to_string(),
+        to: updated.status.clone(),
+    });
+}
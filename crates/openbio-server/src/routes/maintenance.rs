@@ -0,0 +1,449 @@
+//! Equipment maintenance: calibration schedules and service logs
+//!
+//! A schedule says how often an instrument needs a kind of upkeep and when it is
+//! next due. Logging a record against a schedule moves the due date forward by
+//! the schedule's interval. Instruments whose calibration has lapsed cannot be
+//! booked without an override reason (see `bookings::create_booking`).
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use openbio_core::MaintenanceType;
+use prisma_client_rust::chrono::{DateTime, Duration, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::bookings::{find_equipment, parse_time};
+use crate::db::prisma::{
+    digital_asset, equipment, maintenance_record, maintenance_schedule, PrismaClient, SortOrder,
+};
+use crate::events::ChangeEvent;
+use crate::{ApiError, ApiResult, AppState};
+
+/// Longest maintenance interval, and furthest `within_days` looks ahead (or back)
+const MAX_DAYS: i64 = 36_500;
+
+#[derive(Deserialize)]
+pub struct CreateScheduleRequest {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub description: Option<String>,
+    pub interval_days: i32,
+    /// RFC 3339 or YYYY-MM-DD; defaults to one interval from now
+    pub next_due_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateScheduleRequest {
+    pub description: Option<String>,
+    pub interval_days: Option<i32>,
+    /// RFC 3339 or YYYY-MM-DD
+    pub next_due_at: Option<String>,
+}
+
+/// GET /equipment/{id}/maintenance/schedules
+pub(super) async fn list_schedules(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<maintenance_schedule::Data>> {
+    find_equipment(&state.db, &id).await?;
+
+    let schedules = state
+        .db
+        .maintenance_schedule()
+        .find_many(vec![maintenance_schedule::equipment_id::equals(id)])
+        .order_by(maintenance_schedule::next_due_at::order(SortOrder::Asc))
+        .exec()
+        .await?;
+    Ok(Json(schedules))
+}
+
+/// POST /equipment/{id}/maintenance/schedules
+pub(super) async fn create_schedule(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<CreateScheduleRequest>,
+) -> ApiResult<maintenance_schedule::Data> {
+    let kind: MaintenanceType = payload.type_.parse()?;
+    validate_interval(payload.interval_days)?;
+    let next_due_at = match payload.next_due_at.as_deref() {
+        Some(value) => parse_time(value)?,
+        None => days_after(now(), payload.interval_days.into())?,
+    };
+
+    find_equipment(&state.db, &id).await?;
+
+    let schedule = state
+        .db
+        .maintenance_schedule()
+        .create(
+            equipment::id::equals(id),
+            kind.as_str().to_string(),
+            payload.interval_days,
+            next_due_at,
+            vec![maintenance_schedule::description::set(payload.description)],
+        )
+        .exec()
+        .await?;
+
+    state.events.publish(ChangeEvent::EquipmentUpdated {
+        id: schedule.equipment_id.clone(),
+    });
+    Ok(Json(schedule))
+}
+
+/// PATCH /equipment/{id}/maintenance/schedules/{schedule_id}
+pub(super) async fn update_schedule(
+    State(state): State<AppState>,
+    Path((id, schedule_id)): Path<(String, String)>,
+    Json(payload): Json<UpdateScheduleRequest>,
+) -> ApiResult<maintenance_schedule::Data> {
+    find_schedule(&state.db, &id, &schedule_id).await?;
+
+    let mut params: Vec<maintenance_schedule::SetParam> = vec![];
+    if let Some(description) = payload.description {
+        params.push(maintenance_schedule::description::set(Some(description)));
+    }
+    if let Some(interval_days) = payload.interval_days {
+        validate_interval(interval_days)?;
+        params.push(maintenance_schedule::interval_days::set(interval_days));
+    }
+    if let Some(next_due_at) = payload.next_due_at {
+        params.push(maintenance_schedule::next_due_at::set(parse_time(&next_due_at)?));
+    }
+
+    let schedule = state
+        .db
+        .maintenance_schedule()
+        .update(maintenance_schedule::id::equals(schedule_id), params)
+        .exec()
+        .await?;
+
+    state.events.publish(ChangeEvent::EquipmentUpdated {
+        id: schedule.equipment_id.clone(),
+    });
+    Ok(Json(schedule))
+}
+
+/// DELETE /equipment/{id}/maintenance/schedules/{schedule_id}
+///
+/// Records logged against the schedule are kept.
+pub(super) async fn delete_schedule(
+    State(state): State<AppState>,
+    Path((id, schedule_id)): Path<(String, String)>,
+) -> ApiResult<()> {
+    find_schedule(&state.db, &id, &schedule_id).await?;
+
+    state
+        .db
+        .maintenance_schedule()
+        .delete(maintenance_schedule::id::equals(schedule_id))
+        .exec()
+        .await?;
+
+    state.events.publish(ChangeEvent::EquipmentUpdated { id });
+    Ok(Json(()))
+}
+
+#[derive(Deserialize)]
+pub struct CreateRecordRequest {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub performed_by: String,
+    /// RFC 3339 or YYYY-MM-DD; defaults to now
+    pub performed_at: Option<String>,
+    pub notes: Option<String>,
+    /// Schedule this record fulfils. Defaults to the instrument's only schedule
+    /// of the same type, if there is exactly one.
+    pub schedule_id: Option<String>,
+    /// Already uploaded assets (certificates, service reports) to attach
+    #[serde(default)]
+    pub asset_ids: Vec<String>,
+}
+
+/// GET /equipment/{id}/maintenance/records
+///
+/// Newest first, with attachments.
+pub(super) async fn list_records(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<maintenance_record::Data>> {
+    find_equipment(&state.db, &id).await?;
+
+    let records = state
+        .db
+        .maintenance_record()
+        .find_many(vec![maintenance_record::equipment_id::equals(id)])
+        .with(maintenance_record::attachments::fetch(vec![]))
+        .order_by(maintenance_record::performed_at::order(SortOrder::Desc))
+        .exec()
+        .await?;
+    Ok(Json(records))
+}
+
+/// POST /equipment/{id}/maintenance/records
+///
+/// Logs upkeep that was done and, when it fulfils a schedule, moves that
+/// schedule's due date to one interval after `performed_at`.
+pub(super) async fn create_record(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<CreateRecordRequest>,
+) -> ApiResult<maintenance_record::Data> {
+    let kind: MaintenanceType = payload.type_.parse()?;
+    let performed_at = match payload.performed_at.as_deref() {
+        Some(value) => parse_time(value)?,
+        None => now(),
+    };
+
+    find_equipment(&state.db, &id).await?;
+
+    let schedule = match payload.schedule_id.as_deref() {
+        Some(schedule_id) => {
+            let schedule = find_schedule(&state.db, &id, schedule_id).await?;
+            if schedule.r#type != kind.as_str() {
+                return Err(ApiError::Unprocessable(format!(
+                    "Schedule {} is for {}, not {}",
+                    schedule.id, schedule.r#type, kind
+                )));
+            }
+            Some(schedule)
+        }
+        None => {
+            let mut matching = state
+                .db
+                .maintenance_schedule()
+                .find_many(vec![
+                    maintenance_schedule::equipment_id::equals(id.clone()),
+                    maintenance_schedule::r#type::equals(kind.as_str().to_string()),
+                ])
+                .exec()
+                .await?;
+            if matching.len() == 1 {
+                matching.pop()
+            } else {
+                None
+            }
+        }
+    };
+
+    // A late-logged record must not pull the due date backwards
+    let schedule = match schedule {
+        Some(schedule) => {
+            let last = schedule
+                .last_performed_at
+                .map_or(performed_at, |last| last.max(performed_at));
+            let next_due_at = days_after(last, schedule.interval_days.into())?;
+            Some((schedule.id, last, next_due_at))
+        }
+        None => None,
+    };
+
+    // An asset listed twice is attached once
+    let mut asset_ids = payload.asset_ids.clone();
+    asset_ids.sort();
+    asset_ids.dedup();
+
+    let record_id = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            let record = tx
+                .maintenance_record()
+                .create(
+                    equipment::id::equals(id),
+                    kind.as_str().to_string(),
+                    payload.performed_by,
+                    vec![
+                        maintenance_record::performed_at::set(performed_at),
+                        maintenance_record::notes::set(payload.notes),
+                        maintenance_record::schedule_id::set(
+                            schedule.as_ref().map(|(id, _, _)| id.clone()),
+                        ),
+                    ],
+                )
+                .exec()
+                .await?;
+
+            if !asset_ids.is_empty() {
+                let attached = tx
+                    .digital_asset()
+                    .update_many(
+                        vec![digital_asset::id::in_vec(asset_ids.clone())],
+                        vec![digital_asset::maintenance_record_id::set(Some(
+                            record.id.clone(),
+                        ))],
+                    )
+                    .exec()
+                    .await?;
+                if attached as usize != asset_ids.len() {
+                    return Err(ApiError::NotFound(format!(
+                        "Some of the assets {}",
+                        asset_ids.join(", ")
+                    )));
+                }
+            }
+
+            if let Some((schedule_id, last, next_due_at)) = schedule {
+                tx.maintenance_schedule()
+                    .update(
+                        maintenance_schedule::id::equals(schedule_id),
+                        vec![
+                            maintenance_schedule::last_performed_at::set(Some(last)),
+                            maintenance_schedule::next_due_at::set(next_due_at),
+                        ],
+                    )
+                    .exec()
+                    .await?;
+            }
+
+            Ok::<_, ApiError>(record.id)
+        })
+        .await?;
+
+    let record = state
+        .db
+        .maintenance_record()
+        .find_unique(maintenance_record::id::equals(record_id))
+        .with(maintenance_record::attachments::fetch(vec![]))
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::Internal("Maintenance record was not saved".to_string()))?;
+
+    state.events.publish(ChangeEvent::EquipmentUpdated {
+        id: record.equipment_id.clone(),
+    });
+    Ok(Json(record))
+}
+
+#[derive(Deserialize)]
+pub struct OverdueQuery {
+    /// Also include schedules falling due within this many days (at most 36500)
+    #[serde(default)]
+    pub within_days: i64,
+}
+
+/// A schedule that is past (or close to) its due date
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverdueMaintenance {
+    pub schedule_id: String,
+    pub equipment_id: String,
+    pub equipment_name: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub description: Option<String>,
+    pub next_due_at: DateTime<FixedOffset>,
+    pub last_performed_at: Option<DateTime<FixedOffset>>,
+    /// Negative when the schedule is not due yet
+    pub days_overdue: i64,
+}
+
+/// GET /equipment/maintenance/overdue?within_days=14
+///
+/// Maintenance across all instruments, most overdue first.
+pub(super) async fn list_overdue(
+    State(state): State<AppState>,
+    Query(query): Query<OverdueQuery>,
+) -> ApiResult<Vec<OverdueMaintenance>> {
+    if query.within_days.abs() > MAX_DAYS {
+        return Err(ApiError::Unprocessable(format!(
+            "within_days has to be between -{} and {}",
+            MAX_DAYS, MAX_DAYS
+        )));
+    }
+    let now = now();
+    let cutoff = days_after(now, query.within_days)?;
+
+    let schedules = state
+        .db
+        .maintenance_schedule()
+        .find_many(vec![maintenance_schedule::next_due_at::lte(cutoff)])
+        .with(maintenance_schedule::equipment::fetch())
+        .order_by(maintenance_schedule::next_due_at::order(SortOrder::Asc))
+        .exec()
+        .await?;
+
+    let mut overdue = vec![];
+    for schedule in schedules {
+        let equipment_name = schedule
+            .equipment()
+            .map_err(|e| ApiError::Internal(e.to_string()))?
+            .name
+            .clone();
+        overdue.push(OverdueMaintenance {
+            days_overdue: (now - schedule.next_due_at).num_days(),
+            schedule_id: schedule.id,
+            equipment_id: schedule.equipment_id,
+            equipment_name,
+            type_: schedule.r#type,
+            description: schedule.description,
+            next_due_at: schedule.next_due_at,
+            last_performed_at: schedule.last_performed_at,
+        });
+    }
+    Ok(Json(overdue))
+}
+
+/// The instrument's calibration schedule that will have lapsed at `at`, if any
+pub(crate) async fn lapsed_calibration(
+    db: &PrismaClient,
+    equipment_id: &str,
+    at: DateTime<FixedOffset>,
+) -> Result<Option<maintenance_schedule::Data>, ApiError> {
+    Ok(db
+        .maintenance_schedule()
+        .find_first(vec![
+            maintenance_schedule::equipment_id::equals(equipment_id.to_string()),
+            maintenance_schedule::r#type::equals(
+                MaintenanceType::Calibration.as_str().to_string(),
+            ),
+            maintenance_schedule::next_due_at::lt(at),
+        ])
+        .order_by(maintenance_schedule::next_due_at::order(SortOrder::Asc))
+        .exec()
+        .await?)
+}
+
+async fn find_schedule(
+    db: &PrismaClient,
+    equipment_id: &str,
+    schedule_id: &str,
+) -> Result<maintenance_schedule::Data, ApiError> {
+    db.maintenance_schedule()
+        .find_first(vec![
+            maintenance_schedule::id::equals(schedule_id.to_string()),
+            maintenance_schedule::equipment_id::equals(equipment_id.to_string()),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(|| {
+            ApiError::NotFound(format!(
+                "Maintenance schedule {} of equipment {}",
+                schedule_id, equipment_id
+            ))
+        })
+}
+
+fn validate_interval(interval_days: i32) -> Result<(), ApiError> {
+    if !(1..=MAX_DAYS).contains(&interval_days.into()) {
+        return Err(ApiError::Unprocessable(format!(
+            "Maintenance interval has to be between 1 and {} days",
+            MAX_DAYS
+        )));
+    }
+    Ok(())
+}
+
+/// `days` after `at`; 422 when that is beyond the dates chrono can represent
+fn days_after(at: DateTime<FixedOffset>, days: i64) -> Result<DateTime<FixedOffset>, ApiError> {
+    Duration::try_days(days)
+        .and_then(|days| at.checked_add_signed(days))
+        .ok_or_else(|| {
+            ApiError::Unprocessable(format!("{} days after {} is out of range", days, at))
+        })
+}
+
+fn now() -> DateTime<FixedOffset> {
+    Utc::now().fixed_offset()
+}
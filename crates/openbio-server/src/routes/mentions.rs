@@ -0,0 +1,486 @@
+//! @mentions of samples, equipment and papers in notebooks
+//!
+//! `ExperimentMention` rows mirror the mention nodes of `Experiment.content`:
+//! they are reconciled on every content save, with entity snapshots taken
+//! server-side when a mention first appears. Snapshots can be checked against
+//! the live entities and refreshed.
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use openbio_core::notebook::{extract_mentions, MentionRef};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::signatures;
+use crate::db::prisma::{
+    equipment, experiment, experiment_entry, experiment_mention, paper, sample, PrismaClient,
+    SortOrder,
+};
+use crate::events::ChangeEvent;
+use crate::{ApiError, ApiResult, AppState};
+
+/// Entity types that can be mentioned
+pub(crate) const ENTITY_TYPES: [&str; 3] = ["sample", "equipment", "paper"];
+
+/// Reconcile an experiment's mention rows with the mention nodes in `content`.
+///
+/// Each mention node keeps (or gets) one row: existing rows for the same entity
+/// are reused in document order with their original snapshot and updated
+/// position, new mentions are snapshotted now, and rows whose node is gone are
+/// deleted. Mentions of entities that cannot be found are skipped.
+pub(crate) async fn sync_mentions(
+    db: &PrismaClient,
+    experiment_id: &str,
+    content: &str,
+) -> Result<(), ApiError> {
+    let existing = db
+        .experiment_mention()
+        .find_many(vec![experiment_mention::experiment_id::equals(
+            experiment_id.to_string(),
+        )])
+        .exec()
+        .await?;
+
+    // Oldest first, so long-standing mentions keep their snapshot
+    let mut available: HashMap<String, Vec<experiment_mention::Data>> = HashMap::new();
+    let mut sorted = existing;
+    sorted.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+    for row in sorted.into_iter().rev() {
+        available.entry(row.entity_id.clone()).or_default().push(row);
+    }
+
+    for mention in extract_mentions(content) {
+        if let Some(row) = available.get_mut(&mention.entity_id).and_then(Vec::pop) {
+            if row.position != Some(mention.position) {
+                db.experiment_mention()
+                    .update(
+                        experiment_mention::id::equals(row.id),
+                        vec![experiment_mention::position::set(Some(mention.position))],
+                    )
+                    .exec()
+                    .await?;
+            }
+            continue;
+        }
+
+        let Some((entity_type, snapshot)) = resolve_mention(db, &mention).await? else {
+            tracing::warn!(
+                "Experiment {} mentions unknown entity {}",
+                experiment_id,
+                mention.entity_id
+            );
+            continue;
+        };
+
+        db.experiment_mention()
+            .create(
+                experiment::id::equals(experiment_id.to_string()),
+                entity_type,
+                mention.entity_id,
+                snapshot.to_string(),
+                vec![experiment_mention::position::set(Some(mention.position))],
+            )
+            .exec()
+            .await?;
+    }
+
+    let stale: Vec<String> = available
+        .into_values()
+        .flatten()
+        .map(|row| row.id)
+        .collect();
+    if !stale.is_empty() {
+        db.experiment_mention()
+            .delete_many(vec![experiment_mention::id::in_vec(stale)])
+            .exec()
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Find the entity behind a mention node: by its `type` attribute when the
+/// editor stored one, otherwise by looking the ID up in each entity table
+async fn resolve_mention(
+    db: &PrismaClient,
+    mention: &MentionRef,
+) -> Result<Option<(String, Value)>, ApiError> {
+    let candidates: Vec<&str> = match mention.entity_type.as_deref() {
+        Some(entity_type) if ENTITY_TYPES.contains(&entity_type) => vec![entity_type],
+        _ => ENTITY_TYPES.to_vec(),
+    };
+
+    for entity_type in candidates {
+        if let Some(snapshot) = snapshot_entity(db, entity_type, &mention.entity_id).await? {
+            return Ok(Some((entity_type.to_string(), snapshot)));
+        }
+    }
+    Ok(None)
+}
+
+/// Current data of a mentionable entity, as stored in `snapshotData`.
+///
+/// Returns `None` if the entity does not exist.
+pub(crate) async fn snapshot_entity(
+    db: &PrismaClient,
+    entity_type: &str,
+    entity_id: &str,
+) -> Result<Option<Value>, ApiError> {
+    let snapshot = match entity_type {
+        "sample" => db
+            .sample()
+            .find_unique(sample::id::equals(entity_id.to_string()))
+            .exec()
+            .await?
+            .map(serde_json::to_value),
+        "equipment" => db
+            .equipment()
+            .find_unique(equipment::id::equals(entity_id.to_string()))
+            .exec()
+            .await?
+            .map(serde_json::to_value),
+        "paper" => db
+            .paper()
+            .find_unique(paper::id::equals(entity_id.to_string()))
+            .exec()
+            .await?
+            .map(serde_json::to_value),
+        other => {
+            return Err(ApiError::Unprocessable(format!(
+                "Unknown entity type: {} (expected sample, equipment or paper)",
+                other
+            )))
+        }
+    };
+
+    snapshot
+        .transpose()
+        .map_err(|e| ApiError::Internal(e.to_string()))
+}
+
+/// An experiment that mentions an entity
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MentionedIn {
+    pub experiment_id: String,
+    pub experiment_name: String,
+    pub status: String,
+    /// Positions of the mentions in the notebook
+    pub positions: Vec<i32>,
+    pub first_mentioned_at: String,
+}
+
+/// GET /inventory/samples/{id}/mentioned-in
+pub(super) async fn sample_mentioned_in(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<MentionedIn>> {
+    mentioned_in(&state.db, "sample", id).await.map(Json)
+}
+
+/// GET /equipment/{id}/mentioned-in
+pub(super) async fn equipment_mentioned_in(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<MentionedIn>> {
+    mentioned_in(&state.db, "equipment", id).await.map(Json)
+}
+
+/// GET /library/{id}/mentioned-in
+pub(super) async fn paper_mentioned_in(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<MentionedIn>> {
+    mentioned_in(&state.db, "paper", id).await.map(Json)
+}
+
+async fn mentioned_in(
+    db: &PrismaClient,
+    entity_type: &str,
+    entity_id: String,
+) -> Result<Vec<MentionedIn>, ApiError> {
+    let mut mentions = db
+        .experiment_mention()
+        .find_many(vec![
+            experiment_mention::entity_type::equals(entity_type.to_string()),
+            experiment_mention::entity_id::equals(entity_id),
+        ])
+        .with(experiment_mention::experiment::fetch())
+        .exec()
+        .await?;
+    mentions.sort_by_key(|m| (m.created_at, m.position));
+
+    let mut experiments: Vec<MentionedIn> = vec![];
+    for mention in mentions {
+        if let Some(entry) = experiments
+            .iter_mut()
+            .find(|e| e.experiment_id == mention.experiment_id)
+        {
+            entry.positions.extend(mention.position);
+            continue;
+        }
+
+        let experiment = mention
+            .experiment()
+            .map_err(|e| ApiError::Internal(e.to_string()))?;
+        experiments.push(MentionedIn {
+            experiment_id: experiment.id.clone(),
+            experiment_name: experiment.name.clone(),
+            status: experiment.status.clone(),
+            positions: mention.position.into_iter().collect(),
+            first_mentioned_at: mention.created_at.to_rfc3339(),
+        });
+    }
+
+    for entry in &mut experiments {
+        entry.positions.sort_unstable();
+    }
+    Ok(experiments)
+}
+
+/// Fields left out when comparing snapshots: they change on every save, or
+/// with every ingest agent heartbeat for equipment
+const VOLATILE_FIELDS: [&str; 6] = [
+    "updatedAt",
+    "lastSyncAt",
+    "agentStatus",
+    "agentVersion",
+    "agentWatchDir",
+    "agentQueueDepth",
+];
+
+/// A field whose live value differs from the snapshot
+#[derive(Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub snapshot: Value,
+    pub current: Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotState {
+    Current,
+    Changed,
+    Deleted,
+}
+
+/// How a mention's snapshot compares to the live entity
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MentionStaleness {
+    pub mention_id: String,
+    pub entity_type: String,
+    pub entity_id: String,
+    pub state: SnapshotState,
+    pub changes: Vec<FieldChange>,
+}
+
+/// GET /experiments/{id}/mentions/stale
+///
+/// Mentions whose entity changed or was deleted since the snapshot was taken.
+pub(super) async fn stale_mentions(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<MentionStaleness>> {
+    let mentions = state
+        .db
+        .experiment_mention()
+        .find_many(vec![experiment_mention::experiment_id::equals(id)])
+        .order_by(experiment_mention::position::order(SortOrder::Asc))
+        .exec()
+        .await?;
+
+    let mut stale = vec![];
+    for mention in mentions {
+        let report = check_snapshot(&state.db, &mention).await?;
+        if !matches!(report.state, SnapshotState::Current) {
+            stale.push(report);
+        }
+    }
+    Ok(Json(stale))
+}
+
+#[derive(Deserialize)]
+pub struct RefreshSnapshotRequest {
+    pub actor: Option<String>,
+}
+
+/// POST /experiments/{id}/mentions/{mention_id}/refresh
+///
+/// Replace the snapshot with the entity's current data and log the changed
+/// fields as an experiment entry.
+pub(super) async fn refresh_mention(
+    State(state): State<AppState>,
+    Path((id, mention_id)): Path<(String, String)>,
+    Json(payload): Json<RefreshSnapshotRequest>,
+) -> ApiResult<experiment_mention::Data> {
+    let mention = state
+        .db
+        .experiment_mention()
+        .find_first(vec![
+            experiment_mention::id::equals(mention_id.clone()),
+            experiment_mention::experiment_id::equals(id.clone()),
+        ])
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Mention {}", mention_id)))?;
+
+    let current = snapshot_entity(&state.db, &mention.entity_type, &mention.entity_id)
+        .await?
+        .ok_or_else(|| {
+            ApiError::Conflict(format!(
+                "{} {} was deleted; the snapshot is all that is left of it",
+                mention.entity_type, mention.entity_id
+            ))
+        })?;
+
+    let old: Value = serde_json::from_str(&mention.snapshot_data).unwrap_or(Value::Null);
+    let changes = diff_snapshot(&old, &current);
+    if changes.is_empty() {
+        return Ok(Json(mention));
+    }
+
+    let fields: Vec<&str> = changes.iter().map(|c| c.field.as_str()).collect();
+    let note = format!(
+        "Refreshed snapshot of {} {} (changed: {})",
+        mention.entity_type,
+        snapshot_name(&current).unwrap_or(&mention.entity_id),
+        fields.join(", ")
+    );
+
+    let refreshed = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &id).await?;
+            let refreshed = tx
+                .experiment_mention()
+                .update(
+                    experiment_mention::id::equals(mention.id),
+                    vec![experiment_mention::snapshot_data::set(current.to_string())],
+                )
+                .exec()
+                .await?;
+
+            tx.experiment_entry()
+                .create(
+                    experiment::id::equals(id),
+                    note,
+                    vec![experiment_entry::author::set(payload.actor)],
+                )
+                .exec()
+                .await?;
+
+            Ok::<_, ApiError>(refreshed)
+        })
+        .await?;
+
+    state.events.publish(ChangeEvent::ExperimentUpdated {
+        id: refreshed.experiment_id.clone(),
+    });
+    Ok(Json(refreshed))
+}
+
+async fn check_snapshot(
+    db: &PrismaClient,
+    mention: &experiment_mention::Data,
+) -> Result<MentionStaleness, ApiError> {
+    let current = snapshot_entity(db, &mention.entity_type, &mention.entity_id).await?;
+    let (state, changes) = match current {
+        None => (SnapshotState::Deleted, vec![]),
+        Some(current) => {
+            let old: Value = serde_json::from_str(&mention.snapshot_data).unwrap_or(Value::Null);
+            let changes = diff_snapshot(&old, &current);
+            let state = if changes.is_empty() {
+                SnapshotState::Current
+            } else {
+                SnapshotState::Changed
+            };
+            (state, changes)
+        }
+    };
+
+    Ok(MentionStaleness {
+        mention_id: mention.id.clone(),
+        entity_type: mention.entity_type.clone(),
+        entity_id: mention.entity_id.clone(),
+        state,
+        changes,
+    })
+}
+
+/// Compare the top-level fields of a snapshot with the entity's current data
+fn diff_snapshot(snapshot: &Value, current: &Value) -> Vec<FieldChange> {
+    let empty = serde_json::Map::new();
+    let old = snapshot.as_object().unwrap_or(&empty);
+    let new = current.as_object().unwrap_or(&empty);
+
+    let mut fields: Vec<&String> = old.keys().chain(new.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    fields
+        .into_iter()
+        .filter(|field| !VOLATILE_FIELDS.contains(&field.as_str()))
+        .filter_map(|field| {
+            let before = old.get(field).cloned().unwrap_or(Value::Null);
+            let after = new.get(field).cloned().unwrap_or(Value::Null);
+            (before != after).then(|| FieldChange {
+                field: field.clone(),
+                snapshot: before,
+                current: after,
+            })
+        })
+        .collect()
+}
+
+fn snapshot_name(snapshot: &Value) -> Option<&str> {
+    ["name", "title"]
+        .iter()
+        .find_map(|key| snapshot.get(*key).and_then(Value::as_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diff_snapshot_ignores_agent_heartbeats() {
+        let snapshot = json!({
+            "name": "qPCR",
+            "location": "Room 2",
+            "agentStatus": "OFFLINE",
+            "lastSyncAt": null,
+            "agentQueueDepth": null,
+            "updatedAt": "2026-10-01T10:00:00Z",
+        });
+        let current = json!({
+            "name": "qPCR",
+            "location": "Room 2",
+            "agentStatus": "ONLINE",
+            "lastSyncAt": "2026-10-18T09:00:00Z",
+            "agentVersion": "0.1.17",
+            "agentWatchDir": "C:\\Export",
+            "agentQueueDepth": 3,
+            "updatedAt": "2026-10-18T09:00:00Z",
+        });
+        assert!(diff_snapshot(&snapshot, &current).is_empty());
+    }
+
+    #[test]
+    fn diff_snapshot_reports_descriptive_changes() {
+        let snapshot = json!({ "name": "qPCR", "location": "Room 2", "agentStatus": "OFFLINE" });
+        let current = json!({ "name": "qPCR", "location": "Room 3", "agentStatus": "ONLINE" });
+        let changes = diff_snapshot(&snapshot, &current);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "location");
+        assert_eq!(changes[0].snapshot, json!("Room 2"));
+        assert_eq!(changes[0].current, json!("Room 3"));
+    }
+}
@@ -0,0 +1,183 @@
+//! Notebook content revisions
+//!
+//! Saves of `Experiment.content` are kept as `ExperimentRevision`s so an
+//! accidental edit can be inspected, diffed and rolled back. Revisions are
+//! never changed once stored; the editor waits for typing to pause before it
+//! saves, which keeps their number down.
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use openbio_core::notebook::{diff_documents, NotebookDiff};
+use serde::Deserialize;
+
+use super::{collab, mentions, signatures};
+use crate::db::prisma::{experiment, experiment_revision, PrismaClient, SortOrder};
+use crate::events::ChangeEvent;
+use crate::{ApiError, ApiResult, AppState};
+
+/// GET /experiments/{id}/revisions
+pub(super) async fn list_revisions(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<experiment_revision::Data>> {
+    let revisions = state
+        .db
+        .experiment_revision()
+        .find_many(vec![experiment_revision::experiment_id::equals(id)])
+        .order_by(experiment_revision::revision::order(SortOrder::Desc))
+        .exec()
+        .await?;
+    Ok(Json(revisions))
+}
+
+/// GET /experiments/{id}/revisions/{revision}
+pub(super) async fn get_revision(
+    State(state): State<AppState>,
+    Path((id, revision)): Path<(String, i32)>,
+) -> ApiResult<experiment_revision::Data> {
+    let revision = find_revision(&state.db, &id, revision).await?;
+    Ok(Json(revision))
+}
+
+#[derive(Deserialize)]
+pub struct DiffQuery {
+    pub from: i32,
+    /// Revision to compare against; defaults to the current content
+    pub to: Option<i32>,
+}
+
+/// GET /experiments/{id}/revisions/diff?from=1&to=2
+pub(super) async fn diff_revisions(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<DiffQuery>,
+) -> ApiResult<NotebookDiff> {
+    let old = find_revision(&state.db, &id, query.from).await?;
+
+    let new_content = match query.to {
+        Some(to) => find_revision(&state.db, &id, to).await?.content,
+        None => {
+            state
+                .db
+                .experiment()
+                .find_unique(experiment::id::equals(id.clone()))
+                .exec()
+                .await?
+                .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", id)))?
+                .content
+        }
+    };
+
+    // Long notebooks take a while to compare; keep that off the async workers
+    let diff = tokio::task::spawn_blocking(move || diff_documents(&old.content, &new_content))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Diff failed: {}", e)))?;
+    Ok(Json(diff))
+}
+
+#[derive(Deserialize)]
+pub struct RestoreRevisionRequest {
+    pub actor: Option<String>,
+}
+
+/// POST /experiments/{id}/revisions/{revision}/restore
+///
+/// Restoring never rewrites history: the old content is saved again as a new revision.
+pub(super) async fn restore_revision(
+    State(state): State<AppState>,
+    Path((id, revision)): Path<(String, i32)>,
+    Json(payload): Json<RestoreRevisionRequest>,
+) -> ApiResult<experiment::Data> {
+    collab::ensure_room_saved(&state, &id)?;
+    let restored = find_revision(&state.db, &id, revision).await?;
+
+    let experiment = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            signatures::ensure_unlocked(&tx, &id).await?;
+            let updated = tx
+                .experiment()
+                .update(
+                    experiment::id::equals(id.clone()),
+                    vec![experiment::content::set(restored.content.clone())],
+                )
+                .exec()
+                .await?;
+
+            mentions::sync_mentions(&tx, &id, &restored.content).await?;
+            record_revision(&tx, &id, restored.content, payload.actor, Some(restored.revision))
+                .await?;
+
+            Ok::<_, ApiError>(updated)
+        })
+        .await?;
+
+    collab::content_written(&state, &experiment.id, &experiment.content);
+    state.events.publish(ChangeEvent::ExperimentUpdated {
+        id: experiment.id.clone(),
+    });
+    Ok(Json(experiment))
+}
+
+async fn find_revision(
+    db: &PrismaClient,
+    experiment_id: &str,
+    revision: i32,
+) -> Result<experiment_revision::Data, ApiError> {
+    db.experiment_revision()
+        .find_unique(experiment_revision::experiment_id_revision(
+            experiment_id.to_string(),
+            revision,
+        ))
+        .exec()
+        .await?
+        .ok_or_else(|| {
+            ApiError::NotFound(format!("Revision {} of experiment {}", revision, experiment_id))
+        })
+}
+
+/// Store `content` as the next revision of an experiment.
+///
+/// Saving the same content as the latest revision is a no-op and returns that
+/// revision; restores always get a revision of their own.
+pub(crate) async fn record_revision(
+    db: &PrismaClient,
+    experiment_id: &str,
+    content: String,
+    author: Option<String>,
+    restored_from: Option<i32>,
+) -> Result<experiment_revision::Data, ApiError> {
+    let latest = db
+        .experiment_revision()
+        .find_first(vec![experiment_revision::experiment_id::equals(
+            experiment_id.to_string(),
+        )])
+        .order_by(experiment_revision::revision::order(SortOrder::Desc))
+        .exec()
+        .await?;
+
+    if let Some(latest) = latest.as_ref() {
+        if latest.content == content && restored_from.is_none() {
+            return Ok(latest.clone());
+        }
+    }
+
+    let next = latest.map_or(1, |r| r.revision + 1);
+    let revision = db
+        .experiment_revision()
+        .create(
+            experiment::id::equals(experiment_id.to_string()),
+            next,
+            content,
+            vec![
+                experiment_revision::author::set(author),
+                experiment_revision::restored_from::set(restored_from),
+            ],
+        )
+        .exec()
+        .await?;
+    Ok(revision)
+}
@@ -0,0 +1,460 @@
+//! Linking ingested files to samples
+//!
+//! Each instrument has [`SampleRule`]s (`Equipment.sampleRules`) that pull a
+//! sample identifier out of an uploaded file's path, optionally via the run's
+//! sample sheet. The identifier is kept on `DigitalAsset.sampleKey`; when it
+//! names exactly one sample the asset is linked to it. Files whose identifier
+//! matched no sample are listed for manual assignment.
+
+use std::collections::{BTreeSet, HashMap};
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use openbio_core::parsers::illumina::SAMPLE_SHEET;
+use openbio_core::sample_matching::{SampleField, SampleKey, SampleMatcher, SampleRule};
+use prisma_client_rust::chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::agent::agent_machine_ids;
+use super::bookings::find_equipment;
+use crate::db::prisma::{digital_asset, equipment, sample, PrismaClient, SortOrder};
+use crate::events::ChangeEvent;
+use crate::{ApiError, ApiResult, AppState};
+
+/// Identifiers looked up per query, well below SQLite's variable limit
+const LOOKUP_CHUNK: usize = 500;
+
+/// What the rules found for a file
+#[derive(Debug, Default)]
+pub(crate) struct SampleMatch {
+    pub sample_id: Option<String>,
+    /// First identifier the rules extracted, whether or not a sample has it
+    pub key: Option<String>,
+}
+
+/// GET /equipment/{id}/sample-rules
+pub(super) async fn get_sample_rules(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<SampleRule>> {
+    let equipment = find_equipment(&state.db, &id).await?;
+    Ok(Json(rules_of(&equipment)))
+}
+
+/// PUT /equipment/{id}/sample-rules
+///
+/// Only affects files ingested from now on; `POST .../sample-rules/apply`
+/// re-runs the rules over files that are not linked yet.
+pub(super) async fn update_sample_rules(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(rules): Json<Vec<SampleRule>>,
+) -> ApiResult<Vec<SampleRule>> {
+    SampleMatcher::new(&rules)?;
+    find_equipment(&state.db, &id).await?;
+
+    let rules_json =
+        serde_json::to_string(&rules).map_err(|e| ApiError::Internal(e.to_string()))?;
+    let equipment = state
+        .db
+        .equipment()
+        .update(
+            equipment::id::equals(id),
+            vec![equipment::sample_rules::set(rules_json)],
+        )
+        .exec()
+        .await?;
+
+    state.events.publish(ChangeEvent::EquipmentUpdated {
+        id: equipment.id.clone(),
+    });
+    Ok(Json(rules_of(&equipment)))
+}
+
+/// Result of re-running the rules
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleMatchReport {
+    /// Assets linked to a sample by this run
+    pub matched: usize,
+    /// Assets with an identifier that names no sample
+    pub unmatched: usize,
+}
+
+/// POST /equipment/{id}/sample-rules/apply
+pub(super) async fn apply_sample_rules(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<SampleMatchReport> {
+    let equipment = find_equipment(&state.db, &id).await?;
+    Ok(Json(rematch_unlinked(&state, &equipment).await?))
+}
+
+/// A file whose sample could not be determined
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnmatchedAsset {
+    pub asset_id: String,
+    pub filename: String,
+    /// Identifier the rules extracted
+    pub sample_key: String,
+    pub experiment_id: Option<String>,
+    pub created_at: DateTime<FixedOffset>,
+}
+
+/// GET /equipment/{id}/unmatched-assets
+///
+/// Files whose path matched a rule but whose identifier names no sample,
+/// newest first.
+pub(super) async fn list_unmatched_assets(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<UnmatchedAsset>> {
+    let equipment = find_equipment(&state.db, &id).await?;
+    let assets = state
+        .db
+        .digital_asset()
+        .find_many(vec![
+            digital_asset::machine_id::in_vec(agent_machine_ids(&equipment)),
+            digital_asset::sample_id::equals(None),
+            digital_asset::sample_key::not(None),
+        ])
+        .order_by(digital_asset::created_at::order(SortOrder::Desc))
+        .exec()
+        .await?;
+
+    let unmatched = assets
+        .into_iter()
+        .map(|asset| UnmatchedAsset {
+            asset_id: asset.id,
+            filename: asset.filename,
+            sample_key: asset.sample_key.unwrap_or_default(),
+            experiment_id: asset.experiment_id,
+            created_at: asset.created_at,
+        })
+        .collect();
+    Ok(Json(unmatched))
+}
+
+#[derive(Deserialize)]
+pub struct AssignSampleRequest {
+    /// `null` unlinks the asset
+    pub sample_id: Option<String>,
+}
+
+/// PUT /assets/{id}/sample
+pub(super) async fn assign_asset_sample(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<AssignSampleRequest>,
+) -> ApiResult<digital_asset::Data> {
+    state
+        .db
+        .digital_asset()
+        .find_unique(digital_asset::id::equals(id.clone()))
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Asset {}", id)))?;
+
+    let link = match &payload.sample_id {
+        Some(sample_id) => {
+            state
+                .db
+                .sample()
+                .find_unique(sample::id::equals(sample_id.clone()))
+                .exec()
+                .await?
+                .ok_or_else(|| ApiError::NotFound(format!("Sample {}", sample_id)))?;
+            digital_asset::sample::connect(sample::id::equals(sample_id.clone()))
+        }
+        None => digital_asset::sample::disconnect(),
+    };
+
+    let asset = state
+        .db
+        .digital_asset()
+        .update(digital_asset::id::equals(id), vec![link])
+        .exec()
+        .await?;
+
+    state.events.publish(ChangeEvent::AssetSampleLinked {
+        id: asset.id.clone(),
+        sample_id: asset.sample_id.clone(),
+    });
+    Ok(Json(asset))
+}
+
+/// Run an instrument's rules over a file it uploaded
+pub(crate) async fn match_sample(
+    db: &PrismaClient,
+    equipment: &equipment::Data,
+    filename: &str,
+) -> Result<SampleMatch, ApiError> {
+    let matcher = match SampleMatcher::new(&rules_of(equipment)) {
+        Ok(matcher) => matcher,
+        Err(e) => {
+            tracing::warn!("Ignoring sample rules of {}: {}", equipment.name, e);
+            return Ok(SampleMatch::default());
+        }
+    };
+    if matcher.is_empty() {
+        return Ok(SampleMatch::default());
+    }
+
+    let sheets = Sheets::load(db, equipment, &matcher).await?;
+    let keys = matcher.keys(filename, sheets.closest(filename));
+    let samples = find_samples(db, &keys).await?;
+    Ok(resolve(keys, &samples))
+}
+
+/// Re-run the rules over an instrument's assets that are not linked to a
+/// sample, e.g. after the rules changed or a sample sheet arrived.
+///
+/// Sample sheets are loaded once and every identifier is looked up in one
+/// query per field, however many files there are.
+pub(crate) async fn rematch_unlinked(
+    state: &AppState,
+    equipment: &equipment::Data,
+) -> Result<SampleMatchReport, ApiError> {
+    let mut report = SampleMatchReport {
+        matched: 0,
+        unmatched: 0,
+    };
+    let matcher = SampleMatcher::new(&rules_of(equipment))?;
+    if matcher.is_empty() {
+        return Ok(report);
+    }
+
+    let assets = state
+        .db
+        .digital_asset()
+        .find_many(vec![
+            digital_asset::machine_id::in_vec(agent_machine_ids(equipment)),
+            digital_asset::sample_id::equals(None),
+        ])
+        .exec()
+        .await?;
+    let sheets = Sheets::load(&state.db, equipment, &matcher).await?;
+    let keys: Vec<Vec<SampleKey>> = assets
+        .iter()
+        .map(|asset| matcher.keys(&asset.filename, sheets.closest(&asset.filename)))
+        .collect();
+    let samples = find_samples(&state.db, keys.iter().flatten()).await?;
+
+    for (asset, keys) in assets.into_iter().zip(keys) {
+        let found = resolve(keys, &samples);
+        let mut params = vec![digital_asset::sample_key::set(found.key.clone())];
+        if let Some(sample_id) = &found.sample_id {
+            params.push(digital_asset::sample::connect(sample::id::equals(
+                sample_id.clone(),
+            )));
+            report.matched += 1;
+        } else if found.key.is_some() {
+            report.unmatched += 1;
+        }
+        if found.sample_id.is_none() && found.key == asset.sample_key {
+            continue;
+        }
+
+        state
+            .db
+            .digital_asset()
+            .update(digital_asset::id::equals(asset.id.clone()), params)
+            .exec()
+            .await?;
+        if found.sample_id.is_some() {
+            state.events.publish(ChangeEvent::AssetSampleLinked {
+                id: asset.id,
+                sample_id: found.sample_id,
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// The first identifier naming a sample, or else the first identifier
+fn resolve(keys: Vec<SampleKey>, samples: &HashMap<(SampleField, String), String>) -> SampleMatch {
+    for key in &keys {
+        if let Some(sample_id) = samples.get(&(key.field, key.value.clone())) {
+            return SampleMatch {
+                sample_id: Some(sample_id.clone()),
+                key: Some(key.value.clone()),
+            };
+        }
+    }
+    SampleMatch {
+        sample_id: None,
+        key: keys.into_iter().next().map(|key| key.value),
+    }
+}
+
+/// The samples the identifiers name, by field and value. Names are not
+/// unique, so a name shared by several samples names none of them.
+async fn find_samples<'a>(
+    db: &PrismaClient,
+    keys: impl IntoIterator<Item = &'a SampleKey>,
+) -> Result<HashMap<(SampleField, String), String>, ApiError> {
+    let mut external_ids = BTreeSet::new();
+    let mut names = BTreeSet::new();
+    for key in keys {
+        match key.field {
+            SampleField::ExternalId => external_ids.insert(key.value.clone()),
+            SampleField::Name => names.insert(key.value.clone()),
+        };
+    }
+
+    let mut found: HashMap<(SampleField, String), Vec<String>> = HashMap::new();
+    let external_ids: Vec<String> = external_ids.into_iter().collect();
+    for chunk in external_ids.chunks(LOOKUP_CHUNK) {
+        let samples = db
+            .sample()
+            .find_many(vec![sample::external_id::in_vec(chunk.to_vec())])
+            .exec()
+            .await?;
+        for sample in samples {
+            if let Some(external_id) = sample.external_id {
+                found
+                    .entry((SampleField::ExternalId, external_id))
+                    .or_default()
+                    .push(sample.id);
+            }
+        }
+    }
+    let names: Vec<String> = names.into_iter().collect();
+    for chunk in names.chunks(LOOKUP_CHUNK) {
+        let samples = db
+            .sample()
+            .find_many(vec![sample::name::in_vec(chunk.to_vec())])
+            .exec()
+            .await?;
+        for sample in samples {
+            found
+                .entry((SampleField::Name, sample.name))
+                .or_default()
+                .push(sample.id);
+        }
+    }
+
+    Ok(found
+        .into_iter()
+        .filter_map(|(key, mut ids)| (ids.len() == 1).then(|| (key, ids.remove(0))))
+        .collect())
+}
+
+/// An instrument's sample sheets: the folder each was uploaded from and its
+/// rows, oldest first
+struct Sheets(Vec<(String, Option<Vec<Value>>)>);
+
+impl Sheets {
+    /// Only loaded when a rule reads sample sheets
+    async fn load(
+        db: &PrismaClient,
+        equipment: &equipment::Data,
+        matcher: &SampleMatcher,
+    ) -> Result<Self, ApiError> {
+        if !matcher.uses_sheet() {
+            return Ok(Self(vec![]));
+        }
+        let sheets = db
+            .digital_asset()
+            .find_many(vec![
+                digital_asset::machine_id::in_vec(agent_machine_ids(equipment)),
+                digital_asset::metadata_format::equals(Some(SAMPLE_SHEET.to_string())),
+            ])
+            .order_by(digital_asset::created_at::order(SortOrder::Asc))
+            .exec()
+            .await?;
+
+        Ok(Self(
+            sheets
+                .into_iter()
+                .map(|sheet| {
+                    let folder = sheet
+                        .filename
+                        .rsplit_once('/')
+                        .map_or("", |(folder, _)| folder)
+                        .to_string();
+                    let samples = sheet
+                        .metadata
+                        .as_deref()
+                        .and_then(|metadata| serde_json::from_str::<Value>(metadata).ok())
+                        .and_then(|fields| fields["samples"].as_array().cloned());
+                    (folder, samples)
+                })
+                .collect(),
+        ))
+    }
+
+    /// Rows of the sample sheet closest to the file: the newest one from the
+    /// file's folder or the nearest folder above it
+    fn closest(&self, filename: &str) -> Option<&[Value]> {
+        self.0
+            .iter()
+            .filter(|(folder, _)| {
+                folder.is_empty() || filename.starts_with(&format!("{}/", folder))
+            })
+            // Deepest folder; `max_by_key` keeps the last of equals, i.e. the newest
+            .max_by_key(|(folder, _)| folder.len())
+            .and_then(|(_, samples)| samples.as_deref())
+    }
+}
+
+/// Sample rules stored on the Equipment record
+fn rules_of(equipment: &equipment::Data) -> Vec<SampleRule> {
+    serde_json::from_str(&equipment.sample_rules).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn key(field: SampleField, value: &str) -> SampleKey {
+        SampleKey {
+            value: value.to_string(),
+            field,
+        }
+    }
+
+    #[test]
+    fn resolve_takes_the_first_identifier_that_names_a_sample() {
+        let samples =
+            HashMap::from([((SampleField::Name, "HeLa-2".to_string()), "s2".to_string())]);
+        let found = resolve(
+            vec![
+                key(SampleField::ExternalId, "EXT-1"),
+                key(SampleField::Name, "HeLa-2"),
+            ],
+            &samples,
+        );
+        assert_eq!(found.sample_id.as_deref(), Some("s2"));
+        assert_eq!(found.key.as_deref(), Some("HeLa-2"));
+
+        // Same value, other field
+        let found = resolve(vec![key(SampleField::ExternalId, "HeLa-2")], &samples);
+        assert_eq!(found.sample_id, None);
+        assert_eq!(found.key.as_deref(), Some("HeLa-2"));
+    }
+
+    #[test]
+    fn closest_sheet_is_the_newest_in_the_deepest_folder() {
+        let rows = |name: &str| Some(vec![json!({ "Sample_ID": name })]);
+        let sheets = Sheets(vec![
+            (String::new(), rows("root")),
+            ("run1".to_string(), rows("old")),
+            ("run1".to_string(), rows("new")),
+            ("run2".to_string(), rows("other")),
+        ]);
+        let id = |filename: &str| {
+            sheets
+                .closest(filename)
+                .map(|rows| rows[0]["Sample_ID"].clone())
+        };
+        assert_eq!(id("run1/Data/A1.fastq"), Some(json!("new")));
+        assert_eq!(id("run10/A1.fastq"), Some(json!("root")));
+        assert_eq!(Sheets(vec![]).closest("run1/A1.fastq"), None);
+    }
+}
@@ -0,0 +1,193 @@
+//! Electronic signatures and notebook locking
+//!
+//! A completed experiment is signed by its author, which locks it, and then
+//! countersigned by a witness. Each signature records its meaning, a timestamp
+//! and a SHA-256 hash of the record. Once locked, the notebook can only be
+//! amended through separately signed `ExperimentEntry` records.
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use openbio_core::experiment::content_hash;
+use openbio_core::{ExperimentStatus, SignatureRole};
+use prisma_client_rust::chrono::{DateTime, FixedOffset, Utc};
+use serde::Deserialize;
+
+use crate::db::prisma::{
+    experiment, experiment_entry, experiment_signature, PrismaClient, SortOrder,
+};
+use crate::events::ChangeEvent;
+use crate::{ApiError, ApiResult, AppState};
+
+#[derive(Deserialize)]
+pub struct SignRequest {
+    pub signer: String,
+    /// What the signature attests, e.g. "Authored and approved"
+    pub meaning: String,
+}
+
+/// GET /experiments/{id}/signatures
+pub(super) async fn list_signatures(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Vec<experiment_signature::Data>> {
+    let signatures = state
+        .db
+        .experiment_signature()
+        .find_many(vec![experiment_signature::experiment_id::equals(id)])
+        .order_by(experiment_signature::signed_at::order(SortOrder::Asc))
+        .exec()
+        .await?;
+    Ok(Json(signatures))
+}
+
+/// POST /experiments/{id}/signatures/{role}
+pub(super) async fn sign_experiment(
+    State(state): State<AppState>,
+    Path((id, role)): Path<(String, String)>,
+    Json(payload): Json<SignRequest>,
+) -> ApiResult<experiment_signature::Data> {
+    let role: SignatureRole =
+        role.parse().map_err(|e: openbio_core::Error| ApiError::BadRequest(e.to_string()))?;
+
+    if payload.signer.trim().is_empty() || payload.meaning.trim().is_empty() {
+        return Err(ApiError::Unprocessable(
+            "A signature needs a signer and a meaning".to_string(),
+        ));
+    }
+
+    let signature = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            let experiment = tx
+                .experiment()
+                .find_unique(experiment::id::equals(id.clone()))
+                .with(experiment::entries::fetch(vec![]))
+                .with(experiment::signatures::fetch(vec![]))
+                .exec()
+                .await?
+                .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", id)))?;
+
+            let status: ExperimentStatus = experiment.status.parse()?;
+            if status != ExperimentStatus::Completed {
+                return Err(ApiError::Unprocessable(format!(
+                    "Only completed experiments can be signed (status is {})",
+                    status
+                )));
+            }
+
+            let signatures = experiment.signatures().map_err(internal)?;
+            let signed_as = |r: SignatureRole| signatures.iter().find(|s| s.role == r.as_str());
+
+            if signed_as(role).is_some() {
+                return Err(ApiError::Conflict(format!(
+                    "Experiment {} already has a {} signature",
+                    id, role
+                )));
+            }
+
+            let hash = record_hash(&experiment.content, experiment.entries().map_err(internal)?);
+
+            if role == SignatureRole::Witness {
+                let author = signed_as(SignatureRole::Author).ok_or_else(|| {
+                    ApiError::Unprocessable("The author has to sign first".to_string())
+                })?;
+                if author.signer == payload.signer {
+                    return Err(ApiError::Unprocessable(
+                        "The witness must be a different person than the author".to_string(),
+                    ));
+                }
+                if author.content_hash != hash {
+                    return Err(ApiError::Conflict(
+                        "The record changed since the author signed it".to_string(),
+                    ));
+                }
+            }
+
+            let now: DateTime<FixedOffset> = Utc::now().into();
+            let signature = tx
+                .experiment_signature()
+                .create(
+                    experiment::id::equals(id.clone()),
+                    role.to_string(),
+                    payload.signer,
+                    payload.meaning,
+                    hash.clone(),
+                    vec![experiment_signature::signed_at::set(now)],
+                )
+                .exec()
+                .await?;
+
+            if role == SignatureRole::Author {
+                tx.experiment()
+                    .update(
+                        experiment::id::equals(id),
+                        vec![
+                            experiment::locked_at::set(Some(now)),
+                            experiment::content_hash::set(Some(hash)),
+                        ],
+                    )
+                    .exec()
+                    .await?;
+            }
+
+            Ok::<_, ApiError>(signature)
+        })
+        .await?;
+
+    // An author's signature locks the experiment
+    state.events.publish(ChangeEvent::ExperimentUpdated {
+        id: signature.experiment_id.clone(),
+    });
+    Ok(Json(signature))
+}
+
+/// Fetch an experiment, refusing with 409 if it has been signed and locked
+pub(crate) async fn ensure_unlocked(
+    db: &PrismaClient,
+    experiment_id: &str,
+) -> Result<experiment::Data, ApiError> {
+    let experiment = db
+        .experiment()
+        .find_unique(experiment::id::equals(experiment_id.to_string()))
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", experiment_id)))?;
+
+    if experiment.locked_at.is_some() {
+        return Err(locked_error(experiment_id));
+    }
+    Ok(experiment)
+}
+
+pub(crate) fn locked_error(experiment_id: &str) -> ApiError {
+    ApiError::Conflict(format!(
+        "Experiment {} is signed and locked; add a signed amendment entry instead",
+        experiment_id
+    ))
+}
+
+/// Hash of the signed record: the notebook content followed by its original
+/// (non-amendment) entries in chronological order
+pub(crate) fn record_hash(content: &str, entries: &[experiment_entry::Data]) -> String {
+    let mut entries: Vec<&experiment_entry::Data> =
+        entries.iter().filter(|e| !e.is_amendment).collect();
+    entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.id.cmp(&b.id)));
+
+    let mut record = content.to_string();
+    for entry in entries {
+        record.push_str(&format!(
+            "\n--- entry {} {}\n{}",
+            entry.id,
+            entry.timestamp.to_rfc3339(),
+            entry.content
+        ));
+    }
+    content_hash(&record)
+}
+
+fn internal(e: impl std::fmt::Display) -> ApiError {
+    ApiError::Internal(e.to_string())
+}
@@ -0,0 +1,385 @@
+//! Experiment templates and cloning
+//!
+//! A template holds a protocol: a notebook skeleton, the sample roles it expects,
+//! the type of equipment it runs on and a checklist of steps. New experiments
+//! can be created from a template or cloned from an existing experiment; both
+//! start as a fresh DRAFT with their own entries.
+
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use openbio_core::notebook::append_checklist;
+use openbio_core::SampleRole;
+use serde::Deserialize;
+
+use super::{mentions, revisions, SampleLink};
+use crate::db::prisma::{
+    equipment, experiment, experiment_entry, experiment_sample, experiment_template, sample,
+    PrismaClient, SortOrder,
+};
+use crate::events::ChangeEvent;
+use crate::{ApiError, ApiResult, AppState};
+
+#[derive(Deserialize)]
+pub struct CreateTemplateRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub content: Option<String>,
+    #[serde(default)]
+    pub sample_roles: Vec<String>,
+    pub equipment_type: Option<String>,
+    #[serde(default)]
+    pub checklist: Vec<String>,
+    pub created_by: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateTemplateRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub content: Option<String>,
+    pub sample_roles: Option<Vec<String>>,
+    pub equipment_type: Option<String>,
+    pub checklist: Option<Vec<String>>,
+}
+
+/// GET /experiments/templates
+pub(super) async fn list_templates(
+    State(state): State<AppState>,
+) -> ApiResult<Vec<experiment_template::Data>> {
+    let templates = state
+        .db
+        .experiment_template()
+        .find_many(vec![])
+        .order_by(experiment_template::name::order(SortOrder::Asc))
+        .exec()
+        .await?;
+    Ok(Json(templates))
+}
+
+/// POST /experiments/templates
+pub(super) async fn create_template(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateTemplateRequest>,
+) -> ApiResult<experiment_template::Data> {
+    let mut params = vec![
+        experiment_template::description::set(payload.description),
+        experiment_template::sample_roles::set(roles_json(&payload.sample_roles)?),
+        experiment_template::equipment_type::set(payload.equipment_type),
+        experiment_template::checklist::set(checklist_json(&payload.checklist)),
+        experiment_template::created_by::set(payload.created_by),
+    ];
+    if let Some(content) = payload.content {
+        params.push(experiment_template::content::set(content));
+    }
+
+    let template = state
+        .db
+        .experiment_template()
+        .create(payload.name, params)
+        .exec()
+        .await?;
+    Ok(Json(template))
+}
+
+/// GET /experiments/templates/{id}
+pub(super) async fn get_template(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<experiment_template::Data> {
+    Ok(Json(find_template(&state.db, &id).await?))
+}
+
+/// PATCH /experiments/templates/{id}
+pub(super) async fn update_template(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateTemplateRequest>,
+) -> ApiResult<experiment_template::Data> {
+    let mut params: Vec<experiment_template::SetParam> = vec![];
+
+    if let Some(name) = payload.name {
+        params.push(experiment_template::name::set(name));
+    }
+    if let Some(description) = payload.description {
+        params.push(experiment_template::description::set(Some(description)));
+    }
+    if let Some(content) = payload.content {
+        params.push(experiment_template::content::set(content));
+    }
+    if let Some(roles) = payload.sample_roles {
+        params.push(experiment_template::sample_roles::set(roles_json(&roles)?));
+    }
+    if let Some(equipment_type) = payload.equipment_type {
+        params.push(experiment_template::equipment_type::set(Some(equipment_type)));
+    }
+    if let Some(checklist) = payload.checklist {
+        params.push(experiment_template::checklist::set(checklist_json(&checklist)));
+    }
+
+    let template = state
+        .db
+        .experiment_template()
+        .update(experiment_template::id::equals(id), params)
+        .exec()
+        .await?;
+    Ok(Json(template))
+}
+
+/// DELETE /experiments/templates/{id}
+///
+/// Experiments created from the template keep their content; only the link is cleared.
+pub(super) async fn delete_template(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<()> {
+    state
+        .db
+        .experiment_template()
+        .delete(experiment_template::id::equals(id))
+        .exec()
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize)]
+pub struct CreateFromTemplateRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub created_by: Option<String>,
+    pub equipment_id: Option<String>,
+    /// Samples to link; a link without a role takes the template's role for its slot
+    #[serde(default)]
+    pub samples: Vec<SampleLink>,
+}
+
+/// POST /experiments/templates/{id}/experiments
+pub(super) async fn create_from_template(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<CreateFromTemplateRequest>,
+) -> ApiResult<experiment::Data> {
+    let template = find_template(&state.db, &id).await?;
+
+    if let (Some(equipment_id), Some(equipment_type)) =
+        (payload.equipment_id.as_ref(), template.equipment_type.as_ref())
+    {
+        let equipment = state
+            .db
+            .equipment()
+            .find_unique(equipment::id::equals(equipment_id.clone()))
+            .exec()
+            .await?
+            .ok_or_else(|| ApiError::NotFound(format!("Equipment {}", equipment_id)))?;
+        if &equipment.r#type != equipment_type {
+            return Err(ApiError::Unprocessable(format!(
+                "Template {} runs on {} equipment, {} is a {}",
+                template.name, equipment_type, equipment.name, equipment.r#type
+            )));
+        }
+    }
+
+    let default_roles: Vec<String> =
+        serde_json::from_str(&template.sample_roles).unwrap_or_default();
+    let mut links = vec![];
+    for (slot, link) in payload.samples.into_iter().enumerate() {
+        let role = match link.role.or_else(|| default_roles.get(slot).cloned()) {
+            Some(role) => Some(role.parse::<SampleRole>()?.as_str().to_string()),
+            None => None,
+        };
+        links.push((link.sample_id, role));
+    }
+
+    let checklist: Vec<String> = serde_json::from_str(&template.checklist).unwrap_or_default();
+    let experiment = create_copy(
+        &state.db,
+        NewExperiment {
+            name: payload.name,
+            description: payload.description.or(template.description),
+            content: append_checklist(&template.content, &checklist),
+            equipment_id: payload.equipment_id,
+            template_id: Some(template.id),
+            cloned_from_id: None,
+            created_by: payload.created_by,
+            samples: links,
+            provenance: format!("Created from template \"{}\"", template.name),
+        },
+    )
+    .await?;
+
+    state.events.publish(ChangeEvent::ExperimentCreated {
+        id: experiment.id.clone(),
+    });
+    Ok(Json(experiment))
+}
+
+#[derive(Deserialize)]
+pub struct CloneExperimentRequest {
+    /// Defaults to "<name> (copy)"
+    pub name: Option<String>,
+    pub created_by: Option<String>,
+}
+
+/// POST /experiments/{id}/clone
+///
+/// Copies content, equipment and sample links (with roles). Status, signatures,
+/// revisions and entries are not copied.
+pub(super) async fn clone_experiment(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<CloneExperimentRequest>,
+) -> ApiResult<experiment::Data> {
+    let source = state
+        .db
+        .experiment()
+        .find_unique(experiment::id::equals(id.clone()))
+        .with(experiment::samples::fetch(vec![]))
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Experiment {}", id)))?;
+
+    let samples = source
+        .samples()
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .iter()
+        .map(|link| (link.sample_id.clone(), link.role.clone()))
+        .collect();
+
+    let experiment = create_copy(
+        &state.db,
+        NewExperiment {
+            name: payload.name.unwrap_or_else(|| format!("{} (copy)", source.name)),
+            description: source.description.clone(),
+            content: source.content.clone(),
+            equipment_id: source.equipment_id.clone(),
+            template_id: source.template_id.clone(),
+            cloned_from_id: Some(source.id.clone()),
+            created_by: payload.created_by,
+            samples,
+            provenance: format!("Cloned from experiment \"{}\"", source.name),
+        },
+    )
+    .await?;
+
+    state.events.publish(ChangeEvent::ExperimentCreated {
+        id: experiment.id.clone(),
+    });
+    Ok(Json(experiment))
+}
+
+/// A new DRAFT experiment based on a template or another experiment
+struct NewExperiment {
+    name: String,
+    description: Option<String>,
+    content: String,
+    equipment_id: Option<String>,
+    template_id: Option<String>,
+    cloned_from_id: Option<String>,
+    created_by: Option<String>,
+    /// (sample ID, role)
+    samples: Vec<(String, Option<String>)>,
+    /// First entry of the new notebook, recording where it came from
+    provenance: String,
+}
+
+async fn create_copy(db: &PrismaClient, new: NewExperiment) -> Result<experiment::Data, ApiError> {
+    // A sample is linked once, with one role
+    let mut seen = HashSet::new();
+    if let Some(duplicate) = new.samples.iter().map(|(id, _)| id).find(|id| !seen.insert(*id)) {
+        return Err(ApiError::Unprocessable(format!(
+            "Sample {} is listed more than once",
+            duplicate
+        )));
+    }
+
+    let sample_ids: Vec<String> = new.samples.iter().map(|(id, _)| id.clone()).collect();
+    let found = db
+        .sample()
+        .find_many(vec![sample::id::in_vec(sample_ids.clone())])
+        .exec()
+        .await?;
+    if let Some(missing) = sample_ids.iter().find(|id| !found.iter().any(|s| &s.id == *id)) {
+        return Err(ApiError::NotFound(format!("Sample {}", missing)));
+    }
+
+    db._transaction()
+        .run(|tx| async move {
+            let mut params = vec![
+                experiment::description::set(new.description),
+                experiment::content::set(new.content.clone()),
+                experiment::cloned_from_id::set(new.cloned_from_id),
+                experiment::created_by::set(new.created_by.clone()),
+            ];
+            if let Some(equipment_id) = new.equipment_id {
+                params.push(experiment::equipment::connect(equipment::id::equals(equipment_id)));
+            }
+            if let Some(template_id) = new.template_id {
+                params.push(experiment::template::connect(experiment_template::id::equals(
+                    template_id,
+                )));
+            }
+
+            let experiment = tx.experiment().create(new.name, params).exec().await?;
+
+            for (sample_id, role) in new.samples {
+                tx.experiment_sample()
+                    .create(
+                        experiment::id::equals(experiment.id.clone()),
+                        sample::id::equals(sample_id),
+                        vec![experiment_sample::role::set(role)],
+                    )
+                    .exec()
+                    .await?;
+            }
+
+            tx.experiment_entry()
+                .create(
+                    experiment::id::equals(experiment.id.clone()),
+                    new.provenance,
+                    vec![experiment_entry::author::set(new.created_by.clone())],
+                )
+                .exec()
+                .await?;
+
+            if !new.content.is_empty() {
+                mentions::sync_mentions(&tx, &experiment.id, &new.content).await?;
+                revisions::record_revision(&tx, &experiment.id, new.content, new.created_by, None)
+                    .await?;
+            }
+
+            Ok::<_, ApiError>(experiment)
+        })
+        .await
+}
+
+async fn find_template(
+    db: &PrismaClient,
+    id: &str,
+) -> Result<experiment_template::Data, ApiError> {
+    db.experiment_template()
+        .find_unique(experiment_template::id::equals(id.to_string()))
+        .exec()
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Template {}", id)))
+}
+
+/// Validate sample roles and store them as a JSON array
+fn roles_json(roles: &[String]) -> Result<String, ApiError> {
+    let roles = roles
+        .iter()
+        .map(|role| Ok(role.parse::<SampleRole>()?.as_str()))
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    Ok(serde_json::to_string(&roles).unwrap_or_default())
+}
+
+fn checklist_json(items: &[String]) -> String {
+    let items: Vec<&str> = items
+        .iter()
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .collect();
+    serde_json::to_string(&items).unwrap_or_default()
+}
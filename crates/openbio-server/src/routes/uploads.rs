@@ -0,0 +1,381 @@
+//! Resumable uploads from ingest agents
+//!
+//! 1. `POST /agent/uploads` announces a file by size and checksum. A file the
+//!    instrument already uploaded is reported as a duplicate. So is one whose
+//!    content another instrument uploaded, after recording an asset for this
+//!    instrument that shares the stored file. Otherwise the reply carries the
+//!    offset to continue from (0 for a new upload).
+//! 2. `PUT /agent/uploads/{upload_id}?offset=...` appends a chunk.
+//! 3. `POST /agent/uploads/{upload_id}/complete` verifies the checksum, stores
+//!    the file and creates its `DigitalAsset`, linked to the experiment booked
+//!    on the instrument when the file was written and to the sample the
+//!    instrument's sample rules find. Metadata is extracted with the parser
+//!    registry and, for booked files, summarised in an automatic notebook
+//!    entry.
+//!
+//! `GET /agent/assets` lists what an instrument has uploaded so far, which the
+//! agent compares against its watch folder to backfill files it missed.
+
+use std::path::{Component, Path as FsPath};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use openbio_core::agent::{
+    AssetReceipt, ChunkResponse, IngestedFile, UploadRequest, UploadResponse, UPLOAD_CHUNK_SIZE,
+};
+use openbio_core::experiment::content_hash;
+use openbio_core::parsers::illumina::SAMPLE_SHEET;
+use openbio_core::parsers::FileMetadata;
+use openbio_core::AgentStatus;
+use prisma_client_rust::chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use super::agent::{agent_machine_ids, find_agent_equipment};
+use super::agent_keys::AgentIdentity;
+use super::bookings::{equipment_bookings, find_equipment};
+use super::sample_matching::{match_sample, rematch_unlinked};
+use crate::assets::UploadSession;
+use crate::db::prisma::{digital_asset, equipment, experiment, experiment_entry, sample};
+use crate::events::ChangeEvent;
+use crate::{ApiError, ApiResult, AppState};
+
+/// Request body limit for chunk uploads
+pub(super) const CHUNK_BODY_LIMIT: usize = UPLOAD_CHUNK_SIZE + 64 * 1024;
+
+/// POST /agent/uploads
+pub(super) async fn start_upload(
+    State(state): State<AppState>,
+    Extension(agent): Extension<AgentIdentity>,
+    Json(payload): Json<UploadRequest>,
+) -> ApiResult<UploadResponse> {
+    validate_filename(&payload.filename)?;
+    let is_sha256 =
+        payload.checksum.len() == 64 && payload.checksum.chars().all(|c| c.is_ascii_hexdigit());
+    if !is_sha256 {
+        return Err(ApiError::BadRequest(
+            "checksum must be a hex SHA-256".to_string(),
+        ));
+    }
+    let checksum = payload.checksum.to_lowercase();
+
+    let equipment = find_agent_equipment(&state.db, &agent, &payload.machine_id).await?;
+    refuse_locked(&equipment)?;
+
+    // Same file from the same instrument resumes the same upload
+    let upload_id = content_hash(&format!("{}:{}", equipment.id, checksum))[..32].to_string();
+    let session = UploadSession {
+        id: upload_id.clone(),
+        machine_id: payload.machine_id,
+        equipment_id: equipment.id.clone(),
+        filename: payload.filename,
+        size_bytes: payload.size_bytes,
+        checksum,
+        modified_at: payload.modified_at,
+        mime_type: payload.mime_type,
+    };
+
+    let existing = state
+        .db
+        .digital_asset()
+        .find_many(vec![digital_asset::checksum::equals(Some(
+            session.checksum.clone(),
+        ))])
+        .exec()
+        .await?;
+    let machine_ids = agent_machine_ids(&equipment);
+    if let Some(asset) = existing
+        .iter()
+        .find(|asset| asset.machine_id.as_ref().is_some_and(|id| machine_ids.contains(id)))
+    {
+        return Ok(Json(UploadResponse::Duplicate {
+            asset_id: asset.id.clone(),
+        }));
+    }
+    // Another instrument (or a manual upload) stored the same content: this
+    // instrument gets its own asset, sharing the stored file
+    if let Some(stored) = existing.into_iter().next() {
+        let receipt = record_asset(&state, session, stored.storage_key).await?;
+        return Ok(Json(UploadResponse::Duplicate {
+            asset_id: receipt.asset_id,
+        }));
+    }
+
+    let offset = state.assets.begin(&session)?;
+
+    Ok(Json(UploadResponse::Pending { upload_id, offset }))
+}
+
+#[derive(Deserialize)]
+pub struct ChunkQuery {
+    pub offset: u64,
+}
+
+/// PUT /agent/uploads/{upload_id}?offset=...
+///
+/// A chunk that does not start where the previous one ended returns 409; the
+/// agent then asks `POST /agent/uploads` for the current offset.
+pub(super) async fn upload_chunk(
+    State(state): State<AppState>,
+    Extension(agent): Extension<AgentIdentity>,
+    Path(upload_id): Path<String>,
+    Query(query): Query<ChunkQuery>,
+    body: Bytes,
+) -> ApiResult<ChunkResponse> {
+    let session = state.assets.session(&upload_id)?;
+    agent.check(&session.equipment_id)?;
+    refuse_locked(&find_equipment(&state.db, &session.equipment_id).await?)?;
+    let offset = state.assets.append(&session, query.offset, &body)?;
+    Ok(Json(ChunkResponse { offset }))
+}
+
+/// POST /agent/uploads/{upload_id}/complete
+pub(super) async fn complete_upload(
+    State(state): State<AppState>,
+    Extension(agent): Extension<AgentIdentity>,
+    Path(upload_id): Path<String>,
+) -> ApiResult<AssetReceipt> {
+    let session = state.assets.session(&upload_id)?;
+    agent.check(&session.equipment_id)?;
+    // The instrument may have been locked while the file was being sent
+    refuse_locked(&find_equipment(&state.db, &session.equipment_id).await?)?;
+
+    let storage_key = format!(
+        "{}/{}/{}",
+        session.equipment_id, session.id, session.filename
+    );
+    state.assets.finish(&session, &storage_key).await?;
+    Ok(Json(record_asset(&state, session, storage_key).await?))
+}
+
+/// Uploads from a locked instrument are refused at every step
+fn refuse_locked(equipment: &equipment::Data) -> Result<(), ApiError> {
+    if equipment.agent_status == AgentStatus::Locked.as_str() {
+        return Err(ApiError::Conflict(format!(
+            "Equipment {} is locked; uploads are refused",
+            equipment.name
+        )));
+    }
+    Ok(())
+}
+
+/// Create the `DigitalAsset` of a file stored under `storage_key`, linked to
+/// the experiment booked on the instrument when the file was written and to
+/// the sample the instrument's sample rules find
+async fn record_asset(
+    state: &AppState,
+    session: UploadSession,
+    storage_key: String,
+) -> Result<AssetReceipt, ApiError> {
+    let metadata = extract_metadata(state, &storage_key, &session.filename).await;
+
+    // Files belong to whoever had the instrument booked when they were written
+    let written_at = session
+        .modified_at
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .unwrap_or_else(Utc::now);
+    let experiment_id = booked_experiment(state, &session.equipment_id, written_at).await?;
+    let equipment = find_equipment(&state.db, &session.equipment_id).await?;
+    let found = match_sample(&state.db, &equipment, &session.filename).await?;
+
+    let mut params = vec![
+        digital_asset::mime_type::set(session.mime_type),
+        digital_asset::size_bytes::set(i32::try_from(session.size_bytes).ok()),
+        digital_asset::checksum::set(Some(session.checksum)),
+        digital_asset::machine_id::set(Some(session.machine_id.clone())),
+        digital_asset::metadata_format::set(metadata.as_ref().map(|m| m.format.clone())),
+        digital_asset::metadata::set(metadata.as_ref().map(|m| m.fields.to_string())),
+        digital_asset::sample_key::set(found.key),
+    ];
+    if let Some(experiment_id) = &experiment_id {
+        params.push(digital_asset::experiment::connect(experiment::id::equals(
+            experiment_id.clone(),
+        )));
+    }
+    if let Some(sample_id) = found.sample_id {
+        params.push(digital_asset::sample::connect(sample::id::equals(
+            sample_id,
+        )));
+    }
+    let is_sample_sheet = metadata
+        .as_ref()
+        .is_some_and(|metadata| metadata.format == SAMPLE_SHEET);
+
+    let entry = match &experiment_id {
+        Some(experiment_id) => {
+            ingest_entry(
+                state,
+                experiment_id,
+                &equipment,
+                &session,
+                metadata.as_ref(),
+            )
+            .await?
+        }
+        None => None,
+    };
+
+    let asset = state
+        .db
+        ._transaction()
+        .run(|tx| async move {
+            let asset = tx
+                .digital_asset()
+                .create(session.filename, storage_key, params)
+                .exec()
+                .await?;
+            if let Some((experiment_id, content, author)) = entry {
+                tx.experiment_entry()
+                    .create(
+                        experiment::id::equals(experiment_id),
+                        content,
+                        vec![
+                            experiment_entry::author::set(Some(author)),
+                            experiment_entry::attached_asset_id::set(Some(asset.id.clone())),
+                        ],
+                    )
+                    .exec()
+                    .await?;
+            }
+            Ok::<_, ApiError>(asset)
+        })
+        .await?;
+
+    state.events.publish(ChangeEvent::AssetIngested {
+        id: asset.id.clone(),
+        experiment_id: experiment_id.clone(),
+        machine_id: Some(session.machine_id.clone()),
+    });
+    // Files that arrived before their run's sample sheet can be matched now,
+    // without holding up the agent's upload
+    if is_sample_sheet {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = rematch_unlinked(&state, &equipment).await {
+                tracing::warn!("Could not match {} files to samples: {}", equipment.name, e);
+            }
+        });
+    }
+    Ok(AssetReceipt {
+        asset_id: asset.id,
+        experiment_id,
+    })
+}
+
+/// Run the parser registry over a stored file. A file that fails to parse is
+/// still ingested, just without metadata.
+async fn extract_metadata(
+    state: &AppState,
+    storage_key: &str,
+    filename: &str,
+) -> Option<FileMetadata> {
+    let parsers = state.parsers.clone();
+    let path = state.assets.path(storage_key);
+    let name = filename.to_string();
+    match tokio::task::spawn_blocking(move || parsers.parse(&path, &name)).await {
+        Ok(Some(Ok(metadata))) => Some(metadata),
+        Ok(Some(Err(e))) => {
+            tracing::warn!("Could not extract metadata from {}: {}", filename, e);
+            None
+        }
+        Ok(None) => None,
+        Err(e) => {
+            tracing::error!("Metadata extraction for {} panicked: {}", filename, e);
+            None
+        }
+    }
+}
+
+/// Notebook entry recording an ingested file: `(experiment id, content, author)`.
+/// Locked experiments only take signed amendments, so they get none.
+async fn ingest_entry(
+    state: &AppState,
+    experiment_id: &str,
+    equipment: &equipment::Data,
+    session: &UploadSession,
+    metadata: Option<&FileMetadata>,
+) -> Result<Option<(String, String, String)>, ApiError> {
+    let experiment = state
+        .db
+        .experiment()
+        .find_unique(experiment::id::equals(experiment_id.to_string()))
+        .exec()
+        .await?;
+    if experiment.is_none_or(|experiment| experiment.locked_at.is_some()) {
+        return Ok(None);
+    }
+    let mut content = format!("Imported {} from {}", session.filename, equipment.name);
+    if let Some(metadata) = metadata {
+        content.push_str("\n\n");
+        content.push_str(&metadata.summary);
+    }
+    Ok(Some((
+        experiment_id.to_string(),
+        content,
+        equipment.name.clone(),
+    )))
+}
+
+/// The experiment booked on the instrument at `at`, if any
+async fn booked_experiment(
+    state: &AppState,
+    equipment_id: &str,
+    at: DateTime<Utc>,
+) -> Result<Option<String>, ApiError> {
+    let at = at.fixed_offset();
+    Ok(equipment_bookings(&state.db, equipment_id)
+        .await?
+        .into_iter()
+        .find(|booking| booking.start <= at && at < booking.end)
+        .map(|booking| booking.experiment_id))
+}
+
+#[derive(Deserialize)]
+pub struct IngestedQuery {
+    pub machine_id: String,
+}
+
+/// GET /agent/assets?machine_id=...
+pub(super) async fn list_ingested(
+    State(state): State<AppState>,
+    Extension(agent): Extension<AgentIdentity>,
+    Query(query): Query<IngestedQuery>,
+) -> ApiResult<Vec<IngestedFile>> {
+    let equipment = find_agent_equipment(&state.db, &agent, &query.machine_id).await?;
+    let assets = state
+        .db
+        .digital_asset()
+        .find_many(vec![digital_asset::machine_id::in_vec(agent_machine_ids(
+            &equipment,
+        ))])
+        .exec()
+        .await?;
+
+    let files = assets
+        .into_iter()
+        .map(|asset| IngestedFile {
+            filename: asset.filename,
+            size_bytes: asset.size_bytes.map(i64::from),
+            checksum: asset.checksum,
+        })
+        .collect();
+    Ok(Json(files))
+}
+
+/// Upload filenames become part of the storage key, so they must stay relative
+fn validate_filename(filename: &str) -> Result<(), ApiError> {
+    let path = FsPath::new(filename);
+    let valid = !filename.is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !valid {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid filename {:?} (expected a relative path)",
+            filename
+        )));
+    }
+    Ok(())
+}
@@ -1,16 +1,43 @@
 //! Application state for the API server
 
+use crate::assets::AssetStore;
+use crate::collab::CollabHub;
 use crate::db::prisma::PrismaClient;
+use crate::events::EventBus;
+use openbio_core::parsers::ParserRegistry;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 /// Shared application state
 #[derive(Clone)]
 pub struct AppState {
     pub db: Arc<PrismaClient>,
+    /// Open collaborative editing rooms
+    pub collab: Arc<CollabHub>,
+    /// Data change notifications for `/api/events`
+    pub events: EventBus,
+    /// Uploaded instrument files
+    pub assets: Arc<AssetStore>,
+    /// Metadata extraction for ingested files
+    pub parsers: Arc<ParserRegistry>,
+    /// Lets administrative requests in from other machines (see
+    /// [`routes::admin`](crate::routes::admin))
+    pub admin_token: Option<Arc<str>>,
 }
 
 impl AppState {
+    /// Asset files are kept next to the SQLite database
     pub async fn new(database_url: String, apply_migrations: bool) -> anyhow::Result<Self> {
+        let data_dir = data_dir_for(&database_url);
+        Self::with_data_dir(database_url, apply_migrations, &data_dir).await
+    }
+
+    /// Like [`AppState::new`], with asset files kept under `data_dir`
+    pub async fn with_data_dir(
+        database_url: String,
+        apply_migrations: bool,
+        data_dir: &Path,
+    ) -> anyhow::Result<Self> {
         // Initialize Prisma client with runtime database URL
         let db: PrismaClient = PrismaClient::_builder()
             .with_url(database_url.clone())
@@ -23,6 +50,32 @@ impl AppState {
             crate::db::migrations::apply_migrations(&db, &database_url).await?;
         }
 
-        Ok(Self { db: Arc::new(db) })
+        Ok(Self {
+            db: Arc::new(db),
+            collab: Arc::new(CollabHub::new()),
+            events: EventBus::new(),
+            assets: Arc::new(AssetStore::new(data_dir)),
+            parsers: Arc::new(ParserRegistry::default()),
+            admin_token: None,
+        })
+    }
+
+    /// Accept administrative requests carrying `token` from any machine
+    pub fn with_admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = token
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .map(Arc::from);
+        self
     }
 }
+
+/// Directory of the SQLite database; asset files are kept next to it
+fn data_dir_for(database_url: &str) -> PathBuf {
+    database_url
+        .strip_prefix("file:")
+        .and_then(|path| Path::new(path).parent())
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
@@ -0,0 +1,19 @@
+-- AlterTable
+ALTER TABLE "Experiment" ADD COLUMN "statusChangedAt" DATETIME;
+ALTER TABLE "Experiment" ADD COLUMN "statusChangedBy" TEXT;
+
+-- CreateTable
+CREATE TABLE "ExperimentStatusChange" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "experimentId" TEXT NOT NULL,
+    "fromStatus" TEXT NOT NULL,
+    "toStatus" TEXT NOT NULL,
+    "transition" TEXT NOT NULL,
+    "reason" TEXT,
+    "changedAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "changedBy" TEXT,
+    CONSTRAINT "ExperimentStatusChange_experimentId_fkey" FOREIGN KEY ("experimentId") REFERENCES "Experiment" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE INDEX "ExperimentStatusChange_experimentId_idx" ON "ExperimentStatusChange"("experimentId");
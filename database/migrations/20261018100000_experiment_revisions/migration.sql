@@ -0,0 +1,20 @@
+-- CreateTable
+CREATE TABLE "ExperimentRevision" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "experimentId" TEXT NOT NULL,
+    "revision" INTEGER NOT NULL,
+    "content" TEXT NOT NULL,
+    "restoredFrom" INTEGER,
+    "createdAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "author" TEXT,
+    CONSTRAINT "ExperimentRevision_experimentId_fkey" FOREIGN KEY ("experimentId") REFERENCES "Experiment" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "ExperimentRevision_experimentId_revision_key" ON "ExperimentRevision"("experimentId", "revision");
+
+-- Backfill: keep existing notebook content as revision 1
+INSERT INTO "ExperimentRevision" ("id", "experimentId", "revision", "content", "createdAt", "author")
+SELECT lower(hex(randomblob(12))), "id", 1, "content", "updatedAt", "createdBy"
+FROM "Experiment"
+WHERE "content" != '';
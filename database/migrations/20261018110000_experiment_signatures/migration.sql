@@ -0,0 +1,25 @@
+-- AlterTable
+ALTER TABLE "Experiment" ADD COLUMN "lockedAt" DATETIME;
+ALTER TABLE "Experiment" ADD COLUMN "contentHash" TEXT;
+
+-- AlterTable
+ALTER TABLE "ExperimentEntry" ADD COLUMN "isAmendment" BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE "ExperimentEntry" ADD COLUMN "signedBy" TEXT;
+ALTER TABLE "ExperimentEntry" ADD COLUMN "signatureMeaning" TEXT;
+ALTER TABLE "ExperimentEntry" ADD COLUMN "signedAt" DATETIME;
+ALTER TABLE "ExperimentEntry" ADD COLUMN "contentHash" TEXT;
+
+-- CreateTable
+CREATE TABLE "ExperimentSignature" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "experimentId" TEXT NOT NULL,
+    "role" TEXT NOT NULL,
+    "signer" TEXT NOT NULL,
+    "meaning" TEXT NOT NULL,
+    "contentHash" TEXT NOT NULL,
+    "signedAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "ExperimentSignature_experimentId_fkey" FOREIGN KEY ("experimentId") REFERENCES "Experiment" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "ExperimentSignature_experimentId_role_key" ON "ExperimentSignature"("experimentId", "role");
@@ -0,0 +1,41 @@
+-- CreateTable
+CREATE TABLE "ExperimentTemplate" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "name" TEXT NOT NULL,
+    "description" TEXT,
+    "content" TEXT NOT NULL DEFAULT '',
+    "sampleRoles" TEXT NOT NULL DEFAULT '[]',
+    "equipmentType" TEXT,
+    "checklist" TEXT NOT NULL DEFAULT '[]',
+    "createdAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updatedAt" DATETIME NOT NULL,
+    "createdBy" TEXT
+);
+
+-- RedefineTables
+PRAGMA foreign_keys=OFF;
+CREATE TABLE "new_Experiment" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "name" TEXT NOT NULL,
+    "description" TEXT,
+    "status" TEXT NOT NULL DEFAULT 'DRAFT',
+    "statusChangedAt" DATETIME,
+    "statusChangedBy" TEXT,
+    "lockedAt" DATETIME,
+    "contentHash" TEXT,
+    "content" TEXT NOT NULL DEFAULT '',
+    "scheduledAt" DATETIME,
+    "equipmentId" TEXT,
+    "templateId" TEXT,
+    "clonedFromId" TEXT,
+    "createdAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updatedAt" DATETIME NOT NULL,
+    "createdBy" TEXT,
+    CONSTRAINT "Experiment_equipmentId_fkey" FOREIGN KEY ("equipmentId") REFERENCES "Equipment" ("id") ON DELETE SET NULL ON UPDATE CASCADE,
+    CONSTRAINT "Experiment_templateId_fkey" FOREIGN KEY ("templateId") REFERENCES "ExperimentTemplate" ("id") ON DELETE SET NULL ON UPDATE CASCADE
+);
+INSERT INTO "new_Experiment" ("content", "contentHash", "createdAt", "createdBy", "description", "equipmentId", "id", "lockedAt", "name", "scheduledAt", "status", "statusChangedAt", "statusChangedBy", "updatedAt") SELECT "content", "contentHash", "createdAt", "createdBy", "description", "equipmentId", "id", "lockedAt", "name", "scheduledAt", "status", "statusChangedAt", "statusChangedBy", "updatedAt" FROM "Experiment";
+DROP TABLE "Experiment";
+ALTER TABLE "new_Experiment" RENAME TO "Experiment";
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys=ON;
@@ -0,0 +1,5 @@
+-- AlterTable
+ALTER TABLE "Experiment" ADD COLUMN "scheduledEnd" DATETIME;
+
+-- CreateIndex
+CREATE INDEX "Experiment_equipmentId_scheduledAt_idx" ON "Experiment"("equipmentId", "scheduledAt");
@@ -0,0 +1,61 @@
+-- CreateTable
+CREATE TABLE "MaintenanceSchedule" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "equipmentId" TEXT NOT NULL,
+    "type" TEXT NOT NULL,
+    "description" TEXT,
+    "intervalDays" INTEGER NOT NULL,
+    "nextDueAt" DATETIME NOT NULL,
+    "lastPerformedAt" DATETIME,
+    "createdAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updatedAt" DATETIME NOT NULL,
+    CONSTRAINT "MaintenanceSchedule_equipmentId_fkey" FOREIGN KEY ("equipmentId") REFERENCES "Equipment" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateTable
+CREATE TABLE "MaintenanceRecord" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "equipmentId" TEXT NOT NULL,
+    "scheduleId" TEXT,
+    "type" TEXT NOT NULL,
+    "performedAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "performedBy" TEXT NOT NULL,
+    "notes" TEXT,
+    "createdAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "MaintenanceRecord_equipmentId_fkey" FOREIGN KEY ("equipmentId") REFERENCES "Equipment" ("id") ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT "MaintenanceRecord_scheduleId_fkey" FOREIGN KEY ("scheduleId") REFERENCES "MaintenanceSchedule" ("id") ON DELETE SET NULL ON UPDATE CASCADE
+);
+
+-- RedefineTables
+PRAGMA foreign_keys=OFF;
+CREATE TABLE "new_DigitalAsset" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "filename" TEXT NOT NULL,
+    "storageKey" TEXT NOT NULL,
+    "mimeType" TEXT,
+    "sizeBytes" INTEGER,
+    "checksum" TEXT,
+    "experimentId" TEXT,
+    "sampleId" TEXT,
+    "pipelineRunId" TEXT,
+    "maintenanceRecordId" TEXT,
+    "assetType" TEXT NOT NULL DEFAULT 'RAW',
+    "createdAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "uploadedBy" TEXT,
+    "machineId" TEXT,
+    CONSTRAINT "DigitalAsset_experimentId_fkey" FOREIGN KEY ("experimentId") REFERENCES "Experiment" ("id") ON DELETE SET NULL ON UPDATE CASCADE,
+    CONSTRAINT "DigitalAsset_sampleId_fkey" FOREIGN KEY ("sampleId") REFERENCES "Sample" ("id") ON DELETE SET NULL ON UPDATE CASCADE,
+    CONSTRAINT "DigitalAsset_pipelineRunId_fkey" FOREIGN KEY ("pipelineRunId") REFERENCES "PipelineRun" ("id") ON DELETE SET NULL ON UPDATE CASCADE,
+    CONSTRAINT "DigitalAsset_maintenanceRecordId_fkey" FOREIGN KEY ("maintenanceRecordId") REFERENCES "MaintenanceRecord" ("id") ON DELETE SET NULL ON UPDATE CASCADE
+);
+INSERT INTO "new_DigitalAsset" ("assetType", "checksum", "createdAt", "experimentId", "filename", "id", "machineId", "mimeType", "pipelineRunId", "sampleId", "sizeBytes", "storageKey", "uploadedBy") SELECT "assetType", "checksum", "createdAt", "experimentId", "filename", "id", "machineId", "mimeType", "pipelineRunId", "sampleId", "sizeBytes", "storageKey", "uploadedBy" FROM "DigitalAsset";
+DROP TABLE "DigitalAsset";
+ALTER TABLE "new_DigitalAsset" RENAME TO "DigitalAsset";
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys=ON;
+
+-- CreateIndex
+CREATE INDEX "MaintenanceSchedule_nextDueAt_idx" ON "MaintenanceSchedule"("nextDueAt");
+
+-- CreateIndex
+CREATE INDEX "MaintenanceRecord_equipmentId_performedAt_idx" ON "MaintenanceRecord"("equipmentId", "performedAt");
@@ -0,0 +1,4 @@
+-- AlterTable
+ALTER TABLE "Equipment" ADD COLUMN "agentQueueDepth" INTEGER;
+ALTER TABLE "Equipment" ADD COLUMN "agentVersion" TEXT;
+ALTER TABLE "Equipment" ADD COLUMN "agentWatchDir" TEXT;
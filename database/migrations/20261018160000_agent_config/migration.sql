@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE "Equipment" ADD COLUMN "excludeGlobs" TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE "Equipment" ADD COLUMN "includeGlobs" TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE "DigitalAsset" ADD COLUMN "metadata" TEXT;
+ALTER TABLE "DigitalAsset" ADD COLUMN "metadataFormat" TEXT;
@@ -0,0 +1,5 @@
+-- AlterTable
+ALTER TABLE "DigitalAsset" ADD COLUMN "sampleKey" TEXT;
+
+-- AlterTable
+ALTER TABLE "Equipment" ADD COLUMN "sampleRules" TEXT NOT NULL DEFAULT '[]';
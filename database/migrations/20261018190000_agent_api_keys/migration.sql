@@ -0,0 +1,21 @@
+-- CreateTable
+CREATE TABLE "AgentApiKey" (
+    "id" TEXT NOT NULL PRIMARY KEY,
+    "equipmentId" TEXT NOT NULL,
+    "name" TEXT,
+    "prefix" TEXT NOT NULL,
+    "keyHash" TEXT NOT NULL,
+    "createdAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "createdBy" TEXT,
+    "lastUsedAt" DATETIME,
+    "expiresAt" DATETIME,
+    "revokedAt" DATETIME,
+    "replacedById" TEXT,
+    CONSTRAINT "AgentApiKey_equipmentId_fkey" FOREIGN KEY ("equipmentId") REFERENCES "Equipment" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "AgentApiKey_keyHash_key" ON "AgentApiKey"("keyHash");
+
+-- CreateIndex
+CREATE INDEX "AgentApiKey_equipmentId_idx" ON "AgentApiKey"("equipmentId");
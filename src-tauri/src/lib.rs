@@ -2,10 +2,12 @@
 //!
 //! Handles app lifecycle, config management, and embedded server spawning.
 
+use openbio_core::discovery::{self, DiscoveredHub, HubAdvertisement};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Mutex;
+use std::time::Duration;
 use tauri::{AppHandle, Emitter, State};
 use tauri_plugin_updater::UpdaterExt;
 
@@ -136,117 +138,37 @@ fn needs_setup() -> bool {
     !config_path().exists()
 }
 
+/// The Hub's mDNS advertisement, kept for the life of the app
+static HUB_ADVERTISEMENT: Mutex<Option<HubAdvertisement>> = Mutex::new(None);
+
 /// Start mDNS broadcast for hub discovery
 fn start_mdns_broadcast(lab_name: String, port: u16) {
-    std::thread::spawn(move || {
-        use mdns_sd::{ServiceDaemon, ServiceInfo};
-
-        let mdns = match ServiceDaemon::new() {
-            Ok(d) => d,
-            Err(e) => {
-                eprintln!("Failed to create mDNS daemon: {}", e);
-                return;
-            }
-        };
-
-        let service_type = "_openbio._tcp.local.";
-        let host_name = format!("{}.local.", lab_name.replace(' ', "-").to_lowercase());
-
-        let service_info =
-            match ServiceInfo::new(service_type, &lab_name, &host_name, "", port, None) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("Failed to create service info: {}", e);
-                    return;
-                }
-            };
-
-        if let Err(e) = mdns.register(service_info) {
-            eprintln!("Failed to register mDNS service: {}", e);
-            return;
+    match HubAdvertisement::start(&lab_name, port) {
+        Ok(advertisement) => {
+            // Replaces (and withdraws) an earlier advertisement
+            *HUB_ADVERTISEMENT.lock().unwrap() = Some(advertisement);
+            println!("mDNS: Broadcasting '{}' on port {}", lab_name, port);
         }
-
-        println!("mDNS: Broadcasting '{}' on port {}", lab_name, port);
-
-        // Keep thread alive to maintain registration
-        loop {
-            std::thread::sleep(std::time::Duration::from_secs(60));
-        }
-    });
-}
-
-/// Discovered Hub information
-#[derive(Debug, Serialize, Clone)]
-struct DiscoveredHub {
-    name: String,
-    address: String, // IP:Port
+        Err(e) => eprintln!("Failed to start mDNS broadcast: {}", e),
+    }
 }
 
 /// Scan for OpenBio hubs on the network
 #[tauri::command]
 async fn scan_for_hubs() -> Result<Vec<DiscoveredHub>, String> {
-    use mdns_sd::{ServiceDaemon, ServiceEvent};
-    use std::time::Duration;
-
-    // Create daemon and keep it alive for the entire scan
-    let mdns = ServiceDaemon::new().map_err(|e| {
-        eprintln!("Failed to create mDNS daemon: {}", e);
-        e.to_string()
-    })?;
-
-    let service_type = "_openbio._tcp.local.";
-    let receiver = mdns.browse(service_type).map_err(|e| {
-        eprintln!("Failed to start mDNS browse: {}", e);
-        e.to_string()
-    })?;
-
     println!("mDNS: Scanning for OpenBio hubs...");
 
-    let mut hubs = Vec::new();
-    let deadline = std::time::Instant::now() + Duration::from_secs(5); // Increased from 2s
-
-    while std::time::Instant::now() < deadline {
-        match receiver.recv_timeout(Duration::from_millis(100)) {
-            Ok(event) => {
-                match event {
-                    ServiceEvent::ServiceResolved(info) => {
-                        let name = info
-                            .get_fullname()
-                            .replace(&format!(".{}", service_type), "");
-                        // Prefer IPv4
-                        if let Some(addr) = info.get_addresses().iter().find(|ip| ip.is_ipv4()) {
-                            let hub = DiscoveredHub {
-                                name: name.clone(),
-                                address: format!("http://{}:{}", addr, info.get_port()),
-                            };
-                            println!("mDNS: Found hub '{}' at {}", hub.name, hub.address);
-                            hubs.push(hub);
-                        }
-                    }
-                    ServiceEvent::SearchStarted(_) => {
-                        println!("mDNS: Search started");
-                    }
-                    ServiceEvent::ServiceFound(_, _) => {
-                        // Service found but not yet resolved, continue waiting
-                    }
-                    _ => {}
-                }
-            }
-            Err(_) => {
-                // Timeout or other error, continue scanning
-            }
-        }
-    }
+    let hubs = tauri::async_runtime::spawn_blocking(|| discovery::scan(Duration::from_secs(5)))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| {
+            eprintln!("mDNS scan failed: {}", e);
+            e.to_string()
+        })?;
 
-    // Properly shutdown the daemon before dropping
-    if let Err(e) = mdns.shutdown() {
-        eprintln!("Failed to shutdown mDNS daemon: {}", e);
+    for hub in &hubs {
+        println!("mDNS: Found hub '{}' at {}", hub.name, hub.address);
     }
-
-    // Sort by name and remove duplicates
-    hubs.sort_by(|a, b| a.name.cmp(&b.name));
-    hubs.dedup_by(|a, b| a.address == b.address);
-
     println!("mDNS: Scan complete, found {} hub(s)", hubs.len());
 
     Ok(hubs)
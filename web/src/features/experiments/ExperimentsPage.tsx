@@ -2,17 +2,28 @@
  * Experiments Page - The notebook for your experimental work
  * Each experiment has a rich text editor where you can write notes and @mention samples, equipment, and papers
  */
-import React, { useState } from 'react';
-import { experimentsApi, Experiment } from '../../lib/api';
+import React, { useRef, useState } from 'react';
+import { experimentsApi, Experiment, etagFor } from '../../lib/api';
 import { FlaskConical, Plus, Calendar } from 'lucide-react';
 import { useQuery, useMutation, useQueryClient } from '@tanstack/react-query';
 import { NotebookEditor } from '../notebooks/components/NotebookEditor';
 
+/** How long typing has to pause before the notebook is saved */
+const SAVE_DELAY_MS = 800;
+
 export const ExperimentsPage: React.FC = () => {
   const [selectedExperiment, setSelectedExperiment] = useState<Experiment | null>(null);
   const [showCreateModal, setShowCreateModal] = useState(false);
+  const [saveError, setSaveError] = useState<string | null>(null);
   const queryClient = useQueryClient();
 
+  // Saves run one at a time, each sending the ETag of the version the previous save
+  // returned, so quick edits never race each other into a 412.
+  const versions = useRef(new Map<string, string>()); // experiment id -> updatedAt
+  const pendingSaves = useRef(new Map<string, string>()); // experiment id -> content
+  const saveTimer = useRef<ReturnType<typeof setTimeout> | null>(null);
+  const saving = useRef(false);
+
   const { data: experiments = [] } = useQuery({
     queryKey: ['experiments'],
     queryFn: experimentsApi.list,
@@ -23,23 +34,67 @@ export const ExperimentsPage: React.FC = () => {
       experimentsApi.create(data),
     onSuccess: (newExperiment) => {
       queryClient.invalidateQueries({ queryKey: ['experiments'] });
-      setSelectedExperiment(newExperiment);
+      selectExperiment(newExperiment);
       setShowCreateModal(false);
     },
   });
 
   const updateMutation = useMutation({
-    mutationFn: ({ id, content }: { id: string; content: string }) =>
-      experimentsApi.update(id, { content }),
-    onSuccess: () => {
+    mutationFn: ({ id, content, updatedAt }: { id: string; content: string; updatedAt: string }) =>
+      experimentsApi.update(id, { content }, etagFor(updatedAt)),
+    onSuccess: (updated) => {
+      // Keep the latest version so the next save sends a matching If-Match
+      versions.current.set(updated.id, updated.updatedAt);
+      setSelectedExperiment((current) => (current?.id === updated.id ? updated : current));
       queryClient.invalidateQueries({ queryKey: ['experiments'] });
     },
   });
 
+  const selectExperiment = (experiment: Experiment) => {
+    // The list may be older than the version our own saves returned
+    const known = versions.current.get(experiment.id);
+    if (!known || Date.parse(experiment.updatedAt) > Date.parse(known)) {
+      versions.current.set(experiment.id, experiment.updatedAt);
+    }
+    setSaveError(null);
+    setSelectedExperiment(experiment);
+  };
+
+  const flushSaves = () => {
+    const next = pendingSaves.current.entries().next();
+    if (saving.current || next.done) {
+      return;
+    }
+    const [id, content] = next.value;
+    const updatedAt = versions.current.get(id);
+    pendingSaves.current.delete(id);
+    if (!updatedAt) {
+      return;
+    }
+
+    saving.current = true;
+    updateMutation.mutate(
+      { id, content, updatedAt },
+      {
+        onSuccess: () => setSaveError(null),
+        onError: (error) => setSaveError(error.message),
+        onSettled: () => {
+          saving.current = false;
+          flushSaves();
+        },
+      }
+    );
+  };
+
   const handleContentChange = (content: string) => {
-    if (selectedExperiment) {
-      updateMutation.mutate({ id: selectedExperiment.id, content });
+    if (!selectedExperiment) {
+      return;
+    }
+    pendingSaves.current.set(selectedExperiment.id, content);
+    if (saveTimer.current) {
+      clearTimeout(saveTimer.current);
     }
+    saveTimer.current = setTimeout(flushSaves, SAVE_DELAY_MS);
   };
 
   return (
@@ -76,7 +131,7 @@ export const ExperimentsPage: React.FC = () => {
             experiments.map((exp) => (
               <button
                 key={exp.id}
-                onClick={() => setSelectedExperiment(exp)}
+                onClick={() => selectExperiment(exp)}
                 className={`w-full p-3 text-left border-b hover:bg-gray-50 transition-colors ${
                   selectedExperiment?.id === exp.id ? 'bg-blue-50 border-l-4 border-l-blue-500' : ''
                 }`}
@@ -108,6 +163,11 @@ export const ExperimentsPage: React.FC = () => {
               {selectedExperiment.description && (
                 <p className="text-gray-600 text-sm">{selectedExperiment.description}</p>
               )}
+              {saveError && (
+                <p className="text-red-600 text-sm mt-2">
+                  Not saved: {saveError}. Reload the experiment to keep editing.
+                </p>
+              )}
             </div>
 
             <div className="flex-1 overflow-hidden">
@@ -1,6 +1,6 @@
 import { useState } from 'react';
 import { useMutation, useQueryClient } from '@tanstack/react-query';
-import { inventoryApi, Sample } from '../../../lib/api';
+import { inventoryApi, Sample, etagFor } from '../../../lib/api';
 import { X, Beaker } from 'lucide-react';
 
 interface CreateSampleModalProps {
@@ -22,7 +22,7 @@ export function CreateSampleModal({ onClose, containerId, containerName, prefill
                 return inventoryApi.updateSample(editSample.id, {
                     name,
                     metadata,
-                });
+                }, etagFor(editSample.updatedAt));
             } else {
                 return inventoryApi.createSample({
                     name,
@@ -1,10 +1,12 @@
 /**
  * Rich text editor component for laboratory notebooks
- * Uses TipTap with @mention support for samples, equipment, and papers
+ * Uses TipTap with @mention support for samples, equipment, and papers,
+ * and task lists for the checklists templates add
  */
 import { useEditor, EditorContent } from '@tiptap/react';
 import StarterKit from '@tiptap/starter-kit';
 import Mention from '@tiptap/extension-mention';
+import { TaskList, TaskItem } from '@tiptap/extension-list';
 import { ReactRenderer } from '@tiptap/react';
 import tippy from 'tippy.js';
 import 'tippy.js/dist/tippy.css';
@@ -13,6 +15,22 @@ import { experimentsApi, SearchResult } from '../../../lib/api';
 import React from 'react';
 import { Bold, Italic, List, ListOrdered, Undo, Redo } from 'lucide-react';
 
+/**
+ * Notebooks are stored as ProseMirror JSON, which the server reads for mentions,
+ * diffs and exports. Older notebooks hold the editor's HTML and load as such.
+ */
+const parseContent = (content: string) => {
+  try {
+    const doc = JSON.parse(content);
+    if (doc && typeof doc === 'object' && doc.type === 'doc') {
+      return doc;
+    }
+  } catch {
+    // Not JSON: HTML or plain text
+  }
+  return content;
+};
+
 interface NotebookEditorProps {
   initialContent: string;
   onSave: (content: string) => void;
@@ -35,7 +53,22 @@ export const NotebookEditor: React.FC<NotebookEditorProps> = ({
   const editor = useEditor({
     extensions: [
       StarterKit,
-      Mention.configure({
+      TaskList,
+      TaskItem.configure({ nested: true }),
+      // Mention nodes also carry the entity type so the server can resolve them
+      Mention.extend({
+        addAttributes() {
+          return {
+            ...this.parent?.(),
+            type: {
+              default: null,
+              parseHTML: (element: HTMLElement) => element.getAttribute('data-entity-type'),
+              renderHTML: (attributes: { type?: string | null }) =>
+                attributes.type ? { 'data-entity-type': attributes.type } : {},
+            },
+          };
+        },
+      }).configure({
         HTMLAttributes: {
           class: 'mention bg-blue-100 text-blue-700 px-1 rounded',
         },
@@ -121,6 +154,7 @@ export const NotebookEditor: React.FC<NotebookEditorProps> = ({
                   attrs: {
                     id: item.id,
                     label: item.label,
+                    type: item.type,
                   },
                 },
                 {
@@ -138,9 +172,9 @@ export const NotebookEditor: React.FC<NotebookEditorProps> = ({
         },
       }),
     ],
-    content: initialContent,
+    content: parseContent(initialContent),
     onUpdate: ({ editor }) => {
-      onSave(editor.getHTML());
+      onSave(JSON.stringify(editor.getJSON()));
     },
   });
 
@@ -14,6 +14,17 @@ export function getApiBaseUrl() {
     return apiBaseUrl;
 }
 
+// Only needed to manage agent keys and locks on a Hub on another machine
+let adminToken: string | null = null;
+
+export function setAdminToken(token: string | null) {
+    adminToken = token?.trim() || null;
+}
+
+function adminHeaders(): HeadersInit {
+    return adminToken ? { Authorization: `Bearer ${adminToken}` } : {};
+}
+
 /**
  * API client wrapper with error handling
  */
@@ -49,6 +60,14 @@ async function apiRequest<T>(
     }
 }
 
+/**
+ * ETag of a record, derived from its updatedAt (matches the server's ETag header).
+ * Send it as If-Match on PATCH so concurrent edits are rejected instead of overwritten.
+ */
+export function etagFor(updatedAt: string): string {
+    return `"${Date.parse(updatedAt)}"`;
+}
+
 // ============================================
 // Inventory API
 // ============================================
@@ -93,13 +112,14 @@ export const inventoryApi = {
             body: JSON.stringify(payload),
         });
     },
-    updateSample: (id: string, data: Partial<Sample>) => {
+    updateSample: (id: string, data: Partial<Sample>, etag: string) => {
         const payload: any = {
             name: data.name,
             metadata: data.metadata,
         };
         return apiRequest<Sample>(`/api/inventory/samples/${id}`, {
             method: 'PATCH',
+            headers: { 'If-Match': etag },
             body: JSON.stringify(payload),
         });
     },
@@ -146,8 +166,24 @@ export interface Experiment {
     description?: string;
     content: string; // Rich text notebook content
     status: 'DRAFT' | 'SCHEDULED' | 'IN_PROGRESS' | 'COMPLETED' | 'FAILED';
-    scheduledAt?: string;
+    scheduledAt?: string; // Booking start
+    scheduledEnd?: string; // Booking end
     equipmentId?: string;
+    templateId?: string;
+    clonedFromId?: string;
+    createdAt: string;
+    updatedAt: string;
+    createdBy?: string;
+}
+
+export interface ExperimentTemplate {
+    id: string;
+    name: string;
+    description?: string;
+    content: string; // Notebook skeleton
+    sampleRoles: string; // JSON array, e.g. ["input", "control"]
+    equipmentType?: string;
+    checklist: string; // JSON array of steps
     createdAt: string;
     updatedAt: string;
     createdBy?: string;
@@ -174,6 +210,290 @@ export interface ExperimentMention {
     createdAt: string;
 }
 
+export interface Booking {
+    experimentId: string;
+    experimentName: string;
+    status: Experiment['status'];
+    start: string;
+    end: string;
+    createdBy?: string;
+}
+
+export const equipmentApi = {
+    listBookings: (equipmentId: string, range: { from?: string; to?: string } = {}) => {
+        const params = new URLSearchParams();
+        if (range.from) params.set('from', range.from);
+        if (range.to) params.set('to', range.to);
+        return apiRequest<Booking[]>(`/api/equipment/${equipmentId}/bookings?${params}`);
+    },
+    // overrideReason is required when the instrument's calibration has lapsed
+    book: (
+        equipmentId: string,
+        data: { experimentId: string; start: string; end: string; overrideReason?: string; actor?: string }
+    ) =>
+        apiRequest<Booking>(`/api/equipment/${equipmentId}/bookings`, {
+            method: 'POST',
+            body: JSON.stringify({
+                experiment_id: data.experimentId,
+                start: data.start,
+                end: data.end,
+                override_reason: data.overrideReason,
+                actor: data.actor,
+            }),
+        }),
+    cancelBooking: (equipmentId: string, experimentId: string) =>
+        apiRequest<void>(`/api/equipment/${equipmentId}/bookings/${experimentId}`, {
+            method: 'DELETE',
+        }),
+    // Subscribe URL for calendar apps
+    calendarUrl: (equipmentId: string) => `${apiBaseUrl}/api/equipment/${equipmentId}/bookings.ics`,
+
+    // Ingest agents on instrument PCs
+    listAgents: () => apiRequest<AgentOverview[]>('/api/equipment/agents'),
+    getAgentConfig: (equipmentId: string) =>
+        apiRequest<AgentConfig>(`/api/equipment/${equipmentId}/agent-config`),
+    updateAgentConfig: (
+        equipmentId: string,
+        data: { watch_folder?: string; include?: string[]; exclude?: string[]; auto_import?: boolean }
+    ) =>
+        apiRequest<AgentConfig>(`/api/equipment/${equipmentId}/agent-config`, {
+            method: 'PATCH',
+            body: JSON.stringify(data),
+        }),
+    // A locked agent's uploads are refused until it is unlocked
+    lockAgent: (equipmentId: string) =>
+        apiRequest<AgentOverview>(`/api/equipment/${equipmentId}/agent-lock`, {
+            method: 'POST',
+            headers: adminHeaders(),
+        }),
+    unlockAgent: (equipmentId: string) =>
+        apiRequest<AgentOverview>(`/api/equipment/${equipmentId}/agent-lock`, {
+            method: 'DELETE',
+            headers: adminHeaders(),
+        }),
+
+    // Agent API keys (the key itself is only returned when issued)
+    listAgentApiKeys: (equipmentId: string) =>
+        apiRequest<AgentApiKey[]>(`/api/equipment/${equipmentId}/api-keys`),
+    createAgentApiKey: (equipmentId: string, data: { name?: string; created_by?: string }) =>
+        apiRequest<IssuedAgentApiKey>(`/api/equipment/${equipmentId}/api-keys`, {
+            method: 'POST',
+            headers: adminHeaders(),
+            body: JSON.stringify(data),
+        }),
+    rotateAgentApiKey: (
+        equipmentId: string,
+        keyId: string,
+        data: { grace_minutes?: number; created_by?: string } = {}
+    ) =>
+        apiRequest<IssuedAgentApiKey>(`/api/equipment/${equipmentId}/api-keys/${keyId}/rotate`, {
+            method: 'POST',
+            headers: adminHeaders(),
+            body: JSON.stringify(data),
+        }),
+    revokeAgentApiKey: (equipmentId: string, keyId: string) =>
+        apiRequest<AgentApiKey>(`/api/equipment/${equipmentId}/api-keys/${keyId}`, {
+            method: 'DELETE',
+            headers: adminHeaders(),
+        }),
+
+    // Linking ingested files to samples
+    getSampleRules: (equipmentId: string) =>
+        apiRequest<SampleRule[]>(`/api/equipment/${equipmentId}/sample-rules`),
+    updateSampleRules: (equipmentId: string, rules: SampleRule[]) =>
+        apiRequest<SampleRule[]>(`/api/equipment/${equipmentId}/sample-rules`, {
+            method: 'PUT',
+            body: JSON.stringify(rules),
+        }),
+    applySampleRules: (equipmentId: string) =>
+        apiRequest<SampleMatchReport>(`/api/equipment/${equipmentId}/sample-rules/apply`, {
+            method: 'POST',
+        }),
+    listUnmatchedAssets: (equipmentId: string) =>
+        apiRequest<UnmatchedAsset[]>(`/api/equipment/${equipmentId}/unmatched-assets`),
+    assignAssetSample: (assetId: string, sampleId: string | null) =>
+        apiRequest<DigitalAsset>(`/api/assets/${assetId}/sample`, {
+            method: 'PUT',
+            body: JSON.stringify({ sample_id: sampleId }),
+        }),
+
+    // Maintenance
+    listOverdueMaintenance: (withinDays = 0) =>
+        apiRequest<OverdueMaintenance[]>(`/api/equipment/maintenance/overdue?within_days=${withinDays}`),
+    listMaintenanceSchedules: (equipmentId: string) =>
+        apiRequest<MaintenanceSchedule[]>(`/api/equipment/${equipmentId}/maintenance/schedules`),
+    createMaintenanceSchedule: (
+        equipmentId: string,
+        data: { type: MaintenanceType; interval_days: number; next_due_at?: string; description?: string }
+    ) =>
+        apiRequest<MaintenanceSchedule>(`/api/equipment/${equipmentId}/maintenance/schedules`, {
+            method: 'POST',
+            body: JSON.stringify(data),
+        }),
+    updateMaintenanceSchedule: (
+        equipmentId: string,
+        scheduleId: string,
+        data: { interval_days?: number; next_due_at?: string; description?: string }
+    ) =>
+        apiRequest<MaintenanceSchedule>(`/api/equipment/${equipmentId}/maintenance/schedules/${scheduleId}`, {
+            method: 'PATCH',
+            body: JSON.stringify(data),
+        }),
+    deleteMaintenanceSchedule: (equipmentId: string, scheduleId: string) =>
+        apiRequest<void>(`/api/equipment/${equipmentId}/maintenance/schedules/${scheduleId}`, {
+            method: 'DELETE',
+        }),
+    listMaintenanceRecords: (equipmentId: string) =>
+        apiRequest<MaintenanceRecord[]>(`/api/equipment/${equipmentId}/maintenance/records`),
+    logMaintenance: (
+        equipmentId: string,
+        data: {
+            type: MaintenanceType;
+            performed_by: string;
+            performed_at?: string;
+            notes?: string;
+            schedule_id?: string;
+            asset_ids?: string[];
+        }
+    ) =>
+        apiRequest<MaintenanceRecord>(`/api/equipment/${equipmentId}/maintenance/records`, {
+            method: 'POST',
+            body: JSON.stringify(data),
+        }),
+};
+
+export interface AgentOverview {
+    equipmentId: string;
+    equipmentName: string;
+    equipmentType: string;
+    location?: string;
+    status: 'OFFLINE' | 'ONLINE' | 'LOCKED';
+    lastSeenAt?: string;
+    version?: string;
+    watchDir?: string;
+    queueDepth?: number;
+    autoImport: boolean;
+}
+
+// Field names follow the agent protocol (snake_case)
+export interface AgentConfig {
+    equipment_id: string;
+    watch_folder?: string;
+    include: string[];
+    exclude: string[];
+    auto_import: boolean;
+}
+
+export interface AgentApiKey {
+    id: string;
+    equipmentId: string;
+    name?: string;
+    prefix: string;
+    createdAt: string;
+    createdBy?: string;
+    lastUsedAt?: string;
+    expiresAt?: string;
+    revokedAt?: string;
+    replacedById?: string;
+    active: boolean;
+}
+
+export interface IssuedAgentApiKey extends AgentApiKey {
+    key: string;
+}
+
+// Stored as JSON on the Equipment record (snake_case)
+export interface SampleRule {
+    pattern: string;
+    field?: 'external_id' | 'name';
+    sheet?: { key_column: string; sample_column: string };
+}
+
+export interface SampleMatchReport {
+    matched: number;
+    unmatched: number;
+}
+
+export interface UnmatchedAsset {
+    assetId: string;
+    filename: string;
+    sampleKey: string;
+    experimentId?: string;
+    createdAt: string;
+}
+
+export type MaintenanceType = 'CALIBRATION' | 'SERVICE' | 'REPAIR' | 'CLEANING' | 'INSPECTION';
+
+export interface MaintenanceSchedule {
+    id: string;
+    equipmentId: string;
+    type: MaintenanceType;
+    description?: string;
+    intervalDays: number;
+    nextDueAt: string;
+    lastPerformedAt?: string;
+    createdAt: string;
+    updatedAt: string;
+}
+
+export interface DigitalAsset {
+    id: string;
+    filename: string;
+    storageKey: string;
+    mimeType?: string;
+    sizeBytes?: number;
+    checksum?: string;
+    experimentId?: string;
+    sampleId?: string;
+    sampleKey?: string;
+    assetType: string;
+    createdAt: string;
+    uploadedBy?: string;
+    machineId?: string;
+    metadataFormat?: string;
+    metadata?: string;
+}
+
+export interface MaintenanceRecord {
+    id: string;
+    equipmentId: string;
+    scheduleId?: string;
+    type: MaintenanceType;
+    performedAt: string;
+    performedBy: string;
+    notes?: string;
+    attachments?: DigitalAsset[];
+    createdAt: string;
+}
+
+export interface OverdueMaintenance {
+    scheduleId: string;
+    equipmentId: string;
+    equipmentName: string;
+    type: MaintenanceType;
+    description?: string;
+    nextDueAt: string;
+    lastPerformedAt?: string;
+    // Negative when not due yet
+    daysOverdue: number;
+}
+
+export interface MentionStaleness {
+    mentionId: string;
+    entityType: string;
+    entityId: string;
+    state: 'changed' | 'deleted';
+    changes: { field: string; snapshot: unknown; current: unknown }[];
+}
+
+export interface MentionedIn {
+    experimentId: string;
+    experimentName: string;
+    status: Experiment['status'];
+    positions: number[];
+    firstMentionedAt: string;
+}
+
 export interface SearchResult {
     entityType: 'sample' | 'equipment' | 'paper';
     id: string;
@@ -189,9 +509,10 @@ export const experimentsApi = {
             method: 'POST',
             body: JSON.stringify(data),
         }),
-    update: (id: string, data: Partial<Experiment>) =>
+    update: (id: string, data: Partial<Experiment>, etag: string) =>
         apiRequest<Experiment>(`/api/experiments/${id}`, {
             method: 'PATCH',
+            headers: { 'If-Match': etag },
             body: JSON.stringify(data),
         }),
     delete: (id: string) =>
@@ -215,19 +536,53 @@ export const experimentsApi = {
     // Mentions (for @sample, @equipment, @paper)
     listMentions: (experimentId: string) =>
         apiRequest<ExperimentMention[]>(`/api/experiments/${experimentId}/mentions`),
-    createMention: (experimentId: string, data: { entityType: string; entityId: string; snapshotData: string; position?: number }) =>
+    // Mentions are synced from the content on save; the server takes the snapshot
+    createMention: (experimentId: string, data: { entityType: string; entityId: string; position?: number }) =>
         apiRequest<ExperimentMention>(`/api/experiments/${experimentId}/mentions`, {
             method: 'POST',
             body: JSON.stringify({
                 entity_type: data.entityType,
                 entity_id: data.entityId,
-                snapshot_data: data.snapshotData,
                 position: data.position,
             }),
         }),
+    staleMentions: (experimentId: string) =>
+        apiRequest<MentionStaleness[]>(`/api/experiments/${experimentId}/mentions/stale`),
+    refreshMention: (experimentId: string, mentionId: string, actor?: string) =>
+        apiRequest<ExperimentMention>(`/api/experiments/${experimentId}/mentions/${mentionId}/refresh`, {
+            method: 'POST',
+            body: JSON.stringify({ actor }),
+        }),
+    // Back-references: experiments whose notebook mentions an entity
+    mentionedIn: (entityType: 'sample' | 'equipment' | 'paper', id: string) => {
+        const base = { sample: '/api/inventory/samples', equipment: '/api/equipment', paper: '/api/library' }[entityType];
+        return apiRequest<MentionedIn[]>(`${base}/${id}/mentioned-in`);
+    },
     
     // Search for @mentions
     searchEntities: () => apiRequest<SearchResult[]>('/api/experiments/search-entities'),
+
+    // Templates and cloning
+    listTemplates: () => apiRequest<ExperimentTemplate[]>('/api/experiments/templates'),
+    createFromTemplate: (templateId: string, data: { name: string; createdBy?: string; equipmentId?: string; samples?: { sampleId: string; role?: string }[] }) =>
+        apiRequest<Experiment>(`/api/experiments/templates/${templateId}/experiments`, {
+            method: 'POST',
+            body: JSON.stringify({
+                name: data.name,
+                created_by: data.createdBy,
+                equipment_id: data.equipmentId,
+                samples: (data.samples ?? []).map((s) => ({ sample_id: s.sampleId, role: s.role })),
+            }),
+        }),
+    clone: (id: string, data: { name?: string; createdBy?: string } = {}) =>
+        apiRequest<Experiment>(`/api/experiments/${id}/clone`, {
+            method: 'POST',
+            body: JSON.stringify({ name: data.name, created_by: data.createdBy }),
+        }),
+
+    // Download link for a report (signatures, entries, samples, assets)
+    exportUrl: (id: string, format: 'pdf' | 'html' | 'md' = 'pdf') =>
+        `${apiBaseUrl}/api/experiments/${id}/export?format=${format}`,
 };
 
 // ============================================